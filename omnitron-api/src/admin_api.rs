@@ -12,6 +12,7 @@ pub fn admin_api_app(services: &Services) -> impl IntoEndpoint {
   let config = services.config.clone();
   let config_provider = services.config_provider.clone();
   let state = services.state.clone();
+  let recordings = services.recordings.clone();
 
   Route::new()
     .nest("", api_service)
@@ -21,8 +22,21 @@ pub fn admin_api_app(services: &Services) -> impl IntoEndpoint {
       "/sessions/changes",
       crate::api::admin::sessions_list::api_get_sessions_changes_stream,
     )
+    .at(
+      "/sessions/:id/stream",
+      crate::api::admin::sessions_detail::api_get_session_stream,
+    )
+    .at(
+      "/recordings/:id/cast",
+      crate::api::admin::recordings_detail::api_get_recording_cast,
+    )
+    .at(
+      "/recordings/:id/har",
+      crate::api::admin::recordings_detail::api_get_recording_har,
+    )
     .data(db)
     .data(config_provider)
     .data(state)
+    .data(recordings)
     .data(config)
 }
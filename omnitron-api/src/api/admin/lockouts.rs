@@ -0,0 +1,53 @@
+use omnitron_db_entities::LoginFailure::{self, LoginFailureKind};
+use omnitron_gate_common::OmnitronError;
+use omnitron_gate_core::Services;
+use poem::web::Data;
+use poem_openapi::param::Path;
+use poem_openapi::payload::Json;
+use poem_openapi::{ApiResponse, OpenApi};
+
+use super::AnySecurityScheme;
+
+pub struct Api;
+
+#[derive(ApiResponse)]
+enum GetLockoutsResponse {
+  #[oai(status = 200)]
+  Ok(Json<Vec<LoginFailure::Model>>),
+}
+
+#[derive(ApiResponse)]
+enum ClearLockoutResponse {
+  #[oai(status = 204)]
+  Deleted,
+
+  #[oai(status = 404)]
+  NotFound,
+}
+
+#[OpenApi]
+impl Api {
+  /// Usernames and addresses with recent failed logins, including the ones that are currently locked out
+  #[oai(path = "/lockouts", method = "get", operation_id = "get_lockouts")]
+  async fn api_get_lockouts(
+    &self,
+    services: Data<&Services>,
+    _auth: AnySecurityScheme,
+  ) -> Result<GetLockoutsResponse, OmnitronError> {
+    Ok(GetLockoutsResponse::Ok(Json(services.login_protection.list().await?)))
+  }
+
+  #[oai(path = "/lockouts/:kind/:key", method = "delete", operation_id = "clear_lockout")]
+  async fn api_clear_lockout(
+    &self,
+    services: Data<&Services>,
+    kind: Path<LoginFailureKind>,
+    key: Path<String>,
+    _auth: AnySecurityScheme,
+  ) -> Result<ClearLockoutResponse, OmnitronError> {
+    Ok(match services.login_protection.clear(kind.0, &key).await? {
+      true => ClearLockoutResponse::Deleted,
+      false => ClearLockoutResponse::NotFound,
+    })
+  }
+}
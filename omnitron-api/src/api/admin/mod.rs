@@ -3,16 +3,19 @@ use poem_openapi::{OpenApi, SecurityScheme};
 
 mod known_hosts_detail;
 mod known_hosts_list;
+mod lockouts;
 mod logs;
 mod otp_credentials;
 mod pagination;
 mod parameters;
 mod password_credentials;
 mod public_key_credentials;
+pub mod recordings_detail;
 mod roles;
-mod sessions_detail;
+pub mod sessions_detail;
 pub mod sessions_list;
 mod ssh_keys;
+mod sso_credentials;
 mod targets;
 mod tickets_detail;
 mod tickets_list;
@@ -37,17 +40,19 @@ pub enum AnySecurityScheme {
 
 pub fn get() -> impl OpenApi {
   (
-    (sessions_list::Api, sessions_detail::Api),
+    (sessions_list::Api, sessions_detail::Api, recordings_detail::Api),
     (roles::ListApi, roles::DetailApi),
     (tickets_list::Api, tickets_detail::Api),
     (known_hosts_list::Api, known_hosts_detail::Api),
     ssh_keys::Api,
     logs::Api,
+    lockouts::Api,
     (targets::ListApi, targets::DetailApi, targets::RolesApi),
     (users::ListApi, users::DetailApi, users::RolesApi),
     (password_credentials::ListApi, password_credentials::DetailApi),
     (public_key_credentials::ListApi, public_key_credentials::DetailApi),
     (otp_credentials::ListApi, otp_credentials::DetailApi),
+    (sso_credentials::ListApi, sso_credentials::DetailApi),
     parameters::Api,
   )
 }
@@ -0,0 +1,114 @@
+use std::sync::Arc;
+
+use omnitron_db_entities::Recording::{self, RecordingKind};
+use omnitron_gate_common::OmnitronError;
+use omnitron_gate_core::recordings::{har_from_recording, SessionRecordings};
+use poem::http::StatusCode;
+use poem::web::{Data, Path as PoemPath};
+use poem::{handler, Body, IntoResponse, Response};
+use poem_openapi::param::Path;
+use poem_openapi::payload::Json;
+use poem_openapi::{ApiResponse, OpenApi};
+use sea_orm::{DatabaseConnection, EntityTrait};
+use tokio::sync::Mutex;
+use uuid::Uuid;
+
+use super::AnySecurityScheme;
+
+pub struct Api;
+
+#[derive(ApiResponse)]
+enum GetRecordingResponse {
+  #[oai(status = 200)]
+  Ok(Json<Recording::Model>),
+  #[oai(status = 404)]
+  NotFound,
+}
+
+#[OpenApi]
+impl Api {
+  #[oai(path = "/recordings/:id", method = "get", operation_id = "get_recording")]
+  async fn api_get_recording(
+    &self,
+    db: Data<&Arc<Mutex<DatabaseConnection>>>,
+    id: Path<Uuid>,
+    _auth: AnySecurityScheme,
+  ) -> Result<GetRecordingResponse, OmnitronError> {
+    let db = db.lock().await;
+
+    let recording = Recording::Entity::find_by_id(id.0).one(&*db).await?;
+
+    match recording {
+      Some(recording) => Ok(GetRecordingResponse::Ok(Json(recording))),
+      None => Ok(GetRecordingResponse::NotFound),
+    }
+  }
+}
+
+#[handler]
+pub async fn api_get_recording_cast(
+  db: Data<&Arc<Mutex<DatabaseConnection>>>,
+  recordings: Data<&Arc<Mutex<SessionRecordings>>>,
+  id: PoemPath<Uuid>,
+) -> poem::Result<Response> {
+  let recording = {
+    let db = db.lock().await;
+    Recording::Entity::find_by_id(id.0)
+      .one(&*db)
+      .await
+      .map_err(poem::error::InternalServerError)?
+  };
+
+  let Some(recording) = recording else {
+    return Ok(StatusCode::NOT_FOUND.into_response());
+  };
+
+  let path = { recordings.lock().await.path_for(&recording.session_id, &recording.name) };
+
+  let file = tokio::fs::File::open(&path).await.map_err(poem::error::InternalServerError)?;
+
+  Ok(
+    Body::from_async_read(file)
+      .with_content_type("application/x-asciicast")
+      .with_header(
+        poem::http::header::CONTENT_DISPOSITION,
+        format!("attachment; filename=\"{}-{}.cast\"", recording.session_id, recording.name),
+      )
+      .into_response(),
+  )
+}
+
+#[handler]
+pub async fn api_get_recording_har(
+  db: Data<&Arc<Mutex<DatabaseConnection>>>,
+  recordings: Data<&Arc<Mutex<SessionRecordings>>>,
+  id: PoemPath<Uuid>,
+) -> poem::Result<Response> {
+  let recording = {
+    let db = db.lock().await;
+    Recording::Entity::find_by_id(id.0)
+      .one(&*db)
+      .await
+      .map_err(poem::error::InternalServerError)?
+  };
+
+  let Some(recording) = recording.filter(|x| x.kind == RecordingKind::Http) else {
+    return Ok(StatusCode::NOT_FOUND.into_response());
+  };
+
+  let path = { recordings.lock().await.path_for(&recording.session_id, &recording.name) };
+
+  let data = tokio::fs::read(&path).await.map_err(poem::error::InternalServerError)?;
+  let har = har_from_recording(&data).map_err(poem::error::InternalServerError)?;
+
+  Ok(
+    Body::from_json(har)
+      .map_err(poem::error::InternalServerError)?
+      .with_content_type("application/json")
+      .with_header(
+        poem::http::header::CONTENT_DISPOSITION,
+        format!("attachment; filename=\"{}-{}.har\"", recording.session_id, recording.name),
+      )
+      .into_response(),
+  )
+}
@@ -1,7 +1,7 @@
 use std::sync::Arc;
 
 use omnitron_db_entities::Role;
-use omnitron_gate_common::{OmnitronError, Role as RoleConfig};
+use omnitron_gate_common::{IpPolicy, OmnitronError, Role as RoleConfig, SessionLimits, SqlPolicy};
 use omnitron_gate_core::consts::BUILTIN_ADMIN_ROLE_NAME;
 use poem::web::Data;
 use poem_openapi::param::{Path, Query};
@@ -16,6 +16,12 @@ use super::AnySecurityScheme;
 #[derive(Object)]
 struct RoleDataRequest {
   name: String,
+  /// Left unchanged on update if not provided
+  limits: Option<SessionLimits>,
+  /// Left unchanged on update if not provided
+  ip_policy: Option<IpPolicy>,
+  /// Left unchanged on update if not provided
+  sql_policy: Option<SqlPolicy>,
 }
 
 #[derive(ApiResponse)]
@@ -54,7 +60,10 @@ impl ListApi {
 
     let roles = roles.all(&*db).await?;
 
-    Ok(GetRolesResponse::Ok(Json(roles.into_iter().map(Into::into).collect())))
+    let roles: Result<Vec<RoleConfig>, _> = roles.into_iter().map(|x| x.try_into()).collect();
+    let roles = roles.map_err(OmnitronError::from)?;
+
+    Ok(GetRolesResponse::Ok(Json(roles)))
   }
 
   #[oai(path = "/roles", method = "post", operation_id = "create_role")]
@@ -70,16 +79,27 @@ impl ListApi {
       return Ok(CreateRoleResponse::BadRequest(Json("name".into())));
     }
 
+    if let Some(Err(rule)) = body.ip_policy.as_ref().map(IpPolicy::validate) {
+      return Ok(CreateRoleResponse::BadRequest(Json(format!("ip_policy: {rule}"))));
+    }
+
+    if let Some(Err(error)) = body.sql_policy.as_ref().map(SqlPolicy::validate) {
+      return Ok(CreateRoleResponse::BadRequest(Json(format!("sql_policy: {error}"))));
+    }
+
     let db = db.lock().await;
 
     let values = Role::ActiveModel {
       id: Set(Uuid::new_v4()),
       name: Set(body.name.clone()),
+      limits: Set(body.limits.as_ref().map(serde_json::to_value).transpose()?),
+      ip_policy: Set(body.ip_policy.as_ref().map(serde_json::to_value).transpose()?),
+      sql_policy: Set(body.sql_policy.as_ref().map(serde_json::to_value).transpose()?),
     };
 
     let role = values.insert(&*db).await.map_err(OmnitronError::from)?;
 
-    Ok(CreateRoleResponse::Created(Json(role.into())))
+    Ok(CreateRoleResponse::Created(Json(role.try_into()?)))
   }
 }
 
@@ -95,6 +115,8 @@ enum GetRoleResponse {
 enum UpdateRoleResponse {
   #[oai(status = 200)]
   Ok(Json<RoleConfig>),
+  #[oai(status = 400)]
+  BadRequest(Json<String>),
   #[oai(status = 403)]
   Forbidden,
   #[oai(status = 404)]
@@ -127,7 +149,7 @@ impl DetailApi {
     let role = Role::Entity::find_by_id(id.0).one(&*db).await?;
 
     Ok(match role {
-      Some(role) => GetRoleResponse::Ok(Json(role.into())),
+      Some(role) => GetRoleResponse::Ok(Json(role.try_into()?)),
       None => GetRoleResponse::NotFound,
     })
   }
@@ -140,6 +162,14 @@ impl DetailApi {
     id: Path<Uuid>,
     _auth: AnySecurityScheme,
   ) -> Result<UpdateRoleResponse, OmnitronError> {
+    if let Some(Err(rule)) = body.ip_policy.as_ref().map(IpPolicy::validate) {
+      return Ok(UpdateRoleResponse::BadRequest(Json(format!("ip_policy: {rule}"))));
+    }
+
+    if let Some(Err(error)) = body.sql_policy.as_ref().map(SqlPolicy::validate) {
+      return Ok(UpdateRoleResponse::BadRequest(Json(format!("sql_policy: {error}"))));
+    }
+
     let db = db.lock().await;
 
     let Some(role) = Role::Entity::find_by_id(id.0).one(&*db).await? else {
@@ -152,9 +182,18 @@ impl DetailApi {
 
     let mut model: Role::ActiveModel = role.into();
     model.name = Set(body.name.clone());
+    if let Some(ref limits) = body.limits {
+      model.limits = Set(Some(serde_json::to_value(limits)?));
+    }
+    if let Some(ref ip_policy) = body.ip_policy {
+      model.ip_policy = Set(Some(serde_json::to_value(ip_policy)?));
+    }
+    if let Some(ref sql_policy) = body.sql_policy {
+      model.sql_policy = Set(Some(serde_json::to_value(sql_policy)?));
+    }
     let role = model.update(&*db).await?;
 
-    Ok(UpdateRoleResponse::Ok(Json(role.into())))
+    Ok(UpdateRoleResponse::Ok(Json(role.try_into()?)))
   }
 
   #[oai(path = "/role/:id", method = "delete", operation_id = "delete_role")]
@@ -1,13 +1,18 @@
 use std::sync::Arc;
 
-use omnitron_db_entities::Session;
+use futures::{SinkExt, StreamExt};
+use omnitron_db_entities::{Recording, Session};
 use omnitron_gate_common::OmnitronError;
 use omnitron_gate_core::{SessionSnapshot, State};
-use poem::web::Data;
+use poem::http::StatusCode;
+use poem::web::websocket::{Message, WebSocket};
+use poem::web::{Data, Path as PoemPath};
+use poem::{handler, IntoResponse, Response};
 use poem_openapi::param::Path;
 use poem_openapi::payload::Json;
 use poem_openapi::{ApiResponse, OpenApi};
-use sea_orm::{DatabaseConnection, EntityTrait};
+use sea_orm::{ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter, QueryOrder};
+use tokio::sync::broadcast::error::RecvError;
 use tokio::sync::Mutex;
 use uuid::Uuid;
 
@@ -24,6 +29,12 @@ enum GetSessionResponse {
   NotFound,
 }
 
+#[derive(ApiResponse)]
+enum GetSessionRecordingsResponse {
+  #[oai(status = 200)]
+  Ok(Json<Vec<Recording::Model>>),
+}
+
 #[derive(ApiResponse)]
 enum CloseSessionResponse {
   #[oai(status = 201)]
@@ -51,6 +62,22 @@ impl Api {
     }
   }
 
+  #[oai(path = "/sessions/:id/recordings", method = "get", operation_id = "get_session_recordings")]
+  async fn api_get_session_recordings(
+    &self,
+    db: Data<&Arc<Mutex<DatabaseConnection>>>,
+    id: Path<Uuid>,
+    _auth: AnySecurityScheme,
+  ) -> Result<GetSessionRecordingsResponse, OmnitronError> {
+    let db = db.lock().await;
+    let recordings: Vec<Recording::Model> = Recording::Entity::find()
+      .order_by_asc(Recording::Column::Started)
+      .filter(Recording::Column::SessionId.eq(id.0))
+      .all(&*db)
+      .await?;
+    Ok(GetSessionRecordingsResponse::Ok(Json(recordings)))
+  }
+
   #[oai(path = "/sessions/:id/close", method = "post", operation_id = "close_session")]
   async fn api_close_session(
     &self,
@@ -69,3 +96,38 @@ impl Api {
     }
   }
 }
+
+#[handler]
+pub async fn api_get_session_stream(
+  ws: WebSocket,
+  state: Data<&Arc<Mutex<State>>>,
+  id: PoemPath<Uuid>,
+) -> poem::Result<Response> {
+  let session = state.lock().await.sessions.get(&id.0).cloned();
+
+  let Some(session) = session else {
+    return Ok(StatusCode::NOT_FOUND.into_response());
+  };
+
+  let (buffer, mut receiver) = session.lock().await.subscribe_output();
+
+  Ok(
+    ws.on_upgrade(|socket| async move {
+      let (mut sink, _) = socket.split();
+
+      sink.send(Message::Binary(buffer.to_vec())).await?;
+
+      loop {
+        match receiver.recv().await {
+          Ok(data) => sink.send(Message::Binary(data.to_vec())).await?,
+          Err(RecvError::Lagged(_)) => continue,
+          Err(RecvError::Closed) => break,
+        }
+      }
+
+      sink.close().await?;
+      Ok::<(), anyhow::Error>(())
+    })
+    .into_response(),
+  )
+}
@@ -0,0 +1,150 @@
+use std::sync::Arc;
+
+use omnitron_db_entities::SsoCredential;
+use omnitron_gate_common::{OmnitronError, UserSsoCredential};
+use poem::web::Data;
+use poem_openapi::param::Path;
+use poem_openapi::payload::Json;
+use poem_openapi::{ApiResponse, Object, OpenApi};
+use sea_orm::{ActiveModelTrait, ColumnTrait, DatabaseConnection, EntityTrait, ModelTrait, QueryFilter, Set};
+use tokio::sync::Mutex;
+use uuid::Uuid;
+
+use super::AnySecurityScheme;
+
+#[derive(Object)]
+struct ExistingSsoCredential {
+  id: Uuid,
+  provider: Option<String>,
+  email: String,
+}
+
+#[derive(Object)]
+struct NewSsoCredential {
+  provider: Option<String>,
+  email: String,
+}
+
+impl From<SsoCredential::Model> for ExistingSsoCredential {
+  fn from(credential: SsoCredential::Model) -> Self {
+    Self {
+      id: credential.id,
+      provider: credential.provider,
+      email: credential.email,
+    }
+  }
+}
+
+impl From<&NewSsoCredential> for UserSsoCredential {
+  fn from(credential: &NewSsoCredential) -> Self {
+    Self {
+      provider: credential.provider.clone(),
+      email: credential.email.clone(),
+    }
+  }
+}
+
+#[derive(ApiResponse)]
+enum GetSsoCredentialsResponse {
+  #[oai(status = 200)]
+  Ok(Json<Vec<ExistingSsoCredential>>),
+}
+
+#[derive(ApiResponse)]
+enum CreateSsoCredentialResponse {
+  #[oai(status = 201)]
+  Created(Json<ExistingSsoCredential>),
+}
+
+pub struct ListApi;
+
+#[OpenApi]
+impl ListApi {
+  #[oai(
+    path = "/users/:user_id/credentials/sso",
+    method = "get",
+    operation_id = "get_sso_credentials"
+  )]
+  async fn api_get_all(
+    &self,
+    db: Data<&Arc<Mutex<DatabaseConnection>>>,
+    user_id: Path<Uuid>,
+    _auth: AnySecurityScheme,
+  ) -> Result<GetSsoCredentialsResponse, OmnitronError> {
+    let db = db.lock().await;
+
+    let objects = SsoCredential::Entity::find()
+      .filter(SsoCredential::Column::UserId.eq(*user_id))
+      .all(&*db)
+      .await?;
+
+    Ok(GetSsoCredentialsResponse::Ok(Json(
+      objects.into_iter().map(Into::into).collect(),
+    )))
+  }
+
+  #[oai(
+    path = "/users/:user_id/credentials/sso",
+    method = "post",
+    operation_id = "create_sso_credential"
+  )]
+  async fn api_create(
+    &self,
+    db: Data<&Arc<Mutex<DatabaseConnection>>>,
+    body: Json<NewSsoCredential>,
+    user_id: Path<Uuid>,
+    _auth: AnySecurityScheme,
+  ) -> Result<CreateSsoCredentialResponse, OmnitronError> {
+    let db = db.lock().await;
+
+    let object = SsoCredential::ActiveModel {
+      id: Set(Uuid::new_v4()),
+      user_id: Set(*user_id),
+      ..SsoCredential::ActiveModel::from(UserSsoCredential::from(&*body))
+    }
+    .insert(&*db)
+    .await
+    .map_err(OmnitronError::from)?;
+
+    Ok(CreateSsoCredentialResponse::Created(Json(object.into())))
+  }
+}
+
+#[derive(ApiResponse)]
+enum DeleteCredentialResponse {
+  #[oai(status = 204)]
+  Deleted,
+  #[oai(status = 404)]
+  NotFound,
+}
+
+pub struct DetailApi;
+
+#[OpenApi]
+impl DetailApi {
+  #[oai(
+    path = "/users/:user_id/credentials/sso/:id",
+    method = "delete",
+    operation_id = "delete_sso_credential"
+  )]
+  async fn api_delete(
+    &self,
+    db: Data<&Arc<Mutex<DatabaseConnection>>>,
+    user_id: Path<Uuid>,
+    id: Path<Uuid>,
+    _auth: AnySecurityScheme,
+  ) -> Result<DeleteCredentialResponse, OmnitronError> {
+    let db = db.lock().await;
+
+    let Some(credential) = SsoCredential::Entity::find_by_id(id.0)
+      .filter(SsoCredential::Column::UserId.eq(*user_id))
+      .one(&*db)
+      .await?
+    else {
+      return Ok(DeleteCredentialResponse::NotFound);
+    };
+
+    credential.delete(&*db).await?;
+    Ok(DeleteCredentialResponse::Deleted)
+  }
+}
@@ -2,7 +2,10 @@ use std::sync::Arc;
 
 use omnitron_db_entities::Target::TargetKind;
 use omnitron_db_entities::{Role, Target, TargetRoleAssignment};
-use omnitron_gate_common::{OmnitronError, Role as RoleConfig, Target as TargetConfig, TargetOptions};
+use omnitron_gate_common::{
+  IpPolicy, MaskingRule, OmnitronError, Role as RoleConfig, RoleGrant, SessionLimits, SqlPolicy, Target as TargetConfig,
+  TargetOptions,
+};
 use omnitron_gate_core::consts::BUILTIN_ADMIN_ROLE_NAME;
 use poem::web::Data;
 use poem_openapi::param::{Path, Query};
@@ -18,6 +21,14 @@ use super::AnySecurityScheme;
 struct TargetDataRequest {
   name: String,
   options: TargetOptions,
+  /// Left unchanged on update if not provided
+  limits: Option<SessionLimits>,
+  /// Left unchanged on update if not provided
+  ip_policy: Option<IpPolicy>,
+  /// Left unchanged on update if not provided
+  sql_policy: Option<SqlPolicy>,
+  /// Left unchanged on update if not provided
+  masking: Option<Vec<MaskingRule>>,
 }
 
 #[derive(ApiResponse)]
@@ -28,7 +39,7 @@ enum GetTargetsResponse {
 #[derive(ApiResponse)]
 enum CreateTargetResponse {
   #[oai(status = 201)]
-  Created(Json<TargetConfig>),
+  Created(Json<Box<TargetConfig>>),
 
   #[oai(status = 400)]
   BadRequest(Json<String>),
@@ -77,6 +88,18 @@ impl ListApi {
       return Ok(CreateTargetResponse::BadRequest(Json("kind".into())));
     }
 
+    if let Some(Err(rule)) = body.ip_policy.as_ref().map(IpPolicy::validate) {
+      return Ok(CreateTargetResponse::BadRequest(Json(format!("ip_policy: {rule}"))));
+    }
+
+    if let Some(Err(error)) = body.sql_policy.as_ref().map(SqlPolicy::validate) {
+      return Ok(CreateTargetResponse::BadRequest(Json(format!("sql_policy: {error}"))));
+    }
+
+    if let Some(Err(error)) = body.masking.iter().flatten().map(MaskingRule::validate).find(Result::is_err) {
+      return Ok(CreateTargetResponse::BadRequest(Json(format!("masking: {error}"))));
+    }
+
     let db = db.lock().await;
 
     let values = Target::ActiveModel {
@@ -84,20 +107,24 @@ impl ListApi {
       name: Set(body.name.clone()),
       kind: Set((&body.options).into()),
       options: Set(serde_json::to_value(body.options.clone()).map_err(OmnitronError::from)?),
+      limits: Set(body.limits.as_ref().map(serde_json::to_value).transpose()?),
+      ip_policy: Set(body.ip_policy.as_ref().map(serde_json::to_value).transpose()?),
+      sql_policy: Set(body.sql_policy.as_ref().map(serde_json::to_value).transpose()?),
+      masking: Set(body.masking.as_ref().map(serde_json::to_value).transpose()?),
     };
 
     let target = values.insert(&*db).await.map_err(OmnitronError::from)?;
 
-    Ok(CreateTargetResponse::Created(Json(
+    Ok(CreateTargetResponse::Created(Json(Box::new(
       target.try_into().map_err(OmnitronError::from)?,
-    )))
+    ))))
   }
 }
 
 #[derive(ApiResponse)]
 enum GetTargetResponse {
   #[oai(status = 200)]
-  Ok(Json<TargetConfig>),
+  Ok(Json<Box<TargetConfig>>),
   #[oai(status = 404)]
   NotFound,
 }
@@ -105,9 +132,9 @@ enum GetTargetResponse {
 #[derive(ApiResponse)]
 enum UpdateTargetResponse {
   #[oai(status = 200)]
-  Ok(Json<TargetConfig>),
+  Ok(Json<Box<TargetConfig>>),
   #[oai(status = 400)]
-  BadRequest,
+  BadRequest(Json<String>),
   #[oai(status = 404)]
   NotFound,
 }
@@ -141,7 +168,7 @@ impl DetailApi {
       return Ok(GetTargetResponse::NotFound);
     };
 
-    Ok(GetTargetResponse::Ok(Json(target.try_into()?)))
+    Ok(GetTargetResponse::Ok(Json(Box::new(target.try_into()?))))
   }
 
   #[oai(path = "/targets/:id", method = "put", operation_id = "update_target")]
@@ -159,17 +186,41 @@ impl DetailApi {
     };
 
     if target.kind != (&body.options).into() {
-      return Ok(UpdateTargetResponse::BadRequest);
+      return Ok(UpdateTargetResponse::BadRequest(Json("kind".into())));
+    }
+
+    if let Some(Err(rule)) = body.ip_policy.as_ref().map(IpPolicy::validate) {
+      return Ok(UpdateTargetResponse::BadRequest(Json(format!("ip_policy: {rule}"))));
+    }
+
+    if let Some(Err(error)) = body.sql_policy.as_ref().map(SqlPolicy::validate) {
+      return Ok(UpdateTargetResponse::BadRequest(Json(format!("sql_policy: {error}"))));
+    }
+
+    if let Some(Err(error)) = body.masking.iter().flatten().map(MaskingRule::validate).find(Result::is_err) {
+      return Ok(UpdateTargetResponse::BadRequest(Json(format!("masking: {error}"))));
     }
 
     let mut model: Target::ActiveModel = target.into();
     model.name = Set(body.name.clone());
     model.options = Set(serde_json::to_value(body.options.clone()).map_err(OmnitronError::from)?);
+    if let Some(ref limits) = body.limits {
+      model.limits = Set(Some(serde_json::to_value(limits)?));
+    }
+    if let Some(ref ip_policy) = body.ip_policy {
+      model.ip_policy = Set(Some(serde_json::to_value(ip_policy)?));
+    }
+    if let Some(ref sql_policy) = body.sql_policy {
+      model.sql_policy = Set(Some(serde_json::to_value(sql_policy)?));
+    }
+    if let Some(ref masking) = body.masking {
+      model.masking = Set(Some(serde_json::to_value(masking)?));
+    }
     let target = model.update(&*db).await?;
 
-    Ok(UpdateTargetResponse::Ok(Json(
+    Ok(UpdateTargetResponse::Ok(Json(Box::new(
       target.try_into().map_err(OmnitronError::from)?,
-    )))
+    ))))
   }
 
   #[oai(path = "/targets/:id", method = "delete", operation_id = "delete_target")]
@@ -225,6 +276,24 @@ enum DeleteTargetRoleResponse {
   NotFound,
 }
 
+#[derive(ApiResponse)]
+enum GetTargetRoleGrantResponse {
+  #[oai(status = 200)]
+  Ok(Json<RoleGrant>),
+  #[oai(status = 404)]
+  NotFound,
+}
+
+#[derive(ApiResponse)]
+enum UpdateTargetRoleGrantResponse {
+  #[oai(status = 200)]
+  Ok(Json<RoleGrant>),
+  #[oai(status = 400)]
+  BadRequest(Json<String>),
+  #[oai(status = 404)]
+  NotFound,
+}
+
 pub struct RolesApi;
 
 #[OpenApi]
@@ -248,9 +317,10 @@ impl RolesApi {
       return Ok(GetTargetRolesResponse::NotFound);
     };
 
-    Ok(GetTargetRolesResponse::Ok(Json(
-      roles.into_iter().map(|x| x.into()).collect(),
-    )))
+    let roles: Result<Vec<RoleConfig>, _> = roles.into_iter().map(|x| x.try_into()).collect();
+    let roles = roles.map_err(OmnitronError::from)?;
+
+    Ok(GetTargetRolesResponse::Ok(Json(roles)))
   }
 
   #[oai(path = "/targets/:id/roles/:role_id", method = "post", operation_id = "add_target_role")]
@@ -325,4 +395,74 @@ impl RolesApi {
 
     Ok(DeleteTargetRoleResponse::Deleted)
   }
+
+  #[oai(
+    path = "/targets/:id/roles/:role_id/grant",
+    method = "get",
+    operation_id = "get_target_role_grant"
+  )]
+  async fn api_get_target_role_grant(
+    &self,
+    db: Data<&Arc<Mutex<DatabaseConnection>>>,
+    id: Path<Uuid>,
+    role_id: Path<Uuid>,
+    _auth: AnySecurityScheme,
+  ) -> Result<GetTargetRoleGrantResponse, OmnitronError> {
+    let db = db.lock().await;
+
+    let Some(model) = TargetRoleAssignment::Entity::find()
+      .filter(TargetRoleAssignment::Column::TargetId.eq(id.0))
+      .filter(TargetRoleAssignment::Column::RoleId.eq(role_id.0))
+      .one(&*db)
+      .await?
+    else {
+      return Ok(GetTargetRoleGrantResponse::NotFound);
+    };
+
+    Ok(GetTargetRoleGrantResponse::Ok(Json(model.grant())))
+  }
+
+  #[oai(
+    path = "/targets/:id/roles/:role_id/grant",
+    method = "put",
+    operation_id = "update_target_role_grant"
+  )]
+  async fn api_update_target_role_grant(
+    &self,
+    db: Data<&Arc<Mutex<DatabaseConnection>>>,
+    id: Path<Uuid>,
+    role_id: Path<Uuid>,
+    body: Json<RoleGrant>,
+    _auth: AnySecurityScheme,
+  ) -> Result<UpdateTargetRoleGrantResponse, OmnitronError> {
+    if let Err(error) = body.validate() {
+      return Ok(UpdateTargetRoleGrantResponse::BadRequest(Json(error)));
+    }
+
+    let db = db.lock().await;
+
+    let Some(model) = TargetRoleAssignment::Entity::find()
+      .filter(TargetRoleAssignment::Column::TargetId.eq(id.0))
+      .filter(TargetRoleAssignment::Column::RoleId.eq(role_id.0))
+      .one(&*db)
+      .await?
+    else {
+      return Ok(UpdateTargetRoleGrantResponse::NotFound);
+    };
+
+    let mut model: TargetRoleAssignment::ActiveModel = model.into();
+    model.valid_from = Set(body.valid_from);
+    model.valid_until = Set(body.valid_until);
+    model.schedule = Set(
+      body
+        .schedule
+        .as_ref()
+        .map(serde_json::to_value)
+        .transpose()
+        .map_err(OmnitronError::other)?,
+    );
+    let model = model.update(&*db).await?;
+
+    Ok(UpdateTargetRoleGrantResponse::Ok(Json(model.grant())))
+  }
 }
@@ -1,7 +1,9 @@
 use std::sync::Arc;
 
 use omnitron_db_entities::{Role, User, UserRoleAssignment};
-use omnitron_gate_common::{OmnitronError, Role as RoleConfig, User as UserConfig, UserRequireCredentialsPolicy};
+use omnitron_gate_common::{
+  IpPolicy, OmnitronError, Role as RoleConfig, RoleGrant, User as UserConfig, UserRequireCredentialsPolicy,
+};
 use poem::web::Data;
 use poem_openapi::param::{Path, Query};
 use poem_openapi::payload::Json;
@@ -20,6 +22,8 @@ struct CreateUserRequest {
 struct UserDataRequest {
   username: String,
   credential_policy: Option<UserRequireCredentialsPolicy>,
+  /// Left unchanged if not provided
+  ip_policy: Option<IpPolicy>,
 }
 
 #[derive(ApiResponse)]
@@ -81,6 +85,7 @@ impl ListApi {
       id: Set(Uuid::new_v4()),
       username: Set(body.username.clone()),
       credential_policy: Set(serde_json::to_value(UserRequireCredentialsPolicy::default()).map_err(OmnitronError::from)?),
+      ip_policy: Set(None),
     };
 
     let user = values.insert(&*db).await.map_err(OmnitronError::from)?;
@@ -103,6 +108,8 @@ enum GetUserResponse {
 enum UpdateUserResponse {
   #[oai(status = 200)]
   Ok(Json<UserConfig>),
+  #[oai(status = 400)]
+  BadRequest(Json<String>),
   #[oai(status = 404)]
   NotFound,
 }
@@ -144,6 +151,10 @@ impl DetailApi {
     id: Path<Uuid>,
     _auth: AnySecurityScheme,
   ) -> Result<UpdateUserResponse, OmnitronError> {
+    if let Some(Err(rule)) = body.ip_policy.as_ref().map(IpPolicy::validate) {
+      return Ok(UpdateUserResponse::BadRequest(Json(format!("ip_policy: {rule}"))));
+    }
+
     let db = db.lock().await;
 
     let Some(user) = User::Entity::find_by_id(id.0).one(&*db).await? else {
@@ -153,6 +164,9 @@ impl DetailApi {
     let mut model: User::ActiveModel = user.into();
     model.username = Set(body.username.clone());
     model.credential_policy = Set(serde_json::to_value(body.credential_policy.clone()).map_err(OmnitronError::from)?);
+    if let Some(ref ip_policy) = body.ip_policy {
+      model.ip_policy = Set(Some(serde_json::to_value(ip_policy)?));
+    }
     let user = model.update(&*db).await?;
 
     Ok(UpdateUserResponse::Ok(Json(user.try_into().map_err(OmnitronError::from)?)))
@@ -205,6 +219,24 @@ enum DeleteUserRoleResponse {
   NotFound,
 }
 
+#[derive(ApiResponse)]
+enum GetUserRoleGrantResponse {
+  #[oai(status = 200)]
+  Ok(Json<RoleGrant>),
+  #[oai(status = 404)]
+  NotFound,
+}
+
+#[derive(ApiResponse)]
+enum UpdateUserRoleGrantResponse {
+  #[oai(status = 200)]
+  Ok(Json<RoleGrant>),
+  #[oai(status = 400)]
+  BadRequest(Json<String>),
+  #[oai(status = 404)]
+  NotFound,
+}
+
 pub struct RolesApi;
 
 #[OpenApi]
@@ -228,7 +260,10 @@ impl RolesApi {
       return Ok(GetUserRolesResponse::NotFound);
     };
 
-    Ok(GetUserRolesResponse::Ok(Json(roles.into_iter().map(|x| x.into()).collect())))
+    let roles: Result<Vec<RoleConfig>, _> = roles.into_iter().map(|x| x.try_into()).collect();
+    let roles = roles.map_err(OmnitronError::from)?;
+
+    Ok(GetUserRolesResponse::Ok(Json(roles)))
   }
 
   #[oai(path = "/users/:id/roles/:role_id", method = "post", operation_id = "add_user_role")]
@@ -295,4 +330,74 @@ impl RolesApi {
 
     Ok(DeleteUserRoleResponse::Deleted)
   }
+
+  #[oai(
+    path = "/users/:id/roles/:role_id/grant",
+    method = "get",
+    operation_id = "get_user_role_grant"
+  )]
+  async fn api_get_user_role_grant(
+    &self,
+    db: Data<&Arc<Mutex<DatabaseConnection>>>,
+    id: Path<Uuid>,
+    role_id: Path<Uuid>,
+    _auth: AnySecurityScheme,
+  ) -> Result<GetUserRoleGrantResponse, OmnitronError> {
+    let db = db.lock().await;
+
+    let Some(model) = UserRoleAssignment::Entity::find()
+      .filter(UserRoleAssignment::Column::UserId.eq(id.0))
+      .filter(UserRoleAssignment::Column::RoleId.eq(role_id.0))
+      .one(&*db)
+      .await?
+    else {
+      return Ok(GetUserRoleGrantResponse::NotFound);
+    };
+
+    Ok(GetUserRoleGrantResponse::Ok(Json(model.grant())))
+  }
+
+  #[oai(
+    path = "/users/:id/roles/:role_id/grant",
+    method = "put",
+    operation_id = "update_user_role_grant"
+  )]
+  async fn api_update_user_role_grant(
+    &self,
+    db: Data<&Arc<Mutex<DatabaseConnection>>>,
+    id: Path<Uuid>,
+    role_id: Path<Uuid>,
+    body: Json<RoleGrant>,
+    _auth: AnySecurityScheme,
+  ) -> Result<UpdateUserRoleGrantResponse, OmnitronError> {
+    if let Err(error) = body.validate() {
+      return Ok(UpdateUserRoleGrantResponse::BadRequest(Json(error)));
+    }
+
+    let db = db.lock().await;
+
+    let Some(model) = UserRoleAssignment::Entity::find()
+      .filter(UserRoleAssignment::Column::UserId.eq(id.0))
+      .filter(UserRoleAssignment::Column::RoleId.eq(role_id.0))
+      .one(&*db)
+      .await?
+    else {
+      return Ok(UpdateUserRoleGrantResponse::NotFound);
+    };
+
+    let mut model: UserRoleAssignment::ActiveModel = model.into();
+    model.valid_from = Set(body.valid_from);
+    model.valid_until = Set(body.valid_until);
+    model.schedule = Set(
+      body
+        .schedule
+        .as_ref()
+        .map(serde_json::to_value)
+        .transpose()
+        .map_err(OmnitronError::other)?,
+    );
+    let model = model.update(&*db).await?;
+
+    Ok(UpdateUserRoleGrantResponse::Ok(Json(model.grant())))
+  }
 }
@@ -3,8 +3,10 @@ use std::sync::Arc;
 
 use chrono::{DateTime, Utc};
 use omnitron_gate_common::auth::{AuthCredential, AuthResult, AuthState, CredentialKind};
+use omnitron_gate_common::helpers::hash::{hash_password, verify_password_hash};
 use omnitron_gate_common::{OmnitronError, Secret};
 use omnitron_gate_core::Services;
+use once_cell::sync::Lazy;
 use poem::session::Session;
 use poem::web::Data;
 use poem::Request;
@@ -17,12 +19,15 @@ use uuid::Uuid;
 
 use super::common::logout;
 use crate::common::{
-  authorize_session, endpoint_auth, get_auth_state_for_request, RequestAuthorization, SessionAuthorization, SessionExt,
+  authorize_session, endpoint_auth, get_auth_state_for_request, get_client_address, RequestAuthorization, SessionAuthorization,
+  SessionExt,
 };
 use crate::session::SessionStore;
 
 pub struct Api;
 
+static DUMMY_PASSWORD_HASH: Lazy<String> = Lazy::new(|| hash_password("omnitron"));
+
 #[derive(Object)]
 struct LoginRequest {
   username: String,
@@ -42,6 +47,7 @@ enum ApiAuthState {
   OtpNeeded,
   WebUserApprovalNeeded,
   PublicKeyNeeded,
+  SsoNeeded,
   Success,
 }
 
@@ -86,6 +92,7 @@ const PREFERRED_NEED_CRED_ORDER: &[CredentialKind] = &[
   CredentialKind::PublicKey,
   CredentialKind::Password,
   CredentialKind::Totp,
+  CredentialKind::Sso,
   CredentialKind::WebUserApproval,
 ];
 
@@ -103,6 +110,7 @@ impl From<AuthResult> for ApiAuthState {
           Some(CredentialKind::Totp) => ApiAuthState::OtpNeeded,
           Some(CredentialKind::WebUserApproval) => ApiAuthState::WebUserApprovalNeeded,
           Some(CredentialKind::PublicKey) => ApiAuthState::PublicKeyNeeded,
+          Some(CredentialKind::Sso) => ApiAuthState::SsoNeeded,
           None => ApiAuthState::Failed,
         }
       }
@@ -121,12 +129,39 @@ impl Api {
     services: Data<&Services>,
     body: Json<LoginRequest>,
   ) -> poem::Result<LoginResponse> {
+    let address = get_client_address(req, &services).await;
+    if let Some(until) = services.login_protection.locked_until(&body.username, address).await? {
+      warn!(%until, username=%body.username, "Login attempt while locked out");
+      return Ok(LoginResponse::Failure(Json(LoginFailureResponse {
+        state: ApiAuthState::Failed,
+      })));
+    }
+
+    if let Some(address) = address {
+      let denial = services
+        .config_provider
+        .lock()
+        .await
+        .check_address(&body.username, None, address)
+        .await?;
+      if let Some(denial) = denial {
+        warn!(%denial, username=%body.username, "Login refused by IP policy");
+        return Ok(LoginResponse::Failure(Json(LoginFailureResponse {
+          state: ApiAuthState::Failed,
+        })));
+      }
+    }
+
     let mut auth_state_store = services.auth_state_store.lock().await;
     let state_arc = match get_auth_state_for_request(&body.username, session, &mut auth_state_store).await {
       Err(OmnitronError::UserNotFound(_)) => {
+        // Take as long as a wrong password and count towards the same lockouts,
+        // so that unknown usernames can't be told apart
+        let _ = verify_password_hash(&body.password, &DUMMY_PASSWORD_HASH);
+        services.login_protection.record_failure(&body.username, address).await?;
         return Ok(LoginResponse::Failure(Json(LoginFailureResponse {
           state: ApiAuthState::Failed,
-        })))
+        })));
       }
       x => x,
     }?;
@@ -137,11 +172,14 @@ impl Api {
     let password_cred = AuthCredential::Password(Secret::new(body.password.clone()));
     if cp.validate_credential(state.username(), &password_cred).await? {
       state.add_valid_credential(password_cred);
+    } else {
+      services.login_protection.record_failure(state.username(), address).await?;
     }
 
     match state.verify() {
       AuthResult::Accepted { username } => {
         auth_state_store.complete(state.id()).await;
+        services.login_protection.record_success(&username).await?;
         authorize_session(req, username).await?;
         Ok(LoginResponse::Success)
       }
@@ -172,16 +210,27 @@ impl Api {
 
     let mut state = state_arc.lock().await;
 
+    let address = get_client_address(req, &services).await;
+    if let Some(until) = services.login_protection.locked_until(state.username(), address).await? {
+      warn!(%until, username=%state.username(), "Login attempt while locked out");
+      return Ok(LoginResponse::Failure(Json(LoginFailureResponse {
+        state: ApiAuthState::Failed,
+      })));
+    }
+
     let mut cp = services.config_provider.lock().await;
 
     let otp_cred = AuthCredential::Otp(body.otp.clone().into());
     if cp.validate_credential(state.username(), &otp_cred).await? {
       state.add_valid_credential(otp_cred);
+    } else {
+      services.login_protection.record_failure(state.username(), address).await?;
     }
 
     match state.verify() {
       AuthResult::Accepted { username } => {
         auth_state_store.complete(state.id()).await;
+        services.login_protection.record_success(&username).await?;
         authorize_session(req, username).await?;
         Ok(LoginResponse::Success)
       }
@@ -6,6 +6,7 @@ pub mod auth;
 mod common;
 mod credentials;
 pub mod info;
+mod sso;
 pub mod targets_list;
 
 #[derive(SecurityScheme)]
@@ -31,5 +32,6 @@ pub fn get() -> impl OpenApi {
     targets_list::Api,
     credentials::Api,
     api_tokens::Api,
+    sso::Api,
   )
 }
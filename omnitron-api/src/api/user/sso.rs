@@ -0,0 +1,227 @@
+use omnitron_gate_common::auth::{AuthCredential, AuthResult};
+use omnitron_gate_common::OmnitronError;
+use omnitron_gate_core::Services;
+use omnitron_gate_sso::{SsoClient, SsoLoginRequest};
+use poem::http::StatusCode;
+use poem::session::Session;
+use poem::web::Data;
+use poem::Request;
+use poem_openapi::param::{Path, Query};
+use poem_openapi::payload::Json;
+use poem_openapi::{ApiResponse, Object, OpenApi};
+use tracing::*;
+
+use crate::common::{authorize_session, get_auth_state_for_request, get_client_address};
+
+pub struct Api;
+
+static SSO_REQUEST_SESSION_KEY: &str = "sso_request";
+static SSO_NEXT_SESSION_KEY: &str = "sso_next";
+static SSO_RETURN_PATH: &str = "/@omnitron/api/sso/return";
+static LOGIN_PAGE_PATH: &str = "/@omnitron#/login";
+static DEFAULT_NEXT_PATH: &str = "/@omnitron";
+
+#[derive(Object)]
+struct SsoProviderDescription {
+  name: String,
+  label: String,
+}
+
+#[derive(ApiResponse)]
+enum GetSsoProvidersResponse {
+  #[oai(status = 200)]
+  Ok(Json<Vec<SsoProviderDescription>>),
+}
+
+#[derive(ApiResponse)]
+enum StartSsoResponse {
+  #[oai(status = 307)]
+  Redirect(#[oai(header = "Location")] String),
+  #[oai(status = 404)]
+  NotFound,
+}
+
+#[derive(ApiResponse)]
+enum ReturnToSsoResponse {
+  #[oai(status = 307)]
+  Redirect(#[oai(header = "Location")] String),
+}
+
+fn sso_error(status: StatusCode, message: impl Into<String>) -> poem::Error {
+  let message = message.into();
+  warn!("SSO login failed: {}", message);
+  poem::Error::from_string(message, status)
+}
+
+/// Only allow redirecting back to local paths after the login
+fn sanitize_next(next: Option<String>) -> Option<String> {
+  next.filter(|x| x.starts_with('/') && !x.starts_with("//"))
+}
+
+#[OpenApi]
+impl Api {
+  #[oai(path = "/sso/providers", method = "get", operation_id = "get_sso_providers")]
+  async fn api_get_sso_providers(&self, services: Data<&Services>) -> poem::Result<GetSsoProvidersResponse> {
+    let config = services.config.lock().await;
+    Ok(GetSsoProvidersResponse::Ok(Json(
+      config
+        .store
+        .sso_providers
+        .iter()
+        .map(|p| SsoProviderDescription {
+          name: p.name.clone(),
+          label: p.label().to_owned(),
+        })
+        .collect(),
+    )))
+  }
+
+  #[oai(path = "/sso/providers/:name/start", method = "get", operation_id = "start_sso")]
+  async fn api_start_sso(
+    &self,
+    req: &Request,
+    session: &Session,
+    services: Data<&Services>,
+    name: Path<String>,
+    next: Query<Option<String>>,
+  ) -> poem::Result<StartSsoResponse> {
+    let (provider_config, return_url) = {
+      let config = services.config.lock().await;
+      let Some(provider_config) = config.store.sso_providers.iter().find(|p| p.name == *name).cloned() else {
+        return Ok(StartSsoResponse::NotFound);
+      };
+      let return_url = config
+        .construct_external_url(Some(req), None)?
+        .join(SSO_RETURN_PATH)
+        .map_err(OmnitronError::UrlParse)?;
+      (provider_config, return_url)
+    };
+
+    let client = SsoClient::new(provider_config);
+    let sso_request = client
+      .start_login(return_url.to_string())
+      .await
+      .map_err(|e| sso_error(StatusCode::BAD_GATEWAY, e.to_string()))?;
+
+    session.set(SSO_REQUEST_SESSION_KEY, &sso_request);
+    match sanitize_next(next.0) {
+      Some(next) => session.set(SSO_NEXT_SESSION_KEY, next),
+      None => session.remove(SSO_NEXT_SESSION_KEY),
+    }
+
+    Ok(StartSsoResponse::Redirect(sso_request.auth_url))
+  }
+
+  #[oai(path = "/sso/return", method = "get", operation_id = "return_to_sso")]
+  async fn api_return_to_sso(
+    &self,
+    req: &Request,
+    session: &Session,
+    services: Data<&Services>,
+    code: Query<String>,
+    state: Query<String>,
+  ) -> poem::Result<ReturnToSsoResponse> {
+    let Some(sso_request) = session.get::<SsoLoginRequest>(SSO_REQUEST_SESSION_KEY) else {
+      return Err(sso_error(StatusCode::BAD_REQUEST, "no SSO login in progress"));
+    };
+    session.remove(SSO_REQUEST_SESSION_KEY);
+
+    if !sso_request.verify_state(&state) {
+      return Err(sso_error(StatusCode::BAD_REQUEST, "state parameter mismatch"));
+    }
+
+    let provider_config = {
+      let config = services.config.lock().await;
+      config
+        .store
+        .sso_providers
+        .iter()
+        .find(|p| p.name == sso_request.provider)
+        .cloned()
+    };
+    let Some(provider_config) = provider_config else {
+      return Err(sso_error(StatusCode::BAD_REQUEST, "unknown SSO provider"));
+    };
+
+    let client = SsoClient::new(provider_config);
+    let response = client
+      .finish_login(&sso_request, code.0)
+      .await
+      .map_err(|e| sso_error(StatusCode::UNAUTHORIZED, e.to_string()))?;
+
+    let Some(email) = response.email else {
+      return Err(sso_error(
+        StatusCode::UNAUTHORIZED,
+        format!("ID token has no `{}` claim", client.config().email_claim),
+      ));
+    };
+
+    let credential = AuthCredential::Sso {
+      provider: sso_request.provider.clone(),
+      email: email.clone(),
+    };
+
+    let username = services
+      .config_provider
+      .lock()
+      .await
+      .username_for_sso_credential(&credential)
+      .await?;
+    let Some(username) = username else {
+      return Err(sso_error(
+        StatusCode::UNAUTHORIZED,
+        format!("no user has an SSO credential for {email}"),
+      ));
+    };
+
+    let address = get_client_address(req, &services).await;
+    if let Some(until) = services.login_protection.locked_until(&username, address).await? {
+      return Err(sso_error(
+        StatusCode::FORBIDDEN,
+        format!("{username} is locked out until {until}"),
+      ));
+    }
+    if let Some(address) = address {
+      let denial = services
+        .config_provider
+        .lock()
+        .await
+        .check_address(&username, None, address)
+        .await?;
+      if let Some(denial) = denial {
+        warn!(%denial, %username, "SSO login refused by IP policy");
+        return Err(poem::Error::from_string("login refused by IP policy", StatusCode::FORBIDDEN));
+      }
+    }
+
+    info!(%username, provider = %sso_request.provider, "SSO login");
+
+    let mut auth_state_store = services.auth_state_store.lock().await;
+    let state_arc = get_auth_state_for_request(&username, session, &mut auth_state_store).await?;
+    let mut state = state_arc.lock().await;
+    state.add_valid_credential(credential);
+
+    match state.verify() {
+      AuthResult::Accepted { username } => {
+        auth_state_store.complete(state.id()).await;
+        // Only once no other credentials are needed
+        if let Some(roles) = response.roles {
+          services
+            .config_provider
+            .lock()
+            .await
+            .apply_sso_role_mappings(&username, &client.managed_roles(), &roles)
+            .await?;
+        }
+        authorize_session(req, username).await?;
+        let next = session
+          .get::<String>(SSO_NEXT_SESSION_KEY)
+          .unwrap_or_else(|| DEFAULT_NEXT_PATH.to_owned());
+        session.remove(SSO_NEXT_SESSION_KEY);
+        Ok(ReturnToSsoResponse::Redirect(next))
+      }
+      // Let the login page collect the remaining credentials
+      _ => Ok(ReturnToSsoResponse::Redirect(LOGIN_PAGE_PATH.to_owned())),
+    }
+  }
+}
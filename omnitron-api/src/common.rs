@@ -1,4 +1,5 @@
 use core::str;
+use std::net::IpAddr;
 use std::sync::Arc;
 
 use anyhow::Context;
@@ -233,13 +234,29 @@ pub async fn get_auth_state_for_request(
       None,
       username,
       crate::common::PROTOCOL_NAME,
-      &[CredentialKind::Password, CredentialKind::Totp],
+      &[CredentialKind::Password, CredentialKind::Totp, CredentialKind::Sso],
     )
     .await?;
   session.set(AUTH_STATE_ID_SESSION_KEY, AuthStateId(id));
   Ok(state)
 }
 
+/// The address the request came from, taken from `X-Forwarded-For` if it's trusted.
+/// Only the right-most entry is used: it's the one appended by the trusted proxy,
+/// everything to its left was supplied by the client.
+pub async fn get_client_address(req: &Request, services: &Services) -> Option<IpAddr> {
+  if services.config.lock().await.store.http.trust_x_forwarded_headers {
+    if let Some(address) = req
+      .header("x-forwarded-for")
+      .and_then(|x| x.rsplit(',').next())
+      .and_then(|x| x.trim().parse().ok())
+    {
+      return Some(address);
+    }
+  }
+  req.remote_addr().as_socket_addr().map(|x| x.ip())
+}
+
 pub async fn authorize_session(req: &Request, username: String) -> Result<(), OmnitronError> {
   let session_middleware = Data::<&Arc<Mutex<SessionStore>>>::from_request_without_body(req)
     .await
@@ -2,31 +2,83 @@ use std::collections::{BTreeMap, HashMap};
 use std::sync::{Arc, Weak};
 use std::time::{Duration, Instant};
 
+use chrono::Utc;
+use data_encoding::HEXLOWER;
+use omnitron_db_entities::HttpSession;
 use omnitron_gate_common::SessionId;
+use omnitron_gate_core::recordings::{self, HttpRecorder};
 use omnitron_gate_core::{OmnitronServerHandle, Services, SessionStateInit};
-use poem::session::{MemoryStorage, Session, SessionStorage};
+use poem::error::InternalServerError;
+use poem::session::{Session, SessionStorage};
 use poem::web::{Data, RemoteAddr};
 use poem::{FromRequest, Request};
+use sea_orm::sea_query::OnConflict;
+use sea_orm::ActiveValue::Set;
+use sea_orm::{ColumnTrait, Condition, DatabaseConnection, DbErr, EntityTrait, QueryFilter};
 use serde_json::Value;
+use sha2::{Digest, Sha256};
 use tokio::sync::Mutex;
 use tracing::*;
 
 use crate::common::PROTOCOL_NAME;
 use crate::session_handle::{HttpSessionHandle, OmnitronServerHandleFromRequest, SessionHandleCommand};
 
+/// poem session storage persisted in the database, so that web sessions survive restarts.
+/// Rows are keyed by a hash of the session ID, so the database doesn't hold usable cookies.
 #[derive(Clone)]
-pub struct SharedSessionStorage(pub Arc<Mutex<Box<MemoryStorage>>>);
+pub struct SharedSessionStorage {
+  /// A handle of the connection pool, as sessions are loaded for every request
+  /// and shouldn't wait for the shared connection's lock
+  db: DatabaseConnection,
+  /// Lifetime of sessions that poem doesn't give an expiry time
+  max_age: Duration,
+}
 
 static POEM_SESSION_ID_SESSION_KEY: &str = "poem_session_id";
 
+fn hash_session_id(session_id: &str) -> String {
+  HEXLOWER.encode(&Sha256::digest(session_id))
+}
+
+impl SharedSessionStorage {
+  pub async fn new(db: &Arc<Mutex<DatabaseConnection>>, max_age: Duration) -> Self {
+    Self {
+      db: db.lock().await.clone(),
+      max_age,
+    }
+  }
+
+  /// Removes expired sessions
+  pub async fn vacuum(&self) -> Result<(), DbErr> {
+    HttpSession::Entity::delete_many()
+      .filter(
+        Condition::any()
+          .add(HttpSession::Column::Expires.lt(Utc::now()))
+          .add(HttpSession::Column::Expires.is_null()),
+      )
+      .exec(&self.db)
+      .await?;
+    Ok(())
+  }
+}
+
 impl SessionStorage for SharedSessionStorage {
   async fn load_session<'a>(&'a self, session_id: &'a str) -> poem::Result<Option<BTreeMap<String, Value>>> {
-    self.0.lock().await.load_session(session_id).await.map(|o| {
-      o.map(|mut s| {
-        s.insert(POEM_SESSION_ID_SESSION_KEY.to_string(), session_id.to_string().into());
-        s
-      })
-    })
+    let Some(session) = HttpSession::Entity::find_by_id(hash_session_id(session_id))
+      .one(&self.db)
+      .await
+      .map_err(InternalServerError)?
+    else {
+      return Ok(None);
+    };
+
+    if session.expires.map_or(true, |x| x < Utc::now()) {
+      return Ok(None);
+    }
+
+    let mut entries: BTreeMap<String, Value> = serde_json::from_value(session.entries).map_err(InternalServerError)?;
+    entries.insert(POEM_SESSION_ID_SESSION_KEY.to_string(), session_id.to_string().into());
+    Ok(Some(entries))
   }
 
   /// Insert or update a session.
@@ -36,18 +88,42 @@ impl SessionStorage for SharedSessionStorage {
     entries: &'a BTreeMap<String, Value>,
     expires: Option<Duration>,
   ) -> poem::Result<()> {
-    self.0.lock().await.update_session(session_id, entries, expires).await
+    let expires = Utc::now() + chrono::Duration::from_std(expires.unwrap_or(self.max_age)).map_err(InternalServerError)?;
+
+    let mut entries = entries.clone();
+    entries.remove(POEM_SESSION_ID_SESSION_KEY);
+    let values = HttpSession::ActiveModel {
+      id: Set(hash_session_id(session_id)),
+      entries: Set(serde_json::to_value(entries).map_err(InternalServerError)?),
+      expires: Set(Some(expires)),
+    };
+
+    HttpSession::Entity::insert(values)
+      .on_conflict(
+        OnConflict::column(HttpSession::Column::Id)
+          .update_columns([HttpSession::Column::Entries, HttpSession::Column::Expires])
+          .to_owned(),
+      )
+      .exec(&self.db)
+      .await
+      .map_err(InternalServerError)?;
+    Ok(())
   }
 
   /// Remove a session by session id.
   async fn remove_session<'a>(&'a self, session_id: &'a str) -> poem::Result<()> {
-    self.0.lock().await.remove_session(session_id).await
+    HttpSession::Entity::delete_by_id(hash_session_id(session_id))
+      .exec(&self.db)
+      .await
+      .map_err(InternalServerError)?;
+    Ok(())
   }
 }
 
 pub struct SessionStore {
   session_handles: HashMap<SessionId, Arc<Mutex<OmnitronServerHandle>>>,
   session_timestamps: HashMap<SessionId, Instant>,
+  http_recorders: HashMap<SessionId, HashMap<String, HttpRecorder>>,
   this: Weak<Mutex<SessionStore>>,
 }
 
@@ -60,6 +136,7 @@ impl SessionStore {
       Mutex::new(Self {
         session_handles: HashMap::new(),
         session_timestamps: HashMap::new(),
+        http_recorders: HashMap::new(),
         this: me.clone(),
       })
     })
@@ -129,6 +206,7 @@ impl SessionStore {
               let mut that = this.lock().await;
               that.session_handles.remove(&id);
               that.session_timestamps.remove(&id);
+              that.http_recorders.remove(&id);
             }
           }
         }
@@ -147,10 +225,50 @@ impl SessionStore {
       .and_then(|id| self.session_handles.get(&id).cloned())
   }
 
+  /// Returns the recorder capturing the session's requests to `target_name`, starting it if needed.
+  /// Dropping the session ends the recording.
+  pub async fn http_recorder_for(
+    &mut self,
+    services: &Services,
+    session_id: SessionId,
+    target_name: &str,
+  ) -> Option<HttpRecorder> {
+    let recorders = self.http_recorders.entry(session_id).or_default();
+    if let Some(recorder) = recorders.get(target_name) {
+      return Some(recorder.clone());
+    }
+
+    let name = format!(
+      "http-{}",
+      target_name
+        .chars()
+        .map(|x| if x.is_ascii_alphanumeric() { x } else { '-' })
+        .collect::<String>()
+    );
+    let recorder = services
+      .recordings
+      .lock()
+      .await
+      .start::<HttpRecorder>(&session_id, name, ())
+      .await;
+    match recorder {
+      Ok(recorder) => {
+        recorders.insert(target_name.to_owned(), recorder.clone());
+        Some(recorder)
+      }
+      Err(recordings::Error::Disabled) => None,
+      Err(error) => {
+        error!(%session_id, %error, "Failed to start recording");
+        None
+      }
+    }
+  }
+
   pub fn remove_session(&mut self, session: &Session) {
     if let Some(id) = session.get::<SessionId>(SESSION_ID_SESSION_KEY) {
       self.session_handles.remove(&id);
       self.session_timestamps.remove(&id);
+      self.http_recorders.remove(&id);
     }
   }
 
@@ -165,6 +283,79 @@ impl SessionStore {
     for id in to_remove {
       self.session_handles.remove(&id);
       self.session_timestamps.remove(&id);
+      self.http_recorders.remove(&id);
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use sea_orm::{ActiveModelTrait, Database};
+
+  use super::*;
+
+  async fn make_storage() -> SharedSessionStorage {
+    let db = Database::connect("sqlite::memory:").await.unwrap();
+    omnitron_db_migrations::migrate_database(&db).await.unwrap();
+    SharedSessionStorage::new(&Arc::new(Mutex::new(db)), Duration::from_secs(60)).await
+  }
+
+  fn entries() -> BTreeMap<String, Value> {
+    BTreeMap::from([("session_id".to_owned(), Value::from("abc"))])
+  }
+
+  #[tokio::test]
+  async fn test_session_lifecycle() {
+    let storage = make_storage().await;
+    assert_eq!(storage.load_session("cookie").await.unwrap(), None);
+
+    storage.update_session("cookie", &entries(), None).await.unwrap();
+    let loaded = storage.load_session("cookie").await.unwrap().unwrap();
+    assert_eq!(loaded.get("session_id"), Some(&Value::from("abc")));
+    assert_eq!(loaded.get(POEM_SESSION_ID_SESSION_KEY), Some(&Value::from("cookie")));
+
+    // Saving the loaded entries doesn't persist the session ID
+    storage.update_session("cookie", &loaded, None).await.unwrap();
+    let rows = HttpSession::Entity::find().all(&storage.db).await.unwrap();
+    assert_eq!(rows.len(), 1);
+    assert_eq!(rows[0].id, hash_session_id("cookie"));
+    assert!(!rows[0].entries.to_string().contains("cookie"));
+    // Sessions without an expiry time get the storage's maximum age
+    assert!(rows[0]
+      .expires
+      .is_some_and(|x| x > Utc::now() + chrono::Duration::seconds(50)));
+
+    storage.remove_session("cookie").await.unwrap();
+    assert_eq!(storage.load_session("cookie").await.unwrap(), None);
+  }
+
+  #[tokio::test]
+  async fn test_vacuum() {
+    let storage = make_storage().await;
+    storage
+      .update_session("live", &entries(), Some(Duration::from_secs(60)))
+      .await
+      .unwrap();
+    for (id, expires) in [
+      ("expired", Some(Utc::now() - chrono::Duration::seconds(1))),
+      ("unbounded", None),
+    ] {
+      HttpSession::ActiveModel {
+        id: Set(hash_session_id(id)),
+        entries: Set(serde_json::to_value(entries()).unwrap()),
+        expires: Set(expires),
+      }
+      .insert(&storage.db)
+      .await
+      .unwrap();
     }
+
+    // Expired sessions aren't loaded even before they're removed
+    assert_eq!(storage.load_session("expired").await.unwrap(), None);
+    assert_eq!(storage.load_session("unbounded").await.unwrap(), None);
+
+    storage.vacuum().await.unwrap();
+    let rows = HttpSession::Entity::find().all(&storage.db).await.unwrap();
+    assert_eq!(rows.iter().map(|x| &x.id).collect::<Vec<_>>(), [&hash_session_id("live")]);
   }
 }
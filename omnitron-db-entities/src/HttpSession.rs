@@ -0,0 +1,17 @@
+use chrono::{DateTime, Utc};
+use sea_orm::entity::prelude::*;
+
+/// Persisted poem session, keyed by the SHA-256 of the session cookie value
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel)]
+#[sea_orm(table_name = "http_sessions")]
+pub struct Model {
+  #[sea_orm(primary_key, auto_increment = false)]
+  pub id: String,
+  pub entries: Json,
+  pub expires: Option<DateTime<Utc>>,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
@@ -0,0 +1,32 @@
+use chrono::{DateTime, Utc};
+use poem_openapi::{Enum, Object};
+use sea_orm::entity::prelude::*;
+use serde::Serialize;
+
+#[derive(Debug, PartialEq, Eq, Serialize, Clone, Copy, Enum, EnumIter, DeriveActiveEnum)]
+#[sea_orm(rs_type = "String", db_type = "String(StringLen::N(16))")]
+pub enum LoginFailureKind {
+  #[sea_orm(string_value = "username")]
+  Username,
+  #[sea_orm(string_value = "address")]
+  Address,
+}
+
+/// Failed login attempts for a username or a source address
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel, Serialize, Object)]
+#[sea_orm(table_name = "login_failures")]
+#[oai(rename = "LoginFailure")]
+pub struct Model {
+  #[sea_orm(primary_key, auto_increment = false)]
+  pub kind: LoginFailureKind,
+  #[sea_orm(primary_key, auto_increment = false)]
+  pub key: String,
+  pub failures: i32,
+  pub last_failure: DateTime<Utc>,
+  pub locked_until: Option<DateTime<Utc>>,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
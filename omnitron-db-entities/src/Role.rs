@@ -11,6 +11,9 @@ pub struct Model {
   #[sea_orm(primary_key, auto_increment = false)]
   pub id: Uuid,
   pub name: String,
+  pub limits: Option<serde_json::Value>,
+  pub ip_policy: Option<serde_json::Value>,
+  pub sql_policy: Option<serde_json::Value>,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
@@ -18,11 +21,16 @@ pub enum Relation {}
 
 impl ActiveModelBehavior for ActiveModel {}
 
-impl From<Model> for Role {
-  fn from(model: Model) -> Self {
-    Self {
+impl TryFrom<Model> for Role {
+  type Error = serde_json::Error;
+
+  fn try_from(model: Model) -> Result<Self, Self::Error> {
+    Ok(Self {
       id: model.id,
       name: model.name,
-    }
+      limits: model.limits.map(serde_json::from_value).transpose()?.unwrap_or_default(),
+      ip_policy: model.ip_policy.map(serde_json::from_value).transpose()?.unwrap_or_default(),
+      sql_policy: model.sql_policy.map(serde_json::from_value).transpose()?.unwrap_or_default(),
+    })
   }
 }
@@ -19,6 +19,7 @@ pub struct Model {
 #[derive(Copy, Clone, Debug, EnumIter)]
 pub enum Relation {
   Ticket,
+  Recordings,
 }
 
 impl RelationTrait for Relation {
@@ -29,6 +30,10 @@ impl RelationTrait for Relation {
         .to(super::Ticket::Column::Id)
         .on_delete(ForeignKeyAction::SetNull)
         .into(),
+      Self::Recordings => Entity::has_many(super::Recording::Entity)
+        .from(Column::Id)
+        .to(super::Recording::Column::SessionId)
+        .into(),
     }
   }
 }
@@ -39,4 +44,10 @@ impl Related<super::Ticket::Entity> for Entity {
   }
 }
 
+impl Related<super::Recording::Entity> for Entity {
+  fn to() -> RelationDef {
+    Relation::Recordings.def()
+  }
+}
+
 impl ActiveModelBehavior for ActiveModel {}
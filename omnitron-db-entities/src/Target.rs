@@ -49,6 +49,10 @@ pub struct Model {
   pub name: String,
   pub kind: TargetKind,
   pub options: serde_json::Value,
+  pub limits: Option<serde_json::Value>,
+  pub ip_policy: Option<serde_json::Value>,
+  pub sql_policy: Option<serde_json::Value>,
+  pub masking: Option<serde_json::Value>,
 }
 
 impl Related<super::Role::Entity> for Entity {
@@ -71,10 +75,18 @@ impl TryFrom<Model> for Target {
 
   fn try_from(model: Model) -> Result<Self, Self::Error> {
     let options: TargetOptions = serde_json::from_value(model.options)?;
+    let limits = model.limits.map(serde_json::from_value).transpose()?.unwrap_or_default();
+    let ip_policy = model.ip_policy.map(serde_json::from_value).transpose()?.unwrap_or_default();
+    let sql_policy = model.sql_policy.map(serde_json::from_value).transpose()?.unwrap_or_default();
+    let masking = model.masking.map(serde_json::from_value).transpose()?.unwrap_or_default();
     Ok(Self {
       id: model.id,
       name: model.name,
       allow_roles: vec![],
+      limits,
+      ip_policy,
+      sql_policy,
+      masking,
       options,
     })
   }
@@ -5,7 +5,7 @@ use sea_orm::Set;
 use serde::Serialize;
 use uuid::Uuid;
 
-use crate::{OtpCredential, PasswordCredential, PublicKeyCredential, Role};
+use crate::{OtpCredential, PasswordCredential, PublicKeyCredential, Role, SsoCredential};
 
 #[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel, Serialize, Object)]
 #[sea_orm(table_name = "users")]
@@ -15,6 +15,7 @@ pub struct Model {
   pub id: Uuid,
   pub username: String,
   pub credential_policy: serde_json::Value,
+  pub ip_policy: Option<serde_json::Value>,
 }
 
 impl Related<super::Role::Entity> for Entity {
@@ -45,6 +46,12 @@ impl Related<super::PublicKeyCredential::Entity> for Entity {
   }
 }
 
+impl Related<super::SsoCredential::Entity> for Entity {
+  fn to() -> RelationDef {
+    Relation::SsoCredentials.def()
+  }
+}
+
 impl Related<super::ApiToken::Entity> for Entity {
   fn to() -> RelationDef {
     Relation::ApiTokens.def()
@@ -57,6 +64,7 @@ pub enum Relation {
   OtpCredentials,
   PasswordCredentials,
   PublicKeyCredentials,
+  SsoCredentials,
   ApiTokens,
 }
 
@@ -75,6 +83,10 @@ impl RelationTrait for Relation {
         .from(Column::Id)
         .to(super::PublicKeyCredential::Column::UserId)
         .into(),
+      Self::SsoCredentials => Entity::has_many(super::SsoCredential::Entity)
+        .from(Column::Id)
+        .to(super::SsoCredential::Column::UserId)
+        .into(),
       Self::ApiTokens => Entity::has_many(super::ApiToken::Entity)
         .from(Column::Id)
         .to(super::ApiToken::Column::UserId)
@@ -93,6 +105,7 @@ impl TryFrom<Model> for User {
       id: model.id,
       username: model.username,
       credential_policy: serde_json::from_value(model.credential_policy)?,
+      ip_policy: model.ip_policy.map(serde_json::from_value).transpose()?.unwrap_or_default(),
     })
   }
 }
@@ -104,7 +117,6 @@ impl Model {
       .all(db)
       .await?
       .into_iter()
-      .map(Into::<omnitron_gate_common::Role>::into)
       .map(|x| x.name)
       .collect();
 
@@ -133,6 +145,14 @@ impl Model {
         .into_iter()
         .map(|x| x.into()),
     );
+    credentials.extend(
+      self
+        .find_related(SsoCredential::Entity)
+        .all(db)
+        .await?
+        .into_iter()
+        .map(|x| x.into()),
+    );
 
     Ok(omnitron_gate_common::UserDetails {
       inner: self.try_into()?,
@@ -150,6 +170,7 @@ impl TryFrom<User> for ActiveModel {
       id: Set(user.id),
       username: Set(user.username),
       credential_policy: Set(serde_json::to_value(&user.credential_policy)?),
+      ip_policy: Set(Some(serde_json::to_value(&user.ip_policy)?)),
     })
   }
 }
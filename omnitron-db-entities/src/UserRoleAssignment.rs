@@ -1,3 +1,5 @@
+use chrono::{DateTime, Utc};
+use omnitron_gate_common::RoleGrant;
 use poem_openapi::Object;
 use sea_orm::entity::prelude::*;
 use serde::Serialize;
@@ -11,6 +13,9 @@ pub struct Model {
   pub id: i32,
   pub user_id: Uuid,
   pub role_id: Uuid,
+  pub valid_from: Option<DateTime<Utc>>,
+  pub valid_until: Option<DateTime<Utc>>,
+  pub schedule: Option<serde_json::Value>,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter)]
@@ -34,4 +39,20 @@ impl RelationTrait for Relation {
   }
 }
 
+impl Related<super::Role::Entity> for Entity {
+  fn to() -> RelationDef {
+    Relation::Role.def()
+  }
+}
+
 impl ActiveModelBehavior for ActiveModel {}
+
+impl Model {
+  pub fn grant(&self) -> RoleGrant {
+    RoleGrant {
+      valid_from: self.valid_from,
+      valid_until: self.valid_until,
+      schedule: self.schedule.clone().and_then(|x| serde_json::from_value(x).ok()),
+    }
+  }
+}
@@ -1,14 +1,18 @@
 #![allow(non_snake_case)]
 
 pub mod ApiToken;
+pub mod HttpSession;
 pub mod KnownHost;
 pub mod LogEntry;
+pub mod LoginFailure;
 pub mod OtpCredential;
 pub mod Parameters;
 pub mod PasswordCredential;
 pub mod PublicKeyCredential;
+pub mod Recording;
 pub mod Role;
 pub mod Session;
+pub mod SsoCredential;
 pub mod Target;
 pub mod TargetRoleAssignment;
 pub mod Ticket;
@@ -15,6 +15,15 @@ mod m00011_rsa_key_algos;
 mod m00012_add_openssh_public_key_label;
 mod m00013_add_openssh_public_key_dates;
 mod m00014_api_tokens;
+mod m00015_create_recording;
+mod m00016_sso_credentials;
+mod m00017_http_sessions;
+mod m00018_session_limits;
+mod m00019_login_failures;
+mod m00020_ip_policies;
+mod m00021_role_grants;
+mod m00022_sql_policies;
+mod m00023_target_masking;
 
 pub struct Migrator;
 
@@ -35,6 +44,15 @@ impl MigratorTrait for Migrator {
       Box::new(m00012_add_openssh_public_key_label::Migration),
       Box::new(m00013_add_openssh_public_key_dates::Migration),
       Box::new(m00014_api_tokens::Migration),
+      Box::new(m00015_create_recording::Migration),
+      Box::new(m00016_sso_credentials::Migration),
+      Box::new(m00017_http_sessions::Migration),
+      Box::new(m00018_session_limits::Migration),
+      Box::new(m00019_login_failures::Migration),
+      Box::new(m00020_ip_policies::Migration),
+      Box::new(m00021_role_grants::Migration),
+      Box::new(m00022_sql_policies::Migration),
+      Box::new(m00023_target_masking::Migration),
     ]
   }
 }
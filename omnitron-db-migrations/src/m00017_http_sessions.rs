@@ -0,0 +1,54 @@
+use sea_orm::Schema;
+use sea_orm_migration::prelude::*;
+
+pub mod http_session {
+  use chrono::{DateTime, Utc};
+  use sea_orm::entity::prelude::*;
+
+  #[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel)]
+  #[sea_orm(table_name = "http_sessions")]
+  pub struct Model {
+    /// Hash of the session ID, so that the table doesn't hold valid cookies
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub id: String,
+    pub entries: Json,
+    pub expires: Option<DateTime<Utc>>,
+  }
+
+  #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+  pub enum Relation {}
+
+  impl ActiveModelBehavior for ActiveModel {}
+}
+
+pub struct Migration;
+
+impl MigrationName for Migration {
+  fn name(&self) -> &str {
+    "m00017_http_sessions"
+  }
+}
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+  async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+    let builder = manager.get_database_backend();
+    let schema = Schema::new(builder);
+    manager
+      .create_table(schema.create_table_from_entity(http_session::Entity))
+      .await?;
+    manager
+      .create_index(
+        Index::create()
+          .table(http_session::Entity)
+          .name("http_sessions__expires")
+          .col(http_session::Column::Expires)
+          .to_owned(),
+      )
+      .await
+  }
+
+  async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+    manager.drop_table(Table::drop().table(http_session::Entity).to_owned()).await
+  }
+}
@@ -0,0 +1,58 @@
+use sea_orm::Schema;
+use sea_orm_migration::prelude::*;
+
+pub mod login_failure {
+  use chrono::{DateTime, Utc};
+  use sea_orm::entity::prelude::*;
+
+  #[derive(Debug, PartialEq, Eq, Clone, EnumIter, DeriveActiveEnum)]
+  #[sea_orm(rs_type = "String", db_type = "String(StringLen::N(16))")]
+  pub enum LoginFailureKind {
+    #[sea_orm(string_value = "username")]
+    Username,
+    #[sea_orm(string_value = "address")]
+    Address,
+  }
+
+  #[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel)]
+  #[sea_orm(table_name = "login_failures")]
+  pub struct Model {
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub kind: LoginFailureKind,
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub key: String,
+    pub failures: i32,
+    pub last_failure: DateTime<Utc>,
+    pub locked_until: Option<DateTime<Utc>>,
+  }
+
+  #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+  pub enum Relation {}
+
+  impl ActiveModelBehavior for ActiveModel {}
+}
+
+pub struct Migration;
+
+impl MigrationName for Migration {
+  fn name(&self) -> &str {
+    "m00019_login_failures"
+  }
+}
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+  async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+    let builder = manager.get_database_backend();
+    let schema = Schema::new(builder);
+    manager
+      .create_table(schema.create_table_from_entity(login_failure::Entity))
+      .await
+  }
+
+  async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+    manager
+      .drop_table(Table::drop().table(login_failure::Entity).to_owned())
+      .await
+  }
+}
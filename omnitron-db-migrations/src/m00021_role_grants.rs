@@ -0,0 +1,44 @@
+use sea_orm_migration::prelude::*;
+
+pub struct Migration;
+
+impl MigrationName for Migration {
+  fn name(&self) -> &str {
+    "m00021_role_grants"
+  }
+}
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+  async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+    for table in ["user_roles", "target_roles"] {
+      // SQLite can only add one column per statement
+      for column in [
+        ColumnDef::new(Alias::new("valid_from")).date_time().null().to_owned(),
+        ColumnDef::new(Alias::new("valid_until")).date_time().null().to_owned(),
+        ColumnDef::new(Alias::new("schedule")).json().null().to_owned(),
+      ] {
+        manager
+          .alter_table(Table::alter().table(Alias::new(table)).add_column(column).to_owned())
+          .await?;
+      }
+    }
+    Ok(())
+  }
+
+  async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+    for table in ["user_roles", "target_roles"] {
+      for column in ["valid_from", "valid_until", "schedule"] {
+        manager
+          .alter_table(
+            Table::alter()
+              .table(Alias::new(table))
+              .drop_column(Alias::new(column))
+              .to_owned(),
+          )
+          .await?;
+      }
+    }
+    Ok(())
+  }
+}
@@ -0,0 +1,40 @@
+use sea_orm_migration::prelude::*;
+
+pub struct Migration;
+
+impl MigrationName for Migration {
+  fn name(&self) -> &str {
+    "m00022_sql_policies"
+  }
+}
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+  async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+    for table in ["roles", "targets"] {
+      manager
+        .alter_table(
+          Table::alter()
+            .table(Alias::new(table))
+            .add_column(ColumnDef::new(Alias::new("sql_policy")).json().null())
+            .to_owned(),
+        )
+        .await?;
+    }
+    Ok(())
+  }
+
+  async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+    for table in ["roles", "targets"] {
+      manager
+        .alter_table(
+          Table::alter()
+            .table(Alias::new(table))
+            .drop_column(Alias::new("sql_policy"))
+            .to_owned(),
+        )
+        .await?;
+    }
+    Ok(())
+  }
+}
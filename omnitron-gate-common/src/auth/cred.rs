@@ -15,22 +15,44 @@ pub enum CredentialKind {
   Totp,
   #[serde(rename = "web")]
   WebUserApproval,
+  #[serde(rename = "sso")]
+  Sso,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum AuthCredential {
   Otp(Secret<String>),
   Password(Secret<String>),
-  PublicKey { kind: Algorithm, public_key_bytes: Bytes },
+  PublicKey {
+    kind: Algorithm,
+    public_key_bytes: Bytes,
+  },
+  /// An SSH certificate that has already been verified against a trusted user CA
+  Certificate {
+    key_id: String,
+    principals: Vec<String>,
+  },
+  Sso {
+    provider: String,
+    email: String,
+  },
   WebUserApproval,
 }
 
 impl AuthCredential {
+  /// Whether failing with this credential counts towards a brute-force lockout.
+  /// Rejected public keys are routine since SSH clients offer every key they have.
+  pub fn is_guessable(&self) -> bool {
+    matches!(self, Self::Password(_) | Self::Otp(_))
+  }
+
   pub fn kind(&self) -> CredentialKind {
     match self {
       Self::Password { .. } => CredentialKind::Password,
       Self::PublicKey { .. } => CredentialKind::PublicKey,
+      Self::Certificate { .. } => CredentialKind::PublicKey,
       Self::Otp { .. } => CredentialKind::Totp,
+      Self::Sso { .. } => CredentialKind::Sso,
       Self::WebUserApproval => CredentialKind::WebUserApproval,
     }
   }
@@ -39,7 +61,9 @@ impl AuthCredential {
     match self {
       Self::Password { .. } => "password".to_string(),
       Self::PublicKey { .. } => "public key".to_string(),
+      Self::Certificate { key_id, .. } => format!("SSH certificate ({key_id})"),
       Self::Otp { .. } => "one-time password".to_string(),
+      Self::Sso { provider, .. } => format!("SSO ({provider})"),
       Self::WebUserApproval => "in-browser auth".to_string(),
     }
   }
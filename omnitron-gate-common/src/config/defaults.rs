@@ -69,14 +69,110 @@ pub(crate) fn _default_empty_vec<T>() -> Vec<T> {
   vec![]
 }
 
+pub(crate) const fn _default_http_health_check_interval() -> u64 {
+  10
+}
+
+pub(crate) const fn _default_http_health_check_timeout() -> u64 {
+  5
+}
+
+pub(crate) fn _default_http_capture_redact() -> Vec<String> {
+  [
+    "authorization",
+    "proxy-authorization",
+    "cookie",
+    "set-cookie",
+    "password",
+    "token",
+  ]
+  .into_iter()
+  .map(str::to_owned)
+  .collect()
+}
+
 pub(crate) fn _default_ssh_listen() -> ListenEndpoint {
   ListenEndpoint::from(SocketAddr::new(Ipv6Addr::UNSPECIFIED.into(), 2222))
 }
 
+pub(crate) fn _default_recordings_path() -> String {
+  "./data/recordings".to_owned()
+}
+
 pub(crate) fn _default_ssh_keys_path() -> String {
   "./data/keys".to_owned()
 }
 
+pub(crate) fn _default_acme_directory_url() -> String {
+  "https://acme-v02.api.letsencrypt.org/directory".to_owned()
+}
+
+pub(crate) fn _default_acme_http_challenge_listen() -> ListenEndpoint {
+  ListenEndpoint::from(SocketAddr::new(Ipv6Addr::UNSPECIFIED.into(), 80))
+}
+
+pub(crate) fn _default_acme_path() -> String {
+  "./data/acme".to_owned()
+}
+
+pub(crate) fn _default_acme_renew_before() -> Duration {
+  Duration::SECOND * 60 * 60 * 24 * 30
+}
+
 pub(crate) fn _default_ssh_inactivity_timeout() -> Duration {
   Duration::SECOND * 60 * 5
 }
+
+pub(crate) fn _default_ssh_client_certificate_validity() -> Duration {
+  Duration::SECOND * 60 * 5
+}
+
+#[inline]
+pub(crate) fn _default_sso_scopes() -> Vec<String> {
+  vec!["email".to_owned(), "profile".to_owned()]
+}
+
+#[inline]
+pub(crate) fn _default_sso_email_claim() -> String {
+  "email".to_owned()
+}
+
+#[inline]
+pub(crate) fn _default_ldap_user_filter() -> String {
+  "(&(objectClass=person)(uid={username}))".to_owned()
+}
+
+#[inline]
+pub(crate) fn _default_ldap_username_attribute() -> String {
+  "uid".to_owned()
+}
+
+#[inline]
+pub(crate) fn _default_ldap_group_attribute() -> String {
+  "memberOf".to_owned()
+}
+
+#[inline]
+pub(crate) fn _default_ldap_timeout() -> Duration {
+  Duration::from_secs(10)
+}
+
+#[inline]
+pub(crate) fn _default_login_protection_max_attempts() -> u32 {
+  5
+}
+
+#[inline]
+pub(crate) fn _default_login_protection_lockout() -> Duration {
+  Duration::SECOND * 60
+}
+
+#[inline]
+pub(crate) fn _default_login_protection_max_lockout() -> Duration {
+  Duration::SECOND * 60 * 60
+}
+
+#[inline]
+pub(crate) fn _default_login_protection_reset_after() -> Duration {
+  Duration::SECOND * 60 * 60 * 24
+}
@@ -0,0 +1,180 @@
+use chrono::{DateTime, Datelike, NaiveDateTime, NaiveTime, Utc};
+use chrono_tz::Tz;
+use poem_openapi::{Enum, Object};
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+/// Restricts when a role assignment is in effect. The default grant is always in effect.
+#[derive(Debug, Deserialize, Serialize, Clone, Default, PartialEq, Eq, Object)]
+pub struct RoleGrant {
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub valid_from: Option<DateTime<Utc>>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub valid_until: Option<DateTime<Utc>>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub schedule: Option<AccessSchedule>,
+}
+
+/// Recurring weekly windows during which a grant is in effect
+#[derive(Debug, Deserialize, Serialize, Clone, Default, PartialEq, Eq, Object)]
+pub struct AccessSchedule {
+  /// IANA time zone name that the windows are in, UTC if not set
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub timezone: Option<String>,
+  pub windows: Vec<AccessWindow>,
+}
+
+/// A time-of-day range on some days of the week. Windows where `end` isn't after `start`
+/// run past midnight into the next day.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq, Object)]
+pub struct AccessWindow {
+  /// Days on which the window starts, every day if empty
+  #[serde(default, skip_serializing_if = "Vec::is_empty")]
+  pub days: Vec<AccessWeekday>,
+  pub start: NaiveTime,
+  pub end: NaiveTime,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Enum)]
+pub enum AccessWeekday {
+  #[serde(rename = "mon")]
+  #[oai(rename = "mon")]
+  Monday,
+  #[serde(rename = "tue")]
+  #[oai(rename = "tue")]
+  Tuesday,
+  #[serde(rename = "wed")]
+  #[oai(rename = "wed")]
+  Wednesday,
+  #[serde(rename = "thu")]
+  #[oai(rename = "thu")]
+  Thursday,
+  #[serde(rename = "fri")]
+  #[oai(rename = "fri")]
+  Friday,
+  #[serde(rename = "sat")]
+  #[oai(rename = "sat")]
+  Saturday,
+  #[serde(rename = "sun")]
+  #[oai(rename = "sun")]
+  Sunday,
+}
+
+impl From<AccessWeekday> for chrono::Weekday {
+  fn from(day: AccessWeekday) -> Self {
+    match day {
+      AccessWeekday::Monday => chrono::Weekday::Mon,
+      AccessWeekday::Tuesday => chrono::Weekday::Tue,
+      AccessWeekday::Wednesday => chrono::Weekday::Wed,
+      AccessWeekday::Thursday => chrono::Weekday::Thu,
+      AccessWeekday::Friday => chrono::Weekday::Fri,
+      AccessWeekday::Saturday => chrono::Weekday::Sat,
+      AccessWeekday::Sunday => chrono::Weekday::Sun,
+    }
+  }
+}
+
+impl RoleGrant {
+  pub fn is_permanent(&self) -> bool {
+    self == &Self::default()
+  }
+
+  pub fn is_active(&self, now: DateTime<Utc>) -> bool {
+    if self.valid_from.is_some_and(|x| now < x) || self.valid_until.is_some_and(|x| now >= x) {
+      return false;
+    }
+    self.schedule.as_ref().map_or(true, |x| x.is_active(now))
+  }
+
+  pub fn validate(&self) -> Result<(), String> {
+    if let (Some(from), Some(until)) = (self.valid_from, self.valid_until) {
+      if until <= from {
+        return Err("valid_until must be after valid_from".into());
+      }
+    }
+    if let Some(schedule) = &self.schedule {
+      schedule.validate()?;
+    }
+    Ok(())
+  }
+}
+
+impl AccessSchedule {
+  fn timezone(&self) -> Result<Tz, String> {
+    match &self.timezone {
+      Some(name) => name.parse::<Tz>().map_err(|_| format!("unknown time zone {name}")),
+      None => Ok(Tz::UTC),
+    }
+  }
+
+  /// A schedule with an unknown time zone is never active
+  pub fn is_active(&self, now: DateTime<Utc>) -> bool {
+    let timezone = match self.timezone() {
+      Ok(x) => x,
+      Err(error) => {
+        warn!(%error, "Ignoring access schedule");
+        return false;
+      }
+    };
+    let local = now.with_timezone(&timezone).naive_local();
+    self.windows.iter().any(|x| x.contains(local))
+  }
+
+  pub fn validate(&self) -> Result<(), String> {
+    self.timezone().map(|_| ())
+  }
+}
+
+impl AccessWindow {
+  fn starts_on(&self, day: chrono::Weekday) -> bool {
+    self.days.is_empty() || self.days.iter().any(|x| chrono::Weekday::from(*x) == day)
+  }
+
+  fn contains(&self, local: NaiveDateTime) -> bool {
+    let (day, time) = (local.weekday(), local.time());
+    if self.start < self.end {
+      self.starts_on(day) && time >= self.start && time < self.end
+    } else {
+      (self.starts_on(day) && time >= self.start) || (self.starts_on(day.pred()) && time < self.end)
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use chrono::{NaiveTime, TimeZone};
+
+  use super::*;
+
+  fn time(h: u32, m: u32) -> NaiveTime {
+    NaiveTime::from_hms_opt(h, m, 0).unwrap()
+  }
+
+  #[test]
+  fn test_schedule_windows() {
+    // 2024-01-01 is a Monday
+    let schedule = AccessSchedule {
+      timezone: Some("Europe/Berlin".into()),
+      windows: vec![
+        AccessWindow {
+          days: vec![AccessWeekday::Monday],
+          start: time(9, 0),
+          end: time(17, 0),
+        },
+        AccessWindow {
+          days: vec![AccessWeekday::Friday],
+          start: time(22, 0),
+          end: time(2, 0),
+        },
+      ],
+    };
+    assert!(!schedule.is_active(Utc.with_ymd_and_hms(2024, 1, 1, 7, 30, 0).unwrap()));
+    assert!(schedule.is_active(Utc.with_ymd_and_hms(2024, 1, 1, 8, 30, 0).unwrap()));
+    assert!(!schedule.is_active(Utc.with_ymd_and_hms(2024, 1, 1, 16, 0, 0).unwrap()));
+    assert!(!schedule.is_active(Utc.with_ymd_and_hms(2024, 1, 2, 8, 30, 0).unwrap()));
+    assert!(schedule.is_active(Utc.with_ymd_and_hms(2024, 1, 5, 21, 30, 0).unwrap()));
+    assert!(schedule.is_active(Utc.with_ymd_and_hms(2024, 1, 6, 0, 30, 0).unwrap()));
+    assert!(!schedule.is_active(Utc.with_ymd_and_hms(2024, 1, 6, 1, 30, 0).unwrap()));
+    assert!(!schedule.is_active(Utc.with_ymd_and_hms(2024, 1, 7, 0, 30, 0).unwrap()));
+  }
+}
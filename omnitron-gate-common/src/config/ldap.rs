@@ -0,0 +1,74 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use super::defaults::*;
+use crate::Secret;
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct LdapConfig {
+  #[serde(default = "_default_false")]
+  pub enable: bool,
+
+  /// `ldap://` or `ldaps://` server URL
+  #[serde(default)]
+  pub url: String,
+
+  #[serde(default)]
+  pub starttls: bool,
+
+  #[serde(default)]
+  pub no_tls_verify: bool,
+
+  /// Service account used for user searches, anonymous if unset
+  #[serde(default)]
+  pub bind_dn: Option<String>,
+
+  #[serde(default)]
+  pub bind_password: Option<Secret<String>>,
+
+  #[serde(default)]
+  pub user_base_dn: String,
+
+  /// Search filter, `{username}` is replaced with the escaped username
+  #[serde(default = "_default_ldap_user_filter")]
+  pub user_filter: String,
+
+  #[serde(default = "_default_ldap_username_attribute")]
+  pub username_attribute: String,
+
+  #[serde(default = "_default_ldap_group_attribute")]
+  pub group_attribute: String,
+
+  /// Maps group DNs or CNs to Omnitron role names. When empty, group CNs are used as role names
+  #[serde(default)]
+  pub group_role_mappings: HashMap<String, String>,
+
+  /// Attribute holding OpenSSH public keys (e.g. `sshPublicKey`), public key auth is disabled if unset
+  #[serde(default)]
+  pub ssh_public_key_attribute: Option<String>,
+
+  #[serde(default = "_default_ldap_timeout", with = "humantime_serde")]
+  pub timeout: Duration,
+}
+
+impl Default for LdapConfig {
+  fn default() -> Self {
+    Self {
+      enable: false,
+      url: "".to_owned(),
+      starttls: false,
+      no_tls_verify: false,
+      bind_dn: None,
+      bind_password: None,
+      user_base_dn: "".to_owned(),
+      user_filter: _default_ldap_user_filter(),
+      username_attribute: _default_ldap_username_attribute(),
+      group_attribute: _default_ldap_group_attribute(),
+      group_role_mappings: HashMap::new(),
+      ssh_public_key_attribute: None,
+      timeout: _default_ldap_timeout(),
+    }
+  }
+}
@@ -0,0 +1,55 @@
+use poem_openapi::{Enum, Object};
+use regex::RegexBuilder;
+use serde::{Deserialize, Serialize};
+
+/// Replaces the values of matching result set columns before they reach the client
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq, Object)]
+pub struct MaskingRule {
+  /// `schema.table.column`, `table.column` or `column`, where any part can be `*`.
+  /// Result columns match by their source column as well as by the name they're returned as.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub column: Option<String>,
+  /// Case-insensitive regular expression for column names. If it is invalid, all columns are replaced with NULL.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub column_pattern: Option<String>,
+  pub method: MaskMethod,
+  /// Trailing characters left readable by partial masks, 4 if not set
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub visible_chars: Option<u32>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Enum)]
+pub enum MaskMethod {
+  /// HMAC-SHA256 of the value under the install's masking key, in hex
+  #[serde(rename = "hash")]
+  #[oai(rename = "hash")]
+  Hash,
+  /// Everything but the last few characters replaced with `*`
+  #[serde(rename = "partial")]
+  #[oai(rename = "partial")]
+  Partial,
+  #[serde(rename = "null")]
+  #[oai(rename = "null")]
+  Null,
+}
+
+impl MaskingRule {
+  pub const DEFAULT_VISIBLE_CHARS: u32 = 4;
+
+  /// Returns a description of the first problem with the rule
+  pub fn validate(&self) -> Result<(), String> {
+    match (&self.column, &self.column_pattern) {
+      (None, None) => return Err("either column or column_pattern is required".into()),
+      (Some(column), _) if column.split('.').count() > 3 || column.split('.').any(str::is_empty) => {
+        return Err(format!("{column}: expected schema.table.column, table.column or column"));
+      }
+      _ => (),
+    }
+    if let Some(ref pattern) = self.column_pattern {
+      if let Err(error) = RegexBuilder::new(pattern).case_insensitive(true).build() {
+        return Err(format!("{pattern}: {error}"));
+      }
+    }
+    Ok(())
+  }
+}
@@ -1,14 +1,26 @@
 mod defaults;
+mod grant;
+mod ldap;
+mod masking;
+mod sql_policy;
+mod sso;
 mod target;
 
+use std::net::IpAddr;
 use std::ops::Deref;
 use std::path::PathBuf;
 use std::time::Duration;
 
 use defaults::*;
+pub use grant::*;
+use ipnet::IpNet;
+pub use ldap::*;
+pub use masking::*;
 use poem::http::uri;
 use poem_openapi::{Object, Union};
 use serde::{Deserialize, Serialize};
+pub use sql_policy::*;
+pub use sso::*;
 pub use target::*;
 use tracing::warn;
 use uri::Scheme;
@@ -30,6 +42,8 @@ pub enum UserAuthCredential {
   PublicKey(UserPublicKeyCredential),
   #[serde(rename = "otp")]
   Totp(UserTotpCredential),
+  #[serde(rename = "sso")]
+  Sso(UserSsoCredential),
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq, Object)]
@@ -55,12 +69,19 @@ pub struct UserTotpCredential {
   pub key: OtpSecretKey,
 }
 
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq, Object)]
+pub struct UserSsoCredential {
+  pub provider: Option<String>,
+  pub email: String,
+}
+
 impl UserAuthCredential {
   pub fn kind(&self) -> CredentialKind {
     match self {
       Self::Password(_) => CredentialKind::Password,
       Self::PublicKey(_) => CredentialKind::PublicKey,
       Self::Totp(_) => CredentialKind::Totp,
+      Self::Sso(_) => CredentialKind::Sso,
     }
   }
 }
@@ -123,6 +144,8 @@ pub struct User {
   pub username: String,
   #[serde(skip_serializing_if = "Option::is_none", rename = "require")]
   pub credential_policy: Option<UserRequireCredentialsPolicy>,
+  #[serde(default)]
+  pub ip_policy: IpPolicy,
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone, Object)]
@@ -145,6 +168,127 @@ pub struct Role {
   #[serde(default)]
   pub id: Uuid,
   pub name: String,
+  #[serde(default)]
+  pub limits: SessionLimits,
+  #[serde(default)]
+  pub ip_policy: IpPolicy,
+  #[serde(default)]
+  pub sql_policy: SqlPolicy,
+}
+
+/// Networks (in CIDR notation, or single addresses) that connections may or may not come from
+#[derive(Debug, Deserialize, Serialize, Clone, Default, PartialEq, Eq, Hash, Object)]
+pub struct IpPolicy {
+  /// If not empty, only these networks are allowed
+  #[serde(default, skip_serializing_if = "Vec::is_empty")]
+  pub allow: Vec<String>,
+  /// Always refused, even if also allowed
+  #[serde(default, skip_serializing_if = "Vec::is_empty")]
+  pub deny: Vec<String>,
+}
+
+/// Why an [IpPolicy] refused an address
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum IpPolicyDenial {
+  #[error("{address} matches deny rule {rule} of {owner}")]
+  Denied { address: IpAddr, rule: String, owner: String },
+  #[error("{address} is not allowed by {owner}, which only allows {allowed:?}")]
+  NotAllowed {
+    address: IpAddr,
+    allowed: Vec<String>,
+    owner: String,
+  },
+}
+
+impl IpPolicy {
+  pub fn is_empty(&self) -> bool {
+    self.allow.is_empty() && self.deny.is_empty()
+  }
+
+  /// Checks an address against the policy. `owner` describes where the policy comes from in the denial.
+  /// Invalid rules fail closed: an invalid deny rule matches every address, an invalid allow rule none.
+  pub fn check(&self, address: IpAddr, owner: &str) -> Result<(), IpPolicyDenial> {
+    if let Some(rule) = self.deny.iter().find(|x| network_contains(x, address) != Some(false)) {
+      return Err(IpPolicyDenial::Denied {
+        address,
+        rule: rule.clone(),
+        owner: owner.to_owned(),
+      });
+    }
+    if !self.allow.is_empty() && !self.allow.iter().any(|x| network_contains(x, address) == Some(true)) {
+      return Err(IpPolicyDenial::NotAllowed {
+        address,
+        allowed: self.allow.clone(),
+        owner: owner.to_owned(),
+      });
+    }
+    Ok(())
+  }
+
+  /// Returns the first rule that isn't a valid network or address
+  pub fn validate(&self) -> Result<(), String> {
+    match self.allow.iter().chain(self.deny.iter()).find(|x| parse_network(x).is_none()) {
+      Some(rule) => Err(rule.clone()),
+      None => Ok(()),
+    }
+  }
+}
+
+fn parse_network(rule: &str) -> Option<IpNet> {
+  let rule = rule.trim();
+  rule
+    .parse::<IpNet>()
+    .ok()
+    .or_else(|| rule.parse::<IpAddr>().ok().map(IpNet::from))
+}
+
+/// Whether the rule's network contains the address, or `None` if the rule is invalid
+fn network_contains(rule: &str, address: IpAddr) -> Option<bool> {
+  let address = match address {
+    // Dual-stack sockets report IPv4 clients as mapped IPv6 addresses
+    IpAddr::V6(v6) => v6.to_ipv4_mapped().map(IpAddr::V4).unwrap_or(address),
+    x => x,
+  };
+  parse_network(rule).map(|network| network.contains(&address))
+}
+
+/// Limits on how much a user can use a target. Unset values mean no limit.
+#[derive(Debug, Deserialize, Serialize, Clone, Default, PartialEq, Eq, Hash, Object)]
+pub struct SessionLimits {
+  /// Concurrent sessions a user may have open to a target
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub max_sessions_per_user: Option<u32>,
+  /// HTTP requests a user may make to a target per second
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub requests_per_second: Option<u32>,
+}
+
+impl SessionLimits {
+  /// Combines the limits of all roles that grant access - the most permissive one wins
+  pub fn most_permissive<'a>(limits: impl IntoIterator<Item = &'a SessionLimits>) -> Self {
+    let mut limits = limits.into_iter();
+    let Some(first) = limits.next() else {
+      return Self::default();
+    };
+    limits.fold(first.clone(), |a, b| Self {
+      max_sessions_per_user: a.max_sessions_per_user.zip(b.max_sessions_per_user).map(|(a, b)| a.max(b)),
+      requests_per_second: a.requests_per_second.zip(b.requests_per_second).map(|(a, b)| a.max(b)),
+    })
+  }
+
+  /// Applies the stricter value of each limit
+  pub fn restrict(&self, other: &SessionLimits) -> Self {
+    fn min(a: Option<u32>, b: Option<u32>) -> Option<u32> {
+      match (a, b) {
+        (Some(a), Some(b)) => Some(a.min(b)),
+        (a, b) => a.or(b),
+      }
+    }
+    Self {
+      max_sessions_per_user: min(self.max_sessions_per_user, other.max_sessions_per_user),
+      requests_per_second: min(self.requests_per_second, other.requests_per_second),
+    }
+  }
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone, Default, PartialEq, Eq, Copy)]
@@ -180,6 +324,33 @@ pub struct SshConfig {
 
   #[serde(default)]
   pub keepalive_interval: Option<Duration>,
+
+  /// OpenSSH public keys of CAs whose user certificates are accepted
+  #[serde(default)]
+  pub user_ca_keys: Vec<String>,
+
+  #[serde(default)]
+  pub client_certificates: SshClientCertificatesConfig,
+}
+
+/// Lets Omnitron sign short-lived certificates for its own client key
+/// when connecting to targets with public key auth
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct SshClientCertificatesConfig {
+  #[serde(default = "_default_false")]
+  pub enable: bool,
+
+  #[serde(default = "_default_ssh_client_certificate_validity", with = "humantime_serde")]
+  pub validity: Duration,
+}
+
+impl Default for SshClientCertificatesConfig {
+  fn default() -> Self {
+    SshClientCertificatesConfig {
+      enable: false,
+      validity: _default_ssh_client_certificate_validity(),
+    }
+  }
 }
 
 impl Default for SshConfig {
@@ -192,6 +363,8 @@ impl Default for SshConfig {
       external_port: None,
       inactivity_timeout: _default_ssh_inactivity_timeout(),
       keepalive_interval: None,
+      user_ca_keys: vec![],
+      client_certificates: Default::default(),
     }
   }
 }
@@ -219,6 +392,13 @@ pub struct HttpConfig {
   #[serde(default)]
   pub key: String,
 
+  /// Certificates picked by the SNI hostname, `certificate` and `key` are used for all other hosts
+  #[serde(default)]
+  pub sni_certificates: Vec<SniCertificateConfig>,
+
+  #[serde(default)]
+  pub acme: AcmeConfig,
+
   #[serde(default)]
   pub trust_x_forwarded_headers: bool,
 
@@ -229,6 +409,73 @@ pub struct HttpConfig {
   pub cookie_max_age: Duration,
 }
 
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct SniCertificateConfig {
+  /// Hostnames to present this certificate for, e.g. the `external_host` of HTTP targets
+  pub hosts: Vec<String>,
+  pub certificate: String,
+  pub key: String,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AcmeChallengeType {
+  #[serde(rename = "http-01")]
+  #[default]
+  Http01,
+  #[serde(rename = "tls-alpn-01")]
+  TlsAlpn01,
+}
+
+/// Automatic certificate issuance for the HTTP listener.
+///
+/// To test against a local ACME server such as pebble, point `directory_url` at it,
+/// trust its CA through `SSL_CERT_FILE` and move `http_challenge_listen` to its validation port.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct AcmeConfig {
+  #[serde(default = "_default_false")]
+  pub enable: bool,
+
+  #[serde(default = "_default_acme_directory_url")]
+  pub directory_url: String,
+
+  /// Contact URLs for the ACME account, e.g. `mailto:admin@example.com`
+  #[serde(default)]
+  pub contacts: Vec<String>,
+
+  #[serde(default)]
+  pub challenge: AcmeChallengeType,
+
+  /// Plain HTTP listener answering HTTP-01 challenges
+  #[serde(default = "_default_acme_http_challenge_listen")]
+  pub http_challenge_listen: ListenEndpoint,
+
+  /// Names to request in addition to `external_host` and the `external_host` of HTTP targets
+  #[serde(default)]
+  pub domains: Vec<String>,
+
+  /// Directory issued certificates are stored in
+  #[serde(default = "_default_acme_path")]
+  pub path: String,
+
+  #[serde(default = "_default_acme_renew_before", with = "humantime_serde")]
+  pub renew_before: Duration,
+}
+
+impl Default for AcmeConfig {
+  fn default() -> Self {
+    AcmeConfig {
+      enable: false,
+      directory_url: _default_acme_directory_url(),
+      contacts: vec![],
+      challenge: AcmeChallengeType::default(),
+      http_challenge_listen: _default_acme_http_challenge_listen(),
+      domains: vec![],
+      path: _default_acme_path(),
+      renew_before: _default_acme_renew_before(),
+    }
+  }
+}
+
 impl Default for HttpConfig {
   fn default() -> Self {
     HttpConfig {
@@ -237,6 +484,8 @@ impl Default for HttpConfig {
       external_port: None,
       certificate: "".to_owned(),
       key: "".to_owned(),
+      sni_certificates: vec![],
+      acme: AcmeConfig::default(),
       trust_x_forwarded_headers: false,
       session_max_age: _default_session_max_age(),
       cookie_max_age: _default_cookie_max_age(),
@@ -322,6 +571,24 @@ impl PostgresConfig {
   }
 }
 
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct RecordingsConfig {
+  #[serde(default = "_default_false")]
+  pub enable: bool,
+
+  #[serde(default = "_default_recordings_path")]
+  pub path: String,
+}
+
+impl Default for RecordingsConfig {
+  fn default() -> Self {
+    Self {
+      enable: false,
+      path: _default_recordings_path(),
+    }
+  }
+}
+
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct LogConfig {
   #[serde(default = "_default_retention", with = "humantime_serde")]
@@ -340,11 +607,54 @@ impl Default for LogConfig {
   }
 }
 
+/// Lockout of usernames and source addresses after repeated failed logins
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct LoginProtectionConfig {
+  #[serde(default = "_default_true")]
+  pub enable: bool,
+
+  /// Failed attempts allowed before the first lockout
+  #[serde(default = "_default_login_protection_max_attempts")]
+  pub max_attempts: u32,
+
+  /// Length of the first lockout, doubled with every further failure
+  #[serde(default = "_default_login_protection_lockout", with = "humantime_serde")]
+  pub lockout: Duration,
+
+  #[serde(default = "_default_login_protection_max_lockout", with = "humantime_serde")]
+  pub max_lockout: Duration,
+
+  /// Failures are forgotten after this long without a new one
+  #[serde(default = "_default_login_protection_reset_after", with = "humantime_serde")]
+  pub reset_after: Duration,
+}
+
+impl Default for LoginProtectionConfig {
+  fn default() -> Self {
+    Self {
+      enable: true,
+      max_attempts: _default_login_protection_max_attempts(),
+      lockout: _default_login_protection_lockout(),
+      max_lockout: _default_login_protection_max_lockout(),
+      reset_after: _default_login_protection_reset_after(),
+    }
+  }
+}
+
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct OmnitronConfigStore {
   #[serde(default)]
   pub external_host: Option<String>,
 
+  #[serde(default)]
+  pub recordings: RecordingsConfig,
+
+  #[serde(default)]
+  pub sso_providers: Vec<SsoProviderConfig>,
+
+  #[serde(default)]
+  pub ldap: LdapConfig,
+
   #[serde(default = "_default_database_url")]
   pub database_url: Secret<String>,
 
@@ -362,18 +672,25 @@ pub struct OmnitronConfigStore {
 
   #[serde(default)]
   pub log: LogConfig,
+
+  #[serde(default)]
+  pub login_protection: LoginProtectionConfig,
 }
 
 impl Default for OmnitronConfigStore {
   fn default() -> Self {
     Self {
       external_host: None,
+      recordings: <_>::default(),
+      sso_providers: vec![],
+      ldap: <_>::default(),
       database_url: _default_database_url(),
       ssh: <_>::default(),
       http: <_>::default(),
       mysql: <_>::default(),
       postgres: <_>::default(),
       log: <_>::default(),
+      login_protection: <_>::default(),
     }
   }
 }
@@ -477,3 +794,102 @@ impl OmnitronConfig {
     }
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn policy(allow: &[&str], deny: &[&str]) -> IpPolicy {
+    IpPolicy {
+      allow: allow.iter().map(|x| x.to_string()).collect(),
+      deny: deny.iter().map(|x| x.to_string()).collect(),
+    }
+  }
+
+  fn ip(address: &str) -> IpAddr {
+    address.parse().unwrap()
+  }
+
+  #[test]
+  fn test_ip_policy() {
+    assert!(IpPolicy::default().check(ip("203.0.113.7"), "user alice").is_ok());
+
+    let vpn = policy(&["10.8.0.0/16", "192.0.2.1"], &["10.8.13.0/24"]);
+    assert!(vpn.check(ip("10.8.1.2"), "role contractors").is_ok());
+    assert!(vpn.check(ip("192.0.2.1"), "role contractors").is_ok());
+    // Dual-stack listeners see IPv4 clients as mapped addresses
+    assert!(vpn.check(ip("::ffff:10.8.1.2"), "role contractors").is_ok());
+
+    assert_eq!(
+      vpn.check(ip("10.8.13.5"), "role contractors"),
+      Err(IpPolicyDenial::Denied {
+        address: ip("10.8.13.5"),
+        rule: "10.8.13.0/24".into(),
+        owner: "role contractors".into(),
+      })
+    );
+    assert_eq!(
+      vpn.check(ip("192.0.2.2"), "role contractors"),
+      Err(IpPolicyDenial::NotAllowed {
+        address: ip("192.0.2.2"),
+        allowed: vpn.allow.clone(),
+        owner: "role contractors".into(),
+      })
+    );
+
+    let ipv6 = policy(&[], &["2001:db8::/32"]);
+    assert!(ipv6.check(ip("2001:db8::1"), "target db").is_err());
+    assert!(ipv6.check(ip("2001:db9::1"), "target db").is_ok());
+  }
+
+  #[test]
+  fn test_invalid_ip_policy_rules() {
+    let invalid_deny = policy(&[], &["10.0.0.0/33"]);
+    assert_eq!(invalid_deny.validate(), Err("10.0.0.0/33".into()));
+    assert!(matches!(
+      invalid_deny.check(ip("192.0.2.1"), "target db"),
+      Err(IpPolicyDenial::Denied { .. })
+    ));
+
+    let invalid_allow = policy(&["vpn"], &[]);
+    assert_eq!(invalid_allow.validate(), Err("vpn".into()));
+    assert!(invalid_allow.check(ip("10.0.0.1"), "target db").is_err());
+
+    assert_eq!(policy(&[" 10.0.0.0/8 ", "::1"], &[]).validate(), Ok(()));
+  }
+
+  fn limits(max_sessions_per_user: Option<u32>, requests_per_second: Option<u32>) -> SessionLimits {
+    SessionLimits {
+      max_sessions_per_user,
+      requests_per_second,
+    }
+  }
+
+  #[test]
+  fn test_session_limits_most_permissive() {
+    assert_eq!(SessionLimits::most_permissive([]), limits(None, None));
+    assert_eq!(
+      SessionLimits::most_permissive([&limits(Some(2), Some(5))]),
+      limits(Some(2), Some(5))
+    );
+    assert_eq!(
+      SessionLimits::most_permissive([&limits(Some(2), Some(5)), &limits(Some(3), Some(1))]),
+      limits(Some(3), Some(5))
+    );
+    // A role without a limit lifts it
+    assert_eq!(
+      SessionLimits::most_permissive([&limits(Some(2), Some(5)), &limits(None, Some(1))]),
+      limits(None, Some(5))
+    );
+  }
+
+  #[test]
+  fn test_session_limits_restrict() {
+    assert_eq!(
+      limits(Some(2), None).restrict(&limits(Some(3), Some(5))),
+      limits(Some(2), Some(5))
+    );
+    assert_eq!(limits(None, Some(1)).restrict(&limits(None, Some(5))), limits(None, Some(1)));
+    assert_eq!(limits(None, None).restrict(&limits(None, None)), limits(None, None));
+  }
+}
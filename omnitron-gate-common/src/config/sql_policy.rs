@@ -0,0 +1,55 @@
+use poem_openapi::Object;
+use regex::RegexBuilder;
+use serde::{Deserialize, Serialize};
+
+/// Restricts which SQL statements may be sent to a database target. The default policy allows everything.
+/// Any restriction also refuses `PREPARE`, `EXECUTE`, `DO` and `CALL`, since the SQL they run can't be checked.
+#[derive(Debug, Deserialize, Serialize, Clone, Default, PartialEq, Eq, Hash, Object)]
+pub struct SqlPolicy {
+  /// Only allow statements that don't modify data or schema. `SET` is limited to session variables.
+  #[serde(default)]
+  pub read_only: bool,
+  /// Statement types (leading keywords like `DROP`, `TRUNCATE` or `GRANT`) that are always refused
+  #[serde(default, skip_serializing_if = "Vec::is_empty")]
+  pub deny_statements: Vec<String>,
+  /// Case-insensitive regular expressions, statements matching any of them are refused.
+  /// If one of them is invalid, all statements are refused.
+  #[serde(default, skip_serializing_if = "Vec::is_empty")]
+  pub deny_patterns: Vec<String>,
+  /// Refuse `UPDATE` and `DELETE` statements without a `WHERE` clause
+  #[serde(default)]
+  pub deny_unfiltered_writes: bool,
+}
+
+/// Why a [SqlPolicy] refused a statement
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum SqlPolicyDenial {
+  #[error("{statement} statements are not allowed by read-only {owner}")]
+  ReadOnly { statement: String, owner: String },
+  #[error("{statement} statements are denied by {owner}")]
+  Statement { statement: String, owner: String },
+  #[error("statement matches deny pattern {pattern} of {owner}")]
+  Pattern { pattern: String, owner: String },
+  #[error("{statement} without a WHERE clause is denied by {owner}")]
+  Unfiltered { statement: String, owner: String },
+  #[error("{statement} statements run SQL that can't be checked and are denied by {owner}")]
+  Dynamic { statement: String, owner: String },
+  #[error("deny pattern {pattern} of {owner} is invalid, so all statements are denied")]
+  InvalidPattern { pattern: String, owner: String },
+}
+
+impl SqlPolicy {
+  pub fn is_empty(&self) -> bool {
+    !self.read_only && self.deny_statements.is_empty() && self.deny_patterns.is_empty() && !self.deny_unfiltered_writes
+  }
+
+  /// Returns a description of the first pattern that isn't a valid regular expression
+  pub fn validate(&self) -> Result<(), String> {
+    for pattern in &self.deny_patterns {
+      if let Err(error) = RegexBuilder::new(pattern).case_insensitive(true).build() {
+        return Err(format!("{pattern}: {error}"));
+      }
+    }
+    Ok(())
+  }
+}
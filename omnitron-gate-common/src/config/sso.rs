@@ -0,0 +1,42 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use super::defaults::*;
+use crate::Secret;
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct SsoProviderConfig {
+  pub name: String,
+
+  #[serde(default)]
+  pub label: Option<String>,
+
+  pub issuer_url: String,
+
+  pub client_id: String,
+
+  pub client_secret: Secret<String>,
+
+  #[serde(default = "_default_sso_scopes")]
+  pub scopes: Vec<String>,
+
+  /// ID token claim that is matched against the users' SSO credentials.
+  /// Logins are refused if the token has `email_verified` set to false.
+  #[serde(default = "_default_sso_email_claim")]
+  pub email_claim: String,
+
+  /// ID token claim containing the user's groups, used for role mapping
+  #[serde(default)]
+  pub role_claim: Option<String>,
+
+  /// Maps values of `role_claim` to Omnitron role names
+  #[serde(default)]
+  pub role_mappings: HashMap<String, String>,
+}
+
+impl SsoProviderConfig {
+  pub fn label(&self) -> &str {
+    self.label.as_deref().unwrap_or(&self.name)
+  }
+}
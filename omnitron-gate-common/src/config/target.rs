@@ -5,6 +5,7 @@ use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
 use super::defaults::*;
+use super::{IpPolicy, MaskingRule, SessionLimits, SqlPolicy};
 use crate::Secret;
 
 #[derive(Debug, Deserialize, Serialize, Clone, Object)]
@@ -16,10 +17,86 @@ pub struct TargetSSHOptions {
   pub username: String,
   #[serde(default)]
   pub allow_insecure_algos: Option<bool>,
+  /// Lets users forward their SSH agent to this target
+  #[serde(default)]
+  pub allow_agent_forwarding: Option<bool>,
+  #[serde(default)]
+  pub file_transfer: SshFileTransferPolicy,
+  #[serde(default)]
+  pub channel_policy: SshChannelPolicy,
   #[serde(default)]
   pub auth: SSHTargetAuth,
 }
 
+/// Restrictions on SFTP and SCP transfers to and from an SSH target
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq, Object, Default)]
+pub struct SshFileTransferPolicy {
+  /// Reject everything that would modify files on the target
+  #[serde(default)]
+  pub read_only: bool,
+  /// Reject uploads, but still allow renaming and removing files
+  #[serde(default)]
+  pub disable_uploads: bool,
+  /// Maximum number of bytes that can be downloaded from a single file.
+  /// SCP downloads can't be capped and are rejected while this is set.
+  #[serde(default)]
+  pub max_download_size: Option<u64>,
+}
+
+/// Restricts which SSH channels and commands can be used on a target
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq, Object, Default)]
+pub struct SshChannelPolicy {
+  /// Channel and request types users may open, all of them if not set
+  #[serde(default)]
+  pub allowed_channels: Option<Vec<SshChannelType>>,
+  /// Regexes an exec command has to fully match, any command is allowed if not set
+  #[serde(default)]
+  pub exec_allowlist: Option<Vec<String>>,
+  /// Regexes of exec commands that are always rejected
+  #[serde(default)]
+  pub exec_denylist: Vec<String>,
+  /// `host:port` destinations direct TCP/IP channels may connect to, where `*`
+  /// matches any host or port and `*.example.com` any subdomain.
+  /// Any destination is allowed if not set.
+  #[serde(default)]
+  pub allowed_forward_destinations: Option<Vec<String>>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Enum)]
+pub enum SshChannelType {
+  #[serde(rename = "shell")]
+  Shell,
+  #[serde(rename = "exec")]
+  Exec,
+  #[serde(rename = "subsystem")]
+  Subsystem,
+  #[serde(rename = "direct-tcpip")]
+  DirectTcpip,
+  #[serde(rename = "tcpip-forward")]
+  TcpipForward,
+  #[serde(rename = "x11")]
+  X11,
+}
+
+impl std::fmt::Display for SshChannelType {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      Self::Shell => write!(f, "shell"),
+      Self::Exec => write!(f, "exec"),
+      Self::Subsystem => write!(f, "subsystem"),
+      Self::DirectTcpip => write!(f, "direct-tcpip"),
+      Self::TcpipForward => write!(f, "tcpip-forward"),
+      Self::X11 => write!(f, "x11"),
+    }
+  }
+}
+
+impl SshFileTransferPolicy {
+  pub fn allows_uploads(&self) -> bool {
+    !self.read_only && !self.disable_uploads
+  }
+}
+
 #[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq, Union)]
 #[serde(untagged)]
 #[oai(discriminator_name = "kind", one_of)]
@@ -36,7 +113,12 @@ pub struct SshTargetPasswordAuth {
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq, Object, Default)]
-pub struct SshTargetPublicKeyAuth {}
+pub struct SshTargetPublicKeyAuth {
+  /// Offer a certificate signed by the client CA (`ssh.client_certificates`) before the plain keys.
+  /// Only enable this for targets that trust the CA, as a rejected certificate uses up an auth attempt.
+  #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+  pub use_certificate: bool,
+}
 
 impl Default for SSHTargetAuth {
   fn default() -> Self {
@@ -57,6 +139,118 @@ pub struct TargetHTTPOptions {
 
   #[serde(default)]
   pub external_host: Option<String>,
+
+  #[serde(default)]
+  pub capture: HttpCaptureOptions,
+
+  /// Checked in order, requests that match none of them go to `url`
+  #[serde(default)]
+  pub routes: Vec<HttpRoute>,
+
+  /// Further replicas of `url`, requests are balanced across all of them
+  #[serde(default)]
+  pub upstreams: Vec<String>,
+
+  #[serde(default)]
+  pub load_balancing: HttpLoadBalancing,
+
+  #[serde(default)]
+  pub health_check: HttpHealthCheck,
+}
+
+impl TargetHTTPOptions {
+  /// `url` followed by the additional upstreams
+  pub fn upstream_urls(&self) -> Vec<String> {
+    std::iter::once(&self.url).chain(self.upstreams.iter()).cloned().collect()
+  }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Enum, PartialEq, Eq, Default)]
+pub enum HttpLoadBalancing {
+  #[serde(rename = "round-robin")]
+  #[default]
+  RoundRobin,
+  #[serde(rename = "least-connections")]
+  LeastConnections,
+  /// Keeps each session on the same upstream while it's up
+  #[serde(rename = "sticky")]
+  Sticky,
+}
+
+/// Periodically requests a path on each upstream and stops sending
+/// requests to upstreams that fail or don't respond in time
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq, Object)]
+pub struct HttpHealthCheck {
+  /// Health checks are disabled if not set
+  #[serde(default)]
+  pub path: Option<String>,
+  /// Seconds between checks
+  #[serde(default = "_default_http_health_check_interval")]
+  pub interval: u64,
+  /// Seconds to wait for a response
+  #[serde(default = "_default_http_health_check_timeout")]
+  pub timeout: u64,
+}
+
+impl Default for HttpHealthCheck {
+  fn default() -> Self {
+    Self {
+      path: None,
+      interval: _default_http_health_check_interval(),
+      timeout: _default_http_health_check_timeout(),
+    }
+  }
+}
+
+/// Sends a part of the target's requests to a different upstream
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq, Object)]
+pub struct HttpRoute {
+  /// Matched against whole path segments, so `/api` matches `/api/users` but not `/apis`
+  pub path_prefix: String,
+  #[serde(default)]
+  pub header: Option<HttpRouteHeaderMatch>,
+  /// Requests are sent to this URL with its own scheme, the target's `tls.mode` doesn't apply
+  pub url: String,
+  /// Replaces the matched prefix in the forwarded path, an empty string strips it
+  #[serde(default)]
+  pub rewrite_prefix: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq, Object)]
+pub struct HttpRouteHeaderMatch {
+  pub name: String,
+  /// Any value matches if not set
+  #[serde(default)]
+  pub value: Option<String>,
+}
+
+/// Records the requests and responses passing through an HTTP target
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq, Object)]
+pub struct HttpCaptureOptions {
+  #[serde(default)]
+  pub enable: bool,
+  /// Request and response bodies are captured up to this many bytes, and not at all if not set.
+  /// The same goes for the payloads of captured websocket frames.
+  #[serde(default)]
+  pub max_body_size: Option<u64>,
+  #[serde(default)]
+  pub websocket_frames: bool,
+  /// Names of headers, cookies, query parameters, form fields and JSON fields
+  /// whose values are replaced in the capture (case-insensitive).
+  /// `Cookie`, `Set-Cookie` and authorization headers are always redacted.
+  #[serde(default = "_default_http_capture_redact")]
+  pub redact: Vec<String>,
+}
+
+impl Default for HttpCaptureOptions {
+  fn default() -> Self {
+    Self {
+      enable: false,
+      max_body_size: None,
+      websocket_frames: false,
+      redact: _default_http_capture_redact(),
+    }
+  }
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone, Enum, PartialEq, Eq, Default)]
@@ -105,6 +299,11 @@ pub struct TargetMySqlOptions {
 
   #[serde(default)]
   pub tls: Tls,
+
+  /// PEM RSA public key of the target, which the password is encrypted with when
+  /// connecting without TLS. Keys sent by the target itself are never used.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub server_public_key: Option<String>,
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone, Object)]
@@ -135,6 +334,15 @@ pub struct Target {
   pub name: String,
   #[serde(default = "_default_empty_vec")]
   pub allow_roles: Vec<String>,
+  #[serde(default)]
+  pub limits: SessionLimits,
+  #[serde(default)]
+  pub ip_policy: IpPolicy,
+  #[serde(default)]
+  pub sql_policy: SqlPolicy,
+  /// Applied to the result sets of database targets
+  #[serde(default, skip_serializing_if = "Vec::is_empty")]
+  pub masking: Vec<MaskingRule>,
   #[serde(flatten)]
   pub options: TargetOptions,
 }
@@ -36,6 +36,12 @@ pub enum OmnitronError {
   RusshKeys(#[from] russh::keys::Error),
   #[error("I/O: {0}")]
   Io(#[from] std::io::Error),
+  #[error("you already have {limit} session(s) open to {target}, which is the most allowed")]
+  SessionLimitReached { target: String, limit: u32 },
+  #[error("too many requests to {0}")]
+  RateLimited(String),
+  #[error("invalid IP policy rule {rule} in {owner}")]
+  InvalidIpPolicy { rule: String, owner: String },
 
   #[error("Session end")]
   SessionEnd,
@@ -43,7 +49,10 @@ pub enum OmnitronError {
 
 impl ResponseError for OmnitronError {
   fn status(&self) -> poem::http::StatusCode {
-    poem::http::StatusCode::INTERNAL_SERVER_ERROR
+    match self {
+      Self::SessionLimitReached { .. } | Self::RateLimited(_) => poem::http::StatusCode::TOO_MANY_REQUESTS,
+      _ => poem::http::StatusCode::INTERNAL_SERVER_ERROR,
+    }
   }
 }
 
@@ -1,3 +1,6 @@
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
 use std::os::unix::prelude::PermissionsExt;
 use std::path::Path;
 
@@ -16,3 +19,36 @@ pub fn secure_directory<P: AsRef<Path>>(path: P) -> std::io::Result<()> {
 pub fn secure_file<P: AsRef<Path>>(path: P) -> std::io::Result<()> {
   maybe_apply_permissions(path.as_ref(), std::fs::Permissions::from_mode(0o600))
 }
+
+/// Replaces the file with one that is only ever readable by the owner, even if interrupted
+pub fn write_secure_file<P: AsRef<Path>>(path: P, contents: &[u8]) -> std::io::Result<()> {
+  let path = path.as_ref();
+  let mut temp_path = path.as_os_str().to_owned();
+  temp_path.push(".tmp");
+  // Left behind by an interrupted write
+  let _ = std::fs::remove_file(&temp_path);
+  let mut file = OpenOptions::new().write(true).create_new(true).mode(0o600).open(&temp_path)?;
+  file.write_all(contents)?;
+  file.sync_all()?;
+  std::fs::rename(&temp_path, path)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_write_secure_file() {
+    let dir = std::env::temp_dir().join(format!("omnitron-fs-{}", uuid::Uuid::new_v4()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("key.pem");
+    std::fs::write(&path, "old").unwrap();
+    std::fs::write(dir.join("key.pem.tmp"), "stale").unwrap();
+
+    write_secure_file(&path, b"new").unwrap();
+    assert_eq!(std::fs::read(&path).unwrap(), b"new");
+    assert_eq!(std::fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+    assert!(!dir.join("key.pem.tmp").exists());
+    std::fs::remove_dir_all(&dir).unwrap();
+  }
+}
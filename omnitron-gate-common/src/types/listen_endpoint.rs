@@ -11,7 +11,7 @@ use tokio_stream::wrappers::TcpListenerStream;
 
 use crate::OmnitronError;
 
-#[derive(Clone)]
+#[derive(Clone, PartialEq, Eq)]
 pub struct ListenEndpoint(SocketAddr);
 
 impl ListenEndpoint {
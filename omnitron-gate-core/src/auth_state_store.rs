@@ -8,7 +8,7 @@ use once_cell::sync::Lazy;
 use tokio::sync::{broadcast, Mutex};
 use uuid::Uuid;
 
-use crate::ConfigProvider;
+use crate::ConfigProviderArc;
 
 #[allow(clippy::unwrap_used)]
 pub static TIMEOUT: Lazy<Duration> = Lazy::new(|| Duration::from_secs(60 * 10));
@@ -25,13 +25,13 @@ impl AuthCompletionSignal {
 }
 
 pub struct AuthStateStore {
-  config_provider: Arc<Mutex<dyn ConfigProvider + Send + 'static>>,
+  config_provider: ConfigProviderArc,
   store: HashMap<Uuid, (Arc<Mutex<AuthState>>, Instant)>,
   completion_signals: HashMap<Uuid, AuthCompletionSignal>,
 }
 
 impl AuthStateStore {
-  pub fn new(config_provider: Arc<Mutex<dyn ConfigProvider + Send + 'static>>) -> Self {
+  pub fn new(config_provider: ConfigProviderArc) -> Self {
     Self {
       store: HashMap::new(),
       config_provider,
@@ -1,4 +1,5 @@
 use std::collections::{HashMap, HashSet};
+use std::net::IpAddr;
 use std::sync::Arc;
 
 use async_trait::async_trait;
@@ -11,21 +12,35 @@ use omnitron_gate_common::auth::{
 use omnitron_gate_common::helpers::hash::verify_password_hash;
 use omnitron_gate_common::helpers::otp::verify_totp;
 use omnitron_gate_common::{
-  OmnitronError, Role, Target, User, UserAuthCredential, UserPasswordCredential, UserPublicKeyCredential, UserTotpCredential,
+  IpPolicy, IpPolicyDenial, OmnitronConfig, OmnitronError, SessionLimits, Target, User, UserAuthCredential,
+  UserPasswordCredential, UserPublicKeyCredential, UserSsoCredential, UserTotpCredential,
 };
+use sea_orm::sea_query::{Expr, Func};
 use sea_orm::{ActiveModelTrait, ColumnTrait, DatabaseConnection, EntityTrait, ModelTrait, QueryFilter, QueryOrder, Set};
 use tokio::sync::Mutex;
 use tracing::*;
 
-use super::ConfigProvider;
+use super::{
+  active_target_roles, active_user_roles, ip_policy_denial, session_limits_for_roles, sql_guard_for_roles, ConfigProvider,
+};
+use crate::SqlGuard;
 
 pub struct DatabaseConfigProvider {
   db: Arc<Mutex<DatabaseConnection>>,
+  config: Arc<Mutex<OmnitronConfig>>,
 }
 
 impl DatabaseConfigProvider {
-  pub async fn new(db: &Arc<Mutex<DatabaseConnection>>) -> Self {
-    Self { db: db.clone() }
+  pub async fn new(db: &Arc<Mutex<DatabaseConnection>>, config: &Arc<Mutex<OmnitronConfig>>) -> Self {
+    Self {
+      db: db.clone(),
+      config: config.clone(),
+    }
+  }
+
+  /// Whether SSH user certificates can stand in for a user's public keys
+  pub(crate) async fn accepts_user_certificates(&self) -> bool {
+    !self.config.lock().await.store.ssh.user_ca_keys.is_empty()
   }
 }
 
@@ -62,6 +77,7 @@ impl ConfigProvider for DatabaseConfigProvider {
     username: &str,
     supported_credential_types: &[CredentialKind],
   ) -> Result<Option<Box<dyn CredentialPolicy + Sync + Send>>, OmnitronError> {
+    let accepts_user_certificates = self.accepts_user_certificates().await;
     let db = self.db.lock().await;
 
     let user_model = entities::User::Entity::find()
@@ -76,10 +92,18 @@ impl ConfigProvider for DatabaseConfigProvider {
 
     let user = user_model.load_details(&db).await?;
 
-    let supported_credential_types: HashSet<CredentialKind> = user
-      .credentials
-      .iter()
-      .map(|x| x.kind())
+    let mut user_credential_types: HashSet<CredentialKind> = user.credentials.iter().map(|x| x.kind()).collect();
+    if user_credential_types.contains(&CredentialKind::Sso) {
+      // SSO users authenticate non-web protocols by approving the login in the browser
+      user_credential_types.insert(CredentialKind::WebUserApproval);
+    }
+    if accepts_user_certificates {
+      // Any user can present a certificate issued to them by a trusted user CA
+      user_credential_types.insert(CredentialKind::PublicKey);
+    }
+
+    let supported_credential_types: HashSet<CredentialKind> = user_credential_types
+      .into_iter()
       .filter(|x| supported_credential_types.contains(x))
       .collect();
     let default_policy = Box::new(AnySingleCredentialPolicy {
@@ -163,6 +187,9 @@ impl ConfigProvider for DatabaseConfigProvider {
           _ => false,
         }));
       }
+      AuthCredential::Certificate { principals, .. } => {
+        return Ok(principals.iter().any(|x| x == &user_details.username));
+      }
       AuthCredential::Password(client_password) => {
         return Ok(user_details.credentials.iter().any(|credential| match credential {
           UserAuthCredential::Password(UserPasswordCredential {
@@ -182,6 +209,17 @@ impl ConfigProvider for DatabaseConfigProvider {
           _ => false,
         }))
       }
+      AuthCredential::Sso {
+        provider: client_provider,
+        email: client_email,
+      } => {
+        return Ok(user_details.credentials.iter().any(|credential| match credential {
+          UserAuthCredential::Sso(UserSsoCredential { provider, email }) => {
+            provider.as_ref().map_or(true, |p| p == client_provider) && email.to_lowercase() == client_email.to_lowercase()
+          }
+          _ => false,
+        }))
+      }
       _ => return Err(OmnitronError::InvalidCredentialType),
     }
   }
@@ -209,29 +247,75 @@ impl ConfigProvider for DatabaseConfigProvider {
       return Ok(false);
     };
 
-    let target_roles: HashSet<String> = target_model
-      .find_related(entities::Role::Entity)
-      .all(&*db)
+    let target_roles: HashSet<String> = active_target_roles(&db, target_model.id)
       .await?
       .into_iter()
-      .map(Into::<Role>::into)
       .map(|x| x.name)
       .collect();
 
-    let user_roles: HashSet<String> = user_model
-      .find_related(entities::Role::Entity)
-      .all(&*db)
-      .await?
-      .into_iter()
-      .map(Into::<Role>::into)
-      .map(|x| x.name)
-      .collect();
+    let user_roles = active_user_roles(&db, user_model.id).await?;
 
     let intersect = user_roles.intersection(&target_roles).count() > 0;
 
     Ok(intersect)
   }
 
+  async fn get_session_limits(&mut self, username: &str, target_name: &str) -> Result<SessionLimits, OmnitronError> {
+    let db = self.db.lock().await;
+
+    let Some(user_model) = entities::User::Entity::find()
+      .filter(entities::User::Column::Username.eq(username))
+      .one(&*db)
+      .await?
+    else {
+      return Ok(SessionLimits::default());
+    };
+
+    let user_roles = active_user_roles(&db, user_model.id).await?;
+
+    session_limits_for_roles(&db, target_name, &user_roles).await
+  }
+
+  async fn get_sql_guard(&mut self, username: &str, target_name: &str) -> Result<SqlGuard, OmnitronError> {
+    let db = self.db.lock().await;
+
+    let user_roles = match entities::User::Entity::find()
+      .filter(entities::User::Column::Username.eq(username))
+      .one(&*db)
+      .await?
+    {
+      Some(user_model) => active_user_roles(&db, user_model.id).await?,
+      None => HashSet::new(),
+    };
+
+    sql_guard_for_roles(&db, target_name, &user_roles).await
+  }
+
+  async fn check_address(
+    &mut self,
+    username: &str,
+    target_name: Option<&str>,
+    address: IpAddr,
+  ) -> Result<Option<IpPolicyDenial>, OmnitronError> {
+    let db = self.db.lock().await;
+
+    let user_model = entities::User::Entity::find()
+      .filter(entities::User::Column::Username.eq(username))
+      .one(&*db)
+      .await?;
+
+    let (user_policy, user_roles) = match user_model {
+      Some(user_model) => {
+        let user_roles = active_user_roles(&db, user_model.id).await?;
+        let user: User = user_model.try_into()?;
+        (user.ip_policy, user_roles)
+      }
+      None => (IpPolicy::default(), HashSet::new()),
+    };
+
+    ip_policy_denial(&db, &user_policy, username, target_name, &user_roles, address).await
+  }
+
   async fn update_public_key_last_used(&self, credential: Option<AuthCredential>) -> Result<(), OmnitronError> {
     let db = self.db.lock().await;
 
@@ -289,4 +373,221 @@ impl ConfigProvider for DatabaseConfigProvider {
 
     Ok(Some(user.try_into()?))
   }
+
+  async fn username_for_sso_credential(&mut self, client_credential: &AuthCredential) -> Result<Option<String>, OmnitronError> {
+    let AuthCredential::Sso { provider, email } = client_credential else {
+      return Err(OmnitronError::InvalidCredentialType);
+    };
+
+    let db = self.db.lock().await;
+
+    // IdPs don't guarantee the case of email addresses
+    let credentials = entities::SsoCredential::Entity::find()
+      .filter(
+        Expr::expr(Func::lower(Expr::col(entities::SsoCredential::Column::Email)))
+          .eq(email.to_lowercase())
+          .and(
+            entities::SsoCredential::Column::Provider
+              .eq(provider)
+              .or(entities::SsoCredential::Column::Provider.is_null()),
+          ),
+      )
+      .all(&*db)
+      .await?;
+
+    // Credentials for this specific provider take precedence over catch-all ones
+    let specific = credentials.iter().any(|x| x.provider.is_some());
+    let user_ids: HashSet<_> = credentials
+      .iter()
+      .filter(|x| x.provider.is_some() == specific)
+      .map(|x| x.user_id)
+      .collect();
+
+    let user_id = match user_ids.len() {
+      0 => return Ok(None),
+      1 => user_ids.into_iter().next().ok_or(OmnitronError::InconsistentState)?,
+      _ => {
+        warn!(%email, %provider, "Multiple users have an SSO credential for this email, refusing to pick one");
+        return Ok(None);
+      }
+    };
+
+    let Some(user) = entities::User::Entity::find_by_id(user_id).one(&*db).await? else {
+      return Err(OmnitronError::InconsistentState);
+    };
+
+    Ok(Some(user.username))
+  }
+
+  async fn apply_sso_role_mappings(
+    &mut self,
+    username: &str,
+    managed_role_names: &[String],
+    assigned_role_names: &[String],
+  ) -> Result<(), OmnitronError> {
+    let db = self.db.lock().await;
+
+    let Some(user) = entities::User::Entity::find()
+      .filter(entities::User::Column::Username.eq(username))
+      .one(&*db)
+      .await?
+    else {
+      return Err(OmnitronError::UserNotFound(username.into()));
+    };
+
+    for role_name in managed_role_names {
+      let Some(role) = entities::Role::Entity::find()
+        .filter(entities::Role::Column::Name.eq(role_name))
+        .one(&*db)
+        .await?
+      else {
+        warn!("SSO role mapping refers to a nonexistent role: {}", role_name);
+        continue;
+      };
+
+      let assignment = entities::UserRoleAssignment::Entity::find()
+        .filter(entities::UserRoleAssignment::Column::UserId.eq(user.id))
+        .filter(entities::UserRoleAssignment::Column::RoleId.eq(role.id))
+        .one(&*db)
+        .await?;
+
+      match (assignment, assigned_role_names.contains(role_name)) {
+        (None, true) => {
+          info!(%username, role = %role_name, "Granting role from SSO claims");
+          entities::UserRoleAssignment::ActiveModel {
+            user_id: Set(user.id),
+            role_id: Set(role.id),
+            ..Default::default()
+          }
+          .insert(&*db)
+          .await?;
+        }
+        (Some(assignment), false) => {
+          info!(%username, role = %role_name, "Revoking role based on SSO claims");
+          assignment.delete(&*db).await?;
+        }
+        _ => (),
+      }
+    }
+
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::path::PathBuf;
+
+  use omnitron_gate_common::auth::CredentialPolicyResponse;
+  use sea_orm::Database;
+  use uuid::Uuid;
+
+  use super::*;
+
+  async fn make_provider(user_ca_keys: Vec<String>) -> DatabaseConfigProvider {
+    let db = Database::connect("sqlite::memory:").await.unwrap();
+    omnitron_db_migrations::migrate_database(&db).await.unwrap();
+
+    let user = entities::User::ActiveModel {
+      id: Set(Uuid::new_v4()),
+      username: Set("alice".into()),
+      credential_policy: Set(serde_json::Value::Null),
+      ip_policy: Set(None),
+    }
+    .insert(&db)
+    .await
+    .unwrap();
+    entities::PasswordCredential::ActiveModel {
+      id: Set(Uuid::new_v4()),
+      user_id: Set(user.id),
+      argon_hash: Set("unused".into()),
+    }
+    .insert(&db)
+    .await
+    .unwrap();
+
+    let mut config = OmnitronConfig {
+      store: Default::default(),
+      paths_relative_to: PathBuf::new(),
+    };
+    config.store.ssh.user_ca_keys = user_ca_keys;
+    DatabaseConfigProvider::new(&Arc::new(Mutex::new(db)), &Arc::new(Mutex::new(config))).await
+  }
+
+  async fn needed_credentials(provider: &mut DatabaseConfigProvider) -> HashSet<CredentialKind> {
+    let policy = provider
+      .get_credential_policy("alice", &[CredentialKind::Password, CredentialKind::PublicKey])
+      .await
+      .unwrap()
+      .unwrap();
+    match policy.is_sufficient("SSH", &[]) {
+      CredentialPolicyResponse::Need(kinds) => kinds,
+      CredentialPolicyResponse::Ok => panic!("No credentials needed"),
+    }
+  }
+
+  async fn add_sso_user(provider: &DatabaseConfigProvider, username: &str, sso_provider: Option<&str>, email: &str) {
+    let db = provider.db.lock().await;
+    let user = entities::User::ActiveModel {
+      id: Set(Uuid::new_v4()),
+      username: Set(username.into()),
+      credential_policy: Set(serde_json::Value::Null),
+      ip_policy: Set(None),
+    }
+    .insert(&*db)
+    .await
+    .unwrap();
+    entities::SsoCredential::ActiveModel {
+      id: Set(Uuid::new_v4()),
+      user_id: Set(user.id),
+      provider: Set(sso_provider.map(Into::into)),
+      email: Set(email.into()),
+    }
+    .insert(&*db)
+    .await
+    .unwrap();
+  }
+
+  async fn sso_username(provider: &mut DatabaseConfigProvider, email: &str) -> Option<String> {
+    let credential = AuthCredential::Sso {
+      provider: "corp".into(),
+      email: email.into(),
+    };
+    provider.username_for_sso_credential(&credential).await.unwrap()
+  }
+
+  #[tokio::test]
+  async fn test_username_for_sso_credential() {
+    let mut provider = make_provider(vec![]).await;
+    add_sso_user(&provider, "bob", None, "Bob@Example.com").await;
+    add_sso_user(&provider, "carol", Some("other"), "carol@example.com").await;
+    assert_eq!(sso_username(&mut provider, "bob@example.COM").await, Some("bob".into()));
+    assert_eq!(sso_username(&mut provider, "carol@example.com").await, None);
+
+    // Two catch-all credentials for one email are ambiguous
+    add_sso_user(&provider, "bob2", None, "bob@example.com").await;
+    assert_eq!(sso_username(&mut provider, "bob@example.com").await, None);
+
+    // ... unless one of the users has a credential for this provider
+    add_sso_user(&provider, "bob3", Some("corp"), "BOB@example.com").await;
+    assert_eq!(sso_username(&mut provider, "bob@example.com").await, Some("bob3".into()));
+  }
+
+  #[tokio::test]
+  async fn test_user_ca_credential_policy() {
+    let mut provider = make_provider(vec![]).await;
+    assert_eq!(
+      needed_credentials(&mut provider).await,
+      HashSet::from([CredentialKind::Password])
+    );
+
+    let mut provider = make_provider(vec![
+      "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIGf4bmwRrZWpR1lJm9QdW7cE3Un3R7vUPmQ0ob6GDr1y ca".into(),
+    ])
+    .await;
+    assert_eq!(
+      needed_credentials(&mut provider).await,
+      HashSet::from([CredentialKind::Password, CredentialKind::PublicKey])
+    );
+  }
 }
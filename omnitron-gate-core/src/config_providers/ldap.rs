@@ -0,0 +1,584 @@
+use std::collections::{HashMap, HashSet};
+use std::net::IpAddr;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use data_encoding::BASE64;
+use ldap3::{ldap_escape, LdapConnAsync, LdapConnSettings, Scope, SearchEntry};
+use omnitron_db_entities as entities;
+use omnitron_gate_common::auth::{AnySingleCredentialPolicy, AuthCredential, CredentialKind, CredentialPolicy};
+use omnitron_gate_common::{IpPolicy, IpPolicyDenial, LdapConfig, OmnitronConfig, OmnitronError, SessionLimits, Target, User};
+use sea_orm::{ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter};
+use tokio::sync::Mutex;
+use tracing::*;
+use uuid::Uuid;
+
+use super::{
+  active_target_roles, ip_policy_denial, session_limits_for_roles, sql_guard_for_roles, ConfigProvider, DatabaseConfigProvider,
+};
+use crate::SqlGuard;
+
+/// A user entry resolved from the directory
+#[derive(Debug, Clone)]
+pub struct LdapUser {
+  pub dn: String,
+  pub username: String,
+  /// DNs of the groups the user is a member of
+  pub groups: Vec<String>,
+  pub ssh_public_keys: Vec<String>,
+}
+
+/// Directory operations used by [LdapConfigProvider], so that it can be backed by a stub in tests
+#[async_trait]
+pub trait LdapDirectory {
+  async fn find_user(&self, username: &str) -> Result<Option<LdapUser>, OmnitronError>;
+
+  async fn list_users(&self) -> Result<Vec<LdapUser>, OmnitronError>;
+
+  async fn verify_password(&self, dn: &str, password: &str) -> Result<bool, OmnitronError>;
+}
+
+pub struct Ldap3Directory {
+  config: LdapConfig,
+}
+
+impl Ldap3Directory {
+  pub fn new(config: LdapConfig) -> Self {
+    Self { config }
+  }
+
+  async fn connect(&self) -> Result<ldap3::Ldap, OmnitronError> {
+    let settings = LdapConnSettings::new()
+      .set_conn_timeout(self.config.timeout)
+      .set_starttls(self.config.starttls)
+      .set_no_tls_verify(self.config.no_tls_verify);
+    let (conn, ldap) = LdapConnAsync::with_settings(settings, &self.config.url)
+      .await
+      .map_err(OmnitronError::other)?;
+    ldap3::drive!(conn);
+    Ok(ldap)
+  }
+
+  async fn search(&self, filter: &str) -> Result<Vec<LdapUser>, OmnitronError> {
+    let mut ldap = self.connect().await?;
+
+    if let Some(bind_dn) = &self.config.bind_dn {
+      let password = self
+        .config
+        .bind_password
+        .as_ref()
+        .map(|x| x.expose_secret().as_str())
+        .unwrap_or("");
+      ldap
+        .simple_bind(bind_dn, password)
+        .await
+        .and_then(|r| r.success())
+        .map_err(OmnitronError::other)?;
+    }
+
+    let mut attributes = vec![self.config.username_attribute.as_str(), self.config.group_attribute.as_str()];
+    if let Some(attribute) = &self.config.ssh_public_key_attribute {
+      attributes.push(attribute);
+    }
+
+    let (entries, _) = ldap
+      .search(&self.config.user_base_dn, Scope::Subtree, filter, attributes)
+      .await
+      .and_then(|r| r.success())
+      .map_err(OmnitronError::other)?;
+    let _ = ldap.unbind().await;
+
+    Ok(
+      entries
+        .into_iter()
+        .map(SearchEntry::construct)
+        .filter_map(|entry| {
+          let attr = |name: &str| entry.attrs.get(name).cloned().unwrap_or_default();
+          let username = attr(&self.config.username_attribute).into_iter().next()?;
+          Some(LdapUser {
+            username,
+            groups: attr(&self.config.group_attribute),
+            ssh_public_keys: self.config.ssh_public_key_attribute.as_deref().map(attr).unwrap_or_default(),
+            dn: entry.dn,
+          })
+        })
+        .collect(),
+    )
+  }
+}
+
+#[async_trait]
+impl LdapDirectory for Ldap3Directory {
+  async fn find_user(&self, username: &str) -> Result<Option<LdapUser>, OmnitronError> {
+    let filter = self.config.user_filter.replace("{username}", &ldap_escape(username));
+    let mut users = self.search(&filter).await?;
+    if users.len() > 1 {
+      warn!(%username, "Multiple LDAP entries match the username, using the first one");
+    }
+    Ok(if users.is_empty() { None } else { Some(users.remove(0)) })
+  }
+
+  async fn list_users(&self) -> Result<Vec<LdapUser>, OmnitronError> {
+    let filter = self.config.user_filter.replace("{username}", "*");
+    self.search(&filter).await
+  }
+
+  async fn verify_password(&self, dn: &str, password: &str) -> Result<bool, OmnitronError> {
+    // An empty password would result in an unauthenticated bind that always succeeds
+    if password.is_empty() {
+      return Ok(false);
+    }
+    let mut ldap = self.connect().await?;
+    let result = ldap.simple_bind(dn, password).await.map_err(OmnitronError::other)?;
+    let _ = ldap.unbind().await;
+    Ok(result.success().is_ok())
+  }
+}
+
+/// Resolves users from an LDAP directory. Users that exist in the database take precedence
+/// and are handled entirely by the [DatabaseConfigProvider].
+pub struct LdapConfigProvider {
+  db: Arc<Mutex<DatabaseConnection>>,
+  inner: DatabaseConfigProvider,
+  directory: Box<dyn LdapDirectory + Send + Sync>,
+  group_role_mappings: HashMap<String, String>,
+}
+
+impl LdapConfigProvider {
+  pub async fn new(db: &Arc<Mutex<DatabaseConnection>>, config: &Arc<Mutex<OmnitronConfig>>, ldap_config: LdapConfig) -> Self {
+    let group_role_mappings = ldap_config.group_role_mappings.clone();
+    Self::with_directory(db, config, Box::new(Ldap3Directory::new(ldap_config)), group_role_mappings).await
+  }
+
+  pub async fn with_directory(
+    db: &Arc<Mutex<DatabaseConnection>>,
+    config: &Arc<Mutex<OmnitronConfig>>,
+    directory: Box<dyn LdapDirectory + Send + Sync>,
+    group_role_mappings: HashMap<String, String>,
+  ) -> Self {
+    Self {
+      db: db.clone(),
+      inner: DatabaseConfigProvider::new(db, config).await,
+      directory,
+      group_role_mappings,
+    }
+  }
+
+  async fn is_local_user(&self, username: &str) -> Result<bool, OmnitronError> {
+    let db = self.db.lock().await;
+    Ok(
+      entities::User::Entity::find()
+        .filter(entities::User::Column::Username.eq(username))
+        .one(&*db)
+        .await?
+        .is_some(),
+    )
+  }
+
+  /// Returns the directory entry for users that are not managed in the database
+  async fn find_ldap_user(&self, username: &str) -> Result<Option<LdapUser>, OmnitronError> {
+    if self.is_local_user(username).await? {
+      return Ok(None);
+    }
+    self.directory.find_user(username).await
+  }
+
+  fn roles_for(&self, user: &LdapUser) -> HashSet<String> {
+    user
+      .groups
+      .iter()
+      .filter_map(|group| {
+        let cn = group_cn(group);
+        if self.group_role_mappings.is_empty() {
+          return cn.map(str::to_owned);
+        }
+        self
+          .group_role_mappings
+          .get(group)
+          .or_else(|| cn.and_then(|cn| self.group_role_mappings.get(cn)))
+          .cloned()
+      })
+      .collect()
+  }
+}
+
+/// Extracts the value of the leading `cn=` RDN of a DN
+fn group_cn(dn: &str) -> Option<&str> {
+  let rdn = dn.split(',').next()?.trim();
+  let (attribute, value) = rdn.split_once('=')?;
+  attribute.trim().eq_ignore_ascii_case("cn").then_some(value.trim())
+}
+
+/// Compares the key type and data of an OpenSSH public key line, ignoring the comment
+fn openssh_key_matches(key: &str, client_key: &str) -> bool {
+  let mut key = key.split_whitespace();
+  let mut client_key = client_key.split_whitespace();
+  key.next() == client_key.next() && key.next() == client_key.next()
+}
+
+#[async_trait]
+impl ConfigProvider for LdapConfigProvider {
+  async fn list_users(&mut self) -> Result<Vec<User>, OmnitronError> {
+    let mut users = self.inner.list_users().await?;
+    let local_usernames: HashSet<String> = users.iter().map(|x| x.username.clone()).collect();
+
+    for ldap_user in self.directory.list_users().await? {
+      if local_usernames.contains(&ldap_user.username) {
+        continue;
+      }
+      users.push(User {
+        id: Uuid::new_v5(&Uuid::NAMESPACE_OID, ldap_user.dn.as_bytes()),
+        username: ldap_user.username,
+        credential_policy: None,
+        ip_policy: Default::default(),
+      });
+    }
+
+    Ok(users)
+  }
+
+  async fn list_targets(&mut self) -> Result<Vec<Target>, OmnitronError> {
+    self.inner.list_targets().await
+  }
+
+  async fn get_credential_policy(
+    &mut self,
+    username: &str,
+    supported_credential_types: &[CredentialKind],
+  ) -> Result<Option<Box<dyn CredentialPolicy + Sync + Send>>, OmnitronError> {
+    let Some(ldap_user) = self.find_ldap_user(username).await? else {
+      return self.inner.get_credential_policy(username, supported_credential_types).await;
+    };
+
+    // Public keys come either from the directory or from a trusted user CA certificate
+    let mut user_credential_types = HashSet::from([CredentialKind::Password]);
+    if !ldap_user.ssh_public_keys.is_empty() || self.inner.accepts_user_certificates().await {
+      user_credential_types.insert(CredentialKind::PublicKey);
+    }
+
+    Ok(Some(Box::new(AnySingleCredentialPolicy {
+      supported_credential_types: user_credential_types
+        .into_iter()
+        .filter(|x| supported_credential_types.contains(x))
+        .collect(),
+    })))
+  }
+
+  async fn validate_credential(&mut self, username: &str, client_credential: &AuthCredential) -> Result<bool, OmnitronError> {
+    let Some(ldap_user) = self.find_ldap_user(username).await? else {
+      return self.inner.validate_credential(username, client_credential).await;
+    };
+
+    match client_credential {
+      AuthCredential::Password(client_password) => {
+        self
+          .directory
+          .verify_password(&ldap_user.dn, client_password.expose_secret())
+          .await
+      }
+      AuthCredential::PublicKey { kind, public_key_bytes } => {
+        let openssh_public_key = format!("{kind} {}", BASE64.encode(public_key_bytes));
+        debug!(%username, "Client key: {}", openssh_public_key);
+        Ok(
+          ldap_user
+            .ssh_public_keys
+            .iter()
+            .any(|key| openssh_key_matches(key, &openssh_public_key)),
+        )
+      }
+      AuthCredential::Certificate { principals, .. } => Ok(principals.iter().any(|x| x == username)),
+      _ => Ok(false),
+    }
+  }
+
+  async fn authorize_target(&mut self, username: &str, target_name: &str) -> Result<bool, OmnitronError> {
+    let Some(ldap_user) = self.find_ldap_user(username).await? else {
+      return self.inner.authorize_target(username, target_name).await;
+    };
+
+    let user_roles = self.roles_for(&ldap_user);
+
+    let db = self.db.lock().await;
+    let Some(target_model) = entities::Target::Entity::find()
+      .filter(entities::Target::Column::Name.eq(target_name))
+      .one(&*db)
+      .await?
+    else {
+      warn!("Selected target not found: {}", target_name);
+      return Ok(false);
+    };
+
+    let target_roles: HashSet<String> = active_target_roles(&db, target_model.id)
+      .await?
+      .into_iter()
+      .map(|x| x.name)
+      .collect();
+
+    Ok(user_roles.intersection(&target_roles).count() > 0)
+  }
+
+  async fn get_session_limits(&mut self, username: &str, target_name: &str) -> Result<SessionLimits, OmnitronError> {
+    let Some(ldap_user) = self.find_ldap_user(username).await? else {
+      return self.inner.get_session_limits(username, target_name).await;
+    };
+
+    let user_roles = self.roles_for(&ldap_user);
+    let db = self.db.lock().await;
+    session_limits_for_roles(&db, target_name, &user_roles).await
+  }
+
+  async fn get_sql_guard(&mut self, username: &str, target_name: &str) -> Result<SqlGuard, OmnitronError> {
+    let Some(ldap_user) = self.find_ldap_user(username).await? else {
+      return self.inner.get_sql_guard(username, target_name).await;
+    };
+
+    let user_roles = self.roles_for(&ldap_user);
+    let db = self.db.lock().await;
+    sql_guard_for_roles(&db, target_name, &user_roles).await
+  }
+
+  async fn check_address(
+    &mut self,
+    username: &str,
+    target_name: Option<&str>,
+    address: IpAddr,
+  ) -> Result<Option<IpPolicyDenial>, OmnitronError> {
+    let Some(ldap_user) = self.find_ldap_user(username).await? else {
+      return self.inner.check_address(username, target_name, address).await;
+    };
+
+    let user_roles = self.roles_for(&ldap_user);
+    let db = self.db.lock().await;
+    ip_policy_denial(&db, &IpPolicy::default(), username, target_name, &user_roles, address).await
+  }
+
+  async fn update_public_key_last_used(&self, credential: Option<AuthCredential>) -> Result<(), OmnitronError> {
+    self.inner.update_public_key_last_used(credential).await
+  }
+
+  async fn validate_api_token(&mut self, token: &str) -> Result<Option<User>, OmnitronError> {
+    self.inner.validate_api_token(token).await
+  }
+
+  async fn username_for_sso_credential(&mut self, client_credential: &AuthCredential) -> Result<Option<String>, OmnitronError> {
+    self.inner.username_for_sso_credential(client_credential).await
+  }
+
+  async fn apply_sso_role_mappings(
+    &mut self,
+    username: &str,
+    managed_role_names: &[String],
+    assigned_role_names: &[String],
+  ) -> Result<(), OmnitronError> {
+    self
+      .inner
+      .apply_sso_role_mappings(username, managed_role_names, assigned_role_names)
+      .await
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::path::PathBuf;
+
+  use bytes::Bytes;
+  use omnitron_gate_common::auth::CredentialPolicyResponse;
+  use omnitron_gate_common::Secret;
+  use russh::keys::Algorithm;
+  use sea_orm::ActiveValue::Set;
+  use sea_orm::{ActiveModelTrait, Database, IntoActiveModel};
+
+  use super::*;
+  use crate::consts::{BUILTIN_ADMIN_ROLE_NAME, BUILTIN_ADMIN_TARGET_NAME};
+  use crate::db::populate_db;
+
+  const ALICE_KEY: &str = "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIGf4bmwRrZWpR1lJm9QdW7cE3Un3R7vUPmQ0ob6GDr1y alice@laptop";
+
+  struct StubDirectory {
+    users: Vec<(LdapUser, &'static str)>,
+  }
+
+  #[async_trait]
+  impl LdapDirectory for StubDirectory {
+    async fn find_user(&self, username: &str) -> Result<Option<LdapUser>, OmnitronError> {
+      Ok(
+        self
+          .users
+          .iter()
+          .find(|(u, _)| u.username == username)
+          .map(|(u, _)| u.clone()),
+      )
+    }
+
+    async fn list_users(&self) -> Result<Vec<LdapUser>, OmnitronError> {
+      Ok(self.users.iter().map(|(u, _)| u.clone()).collect())
+    }
+
+    async fn verify_password(&self, dn: &str, password: &str) -> Result<bool, OmnitronError> {
+      Ok(self.users.iter().any(|(u, p)| u.dn == dn && *p == password))
+    }
+  }
+
+  async fn make_provider(group_role_mappings: HashMap<String, String>) -> LdapConfigProvider {
+    let mut db = Database::connect("sqlite::memory:").await.unwrap();
+    omnitron_db_migrations::migrate_database(&db).await.unwrap();
+    let mut config = OmnitronConfig {
+      store: Default::default(),
+      paths_relative_to: PathBuf::new(),
+    };
+    populate_db(&mut db, &mut config).await.unwrap();
+
+    let directory = StubDirectory {
+      users: vec![
+        (
+          LdapUser {
+            dn: "uid=alice,ou=people,dc=example,dc=com".into(),
+            username: "alice".into(),
+            groups: vec!["cn=admins,ou=groups,dc=example,dc=com".into()],
+            ssh_public_keys: vec![ALICE_KEY.into()],
+          },
+          "alice-password",
+        ),
+        (
+          LdapUser {
+            dn: "uid=bob,ou=people,dc=example,dc=com".into(),
+            username: "bob".into(),
+            groups: vec!["cn=developers,ou=groups,dc=example,dc=com".into()],
+            ssh_public_keys: vec![],
+          },
+          "bob-password",
+        ),
+      ],
+    };
+
+    LdapConfigProvider::with_directory(
+      &Arc::new(Mutex::new(db)),
+      &Arc::new(Mutex::new(config)),
+      Box::new(directory),
+      group_role_mappings,
+    )
+    .await
+  }
+
+  fn admin_mapping() -> HashMap<String, String> {
+    HashMap::from([("admins".to_owned(), BUILTIN_ADMIN_ROLE_NAME.to_owned())])
+  }
+
+  #[tokio::test]
+  async fn test_password_bind() {
+    let mut provider = make_provider(admin_mapping()).await;
+
+    let good = AuthCredential::Password(Secret::new("alice-password".into()));
+    let bad = AuthCredential::Password(Secret::new("bob-password".into()));
+    let empty = AuthCredential::Password(Secret::new("".into()));
+
+    assert!(provider.validate_credential("alice", &good).await.unwrap());
+    assert!(!provider.validate_credential("alice", &bad).await.unwrap());
+    assert!(!provider.validate_credential("alice", &empty).await.unwrap());
+    assert!(!provider.validate_credential("mallory", &good).await.unwrap());
+  }
+
+  #[tokio::test]
+  async fn test_public_key() {
+    let mut provider = make_provider(admin_mapping()).await;
+
+    let key_bytes = BASE64
+      .decode(ALICE_KEY.split_whitespace().nth(1).unwrap().as_bytes())
+      .unwrap();
+    let credential = AuthCredential::PublicKey {
+      kind: Algorithm::Ed25519,
+      public_key_bytes: Bytes::from(key_bytes),
+    };
+
+    assert!(provider.validate_credential("alice", &credential).await.unwrap());
+    assert!(!provider.validate_credential("bob", &credential).await.unwrap());
+
+    // Public keys are only offered to users that have one in the directory
+    let all_kinds = [CredentialKind::Password, CredentialKind::PublicKey];
+    let alice_policy = provider.get_credential_policy("alice", &all_kinds).await.unwrap().unwrap();
+    let bob_policy = provider.get_credential_policy("bob", &all_kinds).await.unwrap().unwrap();
+    assert_eq!(needed_kinds(&*alice_policy), HashSet::from(all_kinds));
+    assert_eq!(needed_kinds(&*bob_policy), HashSet::from([CredentialKind::Password]));
+
+    // The key from `sshPublicKey` is enough to log in
+    assert!(matches!(
+      alice_policy.is_sufficient("ssh", &[credential]),
+      CredentialPolicyResponse::Ok
+    ));
+  }
+
+  fn needed_kinds(policy: &(dyn CredentialPolicy + Send + Sync)) -> HashSet<CredentialKind> {
+    match policy.is_sufficient("ssh", &[]) {
+      CredentialPolicyResponse::Need(kinds) => kinds,
+      CredentialPolicyResponse::Ok => panic!("no credentials needed"),
+    }
+  }
+
+  #[tokio::test]
+  async fn test_group_role_mapping() {
+    let mut provider = make_provider(admin_mapping()).await;
+    assert!(provider.authorize_target("alice", BUILTIN_ADMIN_TARGET_NAME).await.unwrap());
+    assert!(!provider.authorize_target("bob", BUILTIN_ADMIN_TARGET_NAME).await.unwrap());
+    assert!(!provider.authorize_target("alice", "nonexistent").await.unwrap());
+
+    // Without explicit mappings, group CNs are used as role names
+    let mut provider = make_provider(HashMap::new()).await;
+    assert!(!provider.authorize_target("alice", BUILTIN_ADMIN_TARGET_NAME).await.unwrap());
+  }
+
+  /// Roles mapped from groups are subject to the same IP policies and grants as assigned ones
+  #[tokio::test]
+  async fn test_group_role_checks() {
+    let mut provider = make_provider(admin_mapping()).await;
+    let address = |x: &str| x.parse::<IpAddr>().unwrap();
+
+    {
+      let db = provider.db.lock().await;
+      let role = entities::Role::Entity::find()
+        .filter(entities::Role::Column::Name.eq(BUILTIN_ADMIN_ROLE_NAME))
+        .one(&*db)
+        .await
+        .unwrap()
+        .unwrap();
+      let mut role = role.into_active_model();
+      role.ip_policy = Set(Some(serde_json::json!({ "allow": ["10.0.0.0/8"], "deny": [] })));
+      role.update(&*db).await.unwrap();
+    }
+    assert!(matches!(
+      provider.check_address("alice", Some(BUILTIN_ADMIN_TARGET_NAME), address("192.0.2.1")).await,
+      Ok(Some(IpPolicyDenial::NotAllowed { owner, .. })) if owner == format!("role {BUILTIN_ADMIN_ROLE_NAME}")
+    ));
+    assert_eq!(
+      provider
+        .check_address("alice", Some(BUILTIN_ADMIN_TARGET_NAME), address("10.0.0.1"))
+        .await
+        .unwrap(),
+      None
+    );
+
+    assert!(provider.authorize_target("alice", BUILTIN_ADMIN_TARGET_NAME).await.unwrap());
+    {
+      let db = provider.db.lock().await;
+      for assignment in entities::TargetRoleAssignment::Entity::find().all(&*db).await.unwrap() {
+        let mut assignment = assignment.into_active_model();
+        assignment.valid_until = Set(Some(chrono::Utc::now() - chrono::Duration::hours(1)));
+        assignment.update(&*db).await.unwrap();
+      }
+    }
+    assert!(!provider.authorize_target("alice", BUILTIN_ADMIN_TARGET_NAME).await.unwrap());
+  }
+
+  #[tokio::test]
+  async fn test_list_users() {
+    let mut provider = make_provider(admin_mapping()).await;
+    let usernames: Vec<String> = provider.list_users().await.unwrap().into_iter().map(|x| x.username).collect();
+    assert_eq!(usernames, vec!["alice", "bob"]);
+  }
+
+  #[test]
+  fn test_group_cn() {
+    assert_eq!(group_cn("cn=admins,ou=groups,dc=example,dc=com"), Some("admins"));
+    assert_eq!(group_cn("CN=Domain Admins, OU=Groups"), Some("Domain Admins"));
+    assert_eq!(group_cn("ou=groups,dc=example"), None);
+  }
+}
@@ -1,17 +1,24 @@
 mod db;
+mod ldap;
+use std::collections::HashSet;
+use std::net::IpAddr;
 use std::sync::Arc;
 
 use async_trait::async_trait;
+use chrono::Utc;
 pub use db::DatabaseConfigProvider;
-use omnitron_db_entities::Ticket;
+pub use ldap::{Ldap3Directory, LdapConfigProvider, LdapDirectory, LdapUser};
+use omnitron_db_entities::{self as entities, Ticket};
 use omnitron_gate_common::auth::{AuthCredential, CredentialKind, CredentialPolicy};
-use omnitron_gate_common::{OmnitronError, Secret, Target, User};
+use omnitron_gate_common::{IpPolicy, IpPolicyDenial, OmnitronError, Role, Secret, SessionLimits, Target, User};
 use sea_orm::ActiveValue::Set;
 use sea_orm::{ActiveModelTrait, ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter};
 use tokio::sync::Mutex;
 use tracing::*;
 use uuid::Uuid;
 
+use crate::SqlGuard;
+
 #[async_trait]
 pub trait ConfigProvider {
   async fn list_users(&mut self) -> Result<Vec<User>, OmnitronError>;
@@ -28,9 +35,173 @@ pub trait ConfigProvider {
 
   async fn authorize_target(&mut self, username: &str, target: &str) -> Result<bool, OmnitronError>;
 
+  /// Limits that apply to the user's sessions to the target
+  async fn get_session_limits(&mut self, username: &str, target: &str) -> Result<SessionLimits, OmnitronError>;
+
+  /// Checks a client address against the IP policies of the user and, if a target is given,
+  /// of the target and the user's roles that grant access to it
+  async fn check_address(
+    &mut self,
+    username: &str,
+    target: Option<&str>,
+    address: IpAddr,
+  ) -> Result<Option<IpPolicyDenial>, OmnitronError>;
+
+  /// Statement restrictions that apply to the user's SQL sessions to the target
+  async fn get_sql_guard(&mut self, username: &str, target: &str) -> Result<SqlGuard, OmnitronError>;
+
   async fn update_public_key_last_used(&self, credential: Option<AuthCredential>) -> Result<(), OmnitronError>;
 
   async fn validate_api_token(&mut self, token: &str) -> Result<Option<User>, OmnitronError>;
+
+  async fn username_for_sso_credential(&mut self, client_credential: &AuthCredential) -> Result<Option<String>, OmnitronError>;
+
+  /// Grants `assigned_role_names` to the user and revokes the rest of `managed_role_names`
+  async fn apply_sso_role_mappings(
+    &mut self,
+    username: &str,
+    managed_role_names: &[String],
+    assigned_role_names: &[String],
+  ) -> Result<(), OmnitronError>;
+}
+
+/// Roles assigned to the user whose grants are in effect right now
+async fn active_user_roles(db: &DatabaseConnection, user_id: Uuid) -> Result<HashSet<String>, OmnitronError> {
+  let now = Utc::now();
+  Ok(
+    entities::UserRoleAssignment::Entity::find()
+      .filter(entities::UserRoleAssignment::Column::UserId.eq(user_id))
+      .find_also_related(entities::Role::Entity)
+      .all(db)
+      .await?
+      .into_iter()
+      .filter(|(assignment, _)| assignment.grant().is_active(now))
+      .filter_map(|(_, role)| role)
+      .map(|x| x.name)
+      .collect(),
+  )
+}
+
+/// Roles assigned to the target whose grants are in effect right now
+async fn active_target_roles(db: &DatabaseConnection, target_id: Uuid) -> Result<Vec<Role>, OmnitronError> {
+  let now = Utc::now();
+  let roles: Result<Vec<Role>, _> = entities::TargetRoleAssignment::Entity::find()
+    .filter(entities::TargetRoleAssignment::Column::TargetId.eq(target_id))
+    .find_also_related(entities::Role::Entity)
+    .all(db)
+    .await?
+    .into_iter()
+    .filter(|(assignment, _)| assignment.grant().is_active(now))
+    .filter_map(|(_, role)| role)
+    .map(Role::try_from)
+    .collect();
+  Ok(roles?)
+}
+
+/// The most permissive limits among the user's roles that grant access to the target, further capped by the target's own limits
+async fn session_limits_for_roles(
+  db: &DatabaseConnection,
+  target_name: &str,
+  user_roles: &HashSet<String>,
+) -> Result<SessionLimits, OmnitronError> {
+  let Some(target_model) = entities::Target::Entity::find()
+    .filter(entities::Target::Column::Name.eq(target_name))
+    .one(db)
+    .await?
+  else {
+    return Ok(SessionLimits::default());
+  };
+
+  let role_limits: Vec<SessionLimits> = active_target_roles(db, target_model.id)
+    .await?
+    .into_iter()
+    .filter(|x| user_roles.contains(&x.name))
+    .map(|x| x.limits)
+    .collect();
+
+  let target: Target = target_model.try_into()?;
+  Ok(SessionLimits::most_permissive(&role_limits).restrict(&target.limits))
+}
+
+/// Checks an address against a policy loaded from the provider, refusing to go on if any of its rules is invalid
+fn check_ip_policy(policy: &IpPolicy, address: IpAddr, owner: String) -> Result<Result<(), IpPolicyDenial>, OmnitronError> {
+  policy.validate().map_err(|rule| OmnitronError::InvalidIpPolicy {
+    rule,
+    owner: owner.clone(),
+  })?;
+  Ok(policy.check(address, &owner))
+}
+
+/// A denial by the user's or the target's IP policy, or by the policies of all roles that grant access
+async fn ip_policy_denial(
+  db: &DatabaseConnection,
+  user_policy: &IpPolicy,
+  username: &str,
+  target_name: Option<&str>,
+  user_roles: &HashSet<String>,
+  address: IpAddr,
+) -> Result<Option<IpPolicyDenial>, OmnitronError> {
+  if let Err(denial) = check_ip_policy(user_policy, address, format!("user {username}"))? {
+    return Ok(Some(denial));
+  }
+
+  let Some(target_name) = target_name else {
+    return Ok(None);
+  };
+  let Some(target_model) = entities::Target::Entity::find()
+    .filter(entities::Target::Column::Name.eq(target_name))
+    .one(db)
+    .await?
+  else {
+    return Ok(None);
+  };
+
+  let roles: Vec<Role> = active_target_roles(db, target_model.id)
+    .await?
+    .into_iter()
+    .filter(|x| user_roles.contains(&x.name))
+    .collect();
+
+  let target: Target = target_model.try_into()?;
+  if let Err(denial) = check_ip_policy(&target.ip_policy, address, format!("target {}", target.name))? {
+    return Ok(Some(denial));
+  }
+
+  // Being let in by any one of the roles is enough
+  let mut first_denial = None;
+  for role in roles {
+    match check_ip_policy(&role.ip_policy, address, format!("role {}", role.name))? {
+      Ok(()) => return Ok(None),
+      Err(denial) => {
+        first_denial.get_or_insert(denial);
+      }
+    }
+  }
+  Ok(first_denial)
+}
+
+/// The SQL policies of the target and of the user's roles that grant access to it
+async fn sql_guard_for_roles(
+  db: &DatabaseConnection,
+  target_name: &str,
+  user_roles: &HashSet<String>,
+) -> Result<SqlGuard, OmnitronError> {
+  let Some(target_model) = entities::Target::Entity::find()
+    .filter(entities::Target::Column::Name.eq(target_name))
+    .one(db)
+    .await?
+  else {
+    return Ok(SqlGuard::default());
+  };
+
+  let roles: Vec<Role> = active_target_roles(db, target_model.id)
+    .await?
+    .into_iter()
+    .filter(|x| user_roles.contains(&x.name))
+    .collect();
+
+  let target: Target = target_model.try_into()?;
+  Ok(SqlGuard::new(&target, &roles))
 }
 
 //TODO: move this somewhere
@@ -83,3 +254,158 @@ pub async fn consume_ticket(db: &Arc<Mutex<DatabaseConnection>>, ticket_id: &Uui
 
   Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+  use std::path::PathBuf;
+
+  use omnitron_gate_common::OmnitronConfig;
+  use sea_orm::ActiveValue::NotSet;
+  use sea_orm::{Database, IntoActiveModel};
+
+  use super::*;
+  use crate::consts::{BUILTIN_ADMIN_ROLE_NAME, BUILTIN_ADMIN_TARGET_NAME};
+  use crate::db::populate_db;
+
+  async fn make_db() -> DatabaseConnection {
+    let mut db = Database::connect("sqlite::memory:").await.unwrap();
+    omnitron_db_migrations::migrate_database(&db).await.unwrap();
+    let mut config = OmnitronConfig {
+      store: Default::default(),
+      paths_relative_to: PathBuf::new(),
+    };
+    populate_db(&mut db, &mut config).await.unwrap();
+    db
+  }
+
+  fn policy(allow: &[&str], deny: &[&str]) -> Option<serde_json::Value> {
+    Some(serde_json::json!({ "allow": allow, "deny": deny }))
+  }
+
+  async fn set_target_policy(db: &DatabaseConnection, ip_policy: Option<serde_json::Value>) {
+    let target = entities::Target::Entity::find()
+      .filter(entities::Target::Column::Name.eq(BUILTIN_ADMIN_TARGET_NAME))
+      .one(db)
+      .await
+      .unwrap()
+      .unwrap();
+    let mut model = target.into_active_model();
+    model.ip_policy = Set(ip_policy);
+    model.update(db).await.unwrap();
+  }
+
+  async fn set_role_policy(db: &DatabaseConnection, name: &str, ip_policy: Option<serde_json::Value>) {
+    let role = entities::Role::Entity::find()
+      .filter(entities::Role::Column::Name.eq(name))
+      .one(db)
+      .await
+      .unwrap()
+      .unwrap();
+    let mut model = role.into_active_model();
+    model.ip_policy = Set(ip_policy);
+    model.update(db).await.unwrap();
+  }
+
+  /// Adds another role that grants access to the admin target
+  async fn add_role(db: &DatabaseConnection, name: &str) {
+    let target = entities::Target::Entity::find()
+      .filter(entities::Target::Column::Name.eq(BUILTIN_ADMIN_TARGET_NAME))
+      .one(db)
+      .await
+      .unwrap()
+      .unwrap();
+    let role = entities::Role::ActiveModel {
+      id: Set(Uuid::new_v4()),
+      name: Set(name.into()),
+      limits: Set(None),
+      ip_policy: Set(None),
+      sql_policy: Set(None),
+    }
+    .insert(db)
+    .await
+    .unwrap();
+    entities::TargetRoleAssignment::ActiveModel {
+      id: NotSet,
+      target_id: Set(target.id),
+      role_id: Set(role.id),
+      ..Default::default()
+    }
+    .insert(db)
+    .await
+    .unwrap();
+  }
+
+  async fn denial(
+    db: &DatabaseConnection,
+    user_policy: &IpPolicy,
+    roles: &[&str],
+    address: &str,
+  ) -> Result<Option<IpPolicyDenial>, OmnitronError> {
+    let roles = roles.iter().map(|x| x.to_string()).collect();
+    ip_policy_denial(
+      db,
+      user_policy,
+      "alice",
+      Some(BUILTIN_ADMIN_TARGET_NAME),
+      &roles,
+      address.parse().unwrap(),
+    )
+    .await
+  }
+
+  #[tokio::test]
+  async fn test_ip_policy_denial() {
+    let db = make_db().await;
+    let no_policy = IpPolicy::default();
+    let admin = [BUILTIN_ADMIN_ROLE_NAME];
+
+    assert_eq!(denial(&db, &no_policy, &admin, "192.0.2.1").await.unwrap(), None);
+
+    // The user's own policy applies even without a target
+    let user_policy = IpPolicy {
+      allow: vec![],
+      deny: vec!["192.0.2.0/24".into()],
+    };
+    let result = ip_policy_denial(
+      &db,
+      &user_policy,
+      "alice",
+      None,
+      &HashSet::new(),
+      "192.0.2.1".parse().unwrap(),
+    )
+    .await;
+    assert!(matches!(result, Ok(Some(IpPolicyDenial::Denied { owner, .. })) if owner == "user alice"));
+
+    // The target's policy always applies
+    set_target_policy(&db, policy(&["10.0.0.0/8"], &[])).await;
+    assert!(matches!(
+      denial(&db, &no_policy, &admin, "192.0.2.1").await,
+      Ok(Some(IpPolicyDenial::NotAllowed { owner, .. })) if owner == format!("target {BUILTIN_ADMIN_TARGET_NAME}")
+    ));
+    assert_eq!(denial(&db, &no_policy, &admin, "10.8.0.1").await.unwrap(), None);
+    set_target_policy(&db, None).await;
+
+    // Being let in by any one of the user's roles is enough
+    set_role_policy(&db, BUILTIN_ADMIN_ROLE_NAME, policy(&["10.8.0.0/16"], &[])).await;
+    add_role(&db, "ops").await;
+    assert!(matches!(
+      denial(&db, &no_policy, &admin, "192.0.2.1").await,
+      Ok(Some(IpPolicyDenial::NotAllowed { owner, .. })) if owner == format!("role {BUILTIN_ADMIN_ROLE_NAME}")
+    ));
+    assert_eq!(denial(&db, &no_policy, &admin, "10.8.0.1").await.unwrap(), None);
+    assert_eq!(
+      denial(&db, &no_policy, &[BUILTIN_ADMIN_ROLE_NAME, "ops"], "192.0.2.1")
+        .await
+        .unwrap(),
+      None
+    );
+
+    // Invalid rules fail closed
+    set_target_policy(&db, policy(&[], &["not-a-network"])).await;
+    assert!(matches!(
+      denial(&db, &no_policy, &["ops"], "192.0.2.1").await,
+      Err(OmnitronError::InvalidIpPolicy { rule, .. }) if rule == "not-a-network"
+    ));
+  }
+}
@@ -2,7 +2,7 @@ use std::time::Duration;
 
 use anyhow::Result;
 use omnitron_db_entities::Target::TargetKind;
-use omnitron_db_entities::{LogEntry, Role, Target, TargetRoleAssignment};
+use omnitron_db_entities::{LogEntry, Recording, Role, Target, TargetRoleAssignment};
 use omnitron_db_migrations::migrate_database;
 use omnitron_gate_common::helpers::fs::secure_file;
 use omnitron_gate_common::{OmnitronConfig, OmnitronError, TargetOptions, TargetWebAdminOptions};
@@ -10,9 +10,11 @@ use sea_orm::sea_query::Expr;
 use sea_orm::{
   ActiveModelTrait, ColumnTrait, ConnectOptions, Database, DatabaseConnection, EntityTrait, QueryFilter, TransactionTrait,
 };
+use tracing::*;
 use uuid::Uuid;
 
 use crate::consts::{BUILTIN_ADMIN_ROLE_NAME, BUILTIN_ADMIN_TARGET_NAME};
+use crate::recordings::SessionRecordings;
 
 pub async fn connect_to_db(config: &OmnitronConfig) -> Result<DatabaseConnection> {
   let mut url = url::Url::parse(&config.store.database_url.expose_secret()[..])?;
@@ -80,6 +82,9 @@ pub async fn populate_db(db: &mut DatabaseConnection, _config: &mut OmnitronConf
       let values = Role::ActiveModel {
         id: Set(Uuid::new_v4()),
         name: Set(BUILTIN_ADMIN_ROLE_NAME.to_owned()),
+        limits: Set(None),
+        ip_policy: Set(None),
+        sql_policy: Set(None),
       };
       values.insert(&*db).await.map_err(OmnitronError::from)?
     }
@@ -98,6 +103,10 @@ pub async fn populate_db(db: &mut DatabaseConnection, _config: &mut OmnitronConf
         name: Set(BUILTIN_ADMIN_TARGET_NAME.to_owned()),
         kind: Set(TargetKind::WebAdmin),
         options: Set(serde_json::to_value(TargetOptions::WebAdmin(TargetWebAdminOptions {})).map_err(OmnitronError::from)?),
+        limits: Set(None),
+        ip_policy: Set(None),
+        sql_policy: Set(None),
+        masking: Set(None),
       };
 
       values.insert(&*db).await.map_err(OmnitronError::from)?
@@ -122,10 +131,23 @@ pub async fn populate_db(db: &mut DatabaseConnection, _config: &mut OmnitronConf
   Ok(())
 }
 
-pub async fn cleanup_db(db: &mut DatabaseConnection, retention: &Duration) -> Result<()> {
+pub async fn cleanup_db(db: &mut DatabaseConnection, recordings: &mut SessionRecordings, retention: &Duration) -> Result<()> {
   use omnitron_db_entities::Session;
   let cutoff = chrono::Utc::now() - chrono::Duration::from_std(*retention)?;
 
+  let old_recordings = Recording::Entity::find()
+    .filter(Expr::col(Recording::Column::Ended).is_not_null())
+    .filter(Expr::col(Recording::Column::Ended).lt(cutoff))
+    .all(db)
+    .await?;
+
+  for recording in old_recordings {
+    if let Err(error) = recordings.remove(&recording.session_id, &recording.name).await {
+      error!(%error, recording=%recording.id, "Failed to remove recording");
+    }
+    Recording::Entity::delete_by_id(recording.id).exec(db).await?;
+  }
+
   LogEntry::Entity::delete_many()
     .filter(Expr::col(LogEntry::Column::Timestamp).lt(cutoff))
     .exec(db)
@@ -13,4 +13,11 @@ mod services;
 pub use services::*;
 mod auth_state_store;
 pub use auth_state_store::*;
+mod login_protection;
+pub use login_protection::LoginProtection;
 pub mod logging;
+mod masking;
+pub use masking::{ColumnMask, DataMasker, MaskingKey, ResultColumn};
+pub mod recordings;
+mod sql_policy;
+pub use sql_policy::{SqlDialect, SqlGuard};
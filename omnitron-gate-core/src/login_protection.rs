@@ -0,0 +1,274 @@
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use omnitron_db_entities::LoginFailure::{self, LoginFailureKind};
+use omnitron_gate_common::{LoginProtectionConfig, OmnitronConfig, OmnitronError};
+use sea_orm::sea_query::OnConflict;
+use sea_orm::ActiveValue::Set;
+use sea_orm::{ColumnTrait, Condition, DatabaseConnection, EntityTrait, QueryFilter, QueryOrder};
+use tokio::sync::Mutex;
+use tracing::*;
+
+/// Counts failed logins per username and per source address and locks them
+/// out for exponentially growing periods. The counters live in the database
+/// so that restarting doesn't give an attacker a fresh start.
+#[derive(Clone)]
+pub struct LoginProtection {
+  db: Arc<Mutex<DatabaseConnection>>,
+  config: Arc<Mutex<OmnitronConfig>>,
+}
+
+impl LoginProtection {
+  pub fn new(db: &Arc<Mutex<DatabaseConnection>>, config: &Arc<Mutex<OmnitronConfig>>) -> Self {
+    Self {
+      db: db.clone(),
+      config: config.clone(),
+    }
+  }
+
+  async fn config(&self) -> LoginProtectionConfig {
+    self.config.lock().await.store.login_protection.clone()
+  }
+
+  /// Returns the end of the lockout if either the username or the address is currently locked out
+  pub async fn locked_until(&self, username: &str, address: Option<IpAddr>) -> Result<Option<DateTime<Utc>>, OmnitronError> {
+    if !self.config().await.enable {
+      return Ok(None);
+    }
+
+    let now = Utc::now();
+    let db = self.db.lock().await;
+    let mut result = None;
+    for (kind, key) in keys(username, address) {
+      let Some(model) = LoginFailure::Entity::find_by_id((kind, key)).one(&*db).await? else {
+        continue;
+      };
+      if let Some(until) = model.locked_until.filter(|x| *x > now) {
+        result = result.max(Some(until));
+      }
+    }
+    Ok(result)
+  }
+
+  /// Counts a failed password or OTP attempt against both the username and the address
+  pub async fn record_failure(&self, username: &str, address: Option<IpAddr>) -> Result<(), OmnitronError> {
+    let config = self.config().await;
+    if !config.enable {
+      return Ok(());
+    }
+
+    let now = Utc::now();
+    let reset_after = chrono::Duration::from_std(config.reset_after).map_err(OmnitronError::other)?;
+    let db = self.db.lock().await;
+    for (kind, key) in keys(username, address) {
+      let previous = LoginFailure::Entity::find_by_id((kind, key.clone())).one(&*db).await?;
+      let failures = match previous {
+        Some(previous) if previous.last_failure + reset_after > now => previous.failures + 1,
+        _ => 1,
+      };
+      let locked_until = match lockout_duration(&config, failures) {
+        Some(duration) => Some(now + chrono::Duration::from_std(duration).map_err(OmnitronError::other)?),
+        None => None,
+      };
+
+      if let Some(until) = locked_until {
+        warn!(?kind, %key, %failures, %until, "Locking out after repeated login failures");
+      }
+
+      let values = LoginFailure::ActiveModel {
+        kind: Set(kind),
+        key: Set(key),
+        failures: Set(failures),
+        last_failure: Set(now),
+        locked_until: Set(locked_until),
+      };
+      LoginFailure::Entity::insert(values)
+        .on_conflict(
+          OnConflict::columns([LoginFailure::Column::Kind, LoginFailure::Column::Key])
+            .update_columns([
+              LoginFailure::Column::Failures,
+              LoginFailure::Column::LastFailure,
+              LoginFailure::Column::LockedUntil,
+            ])
+            .to_owned(),
+        )
+        .exec(&*db)
+        .await?;
+    }
+    Ok(())
+  }
+
+  /// Forgets the failures of a username once it has logged in. The address
+  /// keeps its count so that an attacker can't reset it by logging into an
+  /// account of their own.
+  pub async fn record_success(&self, username: &str) -> Result<(), OmnitronError> {
+    let db = self.db.lock().await;
+    LoginFailure::Entity::delete_by_id((LoginFailureKind::Username, username.to_owned()))
+      .exec(&*db)
+      .await?;
+    Ok(())
+  }
+
+  /// All tracked usernames and addresses, most recently failed first
+  pub async fn list(&self) -> Result<Vec<LoginFailure::Model>, OmnitronError> {
+    let db = self.db.lock().await;
+    Ok(
+      LoginFailure::Entity::find()
+        .order_by_desc(LoginFailure::Column::LastFailure)
+        .all(&*db)
+        .await?,
+    )
+  }
+
+  /// Lifts the lockout and resets the counter. Returns `false` if nothing was tracked for the key.
+  pub async fn clear(&self, kind: LoginFailureKind, key: &str) -> Result<bool, OmnitronError> {
+    let db = self.db.lock().await;
+    let result = LoginFailure::Entity::delete_by_id((kind, key.to_owned())).exec(&*db).await?;
+    Ok(result.rows_affected > 0)
+  }
+
+  /// Deletes counters that are past both their lockout and `reset_after`
+  pub async fn vacuum(&self) -> Result<(), OmnitronError> {
+    let config = self.config().await;
+    let now = Utc::now();
+    let cutoff = now - chrono::Duration::from_std(config.reset_after).map_err(OmnitronError::other)?;
+    let db = self.db.lock().await;
+    LoginFailure::Entity::delete_many()
+      .filter(LoginFailure::Column::LastFailure.lt(cutoff))
+      .filter(
+        Condition::any()
+          .add(LoginFailure::Column::LockedUntil.is_null())
+          .add(LoginFailure::Column::LockedUntil.lt(now)),
+      )
+      .exec(&*db)
+      .await?;
+    Ok(())
+  }
+}
+
+fn keys(username: &str, address: Option<IpAddr>) -> Vec<(LoginFailureKind, String)> {
+  let mut keys = vec![(LoginFailureKind::Username, username.to_owned())];
+  if let Some(address) = address {
+    keys.push((LoginFailureKind::Address, address.to_string()));
+  }
+  keys
+}
+
+/// No lockout until `max_attempts` failures, then `lockout` doubling with each failure up to `max_lockout`
+fn lockout_duration(config: &LoginProtectionConfig, failures: i32) -> Option<Duration> {
+  let excess = u32::try_from(failures).ok()?.checked_sub(config.max_attempts)?;
+  let factor = 2u32.checked_pow(excess).unwrap_or(u32::MAX);
+  Some(config.lockout.saturating_mul(factor).min(config.max_lockout))
+}
+
+#[cfg(test)]
+mod tests {
+  use std::net::IpAddr;
+  use std::path::PathBuf;
+  use std::sync::Arc;
+  use std::time::Duration;
+
+  use omnitron_gate_common::{LoginProtectionConfig, OmnitronConfig};
+  use sea_orm::Database;
+  use tokio::sync::Mutex;
+
+  use super::*;
+
+  fn config() -> LoginProtectionConfig {
+    LoginProtectionConfig {
+      enable: true,
+      max_attempts: 3,
+      lockout: Duration::from_secs(60),
+      max_lockout: Duration::from_secs(60 * 60),
+      reset_after: Duration::from_secs(60 * 60 * 24),
+    }
+  }
+
+  async fn make_protection(login_protection: LoginProtectionConfig) -> LoginProtection {
+    let db = Database::connect("sqlite::memory:").await.unwrap();
+    omnitron_db_migrations::migrate_database(&db).await.unwrap();
+    let mut config = OmnitronConfig {
+      store: Default::default(),
+      paths_relative_to: PathBuf::new(),
+    };
+    config.store.login_protection = login_protection;
+    LoginProtection::new(&Arc::new(Mutex::new(db)), &Arc::new(Mutex::new(config)))
+  }
+
+  #[tokio::test]
+  async fn test_lockout() {
+    let protection = make_protection(config()).await;
+    let attacker: IpAddr = "192.0.2.1".parse().unwrap();
+    let other: IpAddr = "192.0.2.2".parse().unwrap();
+
+    for _ in 0..2 {
+      protection.record_failure("alice", Some(attacker)).await.unwrap();
+    }
+    assert_eq!(protection.locked_until("alice", Some(attacker)).await.unwrap(), None);
+
+    protection.record_failure("alice", Some(attacker)).await.unwrap();
+    let until = protection.locked_until("alice", Some(attacker)).await.unwrap().unwrap();
+    let remaining = until - Utc::now();
+    assert!(remaining > chrono::Duration::seconds(50) && remaining <= chrono::Duration::seconds(60));
+
+    // Both the username and the address are locked out
+    assert!(protection.locked_until("alice", Some(other)).await.unwrap().is_some());
+    assert!(protection.locked_until("alice", None).await.unwrap().is_some());
+    assert!(protection.locked_until("bob", Some(attacker)).await.unwrap().is_some());
+    assert_eq!(protection.locked_until("bob", Some(other)).await.unwrap(), None);
+
+    // The lockout doubles with each further failure
+    protection.record_failure("alice", Some(attacker)).await.unwrap();
+    let until = protection.locked_until("alice", None).await.unwrap().unwrap();
+    assert!(until - Utc::now() > chrono::Duration::seconds(110));
+
+    // A successful login only resets the username
+    protection.record_success("alice").await.unwrap();
+    assert_eq!(protection.locked_until("alice", Some(other)).await.unwrap(), None);
+    assert!(protection.locked_until("alice", Some(attacker)).await.unwrap().is_some());
+
+    let tracked = protection.list().await.unwrap();
+    assert_eq!(tracked.len(), 1);
+    assert_eq!(tracked[0].kind, LoginFailureKind::Address);
+    assert_eq!(tracked[0].key, attacker.to_string());
+    assert_eq!(tracked[0].failures, 4);
+
+    assert!(protection
+      .clear(LoginFailureKind::Address, &attacker.to_string())
+      .await
+      .unwrap());
+    assert!(!protection
+      .clear(LoginFailureKind::Address, &attacker.to_string())
+      .await
+      .unwrap());
+    assert_eq!(protection.locked_until("alice", Some(attacker)).await.unwrap(), None);
+  }
+
+  #[tokio::test]
+  async fn test_disabled() {
+    let protection = make_protection(LoginProtectionConfig {
+      enable: false,
+      ..config()
+    })
+    .await;
+    for _ in 0..10 {
+      protection.record_failure("alice", None).await.unwrap();
+    }
+    assert_eq!(protection.locked_until("alice", None).await.unwrap(), None);
+    assert!(protection.list().await.unwrap().is_empty());
+  }
+
+  #[test]
+  fn test_lockout_duration() {
+    let config = config();
+    assert_eq!(lockout_duration(&config, 1), None);
+    assert_eq!(lockout_duration(&config, 2), None);
+    assert_eq!(lockout_duration(&config, 3), Some(Duration::from_secs(60)));
+    assert_eq!(lockout_duration(&config, 4), Some(Duration::from_secs(120)));
+    assert_eq!(lockout_duration(&config, 5), Some(Duration::from_secs(240)));
+    assert_eq!(lockout_duration(&config, 10), Some(Duration::from_secs(60 * 60)));
+    assert_eq!(lockout_duration(&config, 100), Some(Duration::from_secs(60 * 60)));
+  }
+}
@@ -0,0 +1,323 @@
+use std::fs::{create_dir_all, File};
+use std::io::Write;
+
+use anyhow::{bail, Result};
+use data_encoding::HEXLOWER;
+use hmac::{Hmac, Mac};
+use omnitron_gate_common::helpers::fs::{secure_directory, secure_file};
+use omnitron_gate_common::helpers::rng::get_crypto_rng;
+use omnitron_gate_common::{MaskMethod, MaskingRule, OmnitronConfig, Secret, Target};
+use rand::Rng;
+use regex::{Regex, RegexBuilder};
+use sha2::Sha256;
+use tracing::*;
+
+const MASKING_KEY_LENGTH: usize = 32;
+
+/// Per-install secret that `hash` masks are keyed with, so that masked values
+/// can't be recovered by hashing guesses
+#[derive(Clone)]
+pub struct MaskingKey(Secret<Vec<u8>>);
+
+impl MaskingKey {
+  pub fn new(key: Vec<u8>) -> Self {
+    Self(Secret::new(key))
+  }
+
+  /// Loads the key from the keys directory, generating it on first use
+  pub fn load(config: &OmnitronConfig) -> Result<Self> {
+    let path = config.paths_relative_to.join(&config.store.ssh.keys);
+    create_dir_all(&path)?;
+    secure_directory(&path)?;
+
+    let key_path = path.join("masking-key");
+    if !key_path.exists() {
+      info!("Generating data masking key");
+      let mut file = File::create(&key_path)?;
+      secure_file(&key_path)?;
+      file.write_all(&get_crypto_rng().gen::<[u8; MASKING_KEY_LENGTH]>())?;
+    }
+    secure_file(&key_path)?;
+
+    let key = std::fs::read(&key_path)?;
+    if key.len() < MASKING_KEY_LENGTH {
+      bail!("Data masking key {} is too short", key_path.display());
+    }
+    Ok(Self::new(key))
+  }
+
+  fn hash(&self, value: &[u8]) -> Vec<u8> {
+    // HMAC accepts keys of any length
+    #[allow(clippy::expect_used)]
+    let mut mac = Hmac::<Sha256>::new_from_slice(self.0.expose_secret()).expect("HMAC key");
+    mac.update(value);
+    mac.finalize().into_bytes().to_vec()
+  }
+}
+
+/// Identifies a result set column for masking rules. Parts that the protocol doesn't report are `None`.
+#[derive(Debug, Clone, Copy)]
+pub struct ResultColumn<'a> {
+  pub schema: Option<&'a str>,
+  pub table: Option<&'a str>,
+  pub name: &'a str,
+}
+
+/// How the values of one column are masked
+#[derive(Clone)]
+pub struct ColumnMask {
+  pub method: MaskMethod,
+  visible_chars: usize,
+  key: MaskingKey,
+  /// Description of the rule that matched
+  pub rule: String,
+}
+
+impl std::fmt::Debug for ColumnMask {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    f.debug_struct("ColumnMask")
+      .field("method", &self.method)
+      .field("visible_chars", &self.visible_chars)
+      .field("rule", &self.rule)
+      .finish()
+  }
+}
+
+impl ColumnMask {
+  /// Replaces all values with NULL, for columns that the rules can't be checked against
+  pub fn null(rule: String) -> Self {
+    Self {
+      method: MaskMethod::Null,
+      visible_chars: 0,
+      key: MaskingKey::new(vec![]),
+      rule,
+    }
+  }
+
+  /// Returns the masked value, or `None` for NULL
+  pub fn apply(&self, value: &[u8]) -> Option<Vec<u8>> {
+    match self.method {
+      MaskMethod::Null => None,
+      MaskMethod::Hash => Some(HEXLOWER.encode(&self.key.hash(value)).into_bytes()),
+      MaskMethod::Partial => {
+        let text = String::from_utf8_lossy(value);
+        let count = text.chars().count();
+        // Values that would be left entirely readable are masked entirely instead
+        let visible = if count > self.visible_chars { self.visible_chars } else { 0 };
+        let masked = "*".repeat(count - visible) + &text.chars().skip(count - visible).collect::<String>();
+        Some(masked.into_bytes())
+      }
+    }
+  }
+
+  /// Like [Self::apply], for values in a binary encoding. Partial masks can't leave any of those readable.
+  pub fn apply_binary(&self, value: &[u8]) -> Option<Vec<u8>> {
+    match self.method {
+      MaskMethod::Partial => Some(vec![b'*'; value.len()]),
+      _ => self.apply(value),
+    }
+  }
+}
+
+struct CompiledMaskingRule {
+  column: Option<Vec<String>>,
+  pattern: Option<Regex>,
+  mask: ColumnMask,
+}
+
+impl CompiledMaskingRule {
+  /// Returns `Err` with the pattern if the rule's column pattern is invalid
+  fn new(rule: &MaskingRule, key: &MaskingKey) -> Result<Option<Self>, String> {
+    let pattern = match rule.column_pattern {
+      Some(ref pattern) => match RegexBuilder::new(pattern).case_insensitive(true).build() {
+        Ok(regex) => Some(regex),
+        Err(error) => {
+          error!(%pattern, %error, "Invalid masking column pattern, masking all columns");
+          return Err(pattern.clone());
+        }
+      },
+      None => None,
+    };
+    let column = rule.column.as_ref().map(|x| x.split('.').map(str::to_owned).collect());
+    if column.is_none() && pattern.is_none() {
+      return Ok(None);
+    }
+    let description = [rule.column.clone(), rule.column_pattern.as_ref().map(|x| format!("/{x}/"))]
+      .into_iter()
+      .flatten()
+      .collect::<Vec<_>>()
+      .join(" ");
+    Ok(Some(Self {
+      column,
+      pattern,
+      mask: ColumnMask {
+        method: rule.method,
+        visible_chars: rule.visible_chars.unwrap_or(MaskingRule::DEFAULT_VISIBLE_CHARS) as usize,
+        key: key.clone(),
+        rule: description,
+      },
+    }))
+  }
+
+  fn matches(&self, column: &ResultColumn) -> bool {
+    if let Some(ref parts) = self.column {
+      let part_matches = |rule: Option<&String>, value: Option<&str>| match (rule, value) {
+        (Some(rule), Some(value)) => rule == "*" || rule.eq_ignore_ascii_case(value),
+        // Not specified by the rule or unknown for this column
+        _ => true,
+      };
+      let mut rule_parts = parts.iter().rev();
+      let matched = part_matches(rule_parts.next(), Some(column.name))
+        && part_matches(rule_parts.next(), column.table)
+        && part_matches(rule_parts.next(), column.schema);
+      if !matched {
+        return false;
+      }
+    }
+    self.pattern.as_ref().map_or(true, |x| x.is_match(column.name))
+  }
+}
+
+/// Finds the masking rules of a target that apply to result set columns
+#[derive(Default)]
+pub struct DataMasker {
+  rules: Vec<CompiledMaskingRule>,
+}
+
+impl DataMasker {
+  /// If any rule is invalid, all columns are masked with NULL instead
+  pub fn new(target: &Target, key: &MaskingKey) -> Self {
+    let rules = target
+      .masking
+      .iter()
+      .filter_map(|x| CompiledMaskingRule::new(x, key).transpose())
+      .collect::<Result<_, _>>()
+      .unwrap_or_else(|pattern| {
+        vec![CompiledMaskingRule {
+          column: None,
+          pattern: None,
+          mask: ColumnMask::null(format!("invalid pattern /{pattern}/")),
+        }]
+      });
+    Self { rules }
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.rules.is_empty()
+  }
+
+  /// The mask of the first rule that matches any of the names the column is known by
+  pub fn mask_for(&self, names: &[ResultColumn]) -> Option<ColumnMask> {
+    self
+      .rules
+      .iter()
+      .find(|rule| names.iter().any(|x| rule.matches(x)))
+      .map(|x| x.mask.clone())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use omnitron_gate_common::{TargetOptions, TargetWebAdminOptions};
+  use uuid::Uuid;
+
+  use super::*;
+
+  fn masker(masking: Vec<MaskingRule>) -> DataMasker {
+    DataMasker::new(
+      &Target {
+        id: Uuid::new_v4(),
+        name: "db".into(),
+        allow_roles: vec![],
+        limits: Default::default(),
+        ip_policy: Default::default(),
+        sql_policy: Default::default(),
+        masking,
+        options: TargetOptions::WebAdmin(TargetWebAdminOptions {}),
+      },
+      &MaskingKey::new(b"key".to_vec()),
+    )
+  }
+
+  fn rule(column: Option<&str>, column_pattern: Option<&str>, method: MaskMethod) -> MaskingRule {
+    MaskingRule {
+      column: column.map(Into::into),
+      column_pattern: column_pattern.map(Into::into),
+      method,
+      visible_chars: None,
+    }
+  }
+
+  fn column<'a>(schema: Option<&'a str>, table: Option<&'a str>, name: &'a str) -> ResultColumn<'a> {
+    ResultColumn { schema, table, name }
+  }
+
+  #[test]
+  fn test_rule_matching() {
+    let masker = masker(vec![
+      rule(Some("shop.users.email"), None, MaskMethod::Null),
+      rule(Some("*.phone"), None, MaskMethod::Partial),
+      rule(None, Some("^(ssn|tax_id)$"), MaskMethod::Hash),
+    ]);
+    let method = |x: ResultColumn| masker.mask_for(&[x]).map(|x| x.method);
+
+    assert_eq!(method(column(Some("shop"), Some("users"), "EMAIL")), Some(MaskMethod::Null));
+    assert_eq!(method(column(Some("crm"), Some("users"), "email")), None);
+    assert_eq!(method(column(Some("crm"), Some("users"), "phone")), Some(MaskMethod::Partial));
+    assert_eq!(method(column(None, Some("x"), "Tax_ID")), Some(MaskMethod::Hash));
+    assert_eq!(method(column(None, None, "tax_id_2")), None);
+    // Without a table name, only the column part of a rule is compared
+    assert_eq!(method(column(None, None, "email")), Some(MaskMethod::Null));
+  }
+
+  #[test]
+  fn test_invalid_rule() {
+    let masker = masker(vec![
+      rule(Some("a"), None, MaskMethod::Partial),
+      rule(None, Some("(unclosed"), MaskMethod::Partial),
+    ]);
+    for name in ["a", "b"] {
+      let mask = masker.mask_for(&[column(None, Some("t"), name)]).unwrap();
+      assert_eq!(mask.method, MaskMethod::Null);
+      assert_eq!(mask.apply(b"value"), None);
+    }
+  }
+
+  #[test]
+  fn test_mask_values() {
+    let masker = masker(vec![
+      rule(Some("a"), None, MaskMethod::Partial),
+      rule(Some("b"), None, MaskMethod::Hash),
+      rule(Some("c"), None, MaskMethod::Null),
+    ]);
+    let apply = |name, value: &str| {
+      masker
+        .mask_for(&[column(None, None, name)])
+        .and_then(|x| x.apply(value.as_bytes()))
+        .map(|x| String::from_utf8_lossy(&x).into_owned())
+    };
+
+    assert_eq!(apply("a", "+49 170 1234567").as_deref(), Some("***********4567"));
+    assert_eq!(apply("a", "äöü").as_deref(), Some("***"));
+    assert_eq!(
+      apply("b", "abc").as_deref(),
+      Some("9c196e32dc0175f86f4b1cb89289d6619de6bee699e4c378e68309ed97a1a6ab")
+    );
+    assert_eq!(apply("c", "abc"), None);
+  }
+
+  #[test]
+  fn test_masking_key() {
+    let config = OmnitronConfig {
+      store: Default::default(),
+      paths_relative_to: std::env::temp_dir().join(format!("omnitron-masking-{}", Uuid::new_v4())),
+    };
+
+    let key = MaskingKey::load(&config).unwrap();
+    assert_eq!(key.0.expose_secret().len(), MASKING_KEY_LENGTH);
+    // The key is generated once and kept
+    assert_eq!(MaskingKey::load(&config).unwrap().hash(b"abc"), key.hash(b"abc"));
+    assert_ne!(MaskingKey::new(b"other".to_vec()).hash(b"abc"), key.hash(b"abc"));
+    std::fs::remove_dir_all(&config.paths_relative_to).unwrap();
+  }
+}
@@ -1,7 +1,7 @@
 use std::sync::Arc;
 
 use omnitron_db_entities::Session;
-use omnitron_gate_common::{OmnitronError, SessionId, Target};
+use omnitron_gate_common::{OmnitronError, SessionId, SessionLimits, Target};
 use sea_orm::{ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter};
 use tokio::sync::Mutex;
 
@@ -85,6 +85,23 @@ impl OmnitronServerHandle {
 
     Ok(())
   }
+
+  /// Like [Self::set_target], but fails if the user already has as many sessions open to the target as allowed
+  pub async fn set_target_within_limits(
+    &self,
+    username: &str,
+    target: &Target,
+    limits: &SessionLimits,
+  ) -> Result<(), OmnitronError> {
+    // Keep the registry locked until the target is set, so that simultaneous logins can't both slip under the limit
+    let state = self.state.lock().await;
+    state.check_session_limit(self.id, username, &target.name, limits).await?;
+    self.set_target(target).await
+  }
+
+  pub async fn emit_output(&self, data: &[u8]) {
+    self.session_state.lock().await.emit_output(data);
+  }
 }
 
 impl Drop for OmnitronServerHandle {
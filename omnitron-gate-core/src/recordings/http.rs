@@ -0,0 +1,211 @@
+use std::collections::HashMap;
+
+use async_trait::async_trait;
+use omnitron_db_entities::Recording::RecordingKind;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use super::writer::RecordingWriter;
+use super::{Recorder, Result};
+
+/// A request/response pair in the HAR 1.2 format:
+/// <http://www.softwareishard.com/blog/har-12-spec/>
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct HarEntry {
+  pub started_date_time: String,
+  /// Total time in milliseconds
+  pub time: f64,
+  pub request: HarRequest,
+  pub response: HarResponse,
+  pub cache: HashMap<String, String>,
+  pub timings: HarTimings,
+  /// Websocket messages in the format used by Chrome's dev tools
+  #[serde(rename = "_webSocketMessages", default, skip_serializing_if = "Vec::is_empty")]
+  pub websocket_messages: Vec<HarWebSocketMessage>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct HarRequest {
+  pub method: String,
+  pub url: String,
+  pub http_version: String,
+  pub cookies: Vec<HarNameValue>,
+  pub headers: Vec<HarNameValue>,
+  pub query_string: Vec<HarNameValue>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub post_data: Option<HarPostData>,
+  pub headers_size: i64,
+  pub body_size: i64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct HarResponse {
+  pub status: u16,
+  pub status_text: String,
+  pub http_version: String,
+  pub cookies: Vec<HarNameValue>,
+  pub headers: Vec<HarNameValue>,
+  pub content: HarContent,
+  #[serde(rename = "redirectURL")]
+  pub redirect_url: String,
+  pub headers_size: i64,
+  pub body_size: i64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct HarNameValue {
+  pub name: String,
+  pub value: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct HarPostData {
+  pub mime_type: String,
+  pub text: String,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub comment: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct HarContent {
+  pub size: i64,
+  pub mime_type: String,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub text: Option<String>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub encoding: Option<String>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub comment: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct HarTimings {
+  pub send: f64,
+  pub wait: f64,
+  pub receive: f64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum HarWebSocketDirection {
+  /// Sent by the user's browser
+  Send,
+  /// Sent by the target
+  Receive,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct HarWebSocketMessage {
+  #[serde(rename = "type")]
+  pub direction: HarWebSocketDirection,
+  /// Unix timestamp in seconds
+  pub time: f64,
+  pub opcode: u8,
+  pub data: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Har {
+  pub log: HarLog,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct HarLog {
+  pub version: String,
+  pub creator: HarCreator,
+  pub entries: Vec<HarEntry>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct HarCreator {
+  pub name: String,
+  pub version: String,
+}
+
+/// A line in an HTTP recording. Websocket messages arrive after their
+/// upgrade request has been written and refer to it by ID.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+#[allow(clippy::large_enum_variant)]
+enum HttpRecordingItem {
+  Entry { id: Uuid, entry: HarEntry },
+  WebsocketMessage { entry_id: Uuid, message: HarWebSocketMessage },
+}
+
+/// Writes the requests made through an HTTP target as JSON lines,
+/// to be assembled into a HAR file with [`har_from_recording`]
+#[derive(Clone)]
+pub struct HttpRecorder {
+  writer: RecordingWriter,
+}
+
+impl HttpRecorder {
+  async fn write_item(&mut self, item: &HttpRecordingItem) -> Result<()> {
+    let mut line = serde_json::to_vec(item)?;
+    line.push(b'\n');
+    self.writer.write(&line).await
+  }
+
+  pub async fn write_entry(&mut self, id: Uuid, entry: HarEntry) -> Result<()> {
+    self.write_item(&HttpRecordingItem::Entry { id, entry }).await
+  }
+
+  pub async fn write_websocket_message(&mut self, entry_id: Uuid, message: HarWebSocketMessage) -> Result<()> {
+    self
+      .write_item(&HttpRecordingItem::WebsocketMessage { entry_id, message })
+      .await
+  }
+}
+
+#[async_trait]
+impl Recorder for HttpRecorder {
+  type Params = ();
+
+  fn kind() -> RecordingKind {
+    RecordingKind::Http
+  }
+
+  async fn new(writer: RecordingWriter, _params: Self::Params) -> Result<Self> {
+    Ok(Self { writer })
+  }
+}
+
+/// Assembles an HTTP recording into a HAR log. A truncated last line
+/// (e.g. of a recording that's still being written) is skipped.
+pub fn har_from_recording(data: &[u8]) -> Result<Har> {
+  let mut entries: Vec<(Uuid, HarEntry)> = vec![];
+  let lines: Vec<&[u8]> = data.split(|x| *x == b'\n').filter(|x| !x.is_empty()).collect();
+  for (index, line) in lines.iter().enumerate() {
+    let item = match serde_json::from_slice(line) {
+      Ok(item) => item,
+      Err(_) if index == lines.len() - 1 => break,
+      Err(error) => return Err(error.into()),
+    };
+    match item {
+      HttpRecordingItem::Entry { id, entry } => entries.push((id, entry)),
+      HttpRecordingItem::WebsocketMessage { entry_id, message } => {
+        if let Some((_, entry)) = entries.iter_mut().rev().find(|(id, _)| *id == entry_id) {
+          entry.websocket_messages.push(message);
+        }
+      }
+    }
+  }
+
+  Ok(Har {
+    log: HarLog {
+      version: "1.2".to_owned(),
+      creator: HarCreator {
+        name: "Omnitron".to_owned(),
+        version: env!("CARGO_PKG_VERSION").to_owned(),
+      },
+      entries: entries.into_iter().map(|(_, entry)| entry).collect(),
+    },
+  })
+}
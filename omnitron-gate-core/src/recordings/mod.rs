@@ -0,0 +1,120 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use omnitron_db_entities::Recording::{self, RecordingKind};
+use omnitron_gate_common::helpers::fs::secure_directory;
+use omnitron_gate_common::{OmnitronConfig, RecordingsConfig, SessionId};
+use sea_orm::{ActiveModelTrait, DatabaseConnection};
+use tokio::sync::Mutex;
+use tracing::*;
+use uuid::Uuid;
+
+mod http;
+mod terminal;
+mod writer;
+pub use http::*;
+pub use terminal::*;
+pub use writer::RecordingWriter;
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+  #[error("I/O: {0}")]
+  Io(#[from] std::io::Error),
+
+  #[error("Database: {0}")]
+  Database(#[from] sea_orm::DbErr),
+
+  #[error("Failed to serialize a recording item: {0}")]
+  Serialization(#[from] serde_json::Error),
+
+  #[error("Writer is closed")]
+  Closed,
+
+  #[error("Disabled")]
+  Disabled,
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+#[async_trait]
+pub trait Recorder: Sized {
+  type Params: Send;
+
+  fn kind() -> RecordingKind;
+  async fn new(writer: RecordingWriter, params: Self::Params) -> Result<Self>;
+}
+
+/// Whenever both locks are needed, `SessionRecordings` must be locked
+/// before the database connection, since [SessionRecordings::start]
+/// takes the database lock while its caller holds the recordings one.
+pub struct SessionRecordings {
+  db: Arc<Mutex<DatabaseConnection>>,
+  path: PathBuf,
+  config: RecordingsConfig,
+}
+
+impl SessionRecordings {
+  pub fn new(db: Arc<Mutex<DatabaseConnection>>, config: &OmnitronConfig) -> Result<Self> {
+    let mut path = config.paths_relative_to.clone();
+    path.push(&config.store.recordings.path);
+    if config.store.recordings.enable {
+      std::fs::create_dir_all(&path)?;
+      secure_directory(&path)?;
+    }
+    Ok(Self {
+      db,
+      config: config.store.recordings.clone(),
+      path,
+    })
+  }
+
+  pub async fn start<T>(&mut self, id: &SessionId, name: String, params: T::Params) -> Result<T>
+  where
+    T: Recorder,
+  {
+    if !self.config.enable {
+      return Err(Error::Disabled);
+    }
+
+    let path = self.path_for(id, &name);
+    if let Some(parent) = path.parent() {
+      tokio::fs::create_dir_all(parent).await?;
+      secure_directory(parent)?;
+    }
+    info!(%name, path=?path, "Recording session {}", id);
+
+    let model = {
+      use sea_orm::ActiveValue::Set;
+      let values = Recording::ActiveModel {
+        id: Set(Uuid::new_v4()),
+        started: Set(chrono::Utc::now()),
+        session_id: Set(*id),
+        name: Set(name),
+        kind: Set(T::kind()),
+        ..Default::default()
+      };
+
+      let db = self.db.lock().await;
+      values.insert(&*db).await.map_err(Error::Database)?
+    };
+
+    let writer = RecordingWriter::new(path, model, self.db.clone()).await?;
+    T::new(writer, params).await
+  }
+
+  pub fn path_for(&self, session_id: &SessionId, name: &dyn AsRef<std::path::Path>) -> PathBuf {
+    self.path.join(session_id.to_string()).join(name)
+  }
+
+  pub async fn remove(&self, session_id: &SessionId, name: &str) -> Result<()> {
+    let path = self.path_for(session_id, &name);
+    tokio::fs::remove_file(&path).await?;
+    if let Some(parent) = path.parent() {
+      if tokio::fs::read_dir(parent).await?.next_entry().await?.is_none() {
+        tokio::fs::remove_dir(parent).await?;
+      }
+    }
+    Ok(())
+  }
+}
@@ -0,0 +1,296 @@
+use std::collections::HashMap;
+
+use async_trait::async_trait;
+use omnitron_db_entities::Recording::RecordingKind;
+use serde::Serialize;
+use serde_json::json;
+use tokio::time::Instant;
+
+use super::writer::RecordingWriter;
+use super::{Recorder, Result};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TerminalRecordingStreamId {
+  Input,
+  Output,
+  Error,
+}
+
+impl TerminalRecordingStreamId {
+  fn event_code(&self) -> &'static str {
+    match self {
+      Self::Input => "i",
+      // asciicast has no separate stream for stderr
+      Self::Output | Self::Error => "o",
+    }
+  }
+}
+
+#[derive(Debug, Clone)]
+pub struct TerminalRecorderParams {
+  pub cols: u32,
+  pub rows: u32,
+  pub term: Option<String>,
+}
+
+impl Default for TerminalRecorderParams {
+  fn default() -> Self {
+    Self {
+      cols: 80,
+      rows: 24,
+      term: None,
+    }
+  }
+}
+
+#[derive(Serialize)]
+struct AsciicastHeader {
+  version: u8,
+  width: u32,
+  height: u32,
+  timestamp: i64,
+  #[serde(skip_serializing_if = "HashMap::is_empty")]
+  env: HashMap<&'static str, String>,
+}
+
+/// Writes terminal sessions in the asciicast v2 format:
+/// <https://docs.asciinema.org/manual/asciicast/v2/>
+pub struct TerminalRecorder {
+  writer: RecordingWriter,
+  started_at: Instant,
+  /// Trailing bytes of an incomplete UTF-8 sequence, per stream
+  partial_chars: HashMap<TerminalRecordingStreamId, Vec<u8>>,
+}
+
+impl TerminalRecorder {
+  fn elapsed(&self) -> f64 {
+    self.started_at.elapsed().as_secs_f64()
+  }
+
+  async fn write_event(&mut self, code: &str, data: &str) -> Result<()> {
+    let mut line = serde_json::to_vec(&json!([self.elapsed(), code, data]))?;
+    line.push(b'\n');
+    self.writer.write(&line).await
+  }
+
+  pub async fn write(&mut self, stream: TerminalRecordingStreamId, data: &[u8]) -> Result<()> {
+    let mut buffer = self.partial_chars.remove(&stream).unwrap_or_default();
+    buffer.extend_from_slice(data);
+
+    let text = match std::str::from_utf8(&buffer) {
+      Ok(text) => text.to_owned(),
+      Err(error) if error.error_len().is_none() => {
+        // Hold back an incomplete multibyte sequence until the next chunk arrives
+        let remainder = buffer.split_off(error.valid_up_to());
+        self.partial_chars.insert(stream, remainder);
+        String::from_utf8_lossy(&buffer).into_owned()
+      }
+      Err(_) => String::from_utf8_lossy(&buffer).into_owned(),
+    };
+
+    if text.is_empty() {
+      return Ok(());
+    }
+    self.write_event(stream.event_code(), &text).await
+  }
+
+  pub async fn write_pty_resize(&mut self, cols: u32, rows: u32) -> Result<()> {
+    self.write_event("r", &format!("{cols}x{rows}")).await
+  }
+}
+
+#[async_trait]
+impl Recorder for TerminalRecorder {
+  type Params = TerminalRecorderParams;
+
+  fn kind() -> RecordingKind {
+    RecordingKind::Terminal
+  }
+
+  async fn new(mut writer: RecordingWriter, params: Self::Params) -> Result<Self> {
+    let mut env = HashMap::new();
+    if let Some(term) = params.term {
+      env.insert("TERM", term);
+    }
+    let header = AsciicastHeader {
+      version: 2,
+      width: params.cols,
+      height: params.rows,
+      timestamp: chrono::Utc::now().timestamp(),
+      env,
+    };
+    let mut line = serde_json::to_vec(&header)?;
+    line.push(b'\n');
+    writer.write(&line).await?;
+
+    Ok(Self {
+      writer,
+      started_at: Instant::now(),
+      partial_chars: HashMap::new(),
+    })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::path::PathBuf;
+  use std::sync::Arc;
+  use std::time::Duration;
+
+  use omnitron_db_entities::{Recording, Session};
+  use omnitron_gate_common::{OmnitronConfig, SessionId};
+  use sea_orm::{ActiveModelTrait, Database, DatabaseConnection, EntityTrait};
+  use tokio::sync::Mutex;
+  use uuid::Uuid;
+
+  use super::*;
+  use crate::recordings::SessionRecordings;
+
+  async fn make_recordings() -> (SessionRecordings, Arc<Mutex<DatabaseConnection>>, SessionId, PathBuf) {
+    let db = Database::connect("sqlite::memory:").await.unwrap();
+    omnitron_db_migrations::migrate_database(&db).await.unwrap();
+
+    let session_id = Uuid::new_v4();
+    {
+      use sea_orm::ActiveValue::Set;
+      Session::ActiveModel {
+        id: Set(session_id),
+        remote_address: Set("127.0.0.1:22".into()),
+        started: Set(chrono::Utc::now()),
+        protocol: Set("SSH".into()),
+        ..Default::default()
+      }
+      .insert(&db)
+      .await
+      .unwrap();
+    }
+
+    let root = std::env::temp_dir().join(format!("omnitron-recordings-{}", Uuid::new_v4()));
+    let mut config = OmnitronConfig {
+      store: Default::default(),
+      paths_relative_to: root.clone(),
+    };
+    config.store.recordings.enable = true;
+    config.store.recordings.path = "recordings".into();
+
+    let db = Arc::new(Mutex::new(db));
+    let recordings = SessionRecordings::new(db.clone(), &config).unwrap();
+    (recordings, db, session_id, root)
+  }
+
+  /// Drops the recorder and waits for the writer task to flush and mark the recording as ended
+  async fn finish(recorder: TerminalRecorder, db: &Mutex<DatabaseConnection>) -> Recording::Model {
+    drop(recorder);
+    for _ in 0..100 {
+      let recordings = Recording::Entity::find().all(&*db.lock().await).await.unwrap();
+      if let Some(recording) = recordings.into_iter().find(|r| r.ended.is_some()) {
+        return recording;
+      }
+      tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    panic!("Recording was never marked as ended");
+  }
+
+  fn read_lines(recordings: &SessionRecordings, recording: &Recording::Model) -> Vec<serde_json::Value> {
+    let data = std::fs::read_to_string(recordings.path_for(&recording.session_id, &recording.name)).unwrap();
+    data.lines().map(|line| serde_json::from_str(line).unwrap()).collect()
+  }
+
+  #[tokio::test]
+  async fn test_asciicast_output() {
+    let (mut recordings, db, session_id, root) = make_recordings().await;
+    let params = TerminalRecorderParams {
+      cols: 120,
+      rows: 40,
+      term: Some("xterm-256color".into()),
+    };
+    let mut recorder = recordings
+      .start::<TerminalRecorder>(&session_id, "shell-1".into(), params)
+      .await
+      .unwrap();
+
+    recorder.write(TerminalRecordingStreamId::Input, b"ls\r").await.unwrap();
+    recorder
+      .write(TerminalRecordingStreamId::Output, b"file.txt\r\n")
+      .await
+      .unwrap();
+    recorder.write(TerminalRecordingStreamId::Error, b"oops\r\n").await.unwrap();
+    recorder.write_pty_resize(100, 30).await.unwrap();
+
+    let recording = finish(recorder, &db).await;
+    assert_eq!(recording.kind, RecordingKind::Terminal);
+    let lines = read_lines(&recordings, &recording);
+
+    let header = &lines[0];
+    assert_eq!(header["version"], 2);
+    assert_eq!(header["width"], 120);
+    assert_eq!(header["height"], 40);
+    assert_eq!(header["env"]["TERM"], "xterm-256color");
+
+    let events: Vec<_> = lines[1..]
+      .iter()
+      .map(|event| (event[1].as_str().unwrap(), event[2].as_str().unwrap()))
+      .collect();
+    assert_eq!(
+      events,
+      vec![("i", "ls\r"), ("o", "file.txt\r\n"), ("o", "oops\r\n"), ("r", "100x30")]
+    );
+
+    let mut last_time = 0.0;
+    for event in &lines[1..] {
+      let time = event[0].as_f64().unwrap();
+      assert!(time >= last_time);
+      last_time = time;
+    }
+
+    std::fs::remove_dir_all(root).unwrap();
+  }
+
+  #[tokio::test]
+  async fn test_split_utf8_sequences() {
+    let (mut recordings, db, session_id, root) = make_recordings().await;
+    let mut recorder = recordings
+      .start::<TerminalRecorder>(&session_id, "shell-1".into(), TerminalRecorderParams::default())
+      .await
+      .unwrap();
+
+    let text = "héllo ✓".as_bytes();
+    // Split inside the three-byte check mark
+    let (first, second) = text.split_at(text.len() - 2);
+    recorder.write(TerminalRecordingStreamId::Output, first).await.unwrap();
+    // Other streams keep their own partial sequences
+    recorder.write(TerminalRecordingStreamId::Input, b"q").await.unwrap();
+    recorder.write(TerminalRecordingStreamId::Output, second).await.unwrap();
+    // Invalid bytes are replaced rather than held back forever
+    recorder.write(TerminalRecordingStreamId::Output, b"\xffx").await.unwrap();
+
+    let recording = finish(recorder, &db).await;
+    let lines = read_lines(&recordings, &recording);
+
+    assert_eq!(lines[0]["width"], 80);
+    assert_eq!(lines[0]["height"], 24);
+    assert!(lines[0].get("env").is_none());
+
+    let events: Vec<_> = lines[1..]
+      .iter()
+      .map(|event| (event[1].as_str().unwrap(), event[2].as_str().unwrap()))
+      .collect();
+    assert_eq!(events, vec![("o", "héllo "), ("i", "q"), ("o", "✓"), ("o", "\u{fffd}x")]);
+
+    std::fs::remove_dir_all(root).unwrap();
+  }
+
+  #[tokio::test]
+  async fn test_disabled() {
+    let db = Database::connect("sqlite::memory:").await.unwrap();
+    let config = OmnitronConfig {
+      store: Default::default(),
+      paths_relative_to: PathBuf::new(),
+    };
+    let mut recordings = SessionRecordings::new(Arc::new(Mutex::new(db)), &config).unwrap();
+    let result = recordings
+      .start::<TerminalRecorder>(&Uuid::new_v4(), "shell-1".into(), TerminalRecorderParams::default())
+      .await;
+    assert!(matches!(result, Err(crate::recordings::Error::Disabled)));
+  }
+}
@@ -0,0 +1,83 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use bytes::Bytes;
+use omnitron_db_entities::Recording;
+use omnitron_gate_common::helpers::fs::secure_file;
+use sea_orm::{ActiveModelTrait, DatabaseConnection};
+use tokio::fs::File;
+use tokio::io::{AsyncWriteExt, BufWriter};
+use tokio::sync::{mpsc, Mutex};
+use tracing::*;
+
+use super::{Error, Result};
+
+/// Appends recording data to a file in a background task.
+/// The recording is marked as ended in the DB once the writer is dropped.
+#[derive(Clone)]
+pub struct RecordingWriter {
+  sender: mpsc::Sender<Bytes>,
+}
+
+impl RecordingWriter {
+  pub(crate) async fn new(path: PathBuf, model: Recording::Model, db: Arc<Mutex<DatabaseConnection>>) -> Result<Self> {
+    let file = File::create(&path).await?;
+    secure_file(&path)?;
+    let mut writer = BufWriter::new(file);
+    let (sender, mut receiver) = mpsc::channel::<Bytes>(1024);
+
+    tokio::spawn(async move {
+      if let Err(error) = async {
+        let mut last_flush = tokio::time::Instant::now();
+        loop {
+          tokio::select! {
+            data = receiver.recv() => match data {
+              Some(data) => {
+                writer.write_all(&data).await?;
+                if last_flush.elapsed() > Duration::from_secs(5) {
+                  writer.flush().await?;
+                  last_flush = tokio::time::Instant::now();
+                }
+              }
+              None => break,
+            },
+            _ = tokio::time::sleep(Duration::from_secs(5)) => {
+              writer.flush().await?;
+              last_flush = tokio::time::Instant::now();
+            }
+          }
+        }
+        writer.flush().await?;
+        Ok::<_, anyhow::Error>(())
+      }
+      .await
+      {
+        error!(%error, ?path, "Failed to write recording");
+      }
+
+      if let Err(error) = async {
+        use sea_orm::ActiveValue::Set;
+        let db = db.lock().await;
+        let mut model: Recording::ActiveModel = model.into();
+        model.ended = Set(Some(chrono::Utc::now()));
+        model.update(&*db).await?;
+        Ok::<_, anyhow::Error>(())
+      }
+      .await
+      {
+        error!(%error, "Failed to mark the recording as ended");
+      }
+    });
+
+    Ok(Self { sender })
+  }
+
+  pub async fn write(&mut self, data: &[u8]) -> Result<()> {
+    self
+      .sender
+      .send(Bytes::copy_from_slice(data))
+      .await
+      .map_err(|_| Error::Closed)
+  }
+}
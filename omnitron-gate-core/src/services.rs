@@ -5,19 +5,24 @@ use anyhow::Result;
 use omnitron_gate_common::OmnitronConfig;
 use sea_orm::DatabaseConnection;
 use tokio::sync::Mutex;
+use tracing::*;
 
 use crate::db::{connect_to_db, populate_db};
-use crate::{AuthStateStore, ConfigProvider, DatabaseConfigProvider, State};
+use crate::recordings::SessionRecordings;
+use crate::{AuthStateStore, ConfigProvider, DatabaseConfigProvider, LdapConfigProvider, LoginProtection, MaskingKey, State};
 
-type ConfigProviderArc = Arc<Mutex<dyn ConfigProvider + Send + 'static>>;
+pub type ConfigProviderArc = Arc<Mutex<Box<dyn ConfigProvider + Send + 'static>>>;
 
 #[derive(Clone)]
 pub struct Services {
   pub db: Arc<Mutex<DatabaseConnection>>,
   pub config: Arc<Mutex<OmnitronConfig>>,
   pub state: Arc<Mutex<State>>,
+  pub recordings: Arc<Mutex<SessionRecordings>>,
   pub config_provider: ConfigProviderArc,
   pub auth_state_store: Arc<Mutex<AuthStateStore>>,
+  pub login_protection: LoginProtection,
+  pub masking_key: MaskingKey,
   pub admin_token: Arc<Mutex<Option<String>>>,
 }
 
@@ -27,17 +32,27 @@ impl Services {
     populate_db(&mut db, &mut config).await?;
     let db = Arc::new(Mutex::new(db));
 
-    let config = Arc::new(Mutex::new(config));
+    let recordings = SessionRecordings::new(db.clone(), &config)?;
+    let recordings = Arc::new(Mutex::new(recordings));
+
+    let masking_key = MaskingKey::load(&config)?;
 
-    let config_provider = Arc::new(Mutex::new(DatabaseConfigProvider::new(&db).await));
+    let config = Arc::new(Mutex::new(config));
+    let config_provider = Arc::new(Mutex::new(make_config_provider(&db, &config).await));
 
     let auth_state_store = Arc::new(Mutex::new(AuthStateStore::new(config_provider.clone())));
 
+    let login_protection = LoginProtection::new(&db, &config);
+
     tokio::spawn({
       let auth_state_store = auth_state_store.clone();
+      let login_protection = login_protection.clone();
       async move {
         loop {
           auth_state_store.lock().await.vacuum().await;
+          if let Err(error) = login_protection.vacuum().await {
+            error!(?error, "Failed to clean up login failures");
+          }
           tokio::time::sleep(Duration::from_secs(60)).await;
         }
       }
@@ -47,9 +62,63 @@ impl Services {
       db: db.clone(),
       config: config.clone(),
       state: State::new(&db),
+      recordings,
       config_provider,
       auth_state_store,
+      login_protection,
+      masking_key,
       admin_token: Arc::new(Mutex::new(admin_token)),
     })
   }
+
+  /// Replaces the config provider with one for the current config,
+  /// e.g. after LDAP has been enabled or disabled
+  pub async fn reload_config_provider(&self) {
+    let config_provider = make_config_provider(&self.db, &self.config).await;
+    *self.config_provider.lock().await = config_provider;
+  }
+}
+
+async fn make_config_provider(
+  db: &Arc<Mutex<DatabaseConnection>>,
+  config: &Arc<Mutex<OmnitronConfig>>,
+) -> Box<dyn ConfigProvider + Send + 'static> {
+  let ldap_config = config.lock().await.store.ldap.clone();
+  if ldap_config.enable {
+    Box::new(LdapConfigProvider::new(db, config, ldap_config).await)
+  } else {
+    Box::new(DatabaseConfigProvider::new(db, config).await)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[tokio::test]
+  async fn test_reload_config_provider() {
+    let dir = std::env::temp_dir().join(format!("omnitron-services-{}", uuid::Uuid::new_v4()));
+    let config = OmnitronConfig {
+      store: Default::default(),
+      paths_relative_to: dir.clone(),
+    };
+    let services = Services::new(config, None).await.unwrap();
+    let list_users = || async { services.config_provider.lock().await.list_users().await };
+    assert!(list_users().await.is_ok());
+
+    // Switching to LDAP takes effect without a restart - the server isn't reachable
+    {
+      let mut config = services.config.lock().await;
+      config.store.ldap.enable = true;
+      config.store.ldap.url = "ldap://127.0.0.1:1".into();
+    }
+    services.reload_config_provider().await;
+    assert!(list_users().await.is_err());
+
+    services.config.lock().await.store.ldap.enable = false;
+    services.reload_config_provider().await;
+    assert!(list_users().await.is_ok());
+
+    std::fs::remove_dir_all(dir).unwrap();
+  }
 }
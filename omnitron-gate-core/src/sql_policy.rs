@@ -0,0 +1,696 @@
+use omnitron_gate_common::{Role, SqlPolicy, SqlPolicyDenial, Target};
+use regex::{Regex, RegexBuilder};
+use tracing::*;
+
+/// Lexical differences between the supported SQL flavours
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SqlDialect {
+  MySql,
+  Postgres,
+}
+
+/// Statements allowed under a read-only policy
+const READ_ONLY_KEYWORDS: &[&str] = &[
+  "SELECT",
+  "SHOW",
+  "EXPLAIN",
+  "DESCRIBE",
+  "DESC",
+  "USE",
+  "SET",
+  "BEGIN",
+  "START",
+  "COMMIT",
+  "END",
+  "ROLLBACK",
+  "ABORT",
+  "SAVEPOINT",
+  "RELEASE",
+  "VALUES",
+  "TABLE",
+  "FETCH",
+  "CLOSE",
+  "DEALLOCATE",
+];
+
+/// Words that make a `SET` statement reach beyond the current session's variables,
+/// like `SET GLOBAL`, `SET PASSWORD` or `SET ROLE`
+const PRIVILEGED_SET_KEYWORDS: &[&str] = &[
+  "GLOBAL",
+  "PERSIST",
+  "PERSIST_ONLY",
+  "PASSWORD",
+  "ROLE",
+  "AUTHORIZATION",
+  "RESOURCE",
+];
+
+/// Keywords that can start the statement following `WITH` or `EXPLAIN ANALYZE`
+const QUERY_KEYWORDS: &[&str] = &[
+  "SELECT", "INSERT", "UPDATE", "DELETE", "MERGE", "REPLACE", "VALUES", "TABLE", "CREATE", "EXECUTE",
+];
+
+/// Statements that run SQL hidden in a string literal or stored routine, which the other checks can't see into
+const DYNAMIC_SQL_KEYWORDS: &[&str] = &["PREPARE", "EXECUTE", "DO", "CALL"];
+
+/// Sub-statements that modify data when nested in another statement
+const WRITE_KEYWORDS: &[&str] = &["INSERT", "UPDATE", "DELETE", "MERGE", "REPLACE"];
+
+/// What the policy checks need to know about a single statement
+#[derive(Debug, Default)]
+struct Statement {
+  /// Upper-cased words outside of parentheses
+  words: Vec<String>,
+  /// Whether `WHERE` appears outside of parentheses
+  has_where: bool,
+  /// Leading word of each parenthesized group, and whether the group has its own `WHERE`
+  nested: Vec<(String, bool)>,
+}
+
+#[derive(Default)]
+struct Group {
+  first: Option<String>,
+  has_where: bool,
+}
+
+impl Statement {
+  /// Index of the word that decides what the statement does, looking past `WITH` clauses and `EXPLAIN ANALYZE`
+  fn main_index(&self) -> Option<usize> {
+    let first = self.words.first()?;
+    let analyzes = || {
+      self
+        .words
+        .iter()
+        .chain(self.nested.iter().map(|(x, _)| x))
+        .any(|x| x == "ANALYZE" || x == "ANALYSE")
+    };
+    let look_past = first == "WITH" || (["EXPLAIN", "DESCRIBE", "DESC"].contains(&first.as_str()) && analyzes());
+    if look_past {
+      if let Some(index) = self.words.iter().skip(1).position(|x| QUERY_KEYWORDS.contains(&x.as_str())) {
+        return Some(index + 1);
+      }
+    }
+    Some(0)
+  }
+
+  fn keyword(&self) -> Option<&str> {
+    self.main_index().map(|x| self.words[x].as_str())
+  }
+
+  fn is_read_only(&self) -> bool {
+    let keyword_allowed = match self.keyword() {
+      // SELECT ... INTO creates a table or writes a file
+      Some("SELECT") => !self.words.iter().any(|x| x == "INTO"),
+      // Only session variables, `@@global.x` is lexed into a separate `GLOBAL` word
+      Some("SET") => !self.words.iter().any(|x| PRIVILEGED_SET_KEYWORDS.contains(&x.as_str())),
+      Some(keyword) => READ_ONLY_KEYWORDS.contains(&keyword),
+      None => true,
+    };
+    keyword_allowed && !self.nested.iter().any(|(x, _)| WRITE_KEYWORDS.contains(&x.as_str()))
+  }
+
+  /// The `UPDATE` or `DELETE` in this statement that has no `WHERE` clause
+  fn unfiltered_write(&self) -> Option<&str> {
+    let is_filterable = |x: &str| x == "UPDATE" || x == "DELETE";
+    if let Some(keyword) = self.keyword() {
+      if is_filterable(keyword) && !self.has_where {
+        return Some(keyword);
+      }
+    }
+    self
+      .nested
+      .iter()
+      .find(|(x, has_where)| is_filterable(x) && !has_where)
+      .map(|(x, _)| x.as_str())
+  }
+
+  /// The keyword of a statement that runs dynamic SQL
+  fn dynamic_sql(&self) -> Option<&str> {
+    [self.words.first().map(String::as_str), self.keyword()]
+      .into_iter()
+      .flatten()
+      .find(|x| DYNAMIC_SQL_KEYWORDS.contains(x))
+  }
+
+  /// Whether the statement starts with the given words, either literally or after `WITH`/`EXPLAIN ANALYZE`.
+  /// Single keywords also match parenthesized sub-statements.
+  fn starts_with(&self, prefix: &[String]) -> bool {
+    let matches_at = |index: usize| self.words.get(index..).is_some_and(|x| x.starts_with(prefix));
+    matches_at(0)
+      || self.main_index().is_some_and(matches_at)
+      || (prefix.len() == 1 && self.nested.iter().any(|(x, _)| *x == prefix[0]))
+  }
+}
+
+/// Splits SQL text into statements, skipping comments and quoted strings and identifiers.
+/// Whether backslashes escape quotes in plain strings depends on the session (MySQL's
+/// `NO_BACKSLASH_ESCAPES`, Postgres' `standard_conforming_strings`), so the caller picks.
+fn parse_statements(sql: &str, dialect: SqlDialect, backslash_escapes: bool) -> Vec<Statement> {
+  let chars: Vec<char> = sql.chars().collect();
+  let mut statements = vec![];
+  let mut current = Statement::default();
+  let mut groups: Vec<Group> = vec![];
+  let mut i = 0;
+
+  while i < chars.len() {
+    let c = chars[i];
+    let next = chars.get(i + 1).copied();
+    match c {
+      '-' if next == Some('-') => i = skip_line(&chars, i),
+      '#' if dialect == SqlDialect::MySql => i = skip_line(&chars, i),
+      '/' if next == Some('*') => {
+        if dialect == SqlDialect::MySql && chars.get(i + 2) == Some(&'!') {
+          // MySQL executes the contents of /*! ... */ comments
+          i += 3;
+          while chars.get(i).is_some_and(char::is_ascii_digit) {
+            i += 1;
+          }
+        } else {
+          i = skip_block_comment(&chars, i, dialect == SqlDialect::Postgres);
+        }
+      }
+      '*' if next == Some('/') && dialect == SqlDialect::MySql => i += 2,
+      '\'' => i = skip_quoted(&chars, i, backslash_escapes),
+      '"' => i = skip_quoted(&chars, i, dialect == SqlDialect::MySql && backslash_escapes),
+      '`' if dialect == SqlDialect::MySql => i = skip_quoted(&chars, i, false),
+      '$' if dialect == SqlDialect::Postgres => i = skip_dollar_quoted(&chars, i),
+      '(' => {
+        groups.push(Group::default());
+        i += 1;
+      }
+      ')' => {
+        if let Some(Group {
+          first: Some(first),
+          has_where,
+        }) = groups.pop()
+        {
+          current.nested.push((first, has_where));
+        }
+        i += 1;
+      }
+      ';' => {
+        groups.clear();
+        let statement = std::mem::take(&mut current);
+        if !statement.words.is_empty() || !statement.nested.is_empty() {
+          statements.push(statement);
+        }
+        i += 1;
+      }
+      c if c.is_alphabetic() || c == '_' => {
+        let start = i;
+        while chars.get(i).is_some_and(|x| x.is_alphanumeric() || *x == '_' || *x == '$') {
+          i += 1;
+        }
+        // Postgres E'...' strings support backslash escapes
+        if dialect == SqlDialect::Postgres && i == start + 1 && (c == 'E' || c == 'e') && chars.get(i) == Some(&'\'') {
+          i = skip_quoted(&chars, i, true);
+          continue;
+        }
+        let word: String = chars[start..i].iter().collect::<String>().to_uppercase();
+        let is_where = word == "WHERE";
+        match groups.last_mut() {
+          Some(group) => {
+            group.has_where |= is_where;
+            group.first.get_or_insert(word);
+          }
+          None => {
+            current.has_where |= is_where;
+            current.words.push(word);
+          }
+        }
+      }
+      c if c.is_ascii_digit() => {
+        while chars.get(i).is_some_and(|x| x.is_alphanumeric() || *x == '_' || *x == '.') {
+          i += 1;
+        }
+      }
+      _ => i += 1,
+    }
+  }
+
+  if !current.words.is_empty() || !current.nested.is_empty() {
+    statements.push(current);
+  }
+  statements
+}
+
+fn skip_line(chars: &[char], i: usize) -> usize {
+  chars[i..].iter().position(|x| *x == '\n').map_or(chars.len(), |x| i + x + 1)
+}
+
+fn skip_block_comment(chars: &[char], mut i: usize, nested: bool) -> usize {
+  let mut depth = 0;
+  while i < chars.len() {
+    if chars[i] == '/' && chars.get(i + 1) == Some(&'*') && (nested || depth == 0) {
+      depth += 1;
+      i += 2;
+    } else if chars[i] == '*' && chars.get(i + 1) == Some(&'/') {
+      depth -= 1;
+      i += 2;
+      if depth == 0 {
+        return i;
+      }
+    } else {
+      i += 1;
+    }
+  }
+  i
+}
+
+/// Skips a quoted string or identifier starting at `i`. Doubled quotes are always escapes.
+fn skip_quoted(chars: &[char], mut i: usize, backslash_escapes: bool) -> usize {
+  let quote = chars[i];
+  i += 1;
+  while i < chars.len() {
+    if backslash_escapes && chars[i] == '\\' {
+      i += 2;
+    } else if chars[i] == quote {
+      if chars.get(i + 1) == Some(&quote) {
+        i += 2;
+      } else {
+        return i + 1;
+      }
+    } else {
+      i += 1;
+    }
+  }
+  i
+}
+
+/// Skips a `$tag$ ... $tag$` string starting at `i`, or just the `$` if it doesn't start one
+fn skip_dollar_quoted(chars: &[char], i: usize) -> usize {
+  let mut end = i + 1;
+  if chars.get(end).is_some_and(char::is_ascii_digit) {
+    // Positional parameter
+    return end;
+  }
+  while chars.get(end).is_some_and(|x| x.is_alphanumeric() || *x == '_') {
+    end += 1;
+  }
+  if chars.get(end) != Some(&'$') {
+    return i + 1;
+  }
+  let tag = &chars[i..=end];
+  let body = end + 1;
+  chars[body..]
+    .windows(tag.len())
+    .position(|x| x == tag)
+    .map_or(chars.len(), |x| body + x + tag.len())
+}
+
+struct CompiledSqlPolicy {
+  owner: String,
+  policy: SqlPolicy,
+  deny_statements: Vec<Vec<String>>,
+  deny_patterns: Vec<(String, Regex)>,
+  /// A deny pattern that failed to compile - the policy then denies everything
+  invalid_pattern: Option<String>,
+}
+
+impl CompiledSqlPolicy {
+  fn new(policy: &SqlPolicy, owner: String) -> Self {
+    let mut deny_patterns = vec![];
+    let mut invalid_pattern = None;
+    for pattern in &policy.deny_patterns {
+      match RegexBuilder::new(pattern).case_insensitive(true).build() {
+        Ok(regex) => deny_patterns.push((pattern.clone(), regex)),
+        Err(error) => {
+          error!(%owner, %pattern, %error, "Invalid SQL deny pattern, denying all statements");
+          invalid_pattern.get_or_insert_with(|| pattern.clone());
+        }
+      }
+    }
+    let deny_statements = policy
+      .deny_statements
+      .iter()
+      .map(|x| x.split_whitespace().map(str::to_uppercase).collect::<Vec<_>>())
+      .filter(|x| !x.is_empty())
+      .collect();
+    Self {
+      owner,
+      policy: policy.clone(),
+      deny_statements,
+      deny_patterns,
+      invalid_pattern,
+    }
+  }
+
+  fn check(&self, sql: &str, statements: &[Statement]) -> Result<(), SqlPolicyDenial> {
+    if let Some(ref pattern) = self.invalid_pattern {
+      return Err(SqlPolicyDenial::InvalidPattern {
+        pattern: pattern.clone(),
+        owner: self.owner.clone(),
+      });
+    }
+    for statement in statements {
+      let keyword = || statement.keyword().unwrap_or_default().to_owned();
+
+      if let Some(dynamic) = statement.dynamic_sql() {
+        return Err(SqlPolicyDenial::Dynamic {
+          statement: dynamic.to_owned(),
+          owner: self.owner.clone(),
+        });
+      }
+      if let Some(denied) = self.deny_statements.iter().find(|x| statement.starts_with(x)) {
+        return Err(SqlPolicyDenial::Statement {
+          statement: denied.join(" "),
+          owner: self.owner.clone(),
+        });
+      }
+      if self.policy.read_only && !statement.is_read_only() {
+        return Err(SqlPolicyDenial::ReadOnly {
+          statement: keyword(),
+          owner: self.owner.clone(),
+        });
+      }
+      if self.policy.deny_unfiltered_writes {
+        if let Some(write) = statement.unfiltered_write() {
+          return Err(SqlPolicyDenial::Unfiltered {
+            statement: write.to_owned(),
+            owner: self.owner.clone(),
+          });
+        }
+      }
+    }
+
+    if let Some((pattern, _)) = self.deny_patterns.iter().find(|(_, regex)| regex.is_match(sql)) {
+      return Err(SqlPolicyDenial::Pattern {
+        pattern: pattern.clone(),
+        owner: self.owner.clone(),
+      });
+    }
+    Ok(())
+  }
+}
+
+/// Checks statements against the SQL policies of a target and of the user's roles that grant access to it.
+/// The target's policy always applies, while passing the policy of any one of the roles is enough.
+#[derive(Default)]
+pub struct SqlGuard {
+  target: Option<CompiledSqlPolicy>,
+  roles: Vec<CompiledSqlPolicy>,
+}
+
+impl SqlGuard {
+  pub fn new(target: &Target, roles: &[Role]) -> Self {
+    let target_policy =
+      (!target.sql_policy.is_empty()).then(|| CompiledSqlPolicy::new(&target.sql_policy, format!("target {}", target.name)));
+    // An unrestricted role lifts the restrictions of all others
+    let roles = if roles.iter().any(|x| x.sql_policy.is_empty()) {
+      vec![]
+    } else {
+      roles
+        .iter()
+        .map(|x| CompiledSqlPolicy::new(&x.sql_policy, format!("role {}", x.name)))
+        .collect()
+    };
+    Self {
+      target: target_policy,
+      roles,
+    }
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.target.is_none() && self.roles.is_empty()
+  }
+
+  pub fn check(&self, dialect: SqlDialect, sql: &str) -> Result<(), SqlPolicyDenial> {
+    if self.is_empty() {
+      return Ok(());
+    }
+    // Without knowing the session's escaping mode, a statement has to pass under both readings
+    let mut readings = vec![parse_statements(sql, dialect, dialect == SqlDialect::MySql)];
+    if sql.contains('\\') {
+      readings.push(parse_statements(sql, dialect, dialect != SqlDialect::MySql));
+    }
+    let check = |policy: &CompiledSqlPolicy| readings.iter().try_for_each(|statements| policy.check(sql, statements));
+
+    if let Some(ref target) = self.target {
+      check(target)?;
+    }
+
+    let mut first_denial = None;
+    for role in &self.roles {
+      match check(role) {
+        Ok(()) => return Ok(()),
+        Err(denial) => {
+          first_denial.get_or_insert(denial);
+        }
+      }
+    }
+    first_denial.map_or(Ok(()), Err)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use omnitron_gate_common::{TargetOptions, TargetWebAdminOptions};
+  use uuid::Uuid;
+
+  use super::*;
+
+  fn target(sql_policy: SqlPolicy) -> Target {
+    Target {
+      id: Uuid::new_v4(),
+      name: "db".into(),
+      allow_roles: vec![],
+      limits: Default::default(),
+      ip_policy: Default::default(),
+      sql_policy,
+      masking: vec![],
+      options: TargetOptions::WebAdmin(TargetWebAdminOptions {}),
+    }
+  }
+
+  fn role(name: &str, sql_policy: SqlPolicy) -> Role {
+    Role {
+      id: Uuid::new_v4(),
+      name: name.into(),
+      limits: Default::default(),
+      ip_policy: Default::default(),
+      sql_policy,
+    }
+  }
+
+  fn read_only() -> SqlPolicy {
+    SqlPolicy {
+      read_only: true,
+      ..Default::default()
+    }
+  }
+
+  #[test]
+  fn test_read_only() {
+    let guard = SqlGuard::new(&target(read_only()), &[]);
+    let allowed = |sql| guard.check(SqlDialect::Postgres, sql).is_ok();
+
+    assert!(allowed("select * from users where id = 1"));
+    assert!(allowed("  /* delete */ SELECT 'drop table x; delete from y' -- update\n"));
+    assert!(allowed("EXPLAIN DELETE FROM users"));
+    assert!(allowed("WITH x AS (SELECT 1) SELECT * FROM x"));
+    assert!(allowed("BEGIN; SELECT 1; COMMIT"));
+    assert!(allowed("SELECT $$;DROP TABLE x$$, $tag$ ' $tag$, E'\\';DROP'"));
+
+    assert!(!allowed("DELETE FROM users"));
+    assert!(!allowed("SELECT 1; DROP TABLE users"));
+    assert!(!allowed("SELECT * INTO backup FROM users"));
+    assert!(!allowed("WITH x AS (DELETE FROM users RETURNING *) SELECT * FROM x"));
+    assert!(!allowed("EXPLAIN ANALYZE DELETE FROM users"));
+    assert!(!allowed("EXPLAIN (ANALYZE, BUFFERS) UPDATE users SET a = 1"));
+    assert!(!allowed("TRUNCATE users"));
+  }
+
+  #[test]
+  fn test_read_only_set() {
+    let guard = SqlGuard::new(&target(read_only()), &[]);
+    let mysql = |sql| guard.check(SqlDialect::MySql, sql).is_ok();
+    let postgres = |sql| guard.check(SqlDialect::Postgres, sql).is_ok();
+
+    assert!(mysql("SET NAMES utf8mb4"));
+    assert!(mysql("SET autocommit = 0, @x = 'global role'"));
+    assert!(mysql("SET SESSION sql_select_limit = 10"));
+    assert!(mysql("SET @@session.wait_timeout = 60"));
+    assert!(postgres("SET search_path = reporting, public"));
+    assert!(postgres("SET LOCAL statement_timeout = '5s'"));
+
+    assert!(!mysql("SET PASSWORD FOR root = 'x'"));
+    assert!(!mysql("set password = 'x'"));
+    assert!(!mysql("SET GLOBAL read_only = 0"));
+    assert!(!mysql("SET @@GLOBAL.read_only = 0"));
+    assert!(!mysql("SET PERSIST max_connections = 1"));
+    assert!(!mysql("SET @@persist_only.max_connections = 1"));
+    assert!(!mysql("SET ROLE ALL"));
+    assert!(!mysql("SET DEFAULT ROLE admin TO bob"));
+    assert!(!mysql("SET RESOURCE GROUP batch"));
+    assert!(!postgres("SET ROLE postgres"));
+    assert!(!postgres("SET SESSION ROLE postgres"));
+    assert!(!postgres("SET SESSION AUTHORIZATION postgres"));
+  }
+
+  #[test]
+  fn test_mysql_lexing() {
+    let guard = SqlGuard::new(&target(read_only()), &[]);
+    let allowed = |sql| guard.check(SqlDialect::MySql, sql).is_ok();
+
+    assert!(allowed("SELECT `delete`, \"a\"\";drop\" FROM t # ; drop table t"));
+    assert!(allowed("SELECT 'C:\\temp', 'it''s'"));
+    assert!(!allowed("SELECT 1 /*!50000 ; DROP TABLE t */"));
+  }
+
+  #[test]
+  fn test_backslash_escape_modes() {
+    let policy = SqlPolicy {
+      deny_statements: vec!["DROP".into()],
+      ..Default::default()
+    };
+    let guard = SqlGuard::new(&target(policy), &[]);
+    let read_only_guard = SqlGuard::new(&target(read_only()), &[]);
+
+    // With NO_BACKSLASH_ESCAPES the string ends at the backslash and DROP runs
+    let sql = "SELECT 'a\\'; DROP TABLE t; #'";
+    assert!(guard.check(SqlDialect::MySql, sql).is_err());
+    assert!(read_only_guard.check(SqlDialect::MySql, sql).is_err());
+    assert!(guard.check(SqlDialect::MySql, "SELECT \"a\\\"; DROP TABLE t; #\"").is_err());
+    // ...and INTO OUTFILE escapes the read-only check within a single statement
+    assert!(read_only_guard
+      .check(SqlDialect::MySql, "SELECT 'a\\' INTO OUTFILE '/tmp/x' #'")
+      .is_err());
+
+    // With standard_conforming_strings off Postgres reads backslash escapes in plain strings
+    let sql = "SELECT 'a\\''; DROP TABLE t; --'";
+    assert!(guard.check(SqlDialect::Postgres, sql).is_err());
+    assert!(read_only_guard.check(SqlDialect::Postgres, sql).is_err());
+    assert!(guard.check(SqlDialect::Postgres, "SELECT 'a\\'; DROP TABLE t; --'").is_err());
+  }
+
+  #[test]
+  fn test_dynamic_sql() {
+    let deny_drop = SqlPolicy {
+      deny_statements: vec!["DROP".into()],
+      ..Default::default()
+    };
+    let unfiltered = SqlPolicy {
+      deny_unfiltered_writes: true,
+      ..Default::default()
+    };
+    for policy in [deny_drop, unfiltered, read_only()] {
+      let guard = SqlGuard::new(&target(policy), &[]);
+      assert!(matches!(
+        guard.check(SqlDialect::MySql, "PREPARE s FROM 'DROP TABLE t'"),
+        Err(SqlPolicyDenial::Dynamic { .. })
+      ));
+      assert!(matches!(
+        guard.check(SqlDialect::MySql, "SELECT 1; EXECUTE s"),
+        Err(SqlPolicyDenial::Dynamic { .. })
+      ));
+      assert!(guard.check(SqlDialect::MySql, "CALL cleanup()").is_err());
+      assert!(matches!(
+        guard.check(SqlDialect::Postgres, "DO $$ BEGIN EXECUTE 'DELETE FROM t'; END $$"),
+        Err(SqlPolicyDenial::Dynamic { .. })
+      ));
+      assert!(guard.check(SqlDialect::Postgres, "EXPLAIN ANALYZE EXECUTE s(1)").is_err());
+      assert!(guard.check(SqlDialect::Postgres, "SELECT 'execute', 1").is_ok());
+    }
+
+    let guard = SqlGuard::new(&target(SqlPolicy::default()), &[]);
+    assert!(guard.check(SqlDialect::MySql, "PREPARE s FROM 'DROP TABLE t'").is_ok());
+  }
+
+  #[test]
+  fn test_deny_statements_and_patterns() {
+    let policy = SqlPolicy {
+      deny_statements: vec!["drop".into(), "GRANT".into(), "alter user".into()],
+      deny_patterns: vec![r"\bpg_sleep\b".into()],
+      ..Default::default()
+    };
+    let guard = SqlGuard::new(&target(policy), &[]);
+    let check = |sql| guard.check(SqlDialect::Postgres, sql);
+
+    assert!(check("ALTER TABLE users ADD COLUMN a int").is_ok());
+    assert!(check("SELECT 'drop'").is_ok());
+    assert_eq!(
+      check("Drop Table users"),
+      Err(SqlPolicyDenial::Statement {
+        statement: "DROP".into(),
+        owner: "target db".into()
+      })
+    );
+    assert!(check("ALTER USER bob WITH SUPERUSER").is_err());
+    assert!(check("grant all on users to bob").is_err());
+    assert!(matches!(check("SELECT PG_SLEEP(10)"), Err(SqlPolicyDenial::Pattern { .. })));
+  }
+
+  #[test]
+  fn test_invalid_pattern() {
+    let invalid = SqlPolicy {
+      deny_patterns: vec!["(unclosed".into(), r"\bpg_sleep\b".into()],
+      ..Default::default()
+    };
+    let guard = SqlGuard::new(&target(invalid.clone()), &[]);
+    assert_eq!(
+      guard.check(SqlDialect::Postgres, "SELECT 1"),
+      Err(SqlPolicyDenial::InvalidPattern {
+        pattern: "(unclosed".into(),
+        owner: "target db".into()
+      })
+    );
+
+    // A broken role doesn't grant anything, but other roles still can
+    let guard = SqlGuard::new(&target(SqlPolicy::default()), &[role("broken", invalid.clone())]);
+    assert!(guard.check(SqlDialect::MySql, "SELECT 1").is_err());
+    let guard = SqlGuard::new(
+      &target(SqlPolicy::default()),
+      &[role("broken", invalid), role("read-only", read_only())],
+    );
+    assert!(guard.check(SqlDialect::MySql, "SELECT 1").is_ok());
+  }
+
+  #[test]
+  fn test_unfiltered_writes() {
+    let policy = SqlPolicy {
+      deny_unfiltered_writes: true,
+      ..Default::default()
+    };
+    let guard = SqlGuard::new(&target(policy), &[]);
+    let allowed = |sql| guard.check(SqlDialect::Postgres, sql).is_ok();
+
+    assert!(allowed("DELETE FROM users WHERE id = 1"));
+    assert!(allowed("UPDATE users SET a = 1 WHERE id IN (SELECT id FROM x)"));
+    assert!(allowed("INSERT INTO users VALUES (1)"));
+
+    assert!(!allowed("DELETE FROM users"));
+    assert!(!allowed("update users set a = (select b from x where y = 1)"));
+    assert!(!allowed("WITH d AS (DELETE FROM users) SELECT 1"));
+  }
+
+  #[test]
+  fn test_roles() {
+    let restricted = role("restricted", read_only());
+    let unrestricted = role("unrestricted", SqlPolicy::default());
+    let no_drop = role(
+      "no-drop",
+      SqlPolicy {
+        deny_statements: vec!["DROP".into()],
+        ..Default::default()
+      },
+    );
+
+    let guard = SqlGuard::new(&target(SqlPolicy::default()), &[restricted.clone()]);
+    assert!(guard.check(SqlDialect::Postgres, "DELETE FROM x WHERE y").is_err());
+
+    let guard = SqlGuard::new(&target(SqlPolicy::default()), &[restricted.clone(), unrestricted]);
+    assert!(guard.is_empty());
+
+    // Passing any one role is enough
+    let guard = SqlGuard::new(&target(SqlPolicy::default()), &[restricted, no_drop]);
+    assert!(guard.check(SqlDialect::Postgres, "DELETE FROM x WHERE y").is_ok());
+    assert!(matches!(
+      guard.check(SqlDialect::Postgres, "DROP TABLE x"),
+      Err(SqlPolicyDenial::ReadOnly { .. })
+    ));
+
+    // The target's policy always applies
+    let guard = SqlGuard::new(&target(read_only()), &[role("any", SqlPolicy::default())]);
+    assert!(guard.check(SqlDialect::Postgres, "DELETE FROM x WHERE y").is_err());
+  }
+}
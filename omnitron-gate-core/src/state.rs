@@ -1,10 +1,11 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
 use std::net::SocketAddr;
 use std::sync::{Arc, Weak};
 
 use anyhow::{anyhow, Context, Result};
+use bytes::Bytes;
 use omnitron_db_entities::Session;
-use omnitron_gate_common::{OmnitronError, ProtocolName, SessionId, Target};
+use omnitron_gate_common::{OmnitronError, ProtocolName, SessionId, SessionLimits, Target};
 use sea_orm::{ActiveModelTrait, DatabaseConnection, EntityTrait};
 use tokio::sync::{broadcast, Mutex};
 use tracing::*;
@@ -12,6 +13,9 @@ use uuid::Uuid;
 
 use crate::{OmnitronServerHandle, SessionHandle};
 
+/// Amount of recent terminal output kept for replay to new live viewers
+const LIVE_OUTPUT_BUFFER_SIZE: usize = 64 * 1024;
+
 pub struct State {
   pub sessions: HashMap<SessionId, Arc<Mutex<SessionState>>>,
   db: Arc<Mutex<DatabaseConnection>>,
@@ -96,6 +100,39 @@ impl State {
     let _ = self.change_sender.send(());
   }
 
+  /// Refuses a new session (or a session switching targets) if the user already has as many other
+  /// sessions open to the target as `limits` allow
+  pub async fn check_session_limit(
+    &self,
+    session_id: SessionId,
+    username: &str,
+    target_name: &str,
+    limits: &SessionLimits,
+  ) -> Result<(), OmnitronError> {
+    let Some(limit) = limits.max_sessions_per_user else {
+      return Ok(());
+    };
+
+    let mut count = 0;
+    for (id, session) in self.sessions.iter() {
+      if *id == session_id {
+        continue;
+      }
+      let session = session.lock().await;
+      if session.username.as_deref() == Some(username) && session.target.as_ref().map(|x| x.name.as_str()) == Some(target_name) {
+        count += 1;
+      }
+    }
+
+    if count >= limit {
+      return Err(OmnitronError::SessionLimitReached {
+        target: target_name.to_owned(),
+        limit,
+      });
+    }
+    Ok(())
+  }
+
   async fn mark_session_complete(&mut self, id: Uuid) -> Result<()> {
     use sea_orm::ActiveValue::Set;
     let db = self.db.lock().await;
@@ -116,6 +153,8 @@ pub struct SessionState {
   pub target: Option<Target>,
   pub handle: Box<dyn SessionHandle + Send>,
   change_sender: broadcast::Sender<()>,
+  output_sender: broadcast::Sender<Bytes>,
+  output_buffer: VecDeque<u8>,
 }
 
 pub struct SessionStateInit {
@@ -131,10 +170,95 @@ impl SessionState {
       target: None,
       handle: init.handle,
       change_sender,
+      output_sender: broadcast::channel(1024).0,
+      output_buffer: VecDeque::new(),
     }
   }
 
   pub fn emit_change(&self) {
     let _ = self.change_sender.send(());
   }
+
+  /// Feeds terminal output to live viewers of this session
+  pub fn emit_output(&mut self, data: &[u8]) {
+    self.output_buffer.extend(data);
+    let excess = self.output_buffer.len().saturating_sub(LIVE_OUTPUT_BUFFER_SIZE);
+    self.output_buffer.drain(..excess);
+    let _ = self.output_sender.send(Bytes::copy_from_slice(data));
+  }
+
+  /// Returns the buffered recent output along with a receiver for the output that follows it
+  pub fn subscribe_output(&self) -> (Bytes, broadcast::Receiver<Bytes>) {
+    let buffer = self.output_buffer.iter().copied().collect::<Vec<_>>();
+    (buffer.into(), self.output_sender.subscribe())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use tokio::sync::broadcast::error::TryRecvError;
+
+  use super::*;
+
+  struct DummyHandle;
+
+  impl SessionHandle for DummyHandle {
+    fn close(&mut self) {}
+  }
+
+  fn session_state() -> SessionState {
+    SessionState::new(
+      SessionStateInit {
+        remote_address: None,
+        handle: Box::new(DummyHandle),
+      },
+      broadcast::channel(1).0,
+    )
+  }
+
+  #[test]
+  fn test_output_buffer_trim() {
+    let mut state = session_state();
+    state.emit_output(&vec![b'a'; LIVE_OUTPUT_BUFFER_SIZE - 1]);
+    state.emit_output(b"bcd");
+
+    let (buffer, _) = state.subscribe_output();
+    assert_eq!(buffer.len(), LIVE_OUTPUT_BUFFER_SIZE);
+    assert!(buffer[..LIVE_OUTPUT_BUFFER_SIZE - 3].iter().all(|x| *x == b'a'));
+    assert!(buffer.ends_with(b"bcd"));
+  }
+
+  #[test]
+  fn test_late_subscriber() {
+    let mut state = session_state();
+    let (_, mut early) = state.subscribe_output();
+    state.emit_output(b"hello ");
+
+    let (buffer, mut late) = state.subscribe_output();
+    state.emit_output(b"world");
+
+    // The late subscriber gets what it missed from the buffer, and no output twice
+    assert_eq!(buffer, Bytes::from_static(b"hello "));
+    assert_eq!(late.try_recv(), Ok(Bytes::from_static(b"world")));
+    assert_eq!(late.try_recv(), Err(TryRecvError::Empty));
+
+    assert_eq!(early.try_recv(), Ok(Bytes::from_static(b"hello ")));
+    assert_eq!(early.try_recv(), Ok(Bytes::from_static(b"world")));
+  }
+
+  #[test]
+  fn test_lagging_receiver() {
+    let mut state = session_state();
+    let (_, mut receiver) = state.subscribe_output();
+    for i in 0..1026u16 {
+      state.emit_output(&i.to_be_bytes());
+    }
+
+    // The oldest chunks are skipped, the receiver then continues with the rest
+    assert_eq!(receiver.try_recv(), Err(TryRecvError::Lagged(2)));
+    assert_eq!(receiver.try_recv(), Ok(Bytes::copy_from_slice(&2u16.to_be_bytes())));
+
+    let (buffer, _) = state.subscribe_output();
+    assert_eq!(buffer.len(), 1026 * 2);
+  }
 }
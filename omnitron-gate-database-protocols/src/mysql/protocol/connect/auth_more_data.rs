@@ -0,0 +1,53 @@
+use bytes::{Buf, Bytes};
+
+use crate::err_protocol;
+use crate::error::Error;
+use crate::io::Decode;
+
+// https://dev.mysql.com/doc/dev/mysql-server/latest/page_protocol_connection_phase_packets_protocol_auth_more_data.html
+// https://dev.mysql.com/doc/dev/mysql-server/latest/page_caching_sha2_authentication_exchanges.html
+
+/// caching_sha2_password found the account in its cache - an OK packet follows
+pub const CACHING_SHA2_FAST_AUTH_SUCCESS: u8 = 0x03;
+/// caching_sha2_password needs the password in clear text over TLS or RSA-encrypted
+pub const CACHING_SHA2_PERFORM_FULL_AUTH: u8 = 0x04;
+/// Sent by the client to get the server's RSA key for caching_sha2_password
+pub const CACHING_SHA2_REQUEST_PUBLIC_KEY: u8 = 0x02;
+/// Sent by the client to get the server's RSA key for sha256_password
+pub const SHA256_REQUEST_PUBLIC_KEY: u8 = 0x01;
+
+/// Extra authentication data sent by the server during a plugin exchange,
+/// e.g. a fast auth outcome or a PEM-encoded public key
+#[derive(Debug)]
+pub struct AuthMoreData(pub Bytes);
+
+impl Decode<'_> for AuthMoreData {
+  fn decode_with(mut buf: Bytes, _: ()) -> Result<Self, Error> {
+    let header = buf.get_u8();
+    if header != 0x01 {
+      return Err(err_protocol!("expected 0x01 (AUTH_MORE_DATA) but found 0x{:x}", header));
+    }
+
+    Ok(Self(buf))
+  }
+}
+
+#[test]
+#[allow(clippy::unwrap_used)]
+fn test_decode_auth_more_data_full_auth() {
+  const DATA: &[u8] = b"\x01\x04";
+
+  let p = AuthMoreData::decode(DATA.into()).unwrap();
+
+  assert_eq!(&p.0[..], &[CACHING_SHA2_PERFORM_FULL_AUTH]);
+}
+
+#[test]
+#[allow(clippy::unwrap_used)]
+fn test_decode_auth_more_data_public_key() {
+  const DATA: &[u8] = b"\x01-----BEGIN PUBLIC KEY-----\nMFwwDQYJKoZIhvcNAQEBBQADSwAwSAJBAL\n-----END PUBLIC KEY-----\n";
+
+  let p = AuthMoreData::decode(DATA.into()).unwrap();
+
+  assert!(p.0.starts_with(b"-----BEGIN PUBLIC KEY-----"));
+}
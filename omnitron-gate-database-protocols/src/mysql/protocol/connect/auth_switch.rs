@@ -23,12 +23,12 @@ impl Decode<'_> for AuthSwitchRequest {
 
     let plugin = buf.get_str_nul()?.parse()?;
 
+    // The nonce is NUL-terminated for the hashing plugins and absent for mysql_clear_password
     // See: https://github.com/mysql/mysql-server/blob/ea7d2e2d16ac03afdd9cb72a972a95981107bf51/sql/auth/sha2_password.cc#L942
-    if buf.len() != 21 {
-      return Err(err_protocol!("expected 21 bytes but found {} bytes", buf.len()));
-    }
-    let data = buf.get_bytes(20);
-    buf.advance(1); // NUL-terminator
+    let data = match buf.last() {
+      Some(0) => buf.slice(..buf.len() - 1),
+      _ => buf,
+    };
 
     Ok(Self { plugin, data })
   }
@@ -50,3 +50,37 @@ impl Encode<'_, Capabilities> for AuthSwitchResponse {
     buf.extend_from_slice(&self.0);
   }
 }
+
+#[test]
+#[allow(clippy::unwrap_used)]
+fn test_decode_auth_switch_caching_sha2() {
+  const DATA: &[u8] = b"\xfecaching_sha2_password\x00\x114aB0c\x06gtL\x03s\x0f[4\rl4. \x00";
+
+  let p = AuthSwitchRequest::decode(DATA.into()).unwrap();
+
+  assert_eq!(p.plugin, AuthPlugin::CachingSha2Password);
+  assert_eq!(&p.data[..], b"\x114aB0c\x06gtL\x03s\x0f[4\rl4. ");
+}
+
+#[test]
+#[allow(clippy::unwrap_used)]
+fn test_decode_auth_switch_clear_password() {
+  const DATA: &[u8] = b"\xfemysql_clear_password\x00";
+
+  let p = AuthSwitchRequest::decode(DATA.into()).unwrap();
+
+  assert_eq!(p.plugin, AuthPlugin::MySqlClearPassword);
+  assert!(p.data.is_empty());
+}
+
+#[test]
+fn test_encode_auth_switch_roundtrip() {
+  let mut buf = vec![];
+  AuthSwitchRequest {
+    plugin: AuthPlugin::MySqlClearPassword,
+    data: Bytes::new(),
+  }
+  .encode_with(&mut buf, ());
+
+  assert_eq!(&buf[..], b"\xfemysql_clear_password\x00");
+}
@@ -2,11 +2,16 @@
 //!
 //! <https://dev.mysql.com/doc/internals/en/connection-phase.html>
 
+mod auth_more_data;
 mod auth_switch;
 mod handshake;
 mod handshake_response;
 mod ssl_request;
 
+pub use auth_more_data::{
+  AuthMoreData, CACHING_SHA2_FAST_AUTH_SUCCESS, CACHING_SHA2_PERFORM_FULL_AUTH, CACHING_SHA2_REQUEST_PUBLIC_KEY,
+  SHA256_REQUEST_PUBLIC_KEY,
+};
 pub use auth_switch::{AuthSwitchRequest, AuthSwitchResponse};
 pub use handshake::Handshake;
 pub use handshake_response::HandshakeResponse;
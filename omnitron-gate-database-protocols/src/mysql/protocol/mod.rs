@@ -3,9 +3,12 @@ pub mod capabilities;
 pub mod connect;
 pub mod packet;
 pub mod response;
+pub mod result_set;
 pub mod row;
+pub mod statement;
 pub mod text;
 
 pub use capabilities::Capabilities;
 pub use packet::Packet;
+pub use result_set::{ResponsePacket, ResponseProgress, ResultSetTracker};
 pub use row::Row;
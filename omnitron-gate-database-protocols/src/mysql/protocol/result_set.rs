@@ -0,0 +1,269 @@
+use bytes::Bytes;
+
+use crate::err_protocol;
+use crate::error::Error;
+use crate::io::Decode;
+use crate::mysql::io::MySqlBufExt;
+use crate::mysql::protocol::response::{EofPacket, OkPacket, Status};
+use crate::mysql::protocol::Capabilities;
+
+// https://dev.mysql.com/doc/dev/mysql-server/latest/page_protocol_com_query_response.html
+// https://dev.mysql.com/doc/dev/mysql-server/latest/page_protocol_binary_resultset.html
+
+/// Where a command's response stands after a server packet
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResponseProgress {
+  /// More packets will follow from the server
+  More,
+  /// The server asked for a LOCAL INFILE - the client sends the file
+  /// contents next and the server then responds with OK or ERR
+  LocalInfile,
+  /// The response is complete
+  Done,
+}
+
+/// What a server packet in a query response carries
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResponsePacket {
+  /// Starts a result set
+  ColumnCount,
+  ColumnDefinition,
+  Row,
+  /// OK, ERR, EOF and LOCAL INFILE packets
+  Other,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+  Start,
+  Columns(u64),
+  MetadataEof,
+  Rows,
+  Done,
+}
+
+/// Follows the packets of a (text or binary) query response to find where it ends,
+/// including any further result sets announced with `SERVER_MORE_RESULTS_EXISTS`
+#[derive(Debug)]
+pub struct ResultSetTracker {
+  capabilities: Capabilities,
+  state: State,
+}
+
+impl ResultSetTracker {
+  /// Tracks the response to COM_QUERY or COM_STMT_EXECUTE
+  pub fn new(capabilities: Capabilities) -> Self {
+    Self {
+      capabilities,
+      state: State::Start,
+    }
+  }
+
+  /// Tracks the rows sent in response to COM_STMT_FETCH
+  pub fn rows(capabilities: Capabilities) -> Self {
+    Self {
+      capabilities,
+      state: State::Rows,
+    }
+  }
+
+  /// Tells what a packet is, without advancing past it. Call before [Self::feed].
+  pub fn classify(&self, packet: &Bytes) -> ResponsePacket {
+    match (self.state, packet.first()) {
+      (_, Some(0xff)) | (_, None) | (State::Done | State::MetadataEof, _) => ResponsePacket::Other,
+      (State::Start, Some(0x00 | 0xfb)) => ResponsePacket::Other,
+      (State::Start, _) => ResponsePacket::ColumnCount,
+      (State::Columns(_), _) => ResponsePacket::ColumnDefinition,
+      (State::Rows, Some(0xfe)) if packet.len() < 0xffffff => ResponsePacket::Other,
+      (State::Rows, _) => ResponsePacket::Row,
+    }
+  }
+
+  pub fn feed(&mut self, packet: &Bytes) -> Result<ResponseProgress, Error> {
+    let header = packet.first().copied();
+    self.state = match (self.state, header) {
+      (State::Done, _) => return Err(err_protocol!("unexpected packet after the end of the response")),
+      (_, None) => return Err(err_protocol!("unexpected empty packet")),
+      (_, Some(0xff)) => State::Done,
+
+      (State::Start, Some(0x00)) => self.after_status(OkPacket::decode(packet.clone())?.status),
+      (State::Start, Some(0xfb)) => return Ok(ResponseProgress::LocalInfile),
+      (State::Start, _) => State::Columns(packet.clone().get_uint_lenenc()),
+
+      (State::Columns(remaining), _) if remaining > 1 => State::Columns(remaining - 1),
+      (State::Columns(_), _) => match self.capabilities.contains(Capabilities::DEPRECATE_EOF) {
+        true => State::Rows,
+        false => State::MetadataEof,
+      },
+
+      (State::MetadataEof, _) => {
+        let eof = EofPacket::decode_with(packet.clone(), self.capabilities)?;
+        // Rows of an opened cursor are only sent in response to COM_STMT_FETCH
+        match eof.status.contains(Status::SERVER_STATUS_CURSOR_EXISTS) {
+          true => State::Done,
+          false => State::Rows,
+        }
+      }
+
+      // A row can only start with 0xfe if it's longer than any EOF or OK packet can be
+      (State::Rows, Some(0xfe)) if packet.len() < 0xffffff => {
+        let status = match self.capabilities.contains(Capabilities::DEPRECATE_EOF) {
+          true => OkPacket::decode(packet.clone())?.status,
+          false => EofPacket::decode_with(packet.clone(), self.capabilities)?.status,
+        };
+        self.after_status(status)
+      }
+      (State::Rows, _) => State::Rows,
+    };
+
+    Ok(match self.state {
+      State::Done => ResponseProgress::Done,
+      _ => ResponseProgress::More,
+    })
+  }
+
+  fn after_status(&self, status: Status) -> State {
+    match status.contains(Status::SERVER_MORE_RESULTS_EXISTS) {
+      true => State::Start,
+      false => State::Done,
+    }
+  }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+fn feed_all(tracker: &mut ResultSetTracker, packets: &[&'static [u8]]) -> Vec<ResponseProgress> {
+  packets
+    .iter()
+    .map(|x| tracker.feed(&Bytes::from_static(x)).unwrap())
+    .collect()
+}
+
+// MySQL 8.0 response to `CALL p()` where `p` runs `SELECT 1 AS a; SELECT 'x' AS b`
+#[cfg(test)]
+const CALL_PACKETS: &[&[u8]] = &[
+  b"\x01",
+  b"\x03def\x00\x00\x00\x01a\x00\x0c\x3f\x00\x01\x00\x00\x00\x08\x81\x00\x00\x00\x00",
+  b"\x011",
+  b"\xfe\x00\x00\x0a\x00\x00\x00",
+  b"\x01",
+  b"\x03def\x00\x00\x00\x01b\x00\x0c\xff\x00\x04\x00\x00\x00\xfd\x01\x00\x27\x00\x00",
+  b"\x01x",
+  b"\xfe\x00\x00\x0a\x00\x00\x00",
+  b"\x00\x00\x00\x02\x00\x00\x00",
+];
+
+#[test]
+fn test_classify_packets() {
+  let mut tracker = ResultSetTracker::new(Capabilities::PROTOCOL_41 | Capabilities::DEPRECATE_EOF);
+  let kinds = CALL_PACKETS
+    .iter()
+    .map(|x| {
+      let packet = Bytes::from_static(x);
+      let kind = tracker.classify(&packet);
+      tracker.feed(&packet).map(|_| kind)
+    })
+    .collect::<Result<Vec<_>, _>>();
+
+  use ResponsePacket::*;
+  assert_eq!(
+    kinds.ok(),
+    Some(vec![
+      ColumnCount,
+      ColumnDefinition,
+      Row,
+      Other,
+      ColumnCount,
+      ColumnDefinition,
+      Row,
+      Other,
+      Other
+    ])
+  );
+}
+
+#[test]
+fn test_call_with_multiple_result_sets() {
+  let mut tracker = ResultSetTracker::new(Capabilities::PROTOCOL_41 | Capabilities::DEPRECATE_EOF);
+  let progress = feed_all(&mut tracker, CALL_PACKETS);
+
+  assert!(progress[..CALL_PACKETS.len() - 1]
+    .iter()
+    .all(|x| *x == ResponseProgress::More));
+  assert_eq!(progress.last(), Some(&ResponseProgress::Done));
+}
+
+// MySQL 5.7 response to `SELECT 1 AS a; DO 1` without CLIENT_DEPRECATE_EOF
+#[cfg(test)]
+const MULTI_STATEMENT_PACKETS: &[&[u8]] = &[
+  b"\x01",
+  b"\x03def\x00\x00\x00\x01a\x00\x0c\x3f\x00\x01\x00\x00\x00\x08\x81\x00\x00\x00\x00",
+  b"\xfe\x00\x00\x0a\x00",
+  b"\x011",
+  b"\xfe\x00\x00\x0a\x00",
+  b"\x00\x00\x00\x02\x00\x00\x00",
+];
+
+#[test]
+fn test_multi_statement_with_eof() {
+  let mut tracker = ResultSetTracker::new(Capabilities::PROTOCOL_41);
+  let progress = feed_all(&mut tracker, MULTI_STATEMENT_PACKETS);
+
+  assert!(progress[..MULTI_STATEMENT_PACKETS.len() - 1]
+    .iter()
+    .all(|x| *x == ResponseProgress::More));
+  assert_eq!(progress.last(), Some(&ResponseProgress::Done));
+}
+
+#[test]
+fn test_error_ends_multiple_results() {
+  let mut tracker = ResultSetTracker::new(Capabilities::PROTOCOL_41 | Capabilities::DEPRECATE_EOF);
+  let progress = feed_all(
+    &mut tracker,
+    &[
+      b"\x00\x01\x00\x0a\x00\x00\x00",
+      b"\xff\x7a\x04#42S02Table 'test.missing' doesn't exist",
+    ],
+  );
+
+  assert_eq!(progress, vec![ResponseProgress::More, ResponseProgress::Done]);
+}
+
+#[test]
+fn test_local_infile() {
+  let mut tracker = ResultSetTracker::new(Capabilities::PROTOCOL_41 | Capabilities::DEPRECATE_EOF);
+
+  assert_eq!(
+    feed_all(&mut tracker, &[b"\xfb/tmp/data.csv"]),
+    vec![ResponseProgress::LocalInfile]
+  );
+  // The server responds once the client has sent the file
+  assert_eq!(
+    feed_all(&mut tracker, &[b"\x00\x03\x00\x02\x00\x00\x00"]),
+    vec![ResponseProgress::Done]
+  );
+}
+
+#[test]
+fn test_binary_rows_starting_with_zero() {
+  let mut tracker = ResultSetTracker::new(Capabilities::PROTOCOL_41 | Capabilities::DEPRECATE_EOF);
+  let progress = feed_all(
+    &mut tracker,
+    &[
+      b"\x01",
+      b"\x03def\x00\x00\x00\x01a\x00\x0c\x3f\x00\x14\x00\x00\x00\x08\x81\x00\x00\x00\x00",
+      b"\x00\x00\x01\x00\x00\x00\x00\x00\x00\x00",
+      b"\xfe\x00\x00\x02\x00\x00\x00",
+    ],
+  );
+
+  assert_eq!(
+    progress,
+    vec![
+      ResponseProgress::More,
+      ResponseProgress::More,
+      ResponseProgress::More,
+      ResponseProgress::Done
+    ]
+  );
+}
@@ -1,6 +1,6 @@
 use std::ops::Range;
 
-use bytes::Bytes;
+use bytes::{Bytes, BytesMut};
 
 #[derive(Debug)]
 pub struct Row {
@@ -9,7 +9,30 @@ pub struct Row {
 }
 
 impl Row {
-  pub(crate) fn get(&self, index: usize) -> Option<&[u8]> {
+  pub fn get(&self, index: usize) -> Option<&[u8]> {
     self.values[index].as_ref().map(|col| &self.storage[col.start..col.end])
   }
+
+  pub fn len(&self) -> usize {
+    self.values.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.values.is_empty()
+  }
+
+  /// Replaces a value, or sets it to NULL
+  pub fn set(&mut self, index: usize, value: Option<&[u8]>) {
+    self.values[index] = value.map(|value| {
+      let mut storage = BytesMut::from(&self.storage[..]);
+      let start = storage.len();
+      storage.extend_from_slice(value);
+      self.storage = storage.freeze();
+      start..start + value.len()
+    });
+  }
+
+  pub(crate) fn values(&self) -> impl Iterator<Item = Option<&[u8]>> {
+    (0..self.len()).map(|x| self.get(x))
+  }
 }
@@ -0,0 +1,23 @@
+use bytes::{Buf, Bytes};
+
+use super::{ensure_remaining, expect_command, COM_STMT_CLOSE};
+use crate::error::Error;
+use crate::io::Decode;
+
+// https://dev.mysql.com/doc/dev/mysql-server/latest/page_protocol_com_stmt_close.html
+
+/// Deallocates a prepared statement. The server doesn't respond to it.
+#[derive(Debug)]
+pub struct StmtClose {
+  pub statement_id: u32,
+}
+
+impl Decode<'_> for StmtClose {
+  fn decode_with(mut buf: Bytes, _: ()) -> Result<Self, Error> {
+    expect_command(&mut buf, COM_STMT_CLOSE)?;
+    ensure_remaining(&buf, 4)?;
+    Ok(Self {
+      statement_id: buf.get_u32_le(),
+    })
+  }
+}
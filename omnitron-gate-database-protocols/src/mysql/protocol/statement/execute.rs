@@ -0,0 +1,149 @@
+use std::collections::HashSet;
+
+use bytes::{Buf, Bytes};
+
+use super::{ensure_remaining, expect_command, BinaryValue, COM_STMT_EXECUTE};
+use crate::err_protocol;
+use crate::error::Error;
+use crate::io::Decode;
+use crate::mysql::protocol::text::ColumnType;
+
+// https://dev.mysql.com/doc/dev/mysql-server/latest/page_protocol_com_stmt_execute.html
+
+/// Type of a bound statement parameter
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ParamType {
+  pub r#type: ColumnType,
+  pub unsigned: bool,
+}
+
+/// What the client has told the server about a statement before executing it
+#[derive(Debug, Clone, Copy)]
+pub struct StmtExecuteContext<'a> {
+  /// Parameter count from the COM_STMT_PREPARE response
+  pub params: usize,
+  /// Types bound by a previous execution, which the client doesn't have to resend
+  pub bound_types: &'a [ParamType],
+  /// Parameters whose values were sent with COM_STMT_SEND_LONG_DATA instead
+  pub long_data: &'a HashSet<u16>,
+}
+
+#[derive(Debug)]
+pub struct StmtExecute {
+  pub statement_id: u32,
+  /// Cursor type flags
+  pub flags: u8,
+  pub iteration_count: u32,
+  /// Whether `param_types` were sent with this execution
+  pub new_params_bound: bool,
+  pub param_types: Vec<ParamType>,
+  pub params: Vec<BinaryValue>,
+}
+
+impl<'a> Decode<'a, StmtExecuteContext<'a>> for StmtExecute {
+  fn decode_with(mut buf: Bytes, context: StmtExecuteContext<'a>) -> Result<Self, Error> {
+    expect_command(&mut buf, COM_STMT_EXECUTE)?;
+    ensure_remaining(&buf, 9)?;
+    let statement_id = buf.get_u32_le();
+    let flags = buf.get_u8();
+    let iteration_count = buf.get_u32_le();
+
+    let mut result = Self {
+      statement_id,
+      flags,
+      iteration_count,
+      new_params_bound: false,
+      param_types: vec![],
+      params: vec![],
+    };
+
+    if context.params == 0 {
+      return Ok(result);
+    }
+
+    let null_bitmap = {
+      let len = (context.params + 7) / 8;
+      ensure_remaining(&buf, len + 1)?;
+      buf.split_to(len)
+    };
+    result.new_params_bound = buf.get_u8() == 1;
+
+    result.param_types = if result.new_params_bound {
+      ensure_remaining(&buf, context.params * 2)?;
+      (0..context.params)
+        .map(|_| {
+          let r#type = ColumnType::try_from_u16(buf.get_u8())?;
+          let unsigned = buf.get_u8() & 0x80 != 0;
+          Ok(ParamType { r#type, unsigned })
+        })
+        .collect::<Result<_, Error>>()?
+    } else if context.bound_types.len() == context.params {
+      context.bound_types.to_vec()
+    } else {
+      return Err(err_protocol!(
+        "statement {} executed without bound parameter types",
+        statement_id
+      ));
+    };
+
+    for (index, param_type) in result.param_types.iter().enumerate() {
+      let value = if null_bitmap[index / 8] & (1 << (index % 8)) != 0 {
+        BinaryValue::Null
+      } else if u16::try_from(index).map_or(false, |x| context.long_data.contains(&x)) {
+        BinaryValue::LongData
+      } else {
+        BinaryValue::decode(&mut buf, *param_type)?
+      };
+      result.params.push(value);
+    }
+
+    Ok(result)
+  }
+}
+
+#[test]
+#[allow(clippy::unwrap_used)]
+fn test_decode_stmt_execute() {
+  // Three parameters: a NULL, a signed LONGLONG and a VAR_STRING
+  const DATA: &[u8] = b"\x17\x01\x00\x00\x00\x00\x01\x00\x00\x00\x01\x01\x06\x00\x08\x00\xfd\x00\
+    \xd6\xff\xff\xff\xff\xff\xff\xff\x05hello";
+
+  let long_data = HashSet::new();
+  let p = StmtExecute::decode_with(
+    DATA.into(),
+    StmtExecuteContext {
+      params: 3,
+      bound_types: &[],
+      long_data: &long_data,
+    },
+  )
+  .unwrap();
+
+  assert_eq!(p.statement_id, 1);
+  assert!(p.new_params_bound);
+  assert_eq!(
+    p.params,
+    vec![
+      BinaryValue::Null,
+      BinaryValue::Int(-42),
+      BinaryValue::Bytes(Bytes::from_static(b"hello"))
+    ]
+  );
+
+  // Executing again without rebinding reuses the types and takes the long data parameter from elsewhere
+  const DATA2: &[u8] = b"\x17\x01\x00\x00\x00\x00\x01\x00\x00\x00\x00\x00\x07\x00\x00\x00\x00\x00\x00\x00";
+
+  let long_data = HashSet::from([2]);
+  let p = StmtExecute::decode_with(
+    DATA2.into(),
+    StmtExecuteContext {
+      params: 3,
+      bound_types: &p.param_types,
+      long_data: &long_data,
+    },
+  )
+  .unwrap();
+
+  assert!(!p.new_params_bound);
+  assert_eq!(p.params, vec![BinaryValue::Null, BinaryValue::Int(7), BinaryValue::LongData]);
+}
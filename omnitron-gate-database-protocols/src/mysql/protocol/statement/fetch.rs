@@ -0,0 +1,25 @@
+use bytes::{Buf, Bytes};
+
+use super::{ensure_remaining, expect_command, COM_STMT_FETCH};
+use crate::error::Error;
+use crate::io::Decode;
+
+// https://dev.mysql.com/doc/dev/mysql-server/latest/page_protocol_com_stmt_fetch.html
+
+/// Fetches rows from the cursor opened by executing a statement with a cursor flag
+#[derive(Debug)]
+pub struct StmtFetch {
+  pub statement_id: u32,
+  pub rows: u32,
+}
+
+impl Decode<'_> for StmtFetch {
+  fn decode_with(mut buf: Bytes, _: ()) -> Result<Self, Error> {
+    expect_command(&mut buf, COM_STMT_FETCH)?;
+    ensure_remaining(&buf, 8)?;
+    Ok(Self {
+      statement_id: buf.get_u32_le(),
+      rows: buf.get_u32_le(),
+    })
+  }
+}
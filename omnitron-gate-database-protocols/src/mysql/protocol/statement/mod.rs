@@ -0,0 +1,55 @@
+//! Prepared statements and the binary protocol
+//!
+//! <https://dev.mysql.com/doc/dev/mysql-server/latest/page_protocol_command_phase_ps.html>
+
+mod close;
+mod execute;
+mod fetch;
+mod prepare;
+mod prepare_ok;
+mod reset;
+mod row;
+mod send_long_data;
+mod value;
+
+use bytes::{Buf, Bytes};
+pub use close::StmtClose;
+pub use execute::{ParamType, StmtExecute, StmtExecuteContext};
+pub use fetch::StmtFetch;
+pub use prepare::StmtPrepare;
+pub use prepare_ok::StmtPrepareOk;
+pub use reset::StmtReset;
+pub use row::BinaryRow;
+pub use send_long_data::StmtSendLongData;
+pub use value::BinaryValue;
+
+use crate::err_protocol;
+use crate::error::Error;
+
+pub const COM_STMT_PREPARE: u8 = 0x16;
+pub const COM_STMT_EXECUTE: u8 = 0x17;
+pub const COM_STMT_SEND_LONG_DATA: u8 = 0x18;
+pub const COM_STMT_CLOSE: u8 = 0x19;
+pub const COM_STMT_RESET: u8 = 0x1a;
+pub const COM_STMT_FETCH: u8 = 0x1c;
+
+// Buf getters panic on short input, and these packets come straight from the client
+fn ensure_remaining(buf: &Bytes, len: usize) -> Result<(), Error> {
+  if buf.remaining() < len {
+    return Err(err_protocol!(
+      "packet too short: expected {} more bytes but found {}",
+      len,
+      buf.remaining()
+    ));
+  }
+  Ok(())
+}
+
+fn expect_command(buf: &mut Bytes, command: u8) -> Result<(), Error> {
+  ensure_remaining(buf, 1)?;
+  let header = buf.get_u8();
+  if header != command {
+    return Err(err_protocol!("expected 0x{:02x} but found 0x{:02x}", command, header));
+  }
+  Ok(())
+}
@@ -0,0 +1,36 @@
+use bytes::{Buf, Bytes};
+
+use super::{expect_command, COM_STMT_PREPARE};
+use crate::error::Error;
+use crate::io::{BufExt, Decode, Encode};
+use crate::mysql::protocol::Capabilities;
+
+// https://dev.mysql.com/doc/dev/mysql-server/latest/page_protocol_com_stmt_prepare.html
+
+#[derive(Debug)]
+pub struct StmtPrepare(pub String);
+
+impl Encode<'_, Capabilities> for StmtPrepare {
+  fn encode_with(&self, buf: &mut Vec<u8>, _: Capabilities) {
+    buf.push(COM_STMT_PREPARE);
+    buf.extend(self.0.as_bytes())
+  }
+}
+
+impl Decode<'_> for StmtPrepare {
+  fn decode_with(mut buf: Bytes, _: ()) -> Result<Self, Error> {
+    expect_command(&mut buf, COM_STMT_PREPARE)?;
+    let query = buf.get_str(buf.remaining())?;
+    Ok(StmtPrepare(query))
+  }
+}
+
+#[test]
+#[allow(clippy::unwrap_used)]
+fn test_decode_stmt_prepare() {
+  const DATA: &[u8] = b"\x16SELECT * FROM users WHERE id = ?";
+
+  let p = StmtPrepare::decode(DATA.into()).unwrap();
+
+  assert_eq!(p.0, "SELECT * FROM users WHERE id = ?");
+}
@@ -0,0 +1,58 @@
+use bytes::{Buf, Bytes};
+
+use super::ensure_remaining;
+use crate::err_protocol;
+use crate::error::Error;
+use crate::io::Decode;
+
+// https://dev.mysql.com/doc/dev/mysql-server/latest/page_protocol_com_stmt_prepare.html#sect_protocol_com_stmt_prepare_response_ok
+
+/// Sent in response to COM_STMT_PREPARE, followed by `params` and then `columns` column definitions
+#[derive(Debug)]
+pub struct StmtPrepareOk {
+  pub statement_id: u32,
+  pub columns: u16,
+  pub params: u16,
+  pub warnings: u16,
+}
+
+impl Decode<'_> for StmtPrepareOk {
+  fn decode_with(mut buf: Bytes, _: ()) -> Result<Self, Error> {
+    ensure_remaining(&buf, 10)?;
+    let header = buf.get_u8();
+    if header != 0x00 {
+      return Err(err_protocol!(
+        "expected 0x00 (COM_STMT_PREPARE_OK) but found 0x{:02x}",
+        header
+      ));
+    }
+
+    let statement_id = buf.get_u32_le();
+    let columns = buf.get_u16_le();
+    let params = buf.get_u16_le();
+    buf.advance(1); // reserved
+
+    // Servers older than 4.1 don't send a warning count
+    let warnings = if buf.remaining() >= 2 { buf.get_u16_le() } else { 0 };
+
+    Ok(Self {
+      statement_id,
+      columns,
+      params,
+      warnings,
+    })
+  }
+}
+
+#[test]
+#[allow(clippy::unwrap_used)]
+fn test_decode_stmt_prepare_ok() {
+  const DATA: &[u8] = b"\x00\x01\x00\x00\x00\x02\x00\x01\x00\x00\x00\x00";
+
+  let p = StmtPrepareOk::decode(DATA.into()).unwrap();
+
+  assert_eq!(p.statement_id, 1);
+  assert_eq!(p.columns, 2);
+  assert_eq!(p.params, 1);
+  assert_eq!(p.warnings, 0);
+}
@@ -0,0 +1,130 @@
+use bytes::{Buf, Bytes};
+
+use super::{ensure_remaining, BinaryValue, ParamType};
+use crate::err_protocol;
+use crate::error::Error;
+use crate::io::{Decode, Encode};
+use crate::mysql::io::MySqlBufMutExt;
+use crate::mysql::protocol::text::{ColumnDefinition, ColumnFlags};
+use crate::mysql::protocol::Row;
+
+// https://dev.mysql.com/doc/dev/mysql-server/latest/page_protocol_binary_resultset.html#sect_protocol_binary_resultset_row
+
+/// A row of a binary result set, decoded with the column definitions of its result set.
+/// Unlike in text rows, the values are kept in their binary encoding, including any length prefix.
+#[derive(Debug)]
+pub struct BinaryRow(pub Row);
+
+impl BinaryRow {
+  /// Decodes a value with the type of its column
+  pub fn value(&self, index: usize, column: &ColumnDefinition) -> Result<BinaryValue, Error> {
+    match self.0.get(index) {
+      Some(value) => BinaryValue::decode(&mut Bytes::copy_from_slice(value), param_type(column)),
+      None => Ok(BinaryValue::Null),
+    }
+  }
+
+  /// Replaces a value with a length-prefixed string, or sets it to NULL.
+  /// The column needs to be turned into a string column to match.
+  pub fn set_bytes(&mut self, index: usize, value: Option<&[u8]>) {
+    let encoded = value.map(|value| {
+      let mut buf = Vec::with_capacity(value.len() + 9);
+      buf.put_bytes_lenenc(value);
+      buf
+    });
+    self.0.set(index, encoded.as_deref());
+  }
+}
+
+fn param_type(column: &ColumnDefinition) -> ParamType {
+  ParamType {
+    r#type: column.column_type(),
+    unsigned: column.flags().contains(ColumnFlags::UNSIGNED),
+  }
+}
+
+/// The first two bits of the NULL bitmap are reserved in result rows
+const NULL_BITMAP_OFFSET: usize = 2;
+
+impl<'de> Decode<'de, &'de [ColumnDefinition]> for BinaryRow {
+  fn decode_with(buf: Bytes, columns: &'de [ColumnDefinition]) -> Result<Self, Error> {
+    let mut rest = buf.clone();
+    ensure_remaining(&rest, 1)?;
+    let header = rest.get_u8();
+    if header != 0x00 {
+      return Err(err_protocol!("expected 0x00 (binary row) but found 0x{:02x}", header));
+    }
+
+    let bitmap_len = (columns.len() + 7 + NULL_BITMAP_OFFSET) / 8;
+    ensure_remaining(&rest, bitmap_len)?;
+    let bitmap = rest.split_to(bitmap_len);
+
+    let mut values = Vec::with_capacity(columns.len());
+    for (index, column) in columns.iter().enumerate() {
+      let bit = index + NULL_BITMAP_OFFSET;
+      if bitmap[bit / 8] & (1 << (bit % 8)) != 0 {
+        values.push(None);
+        continue;
+      }
+      let start = buf.len() - rest.remaining();
+      BinaryValue::decode(&mut rest, param_type(column))?;
+      values.push(Some(start..buf.len() - rest.remaining()));
+    }
+
+    Ok(Self(Row { storage: buf, values }))
+  }
+}
+
+impl Encode<'_, ()> for BinaryRow {
+  fn encode_with(&self, buf: &mut Vec<u8>, _: ()) {
+    buf.push(0x00);
+    let mut bitmap = vec![0u8; (self.0.len() + 7 + NULL_BITMAP_OFFSET) / 8];
+    for (index, value) in self.0.values().enumerate() {
+      if value.is_none() {
+        let bit = index + NULL_BITMAP_OFFSET;
+        bitmap[bit / 8] |= 1 << (bit % 8);
+      }
+    }
+    buf.extend(bitmap);
+    for value in self.0.values().flatten() {
+      buf.extend(value);
+    }
+  }
+}
+
+#[test]
+#[allow(clippy::unwrap_used)]
+fn test_decode_binary_row() {
+  use crate::mysql::protocol::Capabilities;
+
+  // BIGINT id, VARCHAR email, DATETIME created_at (NULL)
+  const COLUMNS: &[&[u8]] = &[
+    b"\x03def\x04shop\x05users\x05users\x02id\x02id\x0c\x3f\x00\x14\x00\x00\x00\x08\x03\x40\x00\x00\x00",
+    b"\x03def\x04shop\x05users\x05users\x05email\x05email\x0c\x2d\x00\xfc\x03\x00\x00\xfd\x00\x00\x00\x00\x00",
+    b"\x03def\x04shop\x05users\x05users\x07created\x07created\x0c\x3f\x00\x13\x00\x00\x00\x0c\x80\x00\x00\x00\x00",
+  ];
+  const DATA: &[u8] = b"\x00\x10\x07\x00\x00\x00\x00\x00\x00\x00\x07a@b.com";
+
+  let columns = COLUMNS
+    .iter()
+    .map(|x| ColumnDefinition::decode_with(Bytes::from_static(x), Capabilities::PROTOCOL_41).unwrap())
+    .collect::<Vec<_>>();
+  let mut row = BinaryRow::decode_with(DATA.into(), &columns).unwrap();
+
+  assert_eq!(row.value(0, &columns[0]).unwrap(), BinaryValue::Int(7));
+  assert_eq!(
+    row.value(1, &columns[1]).unwrap(),
+    BinaryValue::Bytes(Bytes::from_static(b"a@b.com"))
+  );
+  assert_eq!(row.value(2, &columns[2]).unwrap(), BinaryValue::Null);
+
+  let mut buf = Vec::new();
+  row.encode(&mut buf);
+  assert_eq!(buf, DATA);
+
+  row.set_bytes(0, None);
+  row.set_bytes(1, Some(b"***"));
+  let mut buf = Vec::new();
+  row.encode(&mut buf);
+  assert_eq!(buf, b"\x00\x14\x03***");
+}
@@ -0,0 +1,39 @@
+use bytes::{Buf, Bytes};
+
+use super::{ensure_remaining, expect_command, COM_STMT_SEND_LONG_DATA};
+use crate::error::Error;
+use crate::io::Decode;
+
+// https://dev.mysql.com/doc/dev/mysql-server/latest/page_protocol_com_stmt_send_long_data.html
+
+/// Sends a parameter value in chunks ahead of COM_STMT_EXECUTE. The server doesn't respond to it.
+#[derive(Debug)]
+pub struct StmtSendLongData {
+  pub statement_id: u32,
+  pub param: u16,
+  pub data: Bytes,
+}
+
+impl Decode<'_> for StmtSendLongData {
+  fn decode_with(mut buf: Bytes, _: ()) -> Result<Self, Error> {
+    expect_command(&mut buf, COM_STMT_SEND_LONG_DATA)?;
+    ensure_remaining(&buf, 6)?;
+    Ok(Self {
+      statement_id: buf.get_u32_le(),
+      param: buf.get_u16_le(),
+      data: buf,
+    })
+  }
+}
+
+#[test]
+#[allow(clippy::unwrap_used)]
+fn test_decode_stmt_send_long_data() {
+  const DATA: &[u8] = b"\x18\x05\x00\x00\x00\x01\x00hello";
+
+  let p = StmtSendLongData::decode(DATA.into()).unwrap();
+
+  assert_eq!(p.statement_id, 5);
+  assert_eq!(p.param, 1);
+  assert_eq!(&p.data[..], b"hello");
+}
@@ -0,0 +1,240 @@
+use std::fmt;
+
+use bytes::{Buf, Bytes};
+
+use super::{ensure_remaining, ParamType};
+use crate::err_protocol;
+use crate::error::Error;
+use crate::mysql::io::MySqlBufExt;
+use crate::mysql::protocol::text::ColumnType;
+
+// https://dev.mysql.com/doc/dev/mysql-server/latest/page_protocol_binary_resultset.html#sect_protocol_binary_resultset_row_value
+
+/// A value in the binary protocol
+#[derive(Debug, Clone, PartialEq)]
+pub enum BinaryValue {
+  Null,
+  Int(i64),
+  UInt(u64),
+  Float(f32),
+  Double(f64),
+  /// Strings, decimals, blobs and everything else that is sent length-prefixed
+  Bytes(Bytes),
+  DateTime {
+    year: u16,
+    month: u8,
+    day: u8,
+    hour: u8,
+    minute: u8,
+    second: u8,
+    microsecond: u32,
+  },
+  Time {
+    negative: bool,
+    days: u32,
+    hour: u8,
+    minute: u8,
+    second: u8,
+    microsecond: u32,
+  },
+  /// Sent separately with COM_STMT_SEND_LONG_DATA
+  LongData,
+}
+
+impl BinaryValue {
+  pub fn decode(buf: &mut Bytes, param_type: ParamType) -> Result<Self, Error> {
+    let unsigned = param_type.unsigned;
+    Ok(match param_type.r#type {
+      ColumnType::Null => BinaryValue::Null,
+      ColumnType::Tiny => {
+        ensure_remaining(buf, 1)?;
+        match unsigned {
+          true => BinaryValue::UInt(buf.get_u8().into()),
+          false => BinaryValue::Int(buf.get_i8().into()),
+        }
+      }
+      ColumnType::Short | ColumnType::Year => {
+        ensure_remaining(buf, 2)?;
+        match unsigned {
+          true => BinaryValue::UInt(buf.get_u16_le().into()),
+          false => BinaryValue::Int(buf.get_i16_le().into()),
+        }
+      }
+      ColumnType::Long | ColumnType::Int24 => {
+        ensure_remaining(buf, 4)?;
+        match unsigned {
+          true => BinaryValue::UInt(buf.get_u32_le().into()),
+          false => BinaryValue::Int(buf.get_i32_le().into()),
+        }
+      }
+      ColumnType::LongLong => {
+        ensure_remaining(buf, 8)?;
+        match unsigned {
+          true => BinaryValue::UInt(buf.get_u64_le()),
+          false => BinaryValue::Int(buf.get_i64_le()),
+        }
+      }
+      ColumnType::Float => {
+        ensure_remaining(buf, 4)?;
+        BinaryValue::Float(buf.get_f32_le())
+      }
+      ColumnType::Double => {
+        ensure_remaining(buf, 8)?;
+        BinaryValue::Double(buf.get_f64_le())
+      }
+      ColumnType::Date | ColumnType::Datetime | ColumnType::Timestamp => {
+        ensure_remaining(buf, 1)?;
+        let len = buf.get_u8() as usize;
+        ensure_remaining(buf, len)?;
+        let mut value = buf.split_to(len);
+        let mut next_u8 = || if value.has_remaining() { value.get_u8() } else { 0 };
+        let (year, month, day) = (u16::from(next_u8()) | u16::from(next_u8()) << 8, next_u8(), next_u8());
+        let (hour, minute, second) = (next_u8(), next_u8(), next_u8());
+        let microsecond = if value.remaining() >= 4 { value.get_u32_le() } else { 0 };
+        BinaryValue::DateTime {
+          year,
+          month,
+          day,
+          hour,
+          minute,
+          second,
+          microsecond,
+        }
+      }
+      ColumnType::Time => {
+        ensure_remaining(buf, 1)?;
+        let len = buf.get_u8() as usize;
+        ensure_remaining(buf, len)?;
+        let mut value = buf.split_to(len);
+        if value.remaining() < 8 {
+          BinaryValue::Time {
+            negative: false,
+            days: 0,
+            hour: 0,
+            minute: 0,
+            second: 0,
+            microsecond: 0,
+          }
+        } else {
+          BinaryValue::Time {
+            negative: value.get_u8() == 1,
+            days: value.get_u32_le(),
+            hour: value.get_u8(),
+            minute: value.get_u8(),
+            second: value.get_u8(),
+            microsecond: if value.remaining() >= 4 { value.get_u32_le() } else { 0 },
+          }
+        }
+      }
+      _ => {
+        ensure_remaining(buf, 1)?;
+        let len = match buf[0] {
+          0xfc => 3,
+          0xfd => 4,
+          0xfe => 9,
+          _ => 1,
+        };
+        ensure_remaining(buf, len)?;
+        let size = usize::try_from(buf.clone().get_uint_lenenc())
+          .ok()
+          .and_then(|size| size.checked_add(len))
+          .ok_or_else(|| err_protocol!("value length out of range"))?;
+        ensure_remaining(buf, size)?;
+        BinaryValue::Bytes(buf.get_bytes_lenenc())
+      }
+    })
+  }
+}
+
+impl fmt::Display for BinaryValue {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      BinaryValue::Null => write!(f, "NULL"),
+      BinaryValue::Int(x) => write!(f, "{x}"),
+      BinaryValue::UInt(x) => write!(f, "{x}"),
+      BinaryValue::Float(x) => write!(f, "{x}"),
+      BinaryValue::Double(x) => write!(f, "{x}"),
+      BinaryValue::Bytes(x) => match std::str::from_utf8(x) {
+        Ok(s) => write!(f, "'{}'", s.replace('\'', "''")),
+        Err(_) => {
+          write!(f, "0x")?;
+          x.iter().try_for_each(|b| write!(f, "{b:02x}"))
+        }
+      },
+      BinaryValue::DateTime {
+        year,
+        month,
+        day,
+        hour,
+        minute,
+        second,
+        microsecond,
+      } => {
+        write!(f, "'{year:04}-{month:02}-{day:02}")?;
+        if (hour, minute, second, microsecond) != (&0, &0, &0, &0) {
+          write!(f, " {hour:02}:{minute:02}:{second:02}")?;
+          if *microsecond != 0 {
+            write!(f, ".{microsecond:06}")?;
+          }
+        }
+        write!(f, "'")
+      }
+      BinaryValue::Time {
+        negative,
+        days,
+        hour,
+        minute,
+        second,
+        microsecond,
+      } => {
+        let sign = if *negative { "-" } else { "" };
+        let hours = u64::from(*days) * 24 + u64::from(*hour);
+        write!(f, "'{sign}{hours:02}:{minute:02}:{second:02}")?;
+        if *microsecond != 0 {
+          write!(f, ".{microsecond:06}")?;
+        }
+        write!(f, "'")
+      }
+      BinaryValue::LongData => write!(f, "<long data>"),
+    }
+  }
+}
+
+#[test]
+#[allow(clippy::unwrap_used)]
+fn test_decode_binary_datetime() {
+  const DATA: &[u8] = b"\x0b\xda\x07\x0a\x11\x13\x1b\x1e\x01\x00\x00\x00";
+
+  let value = BinaryValue::decode(
+    &mut DATA.into(),
+    ParamType {
+      r#type: ColumnType::Datetime,
+      unsigned: false,
+    },
+  )
+  .unwrap();
+
+  assert_eq!(value.to_string(), "'2010-10-17 19:27:30.000001'");
+}
+
+#[cfg(test)]
+fn decode_bytes(packet: &'static [u8]) -> Result<BinaryValue, Error> {
+  BinaryValue::decode(
+    &mut Bytes::from_static(packet),
+    ParamType {
+      r#type: ColumnType::VarString,
+      unsigned: false,
+    },
+  )
+}
+
+#[test]
+fn test_decode_bytes() {
+  assert_eq!(
+    decode_bytes(b"\x03abc").ok(),
+    Some(BinaryValue::Bytes(Bytes::from_static(b"abc")))
+  );
+  assert!(decode_bytes(b"\x04abc").is_err());
+  // A length that overflows when added to the size of its prefix
+  assert!(decode_bytes(b"\xfe\xff\xff\xff\xff\xff\xff\xff\xff").is_err());
+}
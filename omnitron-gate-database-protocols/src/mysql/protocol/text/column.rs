@@ -5,8 +5,8 @@ use bytes::{Buf, Bytes};
 
 use crate::err_protocol;
 use crate::error::Error;
-use crate::io::Decode;
-use crate::mysql::io::MySqlBufExt;
+use crate::io::{Decode, Encode};
+use crate::mysql::io::{MySqlBufExt, MySqlBufMutExt};
 use crate::mysql::protocol::Capabilities;
 
 // https://dev.mysql.com/doc/dev/mysql-server/8.0.12/group__group__cs__column__definition__flags.html
@@ -98,15 +98,11 @@ pub enum ColumnType {
 // https://mariadb.com/kb/en/resultset/#column-definition-packet
 // https://dev.mysql.com/doc/internals/en/com-query-response.html#packet-Protocol::ColumnDefinition41
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct ColumnDefinition {
-  #[allow(unused)]
   catalog: Bytes,
-  #[allow(unused)]
   schema: Bytes,
-  #[allow(unused)]
   table_alias: Bytes,
-  #[allow(unused)]
   table: Bytes,
   alias: Bytes,
   name: Bytes,
@@ -114,21 +110,69 @@ pub struct ColumnDefinition {
   pub(crate) max_size: u32,
   pub(crate) r#type: ColumnType,
   pub(crate) flags: ColumnFlags,
-  #[allow(unused)]
   decimals: u8,
 }
 
+/// utf8mb4_general_ci
+const UTF8MB4_CHARSET: u16 = 45;
+/// The "binary" character set of non-text columns
+const BINARY_CHARSET: u16 = 63;
+
 impl ColumnDefinition {
   // NOTE: strings in-protocol are transmitted according to the client character set
   //       as this is UTF-8, all these strings should be UTF-8
 
-  pub(crate) fn name(&self) -> Result<&str, Error> {
+  pub fn schema(&self) -> Result<&str, Error> {
+    from_utf8(&self.schema).map_err(Error::protocol)
+  }
+
+  /// Name of the physical table, empty for computed columns
+  pub fn table(&self) -> Result<&str, Error> {
+    from_utf8(&self.table).map_err(Error::protocol)
+  }
+
+  /// Table name as given in the query
+  pub fn table_alias(&self) -> Result<&str, Error> {
+    from_utf8(&self.table_alias).map_err(Error::protocol)
+  }
+
+  /// Name of the physical column, empty for computed columns
+  pub fn name(&self) -> Result<&str, Error> {
     from_utf8(&self.name).map_err(Error::protocol)
   }
 
-  pub(crate) fn alias(&self) -> Result<&str, Error> {
+  /// Column name as given in the query
+  pub fn alias(&self) -> Result<&str, Error> {
     from_utf8(&self.alias).map_err(Error::protocol)
   }
+
+  pub fn column_type(&self) -> ColumnType {
+    self.r#type
+  }
+
+  pub fn flags(&self) -> ColumnFlags {
+    self.flags
+  }
+
+  /// Turns the column into a string column, for when its values are replaced with text
+  pub fn set_text_type(&mut self) {
+    self.r#type = ColumnType::VarString;
+    if self.char_set == BINARY_CHARSET {
+      self.char_set = UTF8MB4_CHARSET;
+    }
+    self.flags.remove(
+      ColumnFlags::BINARY
+        | ColumnFlags::BLOB
+        | ColumnFlags::NUM
+        | ColumnFlags::UNSIGNED
+        | ColumnFlags::ZEROFILL
+        | ColumnFlags::ENUM
+        | ColumnFlags::SET,
+    );
+    // Hashes are 64 characters, in up to 4 bytes each
+    self.max_size = self.max_size.max(256);
+    self.decimals = 0;
+  }
 }
 
 impl Decode<'_, Capabilities> for ColumnDefinition {
@@ -162,6 +206,24 @@ impl Decode<'_, Capabilities> for ColumnDefinition {
   }
 }
 
+impl Encode<'_, Capabilities> for ColumnDefinition {
+  fn encode_with(&self, buf: &mut Vec<u8>, _: Capabilities) {
+    buf.put_bytes_lenenc(&self.catalog);
+    buf.put_bytes_lenenc(&self.schema);
+    buf.put_bytes_lenenc(&self.table_alias);
+    buf.put_bytes_lenenc(&self.table);
+    buf.put_bytes_lenenc(&self.alias);
+    buf.put_bytes_lenenc(&self.name);
+    buf.put_uint_lenenc(0x0c);
+    buf.extend(self.char_set.to_le_bytes());
+    buf.extend(self.max_size.to_le_bytes());
+    buf.push(self.r#type as u8);
+    buf.extend(self.flags.bits().to_le_bytes());
+    buf.push(self.decimals);
+    buf.extend([0, 0]); // filler
+  }
+}
+
 impl ColumnType {
   pub(crate) fn name(self, char_set: u16, flags: ColumnFlags, max_size: Option<u32>) -> &'static str {
     let is_binary = char_set == 63;
@@ -256,3 +318,30 @@ impl ColumnType {
     })
   }
 }
+
+#[test]
+#[allow(clippy::unwrap_used)]
+fn test_decode_column_definition() {
+  const DATA: &[u8] = b"\x03def\x04shop\x01u\x05users\x04mail\x05email\x0c\x2d\x00\xfc\x03\x00\x00\xfd\x00\x00\x00\x00\x00";
+
+  let mut column = ColumnDefinition::decode_with(DATA.into(), Capabilities::PROTOCOL_41).unwrap();
+
+  assert_eq!(column.schema().unwrap(), "shop");
+  assert_eq!(column.table().unwrap(), "users");
+  assert_eq!(column.table_alias().unwrap(), "u");
+  assert_eq!(column.name().unwrap(), "email");
+  assert_eq!(column.alias().unwrap(), "mail");
+  assert_eq!(column.column_type(), ColumnType::VarString);
+
+  let mut buf = Vec::new();
+  column.encode_with(&mut buf, Capabilities::PROTOCOL_41);
+  assert_eq!(buf, DATA);
+
+  column.r#type = ColumnType::LongLong;
+  column.char_set = 63;
+  column.flags = ColumnFlags::NUM | ColumnFlags::UNSIGNED;
+  column.set_text_type();
+  assert_eq!(column.column_type(), ColumnType::VarString);
+  assert_eq!(column.char_set, 45);
+  assert!(column.flags().is_empty());
+}
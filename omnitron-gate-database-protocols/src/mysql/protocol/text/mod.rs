@@ -2,8 +2,10 @@ mod column;
 mod ping;
 mod query;
 mod quit;
+mod row;
 
 pub use column::{ColumnDefinition, ColumnFlags, ColumnType};
 pub use ping::Ping;
 pub use query::Query;
 pub use quit::Quit;
+pub use row::TextRow;
@@ -0,0 +1,87 @@
+use bytes::{Buf, Bytes};
+
+use crate::err_protocol;
+use crate::error::Error;
+use crate::io::{Decode, Encode};
+use crate::mysql::io::MySqlBufMutExt;
+use crate::mysql::protocol::Row;
+
+// https://dev.mysql.com/doc/dev/mysql-server/latest/page_protocol_com_query_response_text_resultset_row.html
+
+/// A row of a text result set, decoded with the number of columns
+#[derive(Debug)]
+pub struct TextRow(pub Row);
+
+impl Decode<'_, usize> for TextRow {
+  fn decode_with(buf: Bytes, columns: usize) -> Result<Self, Error> {
+    let mut values = Vec::with_capacity(columns);
+    let mut offset = 0;
+
+    for _ in 0..columns {
+      let mut rest = &buf[offset..];
+      let Some(&header) = rest.first() else {
+        return Err(err_protocol!("text row ended after {} of {} values", values.len(), columns));
+      };
+      if header == 0xfb {
+        values.push(None);
+        offset += 1;
+        continue;
+      }
+
+      let prefix = match header {
+        0xfc => 3,
+        0xfd => 4,
+        0xfe => 9,
+        _ => 1,
+      };
+      if rest.len() < prefix {
+        return Err(err_protocol!("truncated value length in text row"));
+      }
+      rest.advance(1);
+      let size = match header {
+        0xfc => u64::from(rest.get_u16_le()),
+        0xfd => rest.get_uint_le(3),
+        0xfe => rest.get_u64_le(),
+        x => u64::from(x),
+      } as usize;
+      let start = offset + prefix;
+      if buf.len() < start + size {
+        return Err(err_protocol!("truncated value in text row"));
+      }
+      values.push(Some(start..start + size));
+      offset = start + size;
+    }
+
+    Ok(Self(Row { storage: buf, values }))
+  }
+}
+
+impl Encode<'_, ()> for TextRow {
+  fn encode_with(&self, buf: &mut Vec<u8>, _: ()) {
+    for value in self.0.values() {
+      match value {
+        Some(value) => buf.put_bytes_lenenc(value),
+        None => buf.push(0xfb),
+      }
+    }
+  }
+}
+
+#[test]
+#[allow(clippy::unwrap_used)]
+fn test_decode_text_row() {
+  const DATA: &[u8] = b"\x011\xfb\x05hello";
+
+  let mut row = TextRow::decode_with(DATA.into(), 3).unwrap();
+
+  assert_eq!(row.0.get(0), Some(&b"1"[..]));
+  assert_eq!(row.0.get(1), None);
+  assert_eq!(row.0.get(2), Some(&b"hello"[..]));
+  assert!(TextRow::decode_with(DATA.into(), 4).is_err());
+
+  row.0.set(0, None);
+  row.0.set(2, Some(b"*****"));
+  let mut buf = Vec::new();
+  row.encode(&mut buf);
+  assert_eq!(buf, b"\xfb\xfb\x05*****");
+}
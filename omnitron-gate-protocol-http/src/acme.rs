@@ -0,0 +1,195 @@
+use std::collections::BTreeSet;
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Result;
+use omnitron_gate_common::helpers::fs::{secure_directory, write_secure_file};
+use omnitron_gate_common::{AcmeChallengeType, AcmeConfig, OmnitronConfig, TargetOptions};
+use omnitron_gate_core::Services;
+use poem::listener::acme::{
+  issue_cert, seconds_until_expiry, AcmeClient, ChallengeType, Http01Endpoint, Http01TokensMap, ResolveServerCert,
+};
+use poem::Server;
+use tracing::*;
+
+use crate::tls::certified_key_from_pem;
+
+const RENEWAL_CHECK_INTERVAL: Duration = Duration::from_secs(60 * 60);
+const RETRY_INTERVAL: Duration = Duration::from_secs(60 * 5);
+
+const CERTIFICATE_FILE: &str = "cert.pem";
+const KEY_FILE: &str = "key.pem";
+const DOMAINS_FILE: &str = "domains";
+
+/// Keeps an ACME certificate for the gate's hostnames issued and renewed
+pub(crate) struct AcmeCertificates {
+  services: Services,
+  resolver: Arc<ResolveServerCert>,
+  http01_tokens: Http01TokensMap,
+  challenge: AcmeChallengeType,
+  path: PathBuf,
+  issued_for: Vec<String>,
+}
+
+impl AcmeCertificates {
+  /// Loads the previously issued certificate, if any
+  pub(crate) fn new(services: &Services, config: &OmnitronConfig) -> Result<Self> {
+    let acme_config = &config.store.http.acme;
+    let path = config.paths_relative_to.join(&acme_config.path);
+    let resolver = Arc::new(ResolveServerCert::default());
+    let mut issued_for = vec![];
+
+    if path.join(CERTIFICATE_FILE).exists() && path.join(KEY_FILE).exists() {
+      let certificate = std::fs::read(path.join(CERTIFICATE_FILE))?;
+      let key = std::fs::read(path.join(KEY_FILE))?;
+      match certified_key_from_pem(&certificate, &key) {
+        Ok(certified_key) => {
+          debug!(path=%path.display(), "Loaded ACME certificate");
+          *resolver.cert.write() = Some(Arc::new(certified_key));
+          issued_for = std::fs::read_to_string(path.join(DOMAINS_FILE))
+            .unwrap_or_default()
+            .lines()
+            .map(str::to_owned)
+            .collect();
+        }
+        Err(error) => warn!(?error, path=%path.display(), "Ignoring unreadable ACME certificate"),
+      }
+    }
+
+    Ok(Self {
+      services: services.clone(),
+      resolver,
+      http01_tokens: Http01TokensMap::new(),
+      challenge: acme_config.challenge,
+      path,
+      issued_for,
+    })
+  }
+
+  pub(crate) fn resolver(&self) -> Arc<ResolveServerCert> {
+    self.resolver.clone()
+  }
+
+  pub(crate) fn challenge(&self) -> AcmeChallengeType {
+    self.challenge
+  }
+
+  /// Serves HTTP-01 challenges if needed and renews the certificate in the background.
+  /// Never returns: failures are logged and retried so that they don't take the HTTP listener down.
+  pub(crate) async fn run(mut self) {
+    if self.challenge == AcmeChallengeType::Http01 {
+      let services = self.services.clone();
+      let tokens = self.http01_tokens.clone();
+      tokio::select! {
+        _ = Self::serve_http01_challenges(&services, &tokens) => (),
+        _ = self.renew_periodically() => (),
+      }
+    } else {
+      self.renew_periodically().await;
+    }
+  }
+
+  async fn serve_http01_challenges(services: &Services, tokens: &Http01TokensMap) {
+    loop {
+      let listen = services.config.lock().await.store.http.acme.http_challenge_listen.clone();
+      let result = async {
+        Server::new(listen.poem_listener().await?)
+          .run(Http01Endpoint { keys: tokens.clone() })
+          .await?;
+        Ok::<_, anyhow::Error>(())
+      }
+      .await;
+      if let Err(error) = result {
+        error!(?error, "ACME challenge listener failed");
+      }
+      tokio::time::sleep(RETRY_INTERVAL).await;
+    }
+  }
+
+  async fn renew_periodically(&mut self) {
+    let mut client = None;
+    loop {
+      let interval = match self.renew_if_needed(&mut client).await {
+        Ok(()) => RENEWAL_CHECK_INTERVAL,
+        Err(error) => {
+          error!(?error, "Failed to obtain a certificate over ACME");
+          client = None;
+          RETRY_INTERVAL
+        }
+      };
+      tokio::time::sleep(interval).await;
+    }
+  }
+
+  async fn renew_if_needed(&mut self, client: &mut Option<AcmeClient>) -> Result<()> {
+    let acme_config = self.services.config.lock().await.store.http.acme.clone();
+    let domains = self.domains(&acme_config).await?;
+    if domains.is_empty() {
+      warn!("ACME is enabled, but there are no hostnames to request a certificate for");
+      return Ok(());
+    }
+
+    let valid = self
+      .resolver
+      .cert
+      .read()
+      .as_ref()
+      .map_or(false, |x| seconds_until_expiry(x) > acme_config.renew_before.as_secs() as i64);
+    if valid && domains == self.issued_for {
+      return Ok(());
+    }
+
+    info!(?domains, "Requesting a certificate over ACME");
+    let client = match client {
+      Some(client) => client,
+      None => client.insert(AcmeClient::try_new(&acme_config.directory_url, acme_config.contacts.clone()).await?),
+    };
+    let challenge = match self.challenge {
+      AcmeChallengeType::Http01 => ChallengeType::Http01,
+      AcmeChallengeType::TlsAlpn01 => ChallengeType::TlsAlpn01,
+    };
+    let result = issue_cert(client, &self.resolver, &domains, challenge, Some(&self.http01_tokens)).await?;
+
+    std::fs::create_dir_all(&self.path)?;
+    secure_directory(&self.path)?;
+    write_secure_file(self.path.join(KEY_FILE), result.private_pem.as_bytes())?;
+    std::fs::write(self.path.join(CERTIFICATE_FILE), &result.public_pem)?;
+    std::fs::write(self.path.join(DOMAINS_FILE), domains.join("\n"))?;
+
+    *self.resolver.cert.write() = Some(result.rustls_key);
+    self.issued_for = domains;
+    info!("ACME certificate issued");
+    Ok(())
+  }
+
+  /// The global `external_host`, the `external_host` of every HTTP target and the extra
+  /// configured domains. IP addresses are left out since they can't be validated.
+  async fn domains(&self, acme_config: &AcmeConfig) -> Result<Vec<String>> {
+    let mut domains = BTreeSet::new();
+    if let Some((_, host, _)) = self.services.config.lock().await.external_host_from_config() {
+      domains.insert(host);
+    }
+    domains.extend(acme_config.domains.iter().cloned());
+
+    let targets = self.services.config_provider.lock().await.list_targets().await?;
+    for target in targets {
+      if let TargetOptions::Http(options) = target.options {
+        if let Some(host) = options.external_host {
+          domains.insert(host.split(':').next().unwrap_or_default().to_owned());
+        }
+      }
+    }
+
+    Ok(
+      domains
+        .into_iter()
+        .map(|x| x.to_lowercase())
+        .filter(|x| !x.is_empty() && x.parse::<IpAddr>().is_err())
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .collect(),
+    )
+  }
+}
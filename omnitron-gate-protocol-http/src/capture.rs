@@ -0,0 +1,513 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex as SyncMutex};
+
+use bytes::Bytes;
+use data_encoding::BASE64;
+use futures::{Stream, TryStreamExt};
+use http::{HeaderMap, StatusCode};
+use omnitron_api::common::SESSION_COOKIE_NAME;
+use omnitron_api::session::SessionStore;
+use omnitron_api::session_handle::OmnitronServerHandleFromRequest;
+use omnitron_gate_common::{HttpCaptureOptions, Target};
+use omnitron_gate_core::recordings::{
+  HarContent, HarEntry, HarNameValue, HarPostData, HarRequest, HarResponse, HarTimings, HarWebSocketDirection,
+  HarWebSocketMessage, HttpRecorder,
+};
+use omnitron_gate_core::Services;
+use poem::web::Data;
+use poem::{Body, FromRequest, Request, Response};
+use serde_json::Value;
+use tokio::sync::Mutex;
+use tokio::time::Instant;
+use tracing::*;
+use uuid::Uuid;
+
+const REDACTED: &str = "[REDACTED]";
+
+/// Headers carrying credentials, including Omnitron's own session cookie - never recorded
+const SENSITIVE_HEADERS: &[http::HeaderName] = &[
+  http::header::COOKIE,
+  http::header::SET_COOKIE,
+  http::header::AUTHORIZATION,
+  http::header::PROXY_AUTHORIZATION,
+];
+
+/// Captures the requests a session makes to an HTTP target into its HTTP recording
+#[derive(Clone)]
+pub struct HttpCapture {
+  recorder: HttpRecorder,
+  max_body_size: usize,
+  websocket_frames: bool,
+  redact: Arc<HashSet<String>>,
+}
+
+impl HttpCapture {
+  pub async fn for_request(req: &Request, target: &Target, options: &HttpCaptureOptions) -> poem::Result<Option<Self>> {
+    if !options.enable {
+      return Ok(None);
+    }
+    let Ok(handle) = OmnitronServerHandleFromRequest::from_request_without_body(req).await else {
+      return Ok(None);
+    };
+    let session_id = handle.lock().await.id();
+    let services = Data::<&Services>::from_request_without_body(req).await?;
+    let session_store = Data::<&Arc<Mutex<SessionStore>>>::from_request_without_body(req).await?;
+
+    let Some(recorder) = session_store
+      .lock()
+      .await
+      .http_recorder_for(&services, session_id, &target.name)
+      .await
+    else {
+      return Ok(None);
+    };
+
+    let mut redact: HashSet<String> = options.redact.iter().map(|x| x.to_lowercase()).collect();
+    redact.insert(SESSION_COOKIE_NAME.to_owned());
+
+    Ok(Some(Self {
+      recorder,
+      max_body_size: options.max_body_size.unwrap_or(0) as usize,
+      websocket_frames: options.websocket_frames,
+      redact: Arc::new(redact),
+    }))
+  }
+
+  /// Starts recording a request. The entry is written once the returned
+  /// exchange is finished or dropped.
+  pub fn start(&self, req: &Request) -> CapturedExchange {
+    let host = req.header(http::header::HOST).unwrap_or_default();
+    let path = req.original_uri().path_and_query().map(|x| x.as_str()).unwrap_or("/");
+    let query_string = req
+      .original_uri()
+      .query()
+      .map(|query| {
+        url::form_urlencoded::parse(query.as_bytes())
+          .map(|(name, value)| self.name_value(&name, &value))
+          .collect()
+      })
+      .unwrap_or_default();
+    let cookies = request_cookies(req.headers(), &self.redact);
+
+    CapturedExchange {
+      capture: self.clone(),
+      id: Uuid::new_v4(),
+      started_date_time: chrono::Utc::now().to_rfc3339(),
+      started_at: Instant::now(),
+      response_at: None,
+      request: HarRequest {
+        method: req.method().to_string(),
+        url: format!("{}://{host}{path}", req.scheme()),
+        http_version: format!("{:?}", req.version()),
+        cookies,
+        headers: capture_headers(req.headers(), &self.redact),
+        query_string,
+        post_data: None,
+        headers_size: -1,
+        body_size: -1,
+      },
+      request_body: CapturedBody::new(mime_type(req.headers())),
+      response: None,
+      response_body: CapturedBody::new(String::new()),
+      finished: false,
+    }
+  }
+
+  pub fn websocket_frames(&self) -> bool {
+    self.websocket_frames
+  }
+
+  pub async fn write_websocket_message(&self, entry_id: Uuid, direction: HarWebSocketDirection, opcode: u8, data: &[u8]) {
+    let message = HarWebSocketMessage {
+      direction,
+      time: chrono::Utc::now().timestamp_micros() as f64 / 1_000_000.0,
+      opcode,
+      data: websocket_message_data(opcode, data, self.max_body_size, &self.redact),
+    };
+    if let Err(error) = self.recorder.clone().write_websocket_message(entry_id, message).await {
+      error!(%error, "Failed to record a websocket message");
+    }
+  }
+
+  fn name_value(&self, name: &str, value: &str) -> HarNameValue {
+    name_value(name, value, &self.redact)
+  }
+
+  fn redact_body(&self, body: &CapturedBody) -> (Option<String>, Option<String>, Option<String>) {
+    if body.data.is_empty() {
+      return (None, None, None);
+    }
+    let truncated = body.size > body.data.len() as u64;
+    let truncated_comment = truncated.then(|| format!("truncated to {} bytes", body.data.len()));
+
+    if body.mime_type.contains("json") {
+      return match serde_json::from_slice::<Value>(&body.data) {
+        Ok(mut value) => {
+          redact_json(&mut value, &self.redact);
+          (Some(value.to_string()), None, None)
+        }
+        Err(_) if truncated => (None, None, Some("truncated JSON body can't be redacted".to_owned())),
+        Err(_) => (Some(String::from_utf8_lossy(&body.data).into_owned()), None, None),
+      };
+    }
+
+    if body.mime_type.starts_with("application/x-www-form-urlencoded") {
+      let text = url::form_urlencoded::Serializer::new(String::new())
+        .extend_pairs(url::form_urlencoded::parse(&body.data).map(|(name, value)| {
+          let value = self.name_value(&name, &value).value;
+          (name.into_owned(), value)
+        }))
+        .finish();
+      return (Some(text), None, truncated_comment);
+    }
+
+    match std::str::from_utf8(&body.data) {
+      Ok(text) => (Some(text.to_owned()), None, truncated_comment),
+      Err(_) => (Some(BASE64.encode(&body.data)), Some("base64".to_owned()), truncated_comment),
+    }
+  }
+}
+
+fn name_value(name: &str, value: &str, redact: &HashSet<String>) -> HarNameValue {
+  HarNameValue {
+    name: name.to_owned(),
+    value: match redact.contains(&name.to_lowercase()) {
+      true => REDACTED.to_owned(),
+      false => value.to_owned(),
+    },
+  }
+}
+
+fn capture_headers(headers: &HeaderMap, redact: &HashSet<String>) -> Vec<HarNameValue> {
+  headers
+    .iter()
+    .map(|(name, value)| match SENSITIVE_HEADERS.contains(name) {
+      true => HarNameValue {
+        name: name.to_string(),
+        value: REDACTED.to_owned(),
+      },
+      false => name_value(name.as_str(), &String::from_utf8_lossy(value.as_bytes()), redact),
+    })
+    .collect()
+}
+
+fn request_cookies(headers: &HeaderMap, redact: &HashSet<String>) -> Vec<HarNameValue> {
+  headers
+    .get_all(http::header::COOKIE)
+    .iter()
+    .filter_map(|x| x.to_str().ok())
+    .flat_map(|x| x.split(';'))
+    .filter_map(|x| x.trim().split_once('='))
+    .map(|(name, value)| name_value(name, value, redact))
+    .collect()
+}
+
+/// The recorded payload of a websocket message, limited to `limit` bytes like the bodies.
+/// Text messages are redacted if they're JSON, and returned as is otherwise.
+fn websocket_message_data(opcode: u8, data: &[u8], limit: usize, redact: &HashSet<String>) -> String {
+  match opcode {
+    // Redact before truncating so that the JSON is still complete
+    1 => {
+      let text = String::from_utf8_lossy(data);
+      let mut text = match serde_json::from_str::<Value>(&text) {
+        Ok(mut value) => {
+          redact_json(&mut value, redact);
+          value.to_string()
+        }
+        Err(_) => text.into_owned(),
+      };
+      if text.len() > limit {
+        let boundary = (0..=limit).rev().find(|x| text.is_char_boundary(*x)).unwrap_or_default();
+        text.truncate(boundary);
+      }
+      text
+    }
+    _ => BASE64.encode(&data[..data.len().min(limit)]),
+  }
+}
+
+fn redact_json(value: &mut Value, redact: &HashSet<String>) {
+  match value {
+    Value::Object(map) => {
+      for (key, value) in map.iter_mut() {
+        if redact.contains(&key.to_lowercase()) {
+          *value = Value::String(REDACTED.to_owned());
+        } else {
+          redact_json(value, redact);
+        }
+      }
+    }
+    Value::Array(items) => items.iter_mut().for_each(|x| redact_json(x, redact)),
+    _ => (),
+  }
+}
+
+fn mime_type(headers: &HeaderMap) -> String {
+  headers
+    .get(http::header::CONTENT_TYPE)
+    .and_then(|x| x.to_str().ok())
+    .unwrap_or_default()
+    .to_owned()
+}
+
+struct CapturedBody {
+  mime_type: String,
+  data: Vec<u8>,
+  /// Total size, including what didn't fit into `data`
+  size: u64,
+}
+
+impl CapturedBody {
+  fn new(mime_type: String) -> Arc<SyncMutex<Self>> {
+    Arc::new(SyncMutex::new(Self {
+      mime_type,
+      data: vec![],
+      size: 0,
+    }))
+  }
+
+  fn push(&mut self, chunk: &[u8], limit: usize) {
+    self.size += chunk.len() as u64;
+    let remaining = limit.saturating_sub(self.data.len());
+    self.data.extend_from_slice(&chunk[..chunk.len().min(remaining)]);
+  }
+}
+
+fn tap_stream<S, E>(stream: S, body: Arc<SyncMutex<CapturedBody>>, limit: usize) -> impl Stream<Item = Result<Bytes, E>>
+where
+  S: Stream<Item = Result<Bytes, E>>,
+{
+  stream.inspect_ok(move |chunk| {
+    if let Ok(mut body) = body.lock() {
+      body.push(chunk, limit);
+    }
+  })
+}
+
+/// A request/response pair being captured
+pub struct CapturedExchange {
+  capture: HttpCapture,
+  id: Uuid,
+  started_date_time: String,
+  started_at: Instant,
+  response_at: Option<Instant>,
+  request: HarRequest,
+  request_body: Arc<SyncMutex<CapturedBody>>,
+  response: Option<HarResponse>,
+  response_body: Arc<SyncMutex<CapturedBody>>,
+  finished: bool,
+}
+
+impl CapturedExchange {
+  pub fn id(&self) -> Uuid {
+    self.id
+  }
+
+  pub fn tap_request_body(&self, body: Body) -> Body {
+    Body::from_bytes_stream(tap_stream(
+      body.into_bytes_stream(),
+      self.request_body.clone(),
+      self.capture.max_body_size,
+    ))
+  }
+
+  /// Records the response as received from the target
+  pub fn set_response(&mut self, status: StatusCode, headers: &HeaderMap) {
+    self.response_at = Some(Instant::now());
+    if let Ok(mut body) = self.response_body.lock() {
+      body.mime_type = mime_type(headers);
+    }
+    let cookies = headers
+      .get_all(http::header::SET_COOKIE)
+      .iter()
+      .filter_map(|x| x.to_str().ok())
+      .filter_map(|x| x.split(';').next()?.split_once('='))
+      .map(|(name, value)| self.capture.name_value(name.trim(), value))
+      .collect();
+    self.response = Some(HarResponse {
+      status: status.as_u16(),
+      status_text: status.canonical_reason().unwrap_or_default().to_owned(),
+      http_version: self.request.http_version.clone(),
+      cookies,
+      headers: capture_headers(headers, &self.capture.redact),
+      content: HarContent::default(),
+      redirect_url: headers
+        .get(http::header::LOCATION)
+        .and_then(|x| x.to_str().ok())
+        .unwrap_or_default()
+        .to_owned(),
+      headers_size: -1,
+      body_size: -1,
+    });
+  }
+
+  /// Passes the response body through the capture. The entry is written
+  /// once the body has been sent to the client.
+  pub fn tap_response_body(self, response: &mut Response) {
+    let body = response.take_body().into_bytes_stream();
+    let response_body = self.response_body.clone();
+    let limit = self.capture.max_body_size;
+    response.set_body(Body::from_bytes_stream(tap_stream(body, response_body, limit).inspect_ok(
+      move |_| {
+        let _exchange = &self;
+      },
+    )));
+  }
+
+  pub async fn finish(mut self) {
+    self.finished = true;
+    let entry = self.make_entry();
+    if let Err(error) = self.capture.recorder.write_entry(self.id, entry).await {
+      error!(%error, "Failed to record an HTTP request");
+    }
+  }
+
+  fn make_entry(&mut self) -> HarEntry {
+    let now = Instant::now();
+    let response_at = self.response_at.unwrap_or(now);
+    let mut request = self.request.clone();
+    if let Ok(body) = self.request_body.lock() {
+      request.body_size = body.size as i64;
+      let (text, _, comment) = self.capture.redact_body(&body);
+      request.post_data = text.map(|text| HarPostData {
+        mime_type: body.mime_type.clone(),
+        text,
+        comment,
+      });
+    }
+
+    let mut response = self.response.clone().unwrap_or_else(|| HarResponse {
+      status: 0,
+      status_text: String::new(),
+      http_version: request.http_version.clone(),
+      cookies: vec![],
+      headers: vec![],
+      content: HarContent::default(),
+      redirect_url: String::new(),
+      headers_size: -1,
+      body_size: -1,
+    });
+    if let Ok(body) = self.response_body.lock() {
+      let (text, encoding, comment) = self.capture.redact_body(&body);
+      response.body_size = body.size as i64;
+      response.content = HarContent {
+        size: body.size as i64,
+        mime_type: body.mime_type.clone(),
+        text,
+        encoding,
+        comment,
+      };
+    }
+
+    HarEntry {
+      started_date_time: self.started_date_time.clone(),
+      time: now.duration_since(self.started_at).as_secs_f64() * 1000.0,
+      request,
+      response,
+      cache: HashMap::new(),
+      timings: HarTimings {
+        send: 0.0,
+        wait: response_at.duration_since(self.started_at).as_secs_f64() * 1000.0,
+        receive: now.duration_since(response_at).as_secs_f64() * 1000.0,
+      },
+      websocket_messages: vec![],
+    }
+  }
+}
+
+impl Drop for CapturedExchange {
+  fn drop(&mut self) {
+    if self.finished {
+      return;
+    }
+    let entry = self.make_entry();
+    let id = self.id;
+    let mut recorder = self.capture.recorder.clone();
+    tokio::spawn(async move {
+      if let Err(error) = recorder.write_entry(id, entry).await {
+        error!(%error, "Failed to record an HTTP request");
+      }
+    });
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_redact_json() {
+    let redact = ["password", "token"].into_iter().map(str::to_owned).collect();
+    let mut value = serde_json::json!({
+      "user": "alice",
+      "Password": "hunter2",
+      "nested": [{ "token": 123, "keep": true }],
+    });
+    redact_json(&mut value, &redact);
+    assert_eq!(
+      value,
+      serde_json::json!({
+        "user": "alice",
+        "Password": REDACTED,
+        "nested": [{ "token": REDACTED, "keep": true }],
+      })
+    );
+  }
+
+  #[test]
+  fn test_redact_credentials() {
+    let redact = [SESSION_COOKIE_NAME.to_owned()].into_iter().collect();
+    let mut headers = HeaderMap::new();
+    headers.insert(
+      http::header::COOKIE,
+      format!("{SESSION_COOKIE_NAME}=secret; theme=dark").parse().unwrap(),
+    );
+    headers.insert(http::header::AUTHORIZATION, "Bearer secret".parse().unwrap());
+    headers.insert(http::header::PROXY_AUTHORIZATION, "Basic secret".parse().unwrap());
+    headers.insert(http::header::SET_COOKIE, "session=secret; HttpOnly".parse().unwrap());
+    headers.insert(http::header::ACCEPT, "*/*".parse().unwrap());
+
+    let captured = capture_headers(&headers, &redact);
+    assert!(!serde_json::to_string(&captured).unwrap().contains("secret"));
+    assert_eq!(
+      captured.iter().find(|x| x.name == "accept").map(|x| x.value.as_str()),
+      Some("*/*")
+    );
+    assert_eq!(
+      captured.iter().find(|x| x.name == "authorization").map(|x| x.value.as_str()),
+      Some(REDACTED)
+    );
+
+    let cookies = request_cookies(&headers, &redact);
+    let cookies: Vec<_> = cookies.iter().map(|x| (x.name.as_str(), x.value.as_str())).collect();
+    assert_eq!(cookies, [(SESSION_COOKIE_NAME, REDACTED), ("theme", "dark")]);
+  }
+
+  #[test]
+  fn test_websocket_message_data() {
+    let redact = ["token"].into_iter().map(str::to_owned).collect();
+    let json = br#"{"token":"secret"}"#;
+    assert_eq!(
+      websocket_message_data(1, json, 100, &redact),
+      format!(r#"{{"token":"{REDACTED}"}}"#)
+    );
+    assert_eq!(websocket_message_data(1, "héllo".as_bytes(), 2, &redact), "h");
+    assert_eq!(
+      websocket_message_data(2, b"\x00\x01\x02", 2, &redact),
+      BASE64.encode(b"\x00\x01")
+    );
+    // Without a body size limit, nothing is captured - same as for bodies
+    assert_eq!(websocket_message_data(1, json, 0, &redact), "");
+    assert_eq!(websocket_message_data(2, b"\x00", 0, &redact), "");
+  }
+
+  #[test]
+  fn test_body_limit() {
+    let body = CapturedBody::new("text/plain".to_owned());
+    let mut body = body.lock().unwrap();
+    body.push(b"hello ", 8);
+    body.push(b"world", 8);
+    assert_eq!(body.data, b"hello wo");
+    assert_eq!(body.size, 11);
+  }
+}
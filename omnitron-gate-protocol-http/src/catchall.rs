@@ -1,6 +1,8 @@
 use std::sync::Arc;
 
-use omnitron_api::common::{RequestAuthorization, SessionAuthorization, SessionExt};
+use futures::TryStreamExt;
+use http::StatusCode;
+use omnitron_api::common::{get_client_address, RequestAuthorization, SessionAuthorization, SessionExt};
 use omnitron_gate_common::{Target, TargetHTTPOptions, TargetOptions};
 use omnitron_gate_core::{OmnitronServerHandle, Services};
 use poem::session::Session;
@@ -11,7 +13,10 @@ use serde::Deserialize;
 use tokio::sync::Mutex;
 use tracing::*;
 
-use crate::proxy::{proxy_normal_request, proxy_websocket_request};
+use crate::capture::HttpCapture;
+use crate::limits::RequestLimits;
+use crate::proxy::{find_route, proxy_normal_request, proxy_websocket_request};
+use crate::upstreams::Upstreams;
 
 #[derive(Deserialize)]
 struct QueryParams {
@@ -24,6 +29,7 @@ pub fn target_select_redirect() -> Response {
 }
 
 #[handler]
+#[allow(clippy::too_many_arguments)]
 pub async fn catchall_endpoint(
   req: &Request,
   ws: Option<WebSocket>,
@@ -31,33 +37,80 @@ pub async fn catchall_endpoint(
   body: Body,
   services: Data<&Services>,
   server_handle: Option<Data<&Arc<Mutex<OmnitronServerHandle>>>>,
+  upstreams: Data<&Upstreams>,
+  request_limits: Data<&RequestLimits>,
 ) -> poem::Result<Response> {
   let target_and_options = get_target_for_request(req, services.0).await?;
-  let Some((target, options)) = target_and_options else {
+  let Some((target, options, username)) = target_and_options else {
     return Ok(target_select_redirect());
   };
 
   session.set_target_name(target.name.clone());
 
+  if let Some(address) = get_client_address(req, &services).await {
+    let denial = services
+      .config_provider
+      .lock()
+      .await
+      .check_address(&username, Some(&target.name), address)
+      .await?;
+    if let Some(denial) = denial {
+      warn!(%denial, target=%target.name, "Request refused by IP policy");
+      return Err(poem::Error::from_string(
+        "Requests from your address are not allowed",
+        StatusCode::FORBIDDEN,
+      ));
+    }
+  }
+
+  let limits = request_limits.get(&services, &username, &target.name).await?;
+  request_limits.check_request_rate(&username, &target.name, &limits)?;
+
   if let Some(server_handle) = server_handle {
-    server_handle.lock().await.set_target(&target).await?;
+    server_handle
+      .lock()
+      .await
+      .set_target_within_limits(&username, &target, &limits)
+      .await?;
   }
 
-  let span = info_span!("", target=%target.name);
+  // Requests that match a route go to the route's URL instead of the balanced upstreams
+  let upstream = match find_route(req, &options) {
+    Some(_) => None,
+    None => upstreams.select(&target.name, &options, session),
+  };
+  let (options, connection) = match upstream {
+    Some((url, connection)) => (TargetHTTPOptions { url, ..options }, Some(connection)),
+    None => (options, None),
+  };
+
+  let span = info_span!("", target=%target.name, upstream=%options.url);
+  let capture = HttpCapture::for_request(req, &target, &options.capture).await?;
 
   Ok(match ws {
-    Some(ws) => proxy_websocket_request(req, ws, &options)
-      .instrument(span)
-      .await?
-      .into_response(),
-    None => proxy_normal_request(req, body, &options)
+    Some(ws) => proxy_websocket_request(req, ws, &options, capture, connection)
       .instrument(span)
       .await?
       .into_response(),
+    None => {
+      let mut response = proxy_normal_request(req, body, &options, capture)
+        .instrument(span)
+        .await?
+        .into_response();
+      // Keep counting the connection until the body has been sent
+      if let Some(connection) = connection {
+        let body = response.take_body().into_bytes_stream();
+        response.set_body(Body::from_bytes_stream(body.inspect_ok(move |_| {
+          let _connection = &connection;
+        })));
+      }
+      response
+    }
   })
 }
 
-async fn get_target_for_request(req: &Request, services: &Services) -> poem::Result<Option<(Target, TargetHTTPOptions)>> {
+/// Returns the target along with the name of the user accessing it
+async fn get_target_for_request(req: &Request, services: &Services) -> poem::Result<Option<(Target, TargetHTTPOptions, String)>> {
   let session = <&Session>::from_request_without_body(req).await?;
   let params: QueryParams = req.params()?;
   let auth = Data::<&RequestAuthorization>::from_request_without_body(req).await?;
@@ -132,7 +185,7 @@ async fn get_target_for_request(req: &Request, services: &Services) -> poem::Res
         return Ok(None);
       }
 
-      return Ok(Some(target));
+      return Ok(Some((target.0, target.1, username.clone())));
     }
   }
 
@@ -4,6 +4,11 @@ use tracing::error;
 
 pub fn error_page(e: poem::Error) -> impl IntoResponse {
   error!("{:?}", e);
+  // Requests refused by Omnitron's own policies aren't the upstream's fault
+  let status = match e.status() {
+    status @ (StatusCode::TOO_MANY_REQUESTS | StatusCode::FORBIDDEN) => status,
+    _ => StatusCode::BAD_GATEWAY,
+  };
   poem::web::Html(format!(
         r#"<!DOCTYPE html>
         <style>
@@ -26,5 +31,5 @@ pub fn error_page(e: poem::Error) -> impl IntoResponse {
             <p>{e}</p>
         </main>
         "#
-    )).with_status(StatusCode::BAD_GATEWAY)
+    )).with_status(status)
 }
@@ -1,38 +1,44 @@
 #![feature(type_alias_impl_trait, try_blocks)]
+mod acme;
+mod capture;
 mod catchall;
 mod error;
+mod limits;
 mod logging;
 mod middleware;
 mod proxy;
+mod tls;
+mod upstreams;
 
 use std::fmt::Debug;
 use std::sync::Arc;
 use std::time::Duration;
 
-use anyhow::{Context, Result};
+use anyhow::Result;
 use async_trait::async_trait;
 use http::HeaderValue;
 use logging::{get_client_ip, log_request_error, log_request_result, span_for_request};
 use omnitron_api::admin_api::admin_api_app;
 use omnitron_api::common::{endpoint_admin_auth, endpoint_auth, page_admin_auth, page_auth, SESSION_COOKIE_NAME};
 use omnitron_api::session::{SessionStore, SharedSessionStorage};
-use omnitron_gate_common::{
-  ListenEndpoint, Target, TargetOptions, TlsCertificateAndPrivateKey, TlsCertificateBundle, TlsPrivateKey,
-};
+use omnitron_gate_common::{ListenEndpoint, Target, TargetHTTPOptions, TargetOptions};
 use omnitron_gate_core::{ProtocolServer, Services, TargetTestError};
 use omnitron_web::Assets;
 use poem::endpoint::{EmbeddedFileEndpoint, EmbeddedFilesEndpoint};
-use poem::listener::{Listener, RustlsConfig};
 use poem::middleware::SetHeader;
-use poem::session::{CookieConfig, MemoryStorage, ServerSession, Session};
+use poem::session::{CookieConfig, ServerSession, Session};
 use poem::web::Data;
 use poem::{Endpoint, EndpointExt, FromRequest, IntoEndpoint, IntoResponse, Route, Server};
 use poem_openapi::OpenApiService;
 use tokio::sync::Mutex;
 use tracing::*;
 
+use crate::acme::AcmeCertificates;
 use crate::error::error_page;
+use crate::limits::RequestLimits;
 use crate::middleware::{CookieHostMiddleware, TicketMiddleware};
+use crate::tls::{CertificateResolver, TlsListener};
+use crate::upstreams::Upstreams;
 
 pub struct HTTPProtocolServer {
   services: Services,
@@ -46,10 +52,6 @@ impl HTTPProtocolServer {
   }
 }
 
-fn make_session_storage() -> SharedSessionStorage {
-  SharedSessionStorage(Arc::new(Mutex::new(Box::<MemoryStorage>::default())))
-}
-
 #[async_trait]
 impl ProtocolServer for HTTPProtocolServer {
   async fn run(self, address: ListenEndpoint) -> Result<()> {
@@ -59,9 +61,10 @@ impl ProtocolServer for HTTPProtocolServer {
     let ui = api_service.swagger_ui();
     let spec = api_service.spec_endpoint();
 
-    let session_storage = make_session_storage();
     let session_store = SessionStore::new();
     let db = self.services.db.clone();
+    let upstreams = Upstreams::default();
+    let request_limits = RequestLimits::default();
 
     let cache_bust = || {
       SetHeader::new().overriding(
@@ -76,6 +79,7 @@ impl ProtocolServer for HTTPProtocolServer {
       let config = self.services.config.lock().await;
       (config.store.http.cookie_max_age, config.store.http.session_max_age)
     };
+    let session_storage = SharedSessionStorage::new(&self.services.db, cookie_max_age).await;
 
     let app = Route::new()
       .nest(
@@ -142,40 +146,47 @@ impl ProtocolServer for HTTPProtocolServer {
       .with(CookieHostMiddleware::new())
       .data(self.services.clone())
       .data(session_store.clone())
-      .data(session_storage)
+      .data(session_storage.clone())
+      .data(upstreams.clone())
+      .data(request_limits)
       .data(db);
 
+    tokio::spawn(upstreams.run_health_checks(self.services.clone()));
+
+    let vacuum_storage = session_storage.clone();
     tokio::spawn(async move {
       loop {
         session_store.lock().await.vacuum(session_max_age).await;
+        if let Err(error) = vacuum_storage.vacuum().await {
+          error!(?error, "Failed to remove expired HTTP sessions");
+        }
         tokio::time::sleep(Duration::from_secs(60)).await;
       }
     });
 
-    let certificate_and_key = {
+    let (resolver, acme) = {
       let config = self.services.config.lock().await;
-      let certificate_path = config.paths_relative_to.join(&config.store.http.certificate);
-      let key_path = config.paths_relative_to.join(&config.store.http.key);
-
-      TlsCertificateAndPrivateKey {
-        certificate: TlsCertificateBundle::from_file(&certificate_path)
-          .await
-          .with_context(|| format!("reading TLS private key from '{}'", key_path.display()))?,
-        private_key: TlsPrivateKey::from_file(&key_path)
-          .await
-          .with_context(|| format!("reading TLS certificate from '{}'", certificate_path.display()))?,
-      }
+      let acme = match config.store.http.acme.enable {
+        true => Some(AcmeCertificates::new(&self.services, &config)?),
+        false => None,
+      };
+      let resolver = CertificateResolver::new(&config, acme.as_ref().map(|x| x.resolver())).await?;
+      (resolver, acme)
     };
 
     info!(?address, "Listening");
-    Server::new(
-      address
-        .poem_listener()
-        .await?
-        .rustls(RustlsConfig::new().fallback(certificate_and_key.into())),
-    )
-    .run(app)
-    .await?;
+    let listener = TlsListener::new(address.poem_listener().await?, resolver, acme.as_ref().map(|x| x.challenge()));
+    let server = Server::new(listener).run(app);
+
+    match acme {
+      Some(acme) => {
+        tokio::select! {
+          result = server => result?,
+          _ = acme.run() => (),
+        }
+      }
+      None => server.await?,
+    }
 
     Ok(())
   }
@@ -185,12 +196,35 @@ impl ProtocolServer for HTTPProtocolServer {
       return Err(TargetTestError::Misconfigured("Not an HTTP target".to_owned()));
     };
 
-    let mut request = poem::Request::builder().uri_str("http://host/").finish();
-    request.extensions_mut().insert(Session::default());
-    crate::proxy::proxy_normal_request(&request, poem::Body::empty(), &options)
-      .await
-      .map_err(|e| TargetTestError::ConnectionError(format!("{e}")))?;
-    Ok(())
+    let mut statuses = vec![];
+    let mut failed = false;
+    for url in options.upstream_urls() {
+      let mut request = poem::Request::builder().uri_str("http://host/").finish();
+      request.extensions_mut().insert(Session::default());
+      let options = TargetHTTPOptions {
+        url: url.clone(),
+        ..options.clone()
+      };
+      match crate::proxy::proxy_normal_request(&request, poem::Body::empty(), &options, None).await {
+        Ok(_) => {
+          info!(upstream=%url, "Upstream is reachable");
+          statuses.push(format!("{url}: reachable"));
+        }
+        Err(error) => {
+          warn!(upstream=%url, %error, "Upstream is unreachable");
+          failed = true;
+          statuses.push(match options.upstreams.is_empty() {
+            true => format!("{error}"),
+            false => format!("{url}: {error}"),
+          });
+        }
+      }
+    }
+
+    match failed {
+      false => Ok(()),
+      true => Err(TargetTestError::ConnectionError(statuses.join("; "))),
+    }
   }
 }
 
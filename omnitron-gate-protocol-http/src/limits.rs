@@ -0,0 +1,119 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use omnitron_gate_common::{OmnitronError, SessionLimits};
+use omnitron_gate_core::Services;
+
+/// How long looked up limits are used before they're looked up again
+const LIMITS_CACHE_TTL: Duration = Duration::from_secs(10);
+const RATE_LIMIT_WINDOW: Duration = Duration::from_secs(1);
+
+struct CachedLimits {
+  fetched: Instant,
+  limits: SessionLimits,
+}
+
+/// Requests made by a user to a target within the current rate limit window
+struct RequestWindow {
+  started: Instant,
+  count: u32,
+}
+
+/// Session limits of the users and targets that requests have been made to, and the
+/// request rates counted against them. Kept apart from the global state, so that
+/// requests don't have to wait for its lock or for the config provider.
+#[derive(Clone, Default)]
+pub struct RequestLimits {
+  cached: Arc<Mutex<HashMap<(String, String), CachedLimits>>>,
+  windows: Arc<Mutex<HashMap<(String, String), RequestWindow>>>,
+}
+
+impl RequestLimits {
+  /// The user's limits for the target, looked up again once the cached ones are [LIMITS_CACHE_TTL] old
+  pub async fn get(&self, services: &Services, username: &str, target_name: &str) -> Result<SessionLimits, OmnitronError> {
+    let key = (username.to_owned(), target_name.to_owned());
+    let cached = {
+      #[allow(clippy::unwrap_used)]
+      let cached = self.cached.lock().unwrap();
+      cached
+        .get(&key)
+        .filter(|x| x.fetched.elapsed() < LIMITS_CACHE_TTL)
+        .map(|x| x.limits.clone())
+    };
+    if let Some(limits) = cached {
+      return Ok(limits);
+    }
+
+    let limits = services
+      .config_provider
+      .lock()
+      .await
+      .get_session_limits(username, target_name)
+      .await?;
+
+    #[allow(clippy::unwrap_used)]
+    let mut cached = self.cached.lock().unwrap();
+    cached.retain(|_, x| x.fetched.elapsed() < LIMITS_CACHE_TTL);
+    cached.insert(
+      key,
+      CachedLimits {
+        fetched: Instant::now(),
+        limits: limits.clone(),
+      },
+    );
+    Ok(limits)
+  }
+
+  /// Counts a request towards the user's per-second limit for the target
+  pub fn check_request_rate(&self, username: &str, target_name: &str, limits: &SessionLimits) -> Result<(), OmnitronError> {
+    let Some(limit) = limits.requests_per_second else {
+      return Ok(());
+    };
+
+    #[allow(clippy::unwrap_used)]
+    let mut windows = self.windows.lock().unwrap();
+    windows.retain(|_, x| x.started.elapsed() < RATE_LIMIT_WINDOW);
+    let window = windows
+      .entry((username.to_owned(), target_name.to_owned()))
+      .or_insert_with(|| RequestWindow {
+        started: Instant::now(),
+        count: 0,
+      });
+
+    if window.count >= limit {
+      return Err(OmnitronError::RateLimited(target_name.to_owned()));
+    }
+    window.count += 1;
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn limits(requests_per_second: Option<u32>) -> SessionLimits {
+    SessionLimits {
+      max_sessions_per_user: None,
+      requests_per_second,
+    }
+  }
+
+  #[test]
+  fn test_check_request_rate() {
+    let limiter = RequestLimits::default();
+    let check = |username, target| limiter.check_request_rate(username, target, &limits(Some(2)));
+
+    assert!(check("alice", "web").is_ok());
+    assert!(check("alice", "web").is_ok());
+    assert!(matches!(check("alice", "web"), Err(OmnitronError::RateLimited(target)) if target == "web"));
+    // Counted separately for each user and target
+    assert!(check("bob", "web").is_ok());
+    assert!(check("alice", "api").is_ok());
+    assert!(limiter.check_request_rate("alice", "web", &limits(None)).is_ok());
+
+    std::thread::sleep(RATE_LIMIT_WINDOW);
+    assert!(check("alice", "web").is_ok());
+  }
+}
@@ -1,6 +1,7 @@
 use std::error::Error;
 
 use http::{Method, StatusCode, Uri};
+use omnitron_api::common::get_client_address;
 use omnitron_api::session_handle::OmnitronServerHandleFromRequest;
 use omnitron_gate_core::Services;
 use poem::web::Data;
@@ -40,22 +41,9 @@ pub fn log_request_error<E: Error>(method: &Method, url: &Uri, client_ip: &str,
 }
 
 pub async fn get_client_ip(req: &Request) -> poem::Result<String> {
-  let services = Data::<&Services>::from_request_without_body(req).await.ok();
-  let trust_x_forwarded_headers = if let Some(services) = services {
-    let config = services.config.lock().await;
-    config.store.http.trust_x_forwarded_headers
-  } else {
-    false
+  let address = match Data::<&Services>::from_request_without_body(req).await {
+    Ok(services) => get_client_address(req, services.0).await,
+    Err(_) => req.remote_addr().as_socket_addr().map(|x| x.ip()),
   };
-
-  let remote_ip = req
-    .remote_addr()
-    .as_socket_addr()
-    .map(|x| x.ip().to_string())
-    .unwrap_or("<unknown>".into());
-
-  match trust_x_forwarded_headers {
-    true => Ok(req.header("x-forwarded-for").map(|x| x.to_string()).unwrap_or(remote_ip)),
-    false => Ok(remote_ip),
-  }
+  Ok(address.map(|x| x.to_string()).unwrap_or("<unknown>".into()))
 }
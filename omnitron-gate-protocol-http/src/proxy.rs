@@ -9,7 +9,8 @@ use http::header::HeaderName;
 use http::uri::{Authority, Scheme};
 use http::Uri;
 use omnitron_api::common::{SessionAuthorization, SessionExt};
-use omnitron_gate_common::{try_block, OmnitronError, TargetHTTPOptions, TlsMode};
+use omnitron_gate_common::{try_block, HttpRoute, OmnitronError, TargetHTTPOptions, TlsMode};
+use omnitron_gate_core::recordings::HarWebSocketDirection;
 use omnitron_web::lookup_built_file;
 use once_cell::sync::Lazy;
 use poem::session::Session;
@@ -20,7 +21,9 @@ use tokio_tungstenite::{connect_async_with_config, tungstenite};
 use tracing::*;
 use url::Url;
 
+use crate::capture::HttpCapture;
 use crate::logging::{get_client_ip, log_request_result};
+use crate::upstreams::UpstreamConnection;
 
 static X_OMNITRON_USERNAME: HeaderName = HeaderName::from_static("x-omnitron-username");
 static X_OMNITRON_AUTHENTICATION_TYPE: HeaderName = HeaderName::from_static("x-omnitron-authentication-type");
@@ -88,18 +91,67 @@ static X_FORWARDED_FOR: HeaderName = HeaderName::from_static("x-forwarded-for");
 static X_FORWARDED_HOST: HeaderName = HeaderName::from_static("x-forwarded-host");
 static X_FORWARDED_PROTO: HeaderName = HeaderName::from_static("x-forwarded-proto");
 
-fn construct_uri(req: &Request, options: &TargetHTTPOptions, websocket: bool) -> Result<Uri> {
-  let target_uri = Uri::try_from(options.url.clone())?;
+fn path_matches_prefix(path: &str, prefix: &str) -> bool {
+  let prefix = prefix.trim_end_matches('/');
+  path
+    .strip_prefix(prefix)
+    .map_or(false, |rest| rest.is_empty() || rest.starts_with('/'))
+}
+
+/// Swaps `prefix` in a path that matches it for `replacement`
+fn replace_path_prefix(path: &str, prefix: &str, replacement: &str) -> String {
+  let rest = &path[prefix.trim_end_matches('/').len()..];
+  let result = format!("{}{rest}", replacement.trim_end_matches('/'));
+  match result.starts_with('/') {
+    true => result,
+    false => format!("/{result}"),
+  }
+}
+
+/// Picks the first of the target's routes that matches the request
+pub(crate) fn find_route<'a>(req: &Request, options: &'a TargetHTTPOptions) -> Option<&'a HttpRoute> {
+  options.routes.iter().find(|route| {
+    path_matches_prefix(req.uri().path(), &route.path_prefix)
+      && route.header.as_ref().map_or(true, |header| {
+        req
+          .headers()
+          .get_all(header.name.as_str())
+          .iter()
+          .any(|value| header.value.as_ref().map_or(true, |x| value.as_bytes() == x.as_bytes()))
+      })
+  })
+}
+
+/// Routes are sent to their URL as is - the target's TLS mode is for its own URL
+fn tls_mode(options: &TargetHTTPOptions, route: Option<&HttpRoute>) -> TlsMode {
+  match route {
+    Some(_) => TlsMode::Preferred,
+    None => options.tls.mode.clone(),
+  }
+}
+
+fn construct_uri(req: &Request, options: &TargetHTTPOptions, route: Option<&HttpRoute>, websocket: bool) -> Result<Uri> {
+  let target_uri = Uri::try_from(route.map_or(&options.url, |x| &x.url).clone())?;
   let source_uri = req.uri().clone();
 
+  let mut path_and_query = source_uri.path_and_query().context("No path in the URL")?.clone();
+  if let Some(route) = route {
+    if let Some(ref replacement) = route.rewrite_prefix {
+      let path = replace_path_prefix(path_and_query.path(), &route.path_prefix, replacement);
+      path_and_query = match path_and_query.query() {
+        Some(query) => format!("{path}?{query}"),
+        None => path,
+      }
+      .parse()?;
+    }
+  }
+
   let authority = target_uri.authority().context("No authority in the URL")?.to_string();
   let authority = authority.split('@').last().context("Authority is empty")?;
   let authority: Authority = authority.try_into()?;
-  let mut uri = http::uri::Builder::new()
-    .authority(authority)
-    .path_and_query(source_uri.path_and_query().context("No path in the URL")?.clone());
+  let mut uri = http::uri::Builder::new().authority(authority).path_and_query(path_and_query);
 
-  let scheme = match options.tls.mode {
+  let scheme = match tls_mode(options, route) {
     TlsMode::Disabled => &Scheme::HTTP,
     TlsMode::Preferred => target_uri.scheme().context("No scheme in the URL")?,
     TlsMode::Required => &Scheme::HTTPS,
@@ -144,21 +196,25 @@ fn rewrite_request<B: SomeRequestBuilder>(mut req: B, options: &TargetHTTPOption
   Ok(req)
 }
 
-fn rewrite_response(resp: &mut Response, options: &TargetHTTPOptions, source_uri: &Uri) -> Result<()> {
-  let target_uri = Uri::try_from(options.url.clone())?;
+/// `source_uri` is the URI the request was sent to upstream
+fn rewrite_response(resp: &mut Response, route: Option<&HttpRoute>, source_uri: &Uri) -> Result<()> {
   let headers = resp.headers_mut();
 
   if let Some(value) = headers.get_mut(http::header::LOCATION) {
     let location = Url::parse(&source_uri.to_string())?.join(value.to_str()?)?;
     let redirect_uri = Uri::try_from(location.to_string())?;
 
-    if redirect_uri.authority() == target_uri.authority() {
+    if redirect_uri.authority() == source_uri.authority() {
       let old_value = value.clone();
-      *value = Uri::builder()
-        .path_and_query(redirect_uri.path_and_query().context("No path in URL")?.clone())
-        .build()?
-        .to_string()
-        .parse()?;
+      let mut path_and_query = redirect_uri.path_and_query().context("No path in URL")?.to_string();
+      if let Some(route) = route {
+        if let Some(ref replacement) = route.rewrite_prefix {
+          if path_matches_prefix(redirect_uri.path(), replacement) {
+            path_and_query = replace_path_prefix(&path_and_query, replacement, &route.path_prefix);
+          }
+        }
+      }
+      *value = Uri::builder().path_and_query(path_and_query).build()?.to_string().parse()?;
       debug!("Rewrote a redirect from {:?} to {:?}", old_value, value);
     }
   }
@@ -228,8 +284,19 @@ async fn inject_own_headers<B: SomeRequestBuilder>(req: &Request, mut target: B)
   Ok(target)
 }
 
-pub async fn proxy_normal_request(req: &Request, body: Body, options: &TargetHTTPOptions) -> poem::Result<Response> {
-  let uri = construct_uri(req, options, false)?;
+pub async fn proxy_normal_request(
+  req: &Request,
+  body: Body,
+  options: &TargetHTTPOptions,
+  capture: Option<HttpCapture>,
+) -> poem::Result<Response> {
+  let route = find_route(req, options);
+  let uri = construct_uri(req, options, route, false)?;
+  let mut exchange = capture.map(|x| x.start(req));
+  let body = match exchange {
+    Some(ref exchange) => exchange.tap_request_body(body),
+    None => body,
+  };
 
   tracing::debug!("URI: {:?}", uri);
 
@@ -237,12 +304,12 @@ pub async fn proxy_normal_request(req: &Request, body: Body, options: &TargetHTT
     .redirect(reqwest::redirect::Policy::none())
     .connection_verbose(true);
 
-  if let TlsMode::Required = options.tls.mode {
+  let tls_mode = tls_mode(options, route);
+  if let TlsMode::Required = tls_mode {
     client = client.https_only(true);
   }
 
   client = client.redirect(reqwest::redirect::Policy::custom({
-    let tls_mode = options.tls.mode.clone();
     let uri = uri.clone();
     move |attempt| {
       if tls_mode == TlsMode::Preferred && uri.scheme() == Some(&Scheme::HTTP) && attempt.url().scheme() == "https" {
@@ -278,6 +345,9 @@ pub async fn proxy_normal_request(req: &Request, body: Body, options: &TargetHTT
     .await
     .map_err(|e| anyhow::anyhow!("Could not execute request: {e}"))?;
   let status = client_response.status();
+  if let Some(ref mut exchange) = exchange {
+    exchange.set_response(status, client_response.headers());
+  }
 
   let mut response: Response = "".into();
 
@@ -286,7 +356,10 @@ pub async fn proxy_normal_request(req: &Request, body: Body, options: &TargetHTT
 
   log_request_result(req.method(), req.original_uri(), &get_client_ip(req).await?, &status);
 
-  rewrite_response(&mut response, options, &uri)?;
+  rewrite_response(&mut response, route, &uri)?;
+  if let Some(exchange) = exchange {
+    exchange.tap_response_body(&mut response);
+  }
   Ok(response)
 }
 
@@ -332,15 +405,29 @@ pub async fn proxy_websocket_request(
   req: &Request,
   ws: WebSocket,
   options: &TargetHTTPOptions,
+  capture: Option<HttpCapture>,
+  connection: Option<UpstreamConnection>,
 ) -> poem::Result<impl IntoResponse> {
-  let uri = construct_uri(req, options, true)?;
-  proxy_ws_inner(req, ws, uri.clone(), options).await.map_err(|error| {
-    tracing::error!(?uri, ?error, "WebSocket proxy failed");
-    error
-  })
+  let route = find_route(req, options);
+  let uri = construct_uri(req, options, route, true)?;
+  proxy_ws_inner(req, ws, uri.clone(), options, route, capture, connection)
+    .await
+    .map_err(|error| {
+      tracing::error!(?uri, ?error, "WebSocket proxy failed");
+      error
+    })
 }
 
-async fn proxy_ws_inner(req: &Request, ws: WebSocket, uri: Uri, options: &TargetHTTPOptions) -> poem::Result<impl IntoResponse> {
+async fn proxy_ws_inner(
+  req: &Request,
+  ws: WebSocket,
+  uri: Uri,
+  options: &TargetHTTPOptions,
+  route: Option<&HttpRoute>,
+  capture: Option<HttpCapture>,
+  connection: Option<UpstreamConnection>,
+) -> poem::Result<impl IntoResponse> {
+  let mut exchange = capture.as_ref().map(|x| x.start(req));
   let mut client_request = http::request::Builder::new()
     .uri(uri.clone())
     .header(http::header::CONNECTION, "Upgrade")
@@ -367,8 +454,22 @@ async fn proxy_ws_inner(req: &Request, ws: WebSocket, uri: Uri, options: &Target
 
   tracing::info!("{:?} {:?} - WebSocket", client_response.status(), uri);
 
+  // Only keep the capture around if frames are recorded
+  let capture = match exchange.take() {
+    Some(mut exchange) => {
+      exchange.set_response(client_response.status(), client_response.headers());
+      let entry_id = exchange.id();
+      exchange.finish().await;
+      capture.filter(|x| x.websocket_frames()).map(|x| (x, entry_id))
+    }
+    None => None,
+  };
+  let server_capture = capture.clone();
+  let client_capture = capture;
+
   let mut response = ws
     .on_upgrade(|socket| async move {
+      let _connection = connection;
       let (mut client_sink, mut client_source) = client.split();
 
       let (mut server_sink, mut server_source) = socket.split();
@@ -377,7 +478,23 @@ async fn proxy_ws_inner(req: &Request, ws: WebSocket, uri: Uri, options: &Target
         let server_to_client = tokio::spawn(async move {
           while let Some(msg) = server_source.next().await {
             tracing::debug!("Server: {:?}", msg);
-            match msg? {
+            let msg = msg?;
+            if let Some((ref capture, entry_id)) = server_capture {
+              match &msg {
+                Message::Text(text) => {
+                  capture
+                    .write_websocket_message(entry_id, HarWebSocketDirection::Send, 1, text.as_bytes())
+                    .await
+                }
+                Message::Binary(data) => {
+                  capture
+                    .write_websocket_message(entry_id, HarWebSocketDirection::Send, 2, data)
+                    .await
+                }
+                _ => (),
+              }
+            }
+            match msg {
               Message::Binary(data) => {
                 client_sink.send(tungstenite::Message::Binary(data.into())).await?;
               }
@@ -408,7 +525,23 @@ async fn proxy_ws_inner(req: &Request, ws: WebSocket, uri: Uri, options: &Target
         let client_to_server = tokio::spawn(async move {
           while let Some(msg) = client_source.next().await {
             tracing::debug!("Client: {:?}", msg);
-            match msg? {
+            let msg = msg?;
+            if let Some((ref capture, entry_id)) = client_capture {
+              match &msg {
+                tungstenite::Message::Text(text) => {
+                  capture
+                    .write_websocket_message(entry_id, HarWebSocketDirection::Receive, 1, text.as_bytes())
+                    .await
+                }
+                tungstenite::Message::Binary(data) => {
+                  capture
+                    .write_websocket_message(entry_id, HarWebSocketDirection::Receive, 2, data)
+                    .await
+                }
+                _ => (),
+              }
+            }
+            match msg {
               tungstenite::Message::Binary(data) => {
                 server_sink.send(Message::Binary(data.into())).await?;
               }
@@ -447,6 +580,124 @@ async fn proxy_ws_inner(req: &Request, ws: WebSocket, uri: Uri, options: &Target
     .into_response();
 
   copy_client_response(&client_response, &mut response);
-  rewrite_response(&mut response, options, &uri)?;
+  rewrite_response(&mut response, route, &uri)?;
   Ok(response)
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_path_prefixes() {
+    assert!(path_matches_prefix("/api", "/api"));
+    assert!(path_matches_prefix("/api/users", "/api/"));
+    assert!(!path_matches_prefix("/apis", "/api"));
+    assert!(path_matches_prefix("/anything", "/"));
+
+    assert_eq!(replace_path_prefix("/api/users", "/api", ""), "/users");
+    assert_eq!(replace_path_prefix("/api", "/api", ""), "/");
+    assert_eq!(replace_path_prefix("/api/users", "/api/", "/v2/"), "/v2/users");
+    assert_eq!(replace_path_prefix("/users", "/", "/app"), "/app/users");
+  }
+
+  fn options(tls_mode: &str) -> TargetHTTPOptions {
+    serde_json::from_value(serde_json::json!({
+      "url": "https://main.example.com",
+      "tls": { "mode": tls_mode },
+      "routes": [
+        { "path_prefix": "/api", "header": { "name": "X-Canary", "value": "1" }, "url": "http://canary:8080" },
+        { "path_prefix": "/api", "header": { "name": "X-Debug" }, "url": "http://debug:8080" },
+        { "path_prefix": "/api", "url": "http://api:8080", "rewrite_prefix": "/v2" },
+        { "path_prefix": "/", "url": "http://fallback:8080" },
+        { "path_prefix": "/never", "url": "http://never:8080" },
+      ],
+    }))
+    .unwrap()
+  }
+
+  fn request(path: &str, headers: &[(&'static str, &str)]) -> Request {
+    headers
+      .iter()
+      .fold(Request::builder().uri_str(path), |req, (name, value)| {
+        req.header(*name, *value)
+      })
+      .finish()
+  }
+
+  fn route_url<'a>(req: &Request, options: &'a TargetHTTPOptions) -> Option<&'a str> {
+    find_route(req, options).map(|x| x.url.as_str())
+  }
+
+  #[test]
+  fn test_find_route() {
+    let options = options("preferred");
+    assert_eq!(
+      route_url(&request("/api/x", &[("X-Canary", "1")]), &options),
+      Some("http://canary:8080")
+    );
+    assert_eq!(
+      route_url(&request("/api/x", &[("X-Canary", "2")]), &options),
+      Some("http://api:8080")
+    );
+    // A header match without a value matches any value
+    assert_eq!(
+      route_url(&request("/api", &[("X-Debug", "")]), &options),
+      Some("http://debug:8080")
+    );
+    assert_eq!(route_url(&request("/api/x", &[]), &options), Some("http://api:8080"));
+    // The first matching route wins, even if a later one is more specific
+    assert_eq!(route_url(&request("/never/x", &[]), &options), Some("http://fallback:8080"));
+
+    let no_routes = TargetHTTPOptions {
+      routes: vec![],
+      ..options
+    };
+    assert_eq!(route_url(&request("/api/x", &[]), &no_routes), None);
+  }
+
+  #[test]
+  fn test_construct_uri() {
+    let uri = |tls_mode: &str, path: &str| {
+      let options = options(tls_mode);
+      let req = request(path, &[]);
+      construct_uri(&req, &options, find_route(&req, &options), false)
+        .unwrap()
+        .to_string()
+    };
+
+    assert_eq!(uri("required", "/api/users?a=b"), "http://api:8080/v2/users?a=b");
+    assert_eq!(uri("disabled", "/other"), "http://fallback:8080/other");
+
+    let main = |tls_mode: &str| {
+      let options = TargetHTTPOptions {
+        routes: vec![],
+        ..options(tls_mode)
+      };
+      construct_uri(&request("/x", &[]), &options, None, false).unwrap().to_string()
+    };
+    assert_eq!(main("required"), "https://main.example.com/x");
+    assert_eq!(main("disabled"), "http://main.example.com/x");
+  }
+
+  #[test]
+  fn test_rewrite_redirect() {
+    let options = options("preferred");
+    let route = &options.routes[2];
+    let source_uri = Uri::from_static("http://api:8080/v2/login");
+    let redirect = |location: &str| {
+      let mut response = Response::builder().header(http::header::LOCATION, location).finish();
+      rewrite_response(&mut response, Some(route), &source_uri).unwrap();
+      response.headers()[http::header::LOCATION].to_str().unwrap().to_owned()
+    };
+
+    // Back under the route's prefix, whether relative or absolute
+    assert_eq!(redirect("/v2/home?x=1"), "/api/home?x=1");
+    assert_eq!(redirect("http://api:8080/v2/home"), "/api/home");
+    assert_eq!(redirect("next"), "/api/next");
+    // Paths outside of the rewritten prefix are left alone
+    assert_eq!(redirect("/static/app.js"), "/static/app.js");
+    // So are redirects to other hosts
+    assert_eq!(redirect("https://sso.example.com/login"), "https://sso.example.com/login");
+  }
+}
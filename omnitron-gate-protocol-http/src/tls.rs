@@ -0,0 +1,219 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::io::{Error as IoError, Result as IoResult};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context as TaskContext, Poll};
+
+use anyhow::{Context, Result};
+use http::uri::Scheme;
+use omnitron_gate_common::{AcmeChallengeType, OmnitronConfig, TlsCertificateBundle, TlsPrivateKey};
+use poem::listener::acme::ResolveServerCert;
+use poem::listener::{Acceptor, Listener};
+use poem::web::{LocalAddr, RemoteAddr};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio_rustls::rustls::crypto::ring::sign::any_supported_type;
+use tokio_rustls::rustls::server::{ClientHello, ResolvesServerCert};
+use tokio_rustls::rustls::sign::CertifiedKey;
+use tokio_rustls::rustls::ServerConfig;
+use tokio_rustls::server::TlsStream;
+use tracing::*;
+
+const ACME_TLS_ALPN_NAME: &[u8] = b"acme-tls/1";
+
+/// Picks the certificate for each TLS connection: TLS-ALPN-01 challenges go to the
+/// ACME resolver, then the SNI hostname is looked up among the dedicated certificates,
+/// then the ACME certificate is used if there is one, and finally the default certificate.
+#[derive(Debug)]
+pub(crate) struct CertificateResolver {
+  fallback: Arc<CertifiedKey>,
+  sni_certificates: HashMap<String, Arc<CertifiedKey>>,
+  acme: Option<Arc<ResolveServerCert>>,
+}
+
+impl CertificateResolver {
+  pub(crate) async fn new(config: &OmnitronConfig, acme: Option<Arc<ResolveServerCert>>) -> Result<Self> {
+    let http_config = &config.store.http;
+    let fallback = Arc::new(load_certified_key(config, &http_config.certificate, &http_config.key).await?);
+
+    let mut sni_certificates = HashMap::new();
+    for sni_certificate in &http_config.sni_certificates {
+      let certified_key = Arc::new(load_certified_key(config, &sni_certificate.certificate, &sni_certificate.key).await?);
+      for host in &sni_certificate.hosts {
+        debug!(%host, "Using a dedicated TLS certificate");
+        sni_certificates.insert(host.to_lowercase(), certified_key.clone());
+      }
+    }
+
+    Ok(Self {
+      fallback,
+      sni_certificates,
+      acme,
+    })
+  }
+}
+
+impl ResolvesServerCert for CertificateResolver {
+  fn resolve(&self, client_hello: ClientHello) -> Option<Arc<CertifiedKey>> {
+    let server_name = client_hello.server_name().map(|x| x.to_lowercase());
+
+    if let Some(acme) = &self.acme {
+      let is_acme_challenge = client_hello
+        .alpn()
+        .map_or(false, |mut protocols| protocols.any(|x| x == ACME_TLS_ALPN_NAME));
+      if is_acme_challenge {
+        return acme.resolve(client_hello);
+      }
+    }
+
+    if let Some(certified_key) = server_name.as_ref().and_then(|x| self.sni_certificates.get(x)) {
+      return Some(certified_key.clone());
+    }
+
+    if let (Some(acme), Some(_)) = (&self.acme, &server_name) {
+      if let Some(certified_key) = acme.cert.read().clone() {
+        return Some(certified_key);
+      }
+    }
+
+    Some(self.fallback.clone())
+  }
+}
+
+async fn load_certified_key(config: &OmnitronConfig, certificate: &str, key: &str) -> Result<CertifiedKey> {
+  let certificate_path = config.paths_relative_to.join(certificate);
+  let key_path = config.paths_relative_to.join(key);
+
+  let certificate: Vec<u8> = TlsCertificateBundle::from_file(&certificate_path)
+    .await
+    .with_context(|| format!("reading TLS certificate from '{}'", certificate_path.display()))?
+    .into();
+  let key: Vec<u8> = TlsPrivateKey::from_file(&key_path)
+    .await
+    .with_context(|| format!("reading TLS private key from '{}'", key_path.display()))?
+    .into();
+
+  certified_key_from_pem(&certificate, &key)
+}
+
+pub(crate) fn certified_key_from_pem(certificate: &[u8], key: &[u8]) -> Result<CertifiedKey> {
+  let certificates = rustls_pemfile::certs(&mut &certificate[..]).collect::<Result<Vec<_>, _>>()?;
+  let key = rustls_pemfile::private_key(&mut &key[..])?.context("no private key found")?;
+  Ok(CertifiedKey::new(certificates, any_supported_type(&key)?))
+}
+
+/// TLS listener for the HTTP gate, used instead of poem's own so that the
+/// certificate can be picked by SNI and updated by ACME without a restart
+pub(crate) struct TlsListener<T> {
+  inner: T,
+  server_config: Arc<ServerConfig>,
+}
+
+impl<T> TlsListener<T> {
+  pub(crate) fn new(inner: T, resolver: CertificateResolver, challenge: Option<AcmeChallengeType>) -> Self {
+    let mut server_config = ServerConfig::builder()
+      .with_no_client_auth()
+      .with_cert_resolver(Arc::new(resolver));
+    server_config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    if challenge == Some(AcmeChallengeType::TlsAlpn01) {
+      server_config.alpn_protocols.push(ACME_TLS_ALPN_NAME.to_vec());
+    }
+    Self {
+      inner,
+      server_config: Arc::new(server_config),
+    }
+  }
+}
+
+impl<T: Listener> Listener for TlsListener<T> {
+  type Acceptor = TlsAcceptor<T::Acceptor>;
+
+  async fn into_acceptor(self) -> IoResult<Self::Acceptor> {
+    Ok(TlsAcceptor {
+      inner: self.inner.into_acceptor().await?,
+      acceptor: tokio_rustls::TlsAcceptor::from(self.server_config),
+    })
+  }
+}
+
+pub(crate) struct TlsAcceptor<T> {
+  inner: T,
+  acceptor: tokio_rustls::TlsAcceptor,
+}
+
+impl<T: Acceptor> Acceptor for TlsAcceptor<T> {
+  type Io = LazyTlsStream<T::Io>;
+
+  fn local_addr(&self) -> Vec<LocalAddr> {
+    self.inner.local_addr()
+  }
+
+  async fn accept(&mut self) -> IoResult<(Self::Io, LocalAddr, RemoteAddr, Scheme)> {
+    let (stream, local_addr, remote_addr, _) = self.inner.accept().await?;
+    let stream = LazyTlsStream::Handshake(Box::new(self.acceptor.accept(stream)));
+    Ok((stream, local_addr, remote_addr, Scheme::HTTPS))
+  }
+}
+
+/// Completes the TLS handshake on first use so that a slow client
+/// can't hold up the accept loop
+pub(crate) enum LazyTlsStream<IO> {
+  Handshake(Box<tokio_rustls::Accept<IO>>),
+  Ready(Box<TlsStream<IO>>),
+  Failed,
+}
+
+impl<IO: AsyncRead + AsyncWrite + Unpin> LazyTlsStream<IO> {
+  fn poll_stream(&mut self, cx: &mut TaskContext<'_>) -> Poll<IoResult<&mut TlsStream<IO>>> {
+    if let Self::Handshake(accept) = self {
+      match Pin::new(accept.as_mut()).poll(cx) {
+        Poll::Pending => return Poll::Pending,
+        Poll::Ready(Ok(stream)) => *self = Self::Ready(Box::new(stream)),
+        Poll::Ready(Err(error)) => {
+          *self = Self::Failed;
+          return Poll::Ready(Err(error));
+        }
+      }
+    }
+    match self {
+      Self::Ready(stream) => Poll::Ready(Ok(stream)),
+      _ => Poll::Ready(Err(IoError::other("TLS handshake failed"))),
+    }
+  }
+}
+
+impl<IO: AsyncRead + AsyncWrite + Unpin> AsyncRead for LazyTlsStream<IO> {
+  fn poll_read(self: Pin<&mut Self>, cx: &mut TaskContext<'_>, buf: &mut ReadBuf<'_>) -> Poll<IoResult<()>> {
+    match self.get_mut().poll_stream(cx) {
+      Poll::Ready(Ok(stream)) => Pin::new(stream).poll_read(cx, buf),
+      Poll::Ready(Err(error)) => Poll::Ready(Err(error)),
+      Poll::Pending => Poll::Pending,
+    }
+  }
+}
+
+impl<IO: AsyncRead + AsyncWrite + Unpin> AsyncWrite for LazyTlsStream<IO> {
+  fn poll_write(self: Pin<&mut Self>, cx: &mut TaskContext<'_>, buf: &[u8]) -> Poll<IoResult<usize>> {
+    match self.get_mut().poll_stream(cx) {
+      Poll::Ready(Ok(stream)) => Pin::new(stream).poll_write(cx, buf),
+      Poll::Ready(Err(error)) => Poll::Ready(Err(error)),
+      Poll::Pending => Poll::Pending,
+    }
+  }
+
+  fn poll_flush(self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<IoResult<()>> {
+    match self.get_mut().poll_stream(cx) {
+      Poll::Ready(Ok(stream)) => Pin::new(stream).poll_flush(cx),
+      Poll::Ready(Err(error)) => Poll::Ready(Err(error)),
+      Poll::Pending => Poll::Pending,
+    }
+  }
+
+  fn poll_shutdown(self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<IoResult<()>> {
+    match self.get_mut().poll_stream(cx) {
+      Poll::Ready(Ok(stream)) => Pin::new(stream).poll_shutdown(cx),
+      Poll::Ready(Err(error)) => Poll::Ready(Err(error)),
+      Poll::Pending => Poll::Pending,
+    }
+  }
+}
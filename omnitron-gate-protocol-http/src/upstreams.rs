@@ -0,0 +1,324 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use omnitron_gate_common::{HttpLoadBalancing, TargetHTTPOptions, TargetOptions, TlsMode};
+use omnitron_gate_core::Services;
+use poem::session::Session;
+use tokio::time::Instant;
+use tracing::*;
+
+const HEALTH_CHECK_TICK: Duration = Duration::from_secs(1);
+/// How often the list of targets to health check is reloaded from the config
+const TARGETS_REFRESH_INTERVAL: Duration = Duration::from_secs(30);
+
+/// A target with several upstreams and a health check path
+struct HealthCheckedTarget {
+  name: String,
+  path: String,
+  options: TargetHTTPOptions,
+}
+
+struct UpstreamState {
+  url: String,
+  healthy: AtomicBool,
+  connections: AtomicUsize,
+}
+
+struct UpstreamPool {
+  upstreams: Vec<Arc<UpstreamState>>,
+  next: AtomicUsize,
+  last_check: Mutex<Option<Instant>>,
+}
+
+/// Counts as an open connection to an upstream until dropped
+pub struct UpstreamConnection(Arc<UpstreamState>);
+
+impl Drop for UpstreamConnection {
+  fn drop(&mut self) {
+    self.0.connections.fetch_sub(1, Ordering::Relaxed);
+  }
+}
+
+/// Balancing and health state of the targets that have several upstreams
+#[derive(Clone, Default)]
+pub struct Upstreams {
+  pools: Arc<Mutex<HashMap<String, Arc<UpstreamPool>>>>,
+}
+
+impl Upstreams {
+  fn pool_for(&self, target_name: &str, options: &TargetHTTPOptions) -> Arc<UpstreamPool> {
+    let urls = options.upstream_urls();
+    #[allow(clippy::unwrap_used)]
+    let mut pools = self.pools.lock().unwrap();
+
+    if let Some(pool) = pools.get(target_name) {
+      if pool.upstreams.iter().map(|x| &x.url).eq(urls.iter()) {
+        return pool.clone();
+      }
+    }
+
+    // The upstream list has changed - carry over the state of the ones that are still there
+    let previous = pools.remove(target_name);
+    let upstreams = urls
+      .into_iter()
+      .map(|url| {
+        previous
+          .as_ref()
+          .and_then(|x| x.upstreams.iter().find(|x| x.url == url).cloned())
+          .unwrap_or_else(|| {
+            Arc::new(UpstreamState {
+              url,
+              healthy: AtomicBool::new(true),
+              connections: AtomicUsize::new(0),
+            })
+          })
+      })
+      .collect();
+    let pool = Arc::new(UpstreamPool {
+      upstreams,
+      next: AtomicUsize::new(0),
+      last_check: Mutex::new(None),
+    });
+    pools.insert(target_name.to_owned(), pool.clone());
+    pool
+  }
+
+  /// Picks the upstream for a request. Returns `None` if the target only has a single upstream.
+  pub fn select(
+    &self,
+    target_name: &str,
+    options: &TargetHTTPOptions,
+    session: &Session,
+  ) -> Option<(String, UpstreamConnection)> {
+    if options.upstreams.is_empty() {
+      return None;
+    }
+    let pool = self.pool_for(target_name, options);
+
+    // If everything is down, keep trying all of them rather than failing outright
+    let mut candidates: Vec<_> = pool.upstreams.iter().filter(|x| x.healthy.load(Ordering::Relaxed)).collect();
+    if candidates.is_empty() {
+      candidates = pool.upstreams.iter().collect();
+    }
+    let offset = pool.next.fetch_add(1, Ordering::Relaxed);
+    let round_robin = candidates[offset % candidates.len()];
+
+    let upstream = match options.load_balancing {
+      HttpLoadBalancing::RoundRobin => round_robin,
+      HttpLoadBalancing::LeastConnections => (0..candidates.len())
+        .map(|x| candidates[(offset + x) % candidates.len()])
+        .min_by_key(|x| x.connections.load(Ordering::Relaxed))
+        .unwrap_or(round_robin),
+      HttpLoadBalancing::Sticky => {
+        let key = format!("upstream:{target_name}");
+        let previous = session.get::<String>(&key);
+        match candidates.iter().find(|x| Some(&x.url) == previous.as_ref()) {
+          Some(upstream) => upstream,
+          None => {
+            session.set(&key, &round_robin.url);
+            round_robin
+          }
+        }
+      }
+    };
+
+    upstream.connections.fetch_add(1, Ordering::Relaxed);
+    Some((upstream.url.clone(), UpstreamConnection(upstream.clone())))
+  }
+
+  /// Runs the configured health checks forever
+  pub async fn run_health_checks(self, services: Services) {
+    let mut targets = vec![];
+    let mut last_refresh: Option<Instant> = None;
+    loop {
+      if last_refresh.map_or(true, |x| x.elapsed() >= TARGETS_REFRESH_INTERVAL) {
+        last_refresh = Some(Instant::now());
+        match health_checked_targets(&services).await {
+          Ok(x) => targets = x,
+          Err(error) => error!(?error, "Failed to load the targets to health check"),
+        }
+      }
+      self.start_due_health_checks(&targets);
+      tokio::time::sleep(HEALTH_CHECK_TICK).await;
+    }
+  }
+
+  fn start_due_health_checks(&self, targets: &[HealthCheckedTarget]) {
+    for target in targets {
+      let pool = self.pool_for(&target.name, &target.options);
+      {
+        #[allow(clippy::unwrap_used)]
+        let mut last_check = pool.last_check.lock().unwrap();
+        let interval = Duration::from_secs(target.options.health_check.interval);
+        if last_check.map_or(false, |x| x.elapsed() < interval) {
+          continue;
+        }
+        *last_check = Some(Instant::now());
+      }
+
+      for upstream in pool.upstreams.iter().cloned() {
+        let options = target.options.clone();
+        let path = target.path.clone();
+        let target_name = target.name.clone();
+        tokio::spawn(async move {
+          let result = check_upstream(&upstream.url, &path, &options).await;
+          let was_healthy = upstream.healthy.swap(result.is_ok(), Ordering::Relaxed);
+          match result {
+            Err(error) if was_healthy => {
+              warn!(target=%target_name, upstream=%upstream.url, %error, "Upstream is down")
+            }
+            Ok(()) if !was_healthy => info!(target=%target_name, upstream=%upstream.url, "Upstream is back up"),
+            _ => (),
+          }
+        });
+      }
+    }
+  }
+}
+
+async fn health_checked_targets(services: &Services) -> Result<Vec<HealthCheckedTarget>> {
+  let targets = services.config_provider.lock().await.list_targets().await?;
+  Ok(
+    targets
+      .into_iter()
+      .filter_map(|target| {
+        let TargetOptions::Http(options) = target.options else {
+          return None;
+        };
+        if options.upstreams.is_empty() {
+          return None;
+        }
+        Some(HealthCheckedTarget {
+          name: target.name,
+          path: options.health_check.path.clone()?,
+          options,
+        })
+      })
+      .collect(),
+  )
+}
+
+async fn check_upstream(url: &str, path: &str, options: &TargetHTTPOptions) -> Result<()> {
+  let mut url = url::Url::parse(url)?.join(path)?;
+  // Same as for proxied requests
+  let scheme = match options.tls.mode {
+    TlsMode::Disabled => Some("http"),
+    TlsMode::Preferred => None,
+    TlsMode::Required => Some("https"),
+  };
+  if let Some(scheme) = scheme {
+    url
+      .set_scheme(scheme)
+      .map_err(|()| anyhow::anyhow!("Cannot use {scheme} for {url}"))?;
+  }
+
+  let client = reqwest::Client::builder()
+    .redirect(reqwest::redirect::Policy::none())
+    .danger_accept_invalid_certs(!options.tls.verify)
+    .https_only(options.tls.mode == TlsMode::Required)
+    .timeout(Duration::from_secs(options.health_check.timeout))
+    .build()
+    .context("Could not build request")?;
+
+  let status = client.get(url).send().await?.status();
+  if !status.is_success() && !status.is_redirection() {
+    anyhow::bail!("health check returned {status}");
+  }
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn options(load_balancing: &str) -> TargetHTTPOptions {
+    serde_json::from_value(serde_json::json!({
+      "url": "http://a",
+      "upstreams": ["http://b", "http://c"],
+      "load_balancing": load_balancing,
+    }))
+    .unwrap()
+  }
+
+  fn set_healthy(upstreams: &Upstreams, options: &TargetHTTPOptions, url: &str, healthy: bool) {
+    let pool = upstreams.pool_for("web", options);
+    let upstream = pool.upstreams.iter().find(|x| x.url == url).unwrap();
+    upstream.healthy.store(healthy, Ordering::Relaxed);
+  }
+
+  fn select(upstreams: &Upstreams, options: &TargetHTTPOptions, session: &Session) -> (String, UpstreamConnection) {
+    upstreams.select("web", options, session).unwrap()
+  }
+
+  #[test]
+  fn test_select_single_upstream() {
+    let options = TargetHTTPOptions {
+      upstreams: vec![],
+      ..options("round-robin")
+    };
+    assert!(Upstreams::default().select("web", &options, &Session::default()).is_none());
+  }
+
+  #[test]
+  fn test_select_round_robin() {
+    let upstreams = Upstreams::default();
+    let options = options("round-robin");
+    let session = Session::default();
+    let next = || select(&upstreams, &options, &session).0;
+
+    assert_eq!(
+      [next(), next(), next(), next()],
+      ["http://a", "http://b", "http://c", "http://a"]
+    );
+
+    set_healthy(&upstreams, &options, "http://b", false);
+    assert!((0..4).all(|_| select(&upstreams, &options, &session).0 != "http://b"));
+
+    // With everything down, requests still go somewhere
+    set_healthy(&upstreams, &options, "http://a", false);
+    set_healthy(&upstreams, &options, "http://c", false);
+    assert!(upstreams.select("web", &options, &session).is_some());
+  }
+
+  #[test]
+  fn test_select_least_connections() {
+    let upstreams = Upstreams::default();
+    let options = options("least-connections");
+    let session = Session::default();
+
+    let a = select(&upstreams, &options, &session);
+    let b = select(&upstreams, &options, &session);
+    let c = select(&upstreams, &options, &session);
+    assert_eq!([&a.0, &b.0, &c.0], ["http://a", "http://b", "http://c"]);
+
+    // Picks whichever has a connection less, regardless of the rotation
+    drop(b);
+    let b = select(&upstreams, &options, &session);
+    assert_eq!(b.0, "http://b");
+    drop(c);
+    assert_eq!(select(&upstreams, &options, &session).0, "http://c");
+  }
+
+  #[test]
+  fn test_select_sticky() {
+    let upstreams = Upstreams::default();
+    let options = options("sticky");
+    let session = Session::default();
+    let other_session = Session::default();
+
+    assert_eq!(select(&upstreams, &options, &session).0, "http://a");
+    assert_eq!(select(&upstreams, &options, &other_session).0, "http://b");
+    assert_eq!(select(&upstreams, &options, &session).0, "http://a");
+    assert_eq!(select(&upstreams, &options, &other_session).0, "http://b");
+
+    // Moves to another upstream when its own goes down, and stays there
+    set_healthy(&upstreams, &options, "http://a", false);
+    let moved = select(&upstreams, &options, &session).0;
+    assert_ne!(moved, "http://a");
+    set_healthy(&upstreams, &options, "http://a", true);
+    assert_eq!(select(&upstreams, &options, &session).0, moved);
+  }
+}
@@ -1,16 +1,19 @@
 use std::sync::Arc;
 
-use bytes::BytesMut;
+use bytes::Bytes;
 use omnitron_gate_common::{configure_tls_connector, TargetMySqlOptions, TlsMode};
 use omnitron_gate_database_protocols::io::Decode;
 use omnitron_gate_database_protocols::mysql::protocol::auth::AuthPlugin;
-use omnitron_gate_database_protocols::mysql::protocol::connect::{Handshake, HandshakeResponse, SslRequest};
+use omnitron_gate_database_protocols::mysql::protocol::connect::{
+  AuthMoreData, AuthSwitchRequest, AuthSwitchResponse, Handshake, HandshakeResponse, SslRequest, CACHING_SHA2_FAST_AUTH_SUCCESS,
+  CACHING_SHA2_PERFORM_FULL_AUTH,
+};
 use omnitron_gate_database_protocols::mysql::protocol::response::ErrPacket;
 use omnitron_gate_database_protocols::mysql::protocol::Capabilities;
 use tokio::net::TcpStream;
 use tracing::*;
 
-use crate::common::compute_auth_challenge_response;
+use crate::common::{compute_auth_challenge_response, compute_caching_sha2_response, encrypt_password};
 use crate::error::MySqlError;
 use crate::stream::MySqlStream;
 
@@ -44,6 +47,10 @@ impl Default for ConnectionOptions {
         | Capabilities::INTERACTIVE
         | Capabilities::TRANSACTIONS
         | Capabilities::DEPRECATE_EOF
+        | Capabilities::MULTI_STATEMENTS
+        | Capabilities::MULTI_RESULTS
+        | Capabilities::PS_MULTI_RESULTS
+        | Capabilities::LOCAL_FILES
         | Capabilities::SECURE_CONNECTION
         | Capabilities::SSL,
     }
@@ -90,56 +97,71 @@ impl MySqlClient {
       info!("Target connection upgraded to TLS");
     }
 
-    let mut response = HandshakeResponse {
-      auth_plugin: None,
-      auth_response: None,
+    let credentials = Credentials {
+      password: target.password.as_deref().unwrap_or(""),
+      is_tls: stream.is_tls(),
+      server_public_key: target.server_public_key.as_deref(),
+    };
+    let mut plugin = handshake.auth_plugin.unwrap_or(AuthPlugin::MySqlNativePassword);
+    let mut nonce = Bytes::from(
+      [
+        &handshake.auth_plugin_data.first_ref()[..],
+        &handshake.auth_plugin_data.last_ref()[..],
+      ]
+      .concat(),
+    );
+
+    let response = HandshakeResponse {
+      auth_plugin: Some(plugin),
+      auth_response: Some(credentials.auth_response(plugin, &nonce)?.into()),
       collation: options.collation,
       database: options.database,
       max_packet_size: options.max_packet_size,
       username: target.username.clone(),
     };
-
-    if handshake.auth_plugin == Some(AuthPlugin::MySqlNativePassword) {
-      let scramble_bytes = [
-        &handshake.auth_plugin_data.first_ref()[..],
-        &handshake.auth_plugin_data.last_ref()[..],
-      ]
-      .concat();
-      match scramble_bytes.try_into() as Result<[u8; 20], Vec<u8>> {
-        Err(scramble_bytes) => {
-          warn!("Invalid scramble length ({})", scramble_bytes.len());
-        }
-        Ok(scramble) => {
-          response.auth_plugin = Some(AuthPlugin::MySqlNativePassword);
-          response.auth_response = Some(
-            BytesMut::from(
-              compute_auth_challenge_response(scramble, target.password.as_deref().unwrap_or(""))
-                .map_err(MySqlError::other)?
-                .as_bytes(),
-            )
-            .freeze(),
-          );
-          trace!(response=?response.auth_response, ?scramble, "auth");
-        }
-      }
-    }
+    trace!(?plugin, response=?response.auth_response, "auth");
 
     stream.push(&response, options.capabilities)?;
     stream.flush().await?;
 
-    let Some(response) = stream.recv().await? else {
-      return Err(MySqlError::Eof);
-    };
-    if response.first() == Some(&0) || response.first() == Some(&0xfe) {
-      debug!("Authorized");
-    } else if response.first() == Some(&0xff) {
-      let error = ErrPacket::decode_with(response, options.capabilities)?;
-      return Err(MySqlError::ProtocolError(format!("handshake failed: {error:?}")));
-    } else {
-      return Err(MySqlError::ProtocolError(format!(
-        "unknown response type {:?}",
-        response.first()
-      )));
+    loop {
+      let Some(response) = stream.recv().await? else {
+        return Err(MySqlError::Eof);
+      };
+      let reply = match response.first() {
+        Some(0x00) => {
+          debug!("Authorized");
+          break;
+        }
+        Some(0xff) => {
+          let error = ErrPacket::decode_with(response, options.capabilities)?;
+          return Err(MySqlError::ProtocolError(format!("handshake failed: {error:?}")));
+        }
+        Some(0xfe) => {
+          let request = AuthSwitchRequest::decode(response)?;
+          debug!(plugin=?request.plugin, "Target requested an auth plugin switch");
+          plugin = request.plugin;
+          nonce = request.data;
+          credentials.auth_response(plugin, &nonce)?
+        }
+        Some(0x01) => {
+          let AuthMoreData(data) = AuthMoreData::decode(response)?;
+          match (plugin, &data[..]) {
+            (AuthPlugin::CachingSha2Password, [CACHING_SHA2_FAST_AUTH_SUCCESS]) => continue,
+            (AuthPlugin::CachingSha2Password, [CACHING_SHA2_PERFORM_FULL_AUTH]) => credentials.full_auth(&nonce)?,
+            // Public keys are never requested, as one sent over plain TCP can't be trusted
+            (plugin, _) => return Err(MySqlError::ProtocolError(format!("unexpected auth data for {plugin:?}"))),
+          }
+        }
+        _ => {
+          return Err(MySqlError::ProtocolError(format!(
+            "unknown response type {:?}",
+            response.first()
+          )));
+        }
+      };
+      stream.push(&AuthSwitchResponse(reply), options.capabilities)?;
+      stream.flush().await?;
     }
 
     stream.reset_sequence_id();
@@ -150,3 +172,93 @@ impl MySqlClient {
     })
   }
 }
+
+struct Credentials<'a> {
+  password: &'a str,
+  is_tls: bool,
+  /// PEM key configured for the target, to send the password encrypted without TLS
+  server_public_key: Option<&'a str>,
+}
+
+impl Credentials<'_> {
+  /// The first response of an auth plugin to the server's nonce
+  fn auth_response(&self, plugin: AuthPlugin, nonce: &[u8]) -> Result<Vec<u8>, MySqlError> {
+    let password = self.password;
+    Ok(match plugin {
+      AuthPlugin::MySqlNativePassword | AuthPlugin::CachingSha2Password if password.is_empty() => vec![],
+      AuthPlugin::MySqlNativePassword => {
+        let scramble: [u8; 20] = nonce
+          .get(..20)
+          .and_then(|x| x.try_into().ok())
+          .ok_or_else(|| MySqlError::ProtocolError(format!("invalid scramble length ({})", nonce.len())))?;
+        compute_auth_challenge_response(scramble, password)
+          .map_err(MySqlError::other)?
+          .as_bytes()
+          .to_vec()
+      }
+      AuthPlugin::CachingSha2Password => compute_caching_sha2_response(nonce, password).to_vec(),
+      AuthPlugin::Sha256Password => self.full_auth(nonce)?,
+      AuthPlugin::MySqlClearPassword if self.is_tls => cleartext_password(password),
+      AuthPlugin::MySqlClearPassword => {
+        return Err(MySqlError::ProtocolError(
+          "target requested a cleartext password over an unencrypted connection".into(),
+        ))
+      }
+    })
+  }
+
+  /// The password itself, for the full authentication of caching_sha2_password and sha256_password
+  fn full_auth(&self, nonce: &[u8]) -> Result<Vec<u8>, MySqlError> {
+    if self.is_tls {
+      return Ok(cleartext_password(self.password));
+    }
+    let Some(public_key) = self.server_public_key else {
+      return Err(MySqlError::ServerPublicKeyRequired);
+    };
+    encrypt_password(nonce, self.password, public_key.as_bytes()).map_err(MySqlError::other)
+  }
+}
+
+fn cleartext_password(password: &str) -> Vec<u8> {
+  let mut data = password.as_bytes().to_vec();
+  data.push(0);
+  data
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const NONCE: &[u8] = b"0123456789abcdefghij";
+
+  fn credentials(is_tls: bool, server_public_key: Option<&str>) -> Credentials<'_> {
+    Credentials {
+      password: "secret",
+      is_tls,
+      server_public_key,
+    }
+  }
+
+  #[test]
+  fn test_full_auth() {
+    assert_eq!(credentials(true, None).full_auth(NONCE).unwrap(), b"secret\0");
+    assert!(matches!(
+      credentials(false, None).full_auth(NONCE),
+      Err(MySqlError::ServerPublicKeyRequired)
+    ));
+    assert!(matches!(
+      credentials(false, None).auth_response(AuthPlugin::Sha256Password, NONCE),
+      Err(MySqlError::ServerPublicKeyRequired)
+    ));
+    assert!(credentials(false, None)
+      .auth_response(AuthPlugin::MySqlClearPassword, NONCE)
+      .is_err());
+    // The scramble doesn't reveal the password, so it's sent without TLS
+    assert_eq!(
+      credentials(false, None)
+        .auth_response(AuthPlugin::CachingSha2Password, NONCE)
+        .unwrap(),
+      compute_caching_sha2_response(NONCE, "secret")
+    );
+  }
+}
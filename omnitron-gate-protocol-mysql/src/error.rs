@@ -15,6 +15,8 @@ pub enum MySqlError {
   TlsNotSupported,
   #[error("client doesn't support TLS")]
   TlsNotSupportedByClient,
+  #[error("target needs the password without TLS; enable TLS or set the target's server_public_key")]
+  ServerPublicKeyRequired,
   #[error("TLS setup failed: {0}")]
   TlsSetup(#[from] RustlsSetupError),
   #[error("TLS stream error: {0}")]
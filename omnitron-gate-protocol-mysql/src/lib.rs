@@ -2,6 +2,7 @@
 mod client;
 mod common;
 mod error;
+mod masking;
 mod session;
 mod session_handle;
 mod stream;
@@ -0,0 +1,219 @@
+use bytes::Bytes;
+use omnitron_gate_common::MaskMethod;
+use omnitron_gate_core::{ColumnMask, DataMasker, ResultColumn};
+use omnitron_gate_database_protocols::io::{Decode, Encode};
+use omnitron_gate_database_protocols::mysql::protocol::statement::{BinaryRow, BinaryValue};
+use omnitron_gate_database_protocols::mysql::protocol::text::{ColumnDefinition, TextRow};
+use omnitron_gate_database_protocols::mysql::protocol::Capabilities;
+use tracing::*;
+
+use crate::error::MySqlError;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum RowFormat {
+  /// COM_QUERY results
+  Text,
+  /// Prepared statement results
+  Binary,
+}
+
+/// Columns of a result set as sent by the target, and the masks that apply to them
+#[derive(Debug, Clone, Default)]
+pub(crate) struct ResultColumns {
+  definitions: Vec<ColumnDefinition>,
+  masks: Vec<Option<ColumnMask>>,
+}
+
+impl ResultColumns {
+  pub fn clear(&mut self) {
+    self.definitions.clear();
+    self.masks.clear();
+  }
+
+  /// Records a column definition and returns the packet to send to the client in its place
+  pub fn add(&mut self, masker: &DataMasker, packet: Bytes, capabilities: Capabilities) -> Result<Bytes, MySqlError> {
+    if masker.is_empty() {
+      return Ok(packet);
+    }
+
+    let mut definition = ColumnDefinition::decode_with(packet.clone(), capabilities)?;
+    let schema = non_empty(definition.schema()?);
+    let mask = masker.mask_for(&[
+      ResultColumn {
+        schema,
+        table: non_empty(definition.table()?),
+        name: definition.name()?,
+      },
+      ResultColumn {
+        schema,
+        table: non_empty(definition.table_alias()?),
+        name: definition.alias()?,
+      },
+    ]);
+    self.definitions.push(definition.clone());
+
+    let Some(mask) = mask else {
+      self.masks.push(None);
+      return Ok(packet);
+    };
+    info!(column=%definition.alias()?, rule=%mask.rule, method=?mask.method, "Masking result column");
+
+    let packet = match mask.method {
+      // NULL fits any column type
+      MaskMethod::Null => packet,
+      MaskMethod::Hash | MaskMethod::Partial => {
+        definition.set_text_type();
+        let mut buf = Vec::with_capacity(packet.len());
+        definition.encode_with(&mut buf, capabilities);
+        buf.into()
+      }
+    };
+    self.masks.push(Some(mask));
+    Ok(packet)
+  }
+
+  /// Returns the row packet to send to the client in place of the target's
+  pub fn mask_row(&self, packet: Bytes, format: RowFormat) -> Result<Bytes, MySqlError> {
+    if self.masks.iter().all(Option::is_none) {
+      return Ok(packet);
+    }
+
+    let mut buf = Vec::with_capacity(packet.len());
+    match format {
+      RowFormat::Text => {
+        let mut row = TextRow::decode_with(packet, self.definitions.len())?;
+        for (index, mask) in self.masked() {
+          if let Some(value) = row.0.get(index) {
+            let masked = mask.apply(value);
+            row.0.set(index, masked.as_deref());
+          }
+        }
+        row.encode(&mut buf);
+      }
+      RowFormat::Binary => {
+        let mut row = BinaryRow::decode_with(packet, &self.definitions)?;
+        for (index, mask) in self.masked() {
+          let text = match row.value(index, &self.definitions[index])? {
+            BinaryValue::Null => continue,
+            BinaryValue::Bytes(bytes) => bytes.to_vec(),
+            // Numbers and dates are masked in their text form
+            value => value.to_string().trim_matches('\'').as_bytes().to_vec(),
+          };
+          row.set_bytes(index, mask.apply(&text).as_deref());
+        }
+        row.encode(&mut buf);
+      }
+    }
+    Ok(buf.into())
+  }
+
+  fn masked(&self) -> impl Iterator<Item = (usize, &ColumnMask)> {
+    self
+      .masks
+      .iter()
+      .enumerate()
+      .filter_map(|(index, mask)| mask.as_ref().map(|x| (index, x)))
+  }
+}
+
+/// Column definitions have empty names where there is no table or schema
+fn non_empty(value: &str) -> Option<&str> {
+  Some(value).filter(|x| !x.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+  use omnitron_gate_common::{MaskingRule, Target, TargetOptions, TargetWebAdminOptions};
+  use omnitron_gate_core::MaskingKey;
+  use uuid::Uuid;
+
+  use super::*;
+
+  // BIGINT id, VARCHAR email returned as "contact"
+  const COLUMNS: &[&[u8]] = &[
+    b"\x03def\x04shop\x05users\x05users\x02id\x02id\x0c\x3f\x00\x14\x00\x00\x00\x08\x03\x40\x00\x00\x00",
+    b"\x03def\x04shop\x01u\x05users\x07contact\x05email\x0c\x2d\x00\xfc\x03\x00\x00\xfd\x00\x00\x00\x00\x00",
+  ];
+
+  fn masker(masking: Vec<MaskingRule>) -> DataMasker {
+    DataMasker::new(
+      &Target {
+        id: Uuid::new_v4(),
+        name: "db".into(),
+        allow_roles: vec![],
+        limits: Default::default(),
+        ip_policy: Default::default(),
+        sql_policy: Default::default(),
+        masking,
+        options: TargetOptions::WebAdmin(TargetWebAdminOptions {}),
+      },
+      &MaskingKey::new(b"key".to_vec()),
+    )
+  }
+
+  fn columns(masking: Vec<MaskingRule>) -> ResultColumns {
+    let masker = masker(masking);
+    let mut columns = ResultColumns::default();
+    for packet in COLUMNS {
+      columns
+        .add(&masker, Bytes::from_static(packet), Capabilities::PROTOCOL_41)
+        .unwrap();
+    }
+    columns
+  }
+
+  fn rule(column: &str, method: MaskMethod) -> MaskingRule {
+    MaskingRule {
+      column: Some(column.into()),
+      column_pattern: None,
+      method,
+      visible_chars: None,
+    }
+  }
+
+  #[test]
+  fn test_aliased_column() {
+    let masker = masker(vec![rule("shop.users.email", MaskMethod::Hash)]);
+    let mut columns = ResultColumns::default();
+    let packet = columns
+      .add(&masker, Bytes::from_static(COLUMNS[1]), Capabilities::PROTOCOL_41)
+      .unwrap();
+    let definition = ColumnDefinition::decode_with(packet, Capabilities::PROTOCOL_41).unwrap();
+    // Matched by the physical column rather than the name it's returned as
+    assert_eq!(definition.alias().unwrap(), "contact");
+    assert_eq!(columns.masks[0].as_ref().map(|x| x.method), Some(MaskMethod::Hash));
+  }
+
+  #[test]
+  fn test_mask_text_row() {
+    let columns = columns(vec![rule("users.email", MaskMethod::Partial)]);
+    let row = columns
+      .mask_row(Bytes::from_static(b"\x017\x0da@example.com"), RowFormat::Text)
+      .unwrap();
+    assert_eq!(&row[..], b"\x017\x0d*********.com");
+
+    let row = columns.mask_row(Bytes::from_static(b"\x017\xfb"), RowFormat::Text).unwrap();
+    assert_eq!(&row[..], b"\x017\xfb");
+    assert!(columns.mask_row(Bytes::from_static(b"\x017"), RowFormat::Text).is_err());
+  }
+
+  #[test]
+  fn test_mask_binary_row() {
+    let columns = columns(vec![rule("id", MaskMethod::Partial), rule("email", MaskMethod::Null)]);
+    let row = columns
+      .mask_row(
+        Bytes::from_static(b"\x00\x00\x39\x30\x00\x00\x00\x00\x00\x00\x07a@b.com"),
+        RowFormat::Binary,
+      )
+      .unwrap();
+    // The number is masked in its text form, 12345, and sent as a string
+    assert_eq!(&row[..], b"\x00\x08\x05*2345");
+  }
+
+  #[test]
+  fn test_no_masks() {
+    let columns = columns(vec![]);
+    let packet = Bytes::from_static(b"\x017\x07a@b.com");
+    assert_eq!(columns.mask_row(packet.clone(), RowFormat::Text).unwrap(), packet);
+  }
+}
@@ -1,3 +1,4 @@
+use std::collections::{HashMap, HashSet};
 use std::net::SocketAddr;
 use std::ops::Deref;
 use std::sync::Arc;
@@ -5,14 +6,18 @@ use std::sync::Arc;
 use bytes::{Buf, Bytes, BytesMut};
 use omnitron_gate_common::auth::{AuthCredential, AuthResult, AuthSelector, CredentialKind};
 use omnitron_gate_common::helpers::rng::get_crypto_rng;
-use omnitron_gate_common::{Secret, TargetMySqlOptions, TargetOptions};
-use omnitron_gate_core::{authorize_ticket, consume_ticket, OmnitronServerHandle, Services};
+use omnitron_gate_common::{OmnitronError, Secret, TargetMySqlOptions, TargetOptions};
+use omnitron_gate_core::{authorize_ticket, consume_ticket, DataMasker, OmnitronServerHandle, Services, SqlDialect, SqlGuard};
 use omnitron_gate_database_protocols::io::{BufExt, Decode};
 use omnitron_gate_database_protocols::mysql::protocol::auth::AuthPlugin;
 use omnitron_gate_database_protocols::mysql::protocol::connect::{AuthSwitchRequest, Handshake, HandshakeResponse};
 use omnitron_gate_database_protocols::mysql::protocol::response::{ErrPacket, OkPacket, Status};
+use omnitron_gate_database_protocols::mysql::protocol::statement::{
+  ParamType, StmtClose, StmtExecute, StmtExecuteContext, StmtPrepare, StmtPrepareOk, StmtReset, StmtSendLongData, COM_STMT_CLOSE,
+  COM_STMT_EXECUTE, COM_STMT_FETCH, COM_STMT_PREPARE, COM_STMT_RESET, COM_STMT_SEND_LONG_DATA,
+};
 use omnitron_gate_database_protocols::mysql::protocol::text::Query;
-use omnitron_gate_database_protocols::mysql::protocol::Capabilities;
+use omnitron_gate_database_protocols::mysql::protocol::{Capabilities, ResponsePacket, ResponseProgress, ResultSetTracker};
 use rand::Rng;
 use rustls::ServerConfig;
 use tokio::net::TcpStream;
@@ -22,8 +27,19 @@ use uuid::Uuid;
 
 use crate::client::{ConnectionOptions, MySqlClient};
 use crate::error::MySqlError;
+use crate::masking::{ResultColumns, RowFormat};
 use crate::stream::MySqlStream;
 
+/// A statement prepared on the target, tracked to decode the parameters of its executions
+struct PreparedStatement {
+  query: String,
+  params: usize,
+  bound_types: Vec<ParamType>,
+  long_data: HashSet<u16>,
+  /// Of the last result set, for rows fetched from a cursor
+  columns: ResultColumns,
+}
+
 pub struct MySqlSession {
   stream: MySqlStream<tokio_rustls::server::TlsStream<TcpStream>>,
   capabilities: Capabilities,
@@ -35,6 +51,9 @@ pub struct MySqlSession {
   id: Uuid,
   services: Services,
   remote_address: SocketAddr,
+  statements: HashMap<u32, PreparedStatement>,
+  sql_guard: SqlGuard,
+  masker: DataMasker,
 }
 
 impl MySqlSession {
@@ -61,6 +80,10 @@ impl MySqlSession {
         | Capabilities::INTERACTIVE
         | Capabilities::TRANSACTIONS
         | Capabilities::DEPRECATE_EOF
+        | Capabilities::MULTI_STATEMENTS
+        | Capabilities::MULTI_RESULTS
+        | Capabilities::PS_MULTI_RESULTS
+        | Capabilities::LOCAL_FILES
         | Capabilities::SECURE_CONNECTION
         | Capabilities::SSL,
       challenge: get_crypto_rng().gen(),
@@ -70,6 +93,9 @@ impl MySqlSession {
       server_handle,
       id,
       remote_address,
+      statements: HashMap::new(),
+      sql_guard: SqlGuard::default(),
+      masker: DataMasker::default(),
     }
   }
 
@@ -176,6 +202,12 @@ impl MySqlSession {
 
     match selector {
       AuthSelector::User { username, target_name } => {
+        let address = Some(self.remote_address.ip());
+        if let Some(until) = self.services.login_protection.locked_until(&username, address).await? {
+          warn!(%until, "Login attempt while locked out");
+          return fail(&mut self).await;
+        }
+
         let state_arc = self
           .services
           .auth_state_store
@@ -197,6 +229,8 @@ impl MySqlSession {
           let mut cp = self.services.config_provider.lock().await;
           if cp.validate_credential(&username, &credential).await? {
             state.add_valid_credential(credential);
+          } else {
+            self.services.login_protection.record_failure(&username, address).await?;
           }
 
           state.verify()
@@ -205,6 +239,7 @@ impl MySqlSession {
         match user_auth_result {
           AuthResult::Accepted { username } => {
             self.services.auth_state_store.lock().await.complete(state.id()).await;
+            self.services.login_protection.record_success(&username).await?;
             let target_auth_result = {
               self
                 .services
@@ -249,17 +284,6 @@ impl MySqlSession {
     username: String,
     target_name: String,
   ) -> Result<(), MySqlError> {
-    self.stream.push(
-      &OkPacket {
-        affected_rows: 0,
-        last_insert_id: 0,
-        status: Status::empty(),
-        warnings: 0,
-      },
-      (),
-    )?;
-    self.stream.flush().await?;
-
     let target = {
       self
         .services
@@ -291,12 +315,65 @@ impl MySqlSession {
       return Ok(());
     };
 
+    let denial = self
+      .services
+      .config_provider
+      .lock()
+      .await
+      .check_address(&username, Some(&target_name), self.remote_address.ip())
+      .await?;
+    if let Some(denial) = denial {
+      warn!(%denial, "Connection refused by IP policy");
+      // ER_HOST_NOT_PRIVILEGED
+      self
+        .send_error(1130, "Omnitron: connections from your address are not allowed")
+        .await?;
+      return Ok(());
+    }
+
+    let limits = self
+      .services
+      .config_provider
+      .lock()
+      .await
+      .get_session_limits(&username, &target_name)
+      .await?;
+
+    self.sql_guard = self
+      .services
+      .config_provider
+      .lock()
+      .await
+      .get_sql_guard(&username, &target_name)
+      .await?;
+    self.masker = DataMasker::new(&target, &self.services.masking_key);
+
     {
       let handle = self.server_handle.lock().await;
-      handle.set_username(username).await?;
-      handle.set_target(&target).await?;
+      handle.set_username(username.clone()).await?;
+      match handle.set_target_within_limits(&username, &target, &limits).await {
+        Err(error @ OmnitronError::SessionLimitReached { .. }) => {
+          drop(handle);
+          warn!(%error, "Session limit reached");
+          // ER_TOO_MANY_USER_CONNECTIONS
+          self.send_error(1203, &format!("Omnitron: {error}")).await?;
+          return Ok(());
+        }
+        x => x?,
+      }
     }
 
+    self.stream.push(
+      &OkPacket {
+        affected_rows: 0,
+        last_insert_id: 0,
+        status: Status::empty(),
+        warnings: 0,
+      },
+      (),
+    )?;
+    self.stream.flush().await?;
+
     self.run_authorized_inner(handshake, mysql_options).await
   }
 
@@ -339,35 +416,22 @@ impl MySqlSession {
       // COM_QUERY
       if com == Some(&0x03) {
         let query = Query::decode(payload)?;
+        if !self.check_sql_policy(&query.0).await? {
+          continue;
+        }
         info!(query=%query.0, "SQL");
 
         client.stream.push(&query, ())?;
         client.stream.flush().await?;
 
-        let mut eof_ctr = 0;
-        loop {
-          let Some(response) = client.stream.recv().await? else {
-            return Err(MySqlError::Eof);
-          };
-          trace!(?response, "client got packet");
-          self.stream.push(&&response[..], ())?;
-          self.stream.flush().await?;
-          if let Some(com) = response.first() {
-            if com == &0xfe {
-              if self.capabilities.contains(Capabilities::DEPRECATE_EOF) {
-                break;
-              }
-              eof_ctr += 1;
-              if eof_ctr == 2 {
-                // todo check multiple results
-                break;
-              }
-            }
-            if com == &0 || com == &0xff {
-              break;
-            }
-          }
-        }
+        self
+          .passthrough_response(
+            &mut client,
+            ResultSetTracker::new(self.capabilities),
+            RowFormat::Text,
+            ResultColumns::default(),
+          )
+          .await?;
       // COM_QUIT
       } else if com == Some(&0x01) {
         break;
@@ -383,6 +447,71 @@ impl MySqlSession {
         self.passthrough_until_result(&mut client).await?;
       // COM_FIELD_LIST, COM_PING, COM_RESET_CONNECTION
       } else if com == Some(&0x04) || com == Some(&0x0e) || com == Some(&0x1f) {
+        if com == Some(&0x1f) {
+          // Resetting the connection deallocates all prepared statements
+          self.statements.clear();
+        }
+        client.stream.push(&&payload[..], ())?;
+        client.stream.flush().await?;
+        self.passthrough_until_result(&mut client).await?;
+      } else if com == Some(&COM_STMT_PREPARE) {
+        let prepare = StmtPrepare::decode(payload.clone())?;
+        if !self.check_sql_policy(&prepare.0).await? {
+          continue;
+        }
+        info!(query=%prepare.0, "Prepared SQL");
+        client.stream.push(&&payload[..], ())?;
+        client.stream.flush().await?;
+        self.passthrough_prepare_response(&mut client, prepare.0).await?;
+      } else if com == Some(&COM_STMT_EXECUTE) {
+        self.log_execute(&payload);
+        client.stream.push(&&payload[..], ())?;
+        client.stream.flush().await?;
+        let columns = self
+          .passthrough_response(
+            &mut client,
+            ResultSetTracker::new(self.capabilities),
+            RowFormat::Binary,
+            ResultColumns::default(),
+          )
+          .await?;
+        if let Some(statement) = statement_id(&payload).and_then(|x| self.statements.get_mut(&x)) {
+          statement.columns = columns;
+        }
+      } else if com == Some(&COM_STMT_FETCH) {
+        let columns = statement_id(&payload)
+          .and_then(|x| self.statements.get(&x))
+          .map(|x| x.columns.clone())
+          .unwrap_or_default();
+        client.stream.push(&&payload[..], ())?;
+        client.stream.flush().await?;
+        self
+          .passthrough_response(
+            &mut client,
+            ResultSetTracker::rows(self.capabilities),
+            RowFormat::Binary,
+            columns,
+          )
+          .await?;
+      } else if com == Some(&COM_STMT_SEND_LONG_DATA) {
+        let long_data = StmtSendLongData::decode(payload.clone())?;
+        if let Some(statement) = self.statements.get_mut(&long_data.statement_id) {
+          statement.long_data.insert(long_data.param);
+        }
+        // No response
+        client.stream.push(&&payload[..], ())?;
+        client.stream.flush().await?;
+      } else if com == Some(&COM_STMT_CLOSE) {
+        let close = StmtClose::decode(payload.clone())?;
+        self.statements.remove(&close.statement_id);
+        // No response
+        client.stream.push(&&payload[..], ())?;
+        client.stream.flush().await?;
+      } else if com == Some(&COM_STMT_RESET) {
+        let reset = StmtReset::decode(payload.clone())?;
+        if let Some(statement) = self.statements.get_mut(&reset.statement_id) {
+          statement.long_data.clear();
+        }
         client.stream.push(&&payload[..], ())?;
         client.stream.flush().await?;
         self.passthrough_until_result(&mut client).await?;
@@ -397,6 +526,154 @@ impl MySqlSession {
     Ok(())
   }
 
+  /// Rejects the query with an error packet if the SQL policies don't allow it. Returns whether it may be forwarded.
+  async fn check_sql_policy(&mut self, query: &str) -> Result<bool, MySqlError> {
+    match self.sql_guard.check(SqlDialect::MySql, query) {
+      Ok(()) => Ok(true),
+      Err(denial) => {
+        warn!(%query, %denial, "SQL denied");
+        // ER_SPECIFIC_ACCESS_DENIED_ERROR
+        self.send_error(1227, &format!("Omnitron: {denial}")).await?;
+        Ok(false)
+      }
+    }
+  }
+
+  /// Logs the statement and its parameter values, and remembers the bound types for later executions
+  fn log_execute(&mut self, payload: &Bytes) {
+    let Some(statement_id) = statement_id(payload) else {
+      return;
+    };
+    let Some(statement) = self.statements.get_mut(&statement_id) else {
+      warn!(%statement_id, "Executing unknown prepared statement");
+      return;
+    };
+
+    match StmtExecute::decode_with(
+      payload.clone(),
+      StmtExecuteContext {
+        params: statement.params,
+        bound_types: &statement.bound_types,
+        long_data: &statement.long_data,
+      },
+    ) {
+      Ok(execute) => {
+        let params = execute.params.iter().map(ToString::to_string).collect::<Vec<_>>().join(", ");
+        info!(query=%statement.query, %params, "Executed prepared SQL");
+        if execute.new_params_bound {
+          statement.bound_types = execute.param_types;
+        }
+      }
+      Err(error) => {
+        warn!(query=%statement.query, %error, "Could not decode prepared statement parameters");
+      }
+    }
+    // Long data is consumed by the execution
+    statement.long_data.clear();
+  }
+
+  async fn relay_packet(&mut self, client: &mut MySqlClient) -> Result<Bytes, MySqlError> {
+    let Some(response) = client.stream.recv().await? else {
+      return Err(MySqlError::Eof);
+    };
+    trace!(?response, "client got packet");
+    self.stream.push(&&response[..], ())?;
+    self.stream.flush().await?;
+    Ok(response)
+  }
+
+  /// Relays COM_STMT_PREPARE_OK and the parameter and column definitions following it, or an ERR packet
+  async fn passthrough_prepare_response(&mut self, client: &mut MySqlClient, query: String) -> Result<(), MySqlError> {
+    let response = self.relay_packet(client).await?;
+    if response.first() != Some(&0) {
+      return Ok(());
+    }
+
+    let prepare_ok = StmtPrepareOk::decode(response)?;
+    for _ in 0..prepare_ok.params {
+      self.relay_packet(client).await?;
+    }
+    if prepare_ok.params > 0 && !self.capabilities.contains(Capabilities::DEPRECATE_EOF) {
+      self.relay_packet(client).await?;
+    }
+
+    // Column definitions are masked the same way as those of the results
+    let mut columns = ResultColumns::default();
+    for _ in 0..prepare_ok.columns {
+      let Some(definition) = client.stream.recv().await? else {
+        return Err(MySqlError::Eof);
+      };
+      let definition = columns.add(&self.masker, definition, self.capabilities)?;
+      self.stream.push(&&definition[..], ())?;
+    }
+    self.stream.flush().await?;
+    if prepare_ok.columns > 0 && !self.capabilities.contains(Capabilities::DEPRECATE_EOF) {
+      self.relay_packet(client).await?;
+    }
+
+    self.statements.insert(
+      prepare_ok.statement_id,
+      PreparedStatement {
+        query,
+        params: prepare_ok.params.into(),
+        bound_types: vec![],
+        long_data: HashSet::new(),
+        columns,
+      },
+    );
+    Ok(())
+  }
+
+  /// Relays any number of result sets and OK or ERR packets until the server is done responding to a command,
+  /// masking the rows on the way. `columns` describe the rows if the response starts with them.
+  /// Returns the columns of the last result set.
+  async fn passthrough_response(
+    &mut self,
+    client: &mut MySqlClient,
+    mut tracker: ResultSetTracker,
+    format: RowFormat,
+    mut columns: ResultColumns,
+  ) -> Result<ResultColumns, MySqlError> {
+    loop {
+      let Some(response) = client.stream.recv().await? else {
+        return Err(MySqlError::Eof);
+      };
+      trace!(?response, "client got packet");
+      let kind = tracker.classify(&response);
+      let progress = tracker.feed(&response)?;
+
+      let response = match kind {
+        ResponsePacket::ColumnCount => {
+          columns.clear();
+          response
+        }
+        ResponsePacket::ColumnDefinition => columns.add(&self.masker, response, self.capabilities)?,
+        ResponsePacket::Row => columns.mask_row(response, format)?,
+        ResponsePacket::Other => response,
+      };
+      self.stream.push(&&response[..], ())?;
+      self.stream.flush().await?;
+
+      match progress {
+        ResponseProgress::More => (),
+        ResponseProgress::Done => return Ok(columns),
+        ResponseProgress::LocalInfile => {
+          // The client sends the file in packets and marks its end with an empty one
+          loop {
+            let Some(data) = self.stream.recv().await? else {
+              return Err(MySqlError::Eof);
+            };
+            client.stream.push(&&data[..], ())?;
+            client.stream.flush().await?;
+            if data.is_empty() {
+              break;
+            }
+          }
+        }
+      }
+    }
+  }
+
   async fn passthrough_until_result(&mut self, client: &mut MySqlClient) -> Result<(), MySqlError> {
     loop {
       let Some(response) = client.stream.recv().await? else {
@@ -414,3 +691,7 @@ impl MySqlSession {
     Ok(())
   }
 }
+
+fn statement_id(payload: &Bytes) -> Option<u32> {
+  payload.get(1..5).and_then(|x| x.try_into().ok()).map(u32::from_le_bytes)
+}
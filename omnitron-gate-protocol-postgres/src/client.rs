@@ -4,6 +4,8 @@ use std::io::Write;
 use std::sync::Arc;
 
 use omnitron_gate_common::{configure_tls_connector, TargetPostgresOptions, TlsMode};
+use pgwire::messages::data::DataRow;
+use pgwire::messages::simplequery::Query;
 use pgwire::messages::PgWireBackendMessage;
 use rsasl::config::SASLConfig;
 use rsasl::prelude::{Mechname, SASLClient};
@@ -220,6 +222,46 @@ impl PostgresClient {
     Ok(())
   }
 
+  /// Runs a simple query before any client messages are forwarded. Returns the
+  /// messages that the target sent while starting up, which the client is yet to
+  /// receive, and the rows of the query.
+  pub async fn query_before_startup(
+    &mut self,
+    query: &str,
+  ) -> Result<(Vec<PgWireGenericBackendMessage>, Vec<DataRow>), PostgresError> {
+    let mut startup = vec![];
+    loop {
+      let Some(msg) = self.recv().await? else {
+        return Err(PostgresError::Eof);
+      };
+      let ready = matches!(msg.0, PgWireBackendMessage::ReadyForQuery(_));
+      startup.push(msg);
+      if ready {
+        break;
+      }
+    }
+
+    self.send(Query::new(query.to_owned())).await?;
+    let mut rows = vec![];
+    let mut error = None;
+    loop {
+      let Some(msg) = self.recv().await? else {
+        return Err(PostgresError::Eof);
+      };
+      match msg.0 {
+        PgWireBackendMessage::DataRow(row) => rows.push(row),
+        PgWireBackendMessage::ErrorResponse(response) => error = Some(response),
+        PgWireBackendMessage::ReadyForQuery(_) => break,
+        _ => (),
+      }
+    }
+
+    match error {
+      Some(error) => Err(error.into()),
+      None => Ok((startup, rows)),
+    }
+  }
+
   pub async fn recv(&mut self) -> Result<Option<PgWireGenericBackendMessage>, PostgresError> {
     self.stream.recv::<PgWireGenericBackendMessage>().await.map_err(Into::into)
   }
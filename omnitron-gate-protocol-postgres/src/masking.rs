@@ -0,0 +1,474 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use omnitron_gate_common::MaskMethod;
+use omnitron_gate_core::{ColumnMask, DataMasker, ResultColumn};
+use pgwire::messages::data::{DataRow, RowDescription};
+use pgwire::messages::extendedquery::{TARGET_TYPE_BYTE_PORTAL, TARGET_TYPE_BYTE_STATEMENT};
+use pgwire::messages::{PgWireBackendMessage, PgWireFrontendMessage};
+use tracing::*;
+
+/// OID of the `text` type, which masked values are sent as
+const TEXT_OID: u32 = 25;
+const FORMAT_BINARY: i16 = 1;
+
+/// A client message that the target is yet to respond to
+#[derive(Debug, PartialEq, Eq)]
+enum Pending {
+  Query,
+  Parse,
+  Bind,
+  DescribeStatement(String),
+  DescribePortal,
+  Execute(String),
+  Close,
+  Sync,
+}
+
+struct Portal {
+  statement: String,
+  result_formats: Vec<i16>,
+}
+
+/// Masks the rows that the target sends. Follows the extended query protocol to
+/// know which statement's columns a row belongs to, as rows of prepared statements
+/// can come long after the statement was described.
+///
+/// Row descriptions only carry the name a column is returned as, so result columns
+/// are traced back to their table column through the catalog, which is read once
+/// when the session starts. Values computed from a column aren't masked.
+#[derive(Default)]
+pub(crate) struct ResultMasking {
+  masker: DataMasker,
+  /// Masks of table columns by table OID and column number
+  columns: HashMap<(i32, i16), ColumnMask>,
+  /// Tables that existed when the catalog was read
+  tables: HashSet<i32>,
+  /// Masks and formats of the last row description
+  masks: Vec<Option<ColumnMask>>,
+  formats: Vec<i16>,
+  /// Column masks of described prepared statements
+  statements: HashMap<String, Vec<Option<ColumnMask>>>,
+  portals: HashMap<String, Portal>,
+  pending: VecDeque<Pending>,
+}
+
+impl ResultMasking {
+  pub fn new(masker: DataMasker) -> Self {
+    Self {
+      masker,
+      ..Default::default()
+    }
+  }
+
+  /// Whether [Self::CATALOG_QUERY] has to be run before the session starts
+  pub fn needs_catalog(&self) -> bool {
+    !self.masker.is_empty()
+  }
+
+  /// Lists table columns as (table OID, column number, schema, table, column)
+  pub const CATALOG_QUERY: &'static str = "SELECT c.oid::int4, a.attnum, n.nspname, c.relname, a.attname \
+    FROM pg_catalog.pg_attribute a \
+    JOIN pg_catalog.pg_class c ON c.oid = a.attrelid \
+    JOIN pg_catalog.pg_namespace n ON n.oid = c.relnamespace \
+    WHERE a.attnum > 0 AND NOT a.attisdropped";
+
+  /// Records a row of [Self::CATALOG_QUERY]
+  pub fn add_catalog_row(&mut self, row: &DataRow) -> Result<(), &'static str> {
+    let values = row_values(row)?;
+    let text = |index: usize| match values.get(index) {
+      Some(Some(value)) => std::str::from_utf8(value).map_err(|_| "invalid catalog row"),
+      _ => Err("incomplete catalog row"),
+    };
+    let table_id = text(0)?.parse().map_err(|_| "invalid table OID")?;
+    let column_id = text(1)?.parse().map_err(|_| "invalid column number")?;
+    self.tables.insert(table_id);
+    let mask = self.masker.mask_for(&[ResultColumn {
+      schema: Some(text(2)?),
+      table: Some(text(3)?),
+      name: text(4)?,
+    }]);
+    if let Some(mask) = mask {
+      self.columns.insert((table_id, column_id), mask);
+    }
+    Ok(())
+  }
+
+  /// Tracks a message forwarded to the target
+  pub fn client_message(&mut self, msg: &PgWireFrontendMessage) {
+    if self.masker.is_empty() {
+      return;
+    }
+    let name = |x: &Option<String>| x.clone().unwrap_or_default();
+    let pending = match msg {
+      PgWireFrontendMessage::Query(_) => {
+        // Simple queries replace the unnamed statement and portal
+        self.statements.remove("");
+        self.portals.remove("");
+        Pending::Query
+      }
+      PgWireFrontendMessage::Parse(parse) => {
+        self.statements.remove(&name(&parse.name));
+        Pending::Parse
+      }
+      PgWireFrontendMessage::Bind(bind) => {
+        self.portals.insert(
+          name(&bind.portal_name),
+          Portal {
+            statement: name(&bind.statement_name),
+            result_formats: bind.result_column_format_codes.clone(),
+          },
+        );
+        Pending::Bind
+      }
+      PgWireFrontendMessage::Describe(describe) if describe.target_type == TARGET_TYPE_BYTE_STATEMENT => {
+        Pending::DescribeStatement(name(&describe.name))
+      }
+      PgWireFrontendMessage::Describe(_) => Pending::DescribePortal,
+      PgWireFrontendMessage::Execute(execute) => Pending::Execute(name(&execute.name)),
+      PgWireFrontendMessage::Close(close) => {
+        match close.target_type {
+          TARGET_TYPE_BYTE_STATEMENT => self.statements.remove(&name(&close.name)),
+          TARGET_TYPE_BYTE_PORTAL => self.portals.remove(&name(&close.name)).map(|_| vec![]),
+          _ => None,
+        };
+        Pending::Close
+      }
+      PgWireFrontendMessage::Sync(_) => Pending::Sync,
+      _ => return,
+    };
+    self.pending.push_back(pending);
+  }
+
+  /// Masks a message from the target in place. Returns whether to forward it at all.
+  pub fn server_message(&mut self, msg: &mut PgWireBackendMessage) -> bool {
+    if self.masker.is_empty() {
+      return true;
+    }
+    match msg {
+      PgWireBackendMessage::ParseComplete(_)
+      | PgWireBackendMessage::BindComplete(_)
+      | PgWireBackendMessage::CloseComplete(_)
+      | PgWireBackendMessage::NoData(_)
+      | PgWireBackendMessage::ReadyForQuery(_) => {
+        self.pending.pop_front();
+      }
+      PgWireBackendMessage::CommandComplete(_)
+      | PgWireBackendMessage::EmptyQueryResponse(_)
+      | PgWireBackendMessage::PortalSuspended(_) => {
+        if let Some(Pending::Execute(_)) = self.pending.front() {
+          self.pending.pop_front();
+        }
+      }
+      PgWireBackendMessage::ErrorResponse(_) => {
+        // The target skips the rest of an extended query until Sync
+        while !matches!(self.pending.front(), None | Some(Pending::Sync | Pending::Query)) {
+          self.pending.pop_front();
+        }
+      }
+      PgWireBackendMessage::RowDescription(description) => {
+        self.masks = self.mask_description(description);
+        self.formats = description.fields.iter().map(|x| x.format_code).collect();
+        match self.pending.front() {
+          Some(Pending::DescribeStatement(name)) => {
+            self.statements.insert(name.clone(), self.masks.clone());
+            self.pending.pop_front();
+          }
+          Some(Pending::DescribePortal) => {
+            self.pending.pop_front();
+          }
+          _ => (),
+        }
+      }
+      PgWireBackendMessage::DataRow(row) => {
+        let portal = match self.pending.front() {
+          Some(Pending::Execute(portal)) => self.portals.get(portal),
+          _ => None,
+        };
+        let (masks, formats) = match portal {
+          Some(portal) => (
+            self.statements.get(&portal.statement).unwrap_or(&self.masks),
+            &portal.result_formats,
+          ),
+          None => (&self.masks, &self.formats),
+        };
+        if let Err(error) = mask_row(row, masks, formats) {
+          warn!(%error, "Withholding a row that could not be masked");
+          return false;
+        }
+      }
+      PgWireBackendMessage::CopyData(_) => {
+        warn!("Withholding COPY output because the target has masking rules");
+        return false;
+      }
+      _ => (),
+    }
+    true
+  }
+
+  fn mask_description(&self, description: &mut RowDescription) -> Vec<Option<ColumnMask>> {
+    description
+      .fields
+      .iter_mut()
+      .map(|field| {
+        let by_name = || {
+          self.masker.mask_for(&[ResultColumn {
+            schema: None,
+            table: None,
+            name: &field.name,
+          }])
+        };
+        let mask = match self.columns.get(&(field.table_id, field.column_id)) {
+          Some(mask) => Some(mask.clone()),
+          None if field.table_id == 0 || self.tables.contains(&field.table_id) => by_name(),
+          // The table was created during the session, so its columns can't be checked
+          None => Some(ColumnMask::null("table unknown to the masking rules".into())),
+        }?;
+        info!(column=%field.name, rule=%mask.rule, method=?mask.method, "Masking result column");
+        if mask.method != MaskMethod::Null {
+          field.type_id = TEXT_OID;
+          field.type_size = -1;
+          field.type_modifier = -1;
+        }
+        Some(mask)
+      })
+      .collect()
+  }
+}
+
+/// Result formats as given in Bind: none for all text, one for all columns, or one per column
+fn format_of(formats: &[i16], index: usize) -> i16 {
+  match formats {
+    [] => 0,
+    [format] => *format,
+    _ => formats.get(index).copied().unwrap_or_default(),
+  }
+}
+
+/// Splits a row into its values, `None` being NULL
+fn row_values(row: &DataRow) -> Result<Vec<Option<Bytes>>, &'static str> {
+  let mut data = row.data.clone().freeze();
+  let mut values = Vec::with_capacity(row.field_count.max(0) as usize);
+  for _ in 0..row.field_count.max(0) {
+    if data.remaining() < 4 {
+      return Err("truncated DataRow");
+    }
+    let len = data.get_i32();
+    values.push(match len {
+      -1 => None,
+      len if len >= 0 && data.remaining() >= len as usize => Some(data.split_to(len as usize)),
+      _ => return Err("truncated DataRow"),
+    });
+  }
+  Ok(values)
+}
+
+fn mask_row(row: &mut DataRow, masks: &[Option<ColumnMask>], formats: &[i16]) -> Result<(), &'static str> {
+  if masks.iter().all(Option::is_none) {
+    return Ok(());
+  }
+
+  let mut masked = BytesMut::with_capacity(row.data.len());
+  for (index, value) in row_values(row)?.into_iter().enumerate() {
+    let value = match (value, masks.get(index).and_then(Option::as_ref)) {
+      (Some(value), Some(mask)) => match format_of(formats, index) {
+        FORMAT_BINARY => mask.apply_binary(&value),
+        _ => mask.apply(&value),
+      }
+      .map(Into::into),
+      (value, _) => value,
+    };
+    match value {
+      Some(value) => {
+        masked.put_i32(value.len() as i32);
+        masked.put_slice(&value);
+      }
+      None => masked.put_i32(-1),
+    }
+  }
+  row.data = masked;
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use omnitron_gate_common::{MaskingRule, Target, TargetOptions, TargetWebAdminOptions};
+  use omnitron_gate_core::MaskingKey;
+  use pgwire::messages::data::FieldDescription;
+  use pgwire::messages::extendedquery::{Bind, BindComplete, Describe, Execute, Parse, ParseComplete, Sync};
+  use pgwire::messages::response::{CommandComplete, ReadyForQuery, TransactionStatus};
+  use pgwire::messages::simplequery::Query;
+  use uuid::Uuid;
+
+  use super::*;
+
+  const USERS_OID: i32 = 16384;
+
+  fn masking(masking: Vec<MaskingRule>) -> ResultMasking {
+    let target = Target {
+      id: Uuid::new_v4(),
+      name: "db".into(),
+      allow_roles: vec![],
+      limits: Default::default(),
+      ip_policy: Default::default(),
+      sql_policy: Default::default(),
+      masking,
+      options: TargetOptions::WebAdmin(TargetWebAdminOptions {}),
+    };
+    let mut masking = ResultMasking::new(DataMasker::new(&target, &MaskingKey::new(b"key".to_vec())));
+    for (table_id, column_id, schema, table, name) in [
+      (USERS_OID, 1, "public", "users", "id"),
+      (USERS_OID, 2, "public", "users", "email"),
+    ] {
+      masking
+        .add_catalog_row(&make_row(&[
+          Some(&table_id.to_string()),
+          Some(&column_id.to_string()),
+          Some(schema),
+          Some(table),
+          Some(name),
+        ]))
+        .unwrap();
+    }
+    masking
+  }
+
+  fn rule(column: &str, method: MaskMethod) -> MaskingRule {
+    MaskingRule {
+      column: Some(column.into()),
+      column_pattern: None,
+      method,
+      visible_chars: None,
+    }
+  }
+
+  fn field(name: &str, table_id: i32, column_id: i16) -> FieldDescription {
+    FieldDescription::new(name.into(), table_id, column_id, 23, 4, -1, 0)
+  }
+
+  fn make_row(values: &[Option<&str>]) -> DataRow {
+    let mut data = BytesMut::new();
+    for value in values {
+      match value {
+        Some(value) => {
+          data.put_i32(value.len() as i32);
+          data.put_slice(value.as_bytes());
+        }
+        None => data.put_i32(-1),
+      }
+    }
+    DataRow::new(data, values.len() as i16)
+  }
+
+  fn values(msg: &PgWireBackendMessage) -> Vec<Option<String>> {
+    let PgWireBackendMessage::DataRow(row) = msg else {
+      panic!("not a row: {msg:?}");
+    };
+    row_values(row)
+      .unwrap()
+      .into_iter()
+      .map(|x| x.map(|x| String::from_utf8_lossy(&x).into_owned()))
+      .collect()
+  }
+
+  #[test]
+  fn test_simple_query() {
+    let mut masking = masking(vec![rule("users.email", MaskMethod::Partial)]);
+    masking.client_message(&PgWireFrontendMessage::Query(Query::new(
+      "SELECT id, email AS contact, email || '' FROM users".into(),
+    )));
+
+    let mut description = PgWireBackendMessage::RowDescription(RowDescription::new(vec![
+      field("id", USERS_OID, 1),
+      field("contact", USERS_OID, 2),
+      field("?column?", 0, 0),
+    ]));
+    assert!(masking.server_message(&mut description));
+    let PgWireBackendMessage::RowDescription(ref description) = description else {
+      unreachable!()
+    };
+    // Masked columns are sent as text
+    assert_eq!(description.fields[0].type_id, 23);
+    assert_eq!(description.fields[1].type_id, TEXT_OID);
+
+    let mut row = PgWireBackendMessage::DataRow(make_row(&[Some("1"), Some("alice@example.com"), None]));
+    assert!(masking.server_message(&mut row));
+    assert_eq!(values(&row), [Some("1".into()), Some("*************.com".into()), None]);
+  }
+
+  #[test]
+  fn test_unknown_table() {
+    let mut masking = masking(vec![rule("users.email", MaskMethod::Partial)]);
+    masking.client_message(&PgWireFrontendMessage::Query(Query::new("SELECT * FROM copy".into())));
+
+    let mut description = PgWireBackendMessage::RowDescription(RowDescription::new(vec![
+      field("id", USERS_OID, 1),
+      field("mail", USERS_OID + 1, 1),
+    ]));
+    masking.server_message(&mut description);
+    let mut row = PgWireBackendMessage::DataRow(make_row(&[Some("1"), Some("alice@example.com")]));
+    masking.server_message(&mut row);
+    // Columns of tables created after the catalog was read are withheld
+    assert_eq!(values(&row), [Some("1".into()), None]);
+  }
+
+  #[test]
+  fn test_extended_query() {
+    let mut masking = masking(vec![rule("email", MaskMethod::Null)]);
+    for msg in [
+      PgWireFrontendMessage::Parse(Parse::new(Some("s1".into()), "SELECT id, email FROM users".into(), vec![])),
+      PgWireFrontendMessage::Describe(Describe::new(TARGET_TYPE_BYTE_STATEMENT, Some("s1".into()))),
+      PgWireFrontendMessage::Sync(Sync::new()),
+    ] {
+      masking.client_message(&msg);
+    }
+    for mut msg in [
+      PgWireBackendMessage::ParseComplete(ParseComplete::new()),
+      PgWireBackendMessage::RowDescription(RowDescription::new(vec![
+        field("id", USERS_OID, 1),
+        field("email", USERS_OID, 2),
+      ])),
+      PgWireBackendMessage::ReadyForQuery(ReadyForQuery::new(TransactionStatus::Idle)),
+    ] {
+      masking.server_message(&mut msg);
+    }
+
+    // An unrelated simple query replaces the last row description
+    masking.client_message(&PgWireFrontendMessage::Query(Query::new("SELECT 1".into())));
+    for mut msg in [
+      PgWireBackendMessage::RowDescription(RowDescription::new(vec![field("?column?", 0, 0)])),
+      PgWireBackendMessage::DataRow(make_row(&[Some("1")])),
+      PgWireBackendMessage::CommandComplete(CommandComplete::new("SELECT 1".into())),
+      PgWireBackendMessage::ReadyForQuery(ReadyForQuery::new(TransactionStatus::Idle)),
+    ] {
+      masking.server_message(&mut msg);
+    }
+
+    // Rows of the prepared statement still use its description
+    for msg in [
+      PgWireFrontendMessage::Bind(Bind::new(None, Some("s1".into()), vec![], vec![], vec![FORMAT_BINARY])),
+      PgWireFrontendMessage::Execute(Execute::new(None, 0)),
+      PgWireFrontendMessage::Sync(Sync::new()),
+    ] {
+      masking.client_message(&msg);
+    }
+    masking.server_message(&mut PgWireBackendMessage::BindComplete(BindComplete::new()));
+    let mut row = PgWireBackendMessage::DataRow(make_row(&[Some("\0\0\0\x01"), Some("alice@example.com")]));
+    assert!(masking.server_message(&mut row));
+    assert_eq!(values(&row), [Some("\0\0\0\x01".into()), None]);
+  }
+
+  #[test]
+  fn test_truncated_row() {
+    let mut masking = masking(vec![rule("email", MaskMethod::Null)]);
+    masking.client_message(&PgWireFrontendMessage::Query(Query::new("SELECT email FROM users".into())));
+    masking.server_message(&mut PgWireBackendMessage::RowDescription(RowDescription::new(vec![field(
+      "email", USERS_OID, 2,
+    )])));
+
+    let mut data = BytesMut::new();
+    data.put_i32(100);
+    data.put_slice(b"short");
+    assert!(!masking.server_message(&mut PgWireBackendMessage::DataRow(DataRow::new(data, 1))));
+  }
+}
@@ -2,9 +2,10 @@ use std::net::SocketAddr;
 use std::sync::Arc;
 
 use omnitron_gate_common::auth::{AuthCredential, AuthResult, AuthSelector, CredentialKind};
-use omnitron_gate_common::{Secret, TargetOptions, TargetPostgresOptions};
-use omnitron_gate_core::{authorize_ticket, consume_ticket, OmnitronServerHandle, Services};
+use omnitron_gate_common::{OmnitronError, Secret, SqlPolicyDenial, TargetOptions, TargetPostgresOptions};
+use omnitron_gate_core::{authorize_ticket, consume_ticket, DataMasker, OmnitronServerHandle, Services, SqlDialect, SqlGuard};
 use pgwire::error::ErrorInfo;
+use pgwire::messages::response::{ErrorResponse, ReadyForQuery, TransactionStatus};
 use pgwire::messages::{PgWireBackendMessage, PgWireFrontendMessage};
 use rustls::ServerConfig;
 use tokio::net::TcpStream;
@@ -15,6 +16,7 @@ use uuid::Uuid;
 
 use crate::client::{ConnectionOptions, PostgresClient};
 use crate::error::PostgresError;
+use crate::masking::ResultMasking;
 use crate::stream::{PgWireGenericFrontendMessage, PgWireStartupOrSslRequest, PostgresStream};
 
 pub struct PostgresSession {
@@ -26,6 +28,12 @@ pub struct PostgresSession {
   id: Uuid,
   services: Services,
   remote_address: SocketAddr,
+  sql_guard: SqlGuard,
+  /// As last reported by the target
+  transaction_status: TransactionStatus,
+  /// Set after refusing part of an extended query, until the client ends it with Sync
+  discard_until_sync: bool,
+  masking: ResultMasking,
 }
 
 impl PostgresSession {
@@ -47,6 +55,10 @@ impl PostgresSession {
       server_handle,
       id,
       remote_address,
+      sql_guard: SqlGuard::default(),
+      transaction_status: TransactionStatus::Idle,
+      discard_until_sync: false,
+      masking: ResultMasking::default(),
     }
   }
 
@@ -120,15 +132,19 @@ impl PostgresSession {
     async fn fail(this: &mut PostgresSession) -> Result<(), PostgresError> {
       let error_info = ErrorInfo::new("FATAL".to_owned(), "28P01".to_owned(), "Authentication failed".to_owned());
 
-      this
-        .stream
-        .push(pgwire::messages::response::ErrorResponse::from(error_info))?;
+      this.stream.push(ErrorResponse::from(error_info))?;
       this.stream.flush().await?;
       Ok(())
     }
 
     match selector {
       AuthSelector::User { username, target_name } => {
+        let address = Some(self.remote_address.ip());
+        if let Some(until) = self.services.login_protection.locked_until(&username, address).await? {
+          warn!(%until, "Login attempt while locked out");
+          return fail(&mut self).await;
+        }
+
         let state_arc = self
           .services
           .auth_state_store
@@ -150,6 +166,8 @@ impl PostgresSession {
           let mut cp = self.services.config_provider.lock().await;
           if cp.validate_credential(&username, &credential).await? {
             state.add_valid_credential(credential);
+          } else {
+            self.services.login_protection.record_failure(&username, address).await?;
           }
 
           state.verify()
@@ -158,6 +176,7 @@ impl PostgresSession {
         match user_auth_result {
           AuthResult::Accepted { username } => {
             self.services.auth_state_store.lock().await.complete(state.id()).await;
+            self.services.login_protection.record_success(&username).await?;
             let target_auth_result = {
               self
                 .services
@@ -202,9 +221,6 @@ impl PostgresSession {
     username: String,
     target_name: String,
   ) -> Result<(), PostgresError> {
-    self.stream.push(pgwire::messages::startup::Authentication::Ok)?;
-    self.stream.flush().await?;
-
     let target = {
       self
         .services
@@ -230,20 +246,66 @@ impl PostgresSession {
       return Ok(());
     };
 
+    let denial = self
+      .services
+      .config_provider
+      .lock()
+      .await
+      .check_address(&username, Some(&target_name), self.remote_address.ip())
+      .await?;
+    if let Some(denial) = denial {
+      warn!(%denial, "Connection refused by IP policy");
+      // invalid_authorization_specification
+      self
+        .send_error_response(
+          "28000".into(),
+          "Omnitron: connections from your address are not allowed".into(),
+        )
+        .await?;
+      return Ok(());
+    }
+
+    let limits = self
+      .services
+      .config_provider
+      .lock()
+      .await
+      .get_session_limits(&username, &target_name)
+      .await?;
+
+    self.sql_guard = self
+      .services
+      .config_provider
+      .lock()
+      .await
+      .get_sql_guard(&username, &target_name)
+      .await?;
+    self.masking = ResultMasking::new(DataMasker::new(&target, &self.services.masking_key));
+
     {
       let handle = self.server_handle.lock().await;
-      handle.set_username(username).await?;
-      handle.set_target(&target).await?;
+      handle.set_username(username.clone()).await?;
+      match handle.set_target_within_limits(&username, &target, &limits).await {
+        Err(error @ OmnitronError::SessionLimitReached { .. }) => {
+          drop(handle);
+          warn!(%error, "Session limit reached");
+          // too_many_connections
+          self.send_error_response("53300".into(), format!("Omnitron: {error}")).await?;
+          return Ok(());
+        }
+        x => x?,
+      }
     }
 
+    self.stream.push(pgwire::messages::startup::Authentication::Ok)?;
+    self.stream.flush().await?;
+
     self.run_authorized_inner(startup, postgres_options).await
   }
 
   async fn send_error_response(&mut self, code: String, message: String) -> Result<(), PostgresError> {
     let error_info = ErrorInfo::new("FATAL".to_owned(), code, message);
-    self
-      .stream
-      .push(pgwire::messages::response::ErrorResponse::from(error_info))?;
+    self.stream.push(ErrorResponse::from(error_info))?;
     self.stream.flush().await?;
     Ok(())
   }
@@ -272,13 +334,50 @@ impl PostgresSession {
       x => x,
     }?;
 
+    if self.masking.needs_catalog() {
+      let (startup, rows) = match client.query_before_startup(ResultMasking::CATALOG_QUERY).await {
+        Err(error) => {
+          self
+            .send_error_response("0W002".into(), "Omnitron could not look up the columns to mask".into())
+            .await?;
+          return Err(error);
+        }
+        Ok(x) => x,
+      };
+      for row in &rows {
+        self
+          .masking
+          .add_catalog_row(row)
+          .map_err(|error| PostgresError::ProtocolError(error.into()))?;
+      }
+      for msg in startup {
+        self.maybe_log_server_msg(&msg.0);
+        if let PgWireBackendMessage::ReadyForQuery(ref ready) = msg.0 {
+          self.transaction_status = ready.status;
+        }
+        self.stream.push(msg)?;
+      }
+      self.stream.flush().await?;
+    }
+
     loop {
       tokio::select! {
           c_to_s = self.stream.recv::<PgWireGenericFrontendMessage>() => {
               match c_to_s {
                   Ok(Some(msg)) => {
-                      self.maybe_log_client_msg(&msg.0);
-                      client.send(msg).await?;
+                      if self.discard_until_sync {
+                          if !matches!(msg.0, PgWireFrontendMessage::Sync(_)) {
+                              continue;
+                          }
+                          self.discard_until_sync = false;
+                      }
+                      match self.maybe_log_client_msg(&msg.0) {
+                          Ok(()) => {
+                              self.masking.client_message(&msg.0);
+                              client.send(msg).await?
+                          }
+                          Err(denial) => self.reject_client_msg(&msg.0, denial).await?,
+                      }
                   }
                   Ok(None) => {
                       break
@@ -291,8 +390,14 @@ impl PostgresSession {
           },
           s_to_c = client.recv() => {
               match s_to_c {
-                  Ok(Some(msg)) => {
+                  Ok(Some(mut msg)) => {
                       self.maybe_log_server_msg(&msg.0);
+                      if let PgWireBackendMessage::ReadyForQuery(ref ready) = msg.0 {
+                          self.transaction_status = ready.status;
+                      }
+                      if !self.masking.server_message(&mut msg.0) {
+                          continue;
+                      }
                       self.stream.push(msg)?;
                       self.stream.flush().await?;
                   }
@@ -311,20 +416,46 @@ impl PostgresSession {
     Ok(())
   }
 
-  fn maybe_log_client_msg(&self, msg: &PgWireFrontendMessage) {
+  /// Logs the message and checks the SQL in it against the SQL policies
+  fn maybe_log_client_msg(&self, msg: &PgWireFrontendMessage) -> Result<(), SqlPolicyDenial> {
     debug!(?msg, "C->S message");
     match msg {
       PgWireFrontendMessage::Parse(query) => {
+        self.check_sql_policy(&query.query)?;
         info!(query_name=?query.name, "Preparing query");
       }
       PgWireFrontendMessage::Execute(query) => {
         info!(query_name=?query.name, "Executing prepared query");
       }
       PgWireFrontendMessage::Query(query) => {
+        self.check_sql_policy(&query.query)?;
         info!(query=%query.query, "Query");
       }
       _ => (),
     }
+    Ok(())
+  }
+
+  fn check_sql_policy(&self, query: &str) -> Result<(), SqlPolicyDenial> {
+    self
+      .sql_guard
+      .check(SqlDialect::Postgres, query)
+      .inspect_err(|denial| warn!(%query, %denial, "SQL denied"))
+  }
+
+  /// Answers a message refused by the SQL policies in place of the target
+  async fn reject_client_msg(&mut self, msg: &PgWireFrontendMessage, denial: SqlPolicyDenial) -> Result<(), PostgresError> {
+    // insufficient_privilege
+    let error_info = ErrorInfo::new("ERROR".to_owned(), "42501".to_owned(), format!("Omnitron: {denial}"));
+    self.stream.push(ErrorResponse::from(error_info))?;
+    if let PgWireFrontendMessage::Query(_) = msg {
+      self.stream.push(ReadyForQuery::new(self.transaction_status))?;
+    } else {
+      // The rest of the extended query is skipped, and the target answers its Sync with ReadyForQuery
+      self.discard_until_sync = true;
+    }
+    self.stream.flush().await?;
+    Ok(())
   }
 
   fn maybe_log_server_msg(&self, msg: &PgWireBackendMessage) {
@@ -0,0 +1,244 @@
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use ipnet::IpNet;
+use omnitron_gate_common::helpers::rng::get_crypto_rng;
+use omnitron_gate_common::{OmnitronConfig, SshConfig};
+use russh::keys::ssh_key::certificate::{Builder, CertType};
+use russh::keys::ssh_key::{self, Fingerprint};
+use russh::keys::{Certificate, HashAlg, PrivateKey, PublicKey};
+use tracing::*;
+
+use crate::{load_client_ca_key, load_client_keys};
+
+/// Critical option restricting the addresses a certificate may be used from
+const SOURCE_ADDRESS_OPTION: &str = "source-address";
+
+/// Clock skew tolerated between Omnitron and targets when issuing certificates
+const CLOCK_SKEW: Duration = Duration::from_secs(60);
+
+#[derive(thiserror::Error, Debug)]
+pub enum CertificateError {
+  #[error("certificate is not signed by a trusted CA or is outside its validity window")]
+  Invalid(#[from] ssh_key::Error),
+  #[error("not a user certificate")]
+  NotUserCertificate,
+  #[error("principal {0:?} is not listed in the certificate")]
+  PrincipalNotAllowed(String),
+  #[error("unsupported critical option {0:?}")]
+  UnsupportedCriticalOption(String),
+  #[error("invalid source-address value {0:?}")]
+  InvalidSourceAddress(String),
+  #[error("connections from {0:?} are not allowed by the certificate")]
+  SourceAddressNotAllowed(Option<IpAddr>),
+}
+
+fn trusted_user_ca_fingerprints(config: &SshConfig) -> Vec<Fingerprint> {
+  config
+    .user_ca_keys
+    .iter()
+    .filter_map(|key| match PublicKey::from_openssh(key) {
+      Ok(key) => Some(key.fingerprint(HashAlg::Sha256)),
+      Err(error) => {
+        warn!(%error, "Ignoring invalid user CA key: {}", key);
+        None
+      }
+    })
+    .collect()
+}
+
+/// Checks that the certificate was issued by one of the configured user CAs,
+/// is currently valid and allows `principal` to log in from `remote_address`
+pub fn validate_user_certificate(
+  config: &SshConfig,
+  certificate: &Certificate,
+  principal: &str,
+  remote_address: Option<SocketAddr>,
+) -> Result<(), CertificateError> {
+  certificate.validate(&trusted_user_ca_fingerprints(config))?;
+
+  if certificate.cert_type() != CertType::User {
+    return Err(CertificateError::NotUserCertificate);
+  }
+
+  // Like sshd, require an explicit principal - certificates without any are rejected too
+  if !certificate.valid_principals().iter().any(|x| x == principal) {
+    return Err(CertificateError::PrincipalNotAllowed(principal.to_owned()));
+  }
+
+  for (name, value) in certificate.critical_options().iter() {
+    match name.as_str() {
+      SOURCE_ADDRESS_OPTION => check_source_address(value, remote_address)?,
+      _ => return Err(CertificateError::UnsupportedCriticalOption(name.clone())),
+    }
+  }
+
+  Ok(())
+}
+
+fn check_source_address(value: &str, remote_address: Option<SocketAddr>) -> Result<(), CertificateError> {
+  let networks = value
+    .split(',')
+    .map(|x| {
+      let x = x.trim();
+      IpNet::from_str(x)
+        .or_else(|_| IpAddr::from_str(x).map(IpNet::from))
+        .map_err(|_| CertificateError::InvalidSourceAddress(value.to_owned()))
+    })
+    .collect::<Result<Vec<_>, _>>()?;
+
+  let ip = remote_address.map(|x| x.ip().to_canonical());
+  match ip {
+    Some(ip) if networks.iter().any(|x| x.contains(&ip)) => Ok(()),
+    _ => Err(CertificateError::SourceAddressNotAllowed(ip)),
+  }
+}
+
+/// Signs a short-lived user certificate for `key`, valid only for `principal`
+pub fn issue_client_certificate(
+  ca_key: &PrivateKey,
+  key: &PrivateKey,
+  principal: &str,
+  validity: Duration,
+) -> Result<Certificate, ssh_key::Error> {
+  let now = SystemTime::now();
+  let mut builder = Builder::new_with_random_nonce(
+    &mut get_crypto_rng(),
+    key.public_key().key_data().clone(),
+    unix_seconds(now - CLOCK_SKEW),
+    unix_seconds(now + validity),
+  )?;
+  builder.cert_type(CertType::User)?;
+  builder.key_id(format!("omnitron-{principal}"))?;
+  builder.valid_principal(principal)?;
+  builder.extension("permit-pty", "")?;
+  builder.extension("permit-port-forwarding", "")?;
+  builder.extension("permit-agent-forwarding", "")?;
+  builder.extension("permit-X11-forwarding", "")?;
+  builder.sign(ca_key)
+}
+
+/// Issues a fresh certificate for each of Omnitron's non-RSA client keys,
+/// or none at all if client certificates are disabled
+pub fn issue_client_certificates(
+  config: &OmnitronConfig,
+  principal: &str,
+) -> Result<Vec<(Arc<PrivateKey>, Certificate)>, russh::keys::Error> {
+  let settings = &config.store.ssh.client_certificates;
+  if !settings.enable {
+    return Ok(vec![]);
+  }
+
+  let ca_key = load_client_ca_key(config)?;
+  let mut result = vec![];
+  for key in load_client_keys(config)? {
+    // russh can only sign RSA certificate auth with SHA-1
+    if key.key_data().is_rsa() {
+      continue;
+    }
+    let certificate = issue_client_certificate(&ca_key, &key, principal, settings.validity)?;
+    result.push((Arc::new(key), certificate));
+  }
+  Ok(result)
+}
+
+fn unix_seconds(time: SystemTime) -> u64 {
+  time
+    .duration_since(SystemTime::UNIX_EPOCH)
+    .map(|x| x.as_secs())
+    .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+  use russh::keys::Algorithm;
+
+  use super::*;
+
+  fn make_key() -> PrivateKey {
+    PrivateKey::random(&mut get_crypto_rng(), Algorithm::Ed25519).unwrap()
+  }
+
+  fn make_config(ca_key: &PrivateKey) -> SshConfig {
+    SshConfig {
+      user_ca_keys: vec![ca_key.public_key().to_openssh().unwrap()],
+      ..Default::default()
+    }
+  }
+
+  fn remote(ip: &str) -> Option<SocketAddr> {
+    Some(SocketAddr::new(ip.parse().unwrap(), 1234))
+  }
+
+  #[test]
+  fn test_issued_certificate_is_accepted() {
+    let ca_key = make_key();
+    let certificate = issue_client_certificate(&ca_key, &make_key(), "alice", Duration::from_secs(60)).unwrap();
+    let config = make_config(&ca_key);
+
+    validate_user_certificate(&config, &certificate, "alice", remote("10.0.0.1")).unwrap();
+    assert!(matches!(
+      validate_user_certificate(&config, &certificate, "bob", remote("10.0.0.1")),
+      Err(CertificateError::PrincipalNotAllowed(_))
+    ));
+  }
+
+  #[test]
+  fn test_untrusted_ca_is_rejected() {
+    let certificate = issue_client_certificate(&make_key(), &make_key(), "alice", Duration::from_secs(60)).unwrap();
+    let config = make_config(&make_key());
+
+    assert!(matches!(
+      validate_user_certificate(&config, &certificate, "alice", None),
+      Err(CertificateError::Invalid(_))
+    ));
+  }
+
+  #[test]
+  fn test_expired_certificate_is_rejected() {
+    let ca_key = make_key();
+    let key = make_key();
+    let now = unix_seconds(SystemTime::now());
+    let mut builder = Builder::new(vec![0; 16], key.public_key().key_data().clone(), now - 600, now - 300).unwrap();
+    builder.cert_type(CertType::User).unwrap();
+    builder.valid_principal("alice").unwrap();
+    let certificate = builder.sign(&ca_key).unwrap();
+
+    assert!(matches!(
+      validate_user_certificate(&make_config(&ca_key), &certificate, "alice", None),
+      Err(CertificateError::Invalid(_))
+    ));
+  }
+
+  #[test]
+  fn test_critical_options() {
+    let ca_key = make_key();
+    let key = make_key();
+    let now = unix_seconds(SystemTime::now());
+    let config = make_config(&ca_key);
+
+    let sign = |option: &str, value: &str| {
+      let mut builder = Builder::new(vec![0; 16], key.public_key().key_data().clone(), now - 60, now + 60).unwrap();
+      builder.cert_type(CertType::User).unwrap();
+      builder.valid_principal("alice").unwrap();
+      builder.critical_option(option, value).unwrap();
+      builder.sign(&ca_key).unwrap()
+    };
+
+    let certificate = sign(SOURCE_ADDRESS_OPTION, "10.0.0.0/8,192.168.1.1");
+    validate_user_certificate(&config, &certificate, "alice", remote("10.1.2.3")).unwrap();
+    validate_user_certificate(&config, &certificate, "alice", remote("::ffff:192.168.1.1")).unwrap();
+    assert!(matches!(
+      validate_user_certificate(&config, &certificate, "alice", remote("192.168.1.2")),
+      Err(CertificateError::SourceAddressNotAllowed(_))
+    ));
+
+    let certificate = sign("force-command", "/bin/true");
+    assert!(matches!(
+      validate_user_certificate(&config, &certificate, "alice", remote("10.1.2.3")),
+      Err(CertificateError::UnsupportedCriticalOption(_))
+    ));
+  }
+}
@@ -97,6 +97,9 @@ impl SessionChannel {
                           request.x11_screen_number,
                       ).await?;
                   }
+                  Some(ChannelOperation::RequestAgentForwarding) => {
+                      self.client_channel.agent_forward(false).await?;
+                  }
                   Some(ChannelOperation::Close) => break,
                   None => break,
               }
@@ -17,6 +17,7 @@ pub enum ClientHandlerEvent {
   HostKeyUnknown(PublicKey, oneshot::Sender<bool>),
   ForwardedTcpIp(Channel<Msg>, ForwardedTcpIpParams),
   X11(Channel<Msg>, String, u32),
+  AgentForward(Channel<Msg>),
   Disconnect,
 }
 
@@ -127,6 +128,15 @@ impl russh::client::Handler for ClientHandler {
       .send(ClientHandlerEvent::X11(channel, originator_address, originator_port));
     Ok(())
   }
+
+  async fn server_channel_open_agent_forward(
+    &mut self,
+    channel: Channel<Msg>,
+    _session: &mut Session,
+  ) -> Result<(), Self::Error> {
+    let _ = self.event_tx.send(ClientHandlerEvent::AgentForward(channel));
+    Ok(())
+  }
 }
 
 impl Drop for ClientHandler {
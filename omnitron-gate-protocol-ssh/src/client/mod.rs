@@ -29,7 +29,7 @@ use uuid::Uuid;
 use self::handler::ClientHandlerEvent;
 use super::{ChannelOperation, DirectTCPIPParams};
 use crate::client::handler::ClientHandlerError;
-use crate::{load_all_usable_private_keys, ForwardedTcpIpParams};
+use crate::{issue_client_certificates, load_all_usable_private_keys, ForwardedTcpIpParams};
 
 #[derive(Debug, thiserror::Error)]
 pub enum ConnectionError {
@@ -92,13 +92,14 @@ pub enum RCEvent {
   HostKeyUnknown(PublicKey, oneshot::Sender<bool>),
   ForwardedTcpIp(Uuid, ForwardedTcpIpParams),
   X11(Uuid, String, u32),
+  AgentForward(Uuid),
 }
 
 pub type RCCommandReply = oneshot::Sender<Result<(), SshClientError>>;
 
 #[derive(Clone, Debug)]
 pub enum RCCommand {
-  Connect(TargetSSHOptions),
+  Connect(Box<TargetSSHOptions>),
   Channel(Uuid, ChannelOperation),
   ForwardTCPIP(String, u32),
   CancelTCPIPForward(String, u32),
@@ -308,6 +309,11 @@ impl RemoteClient {
             let id = self.setup_server_initiated_channel(channel).await?;
             let _ = self.tx.send(RCEvent::X11(id, originator_address, originator_port));
           }
+          ClientHandlerEvent::AgentForward(channel) => {
+            info!("New agent forwarding connection");
+            let id = self.setup_server_initiated_channel(channel).await?;
+            let _ = self.tx.send(RCEvent::AgentForward(id));
+          }
           event => {
             error!(?event, "Unhandled client handler event");
           }
@@ -336,7 +342,7 @@ impl RemoteClient {
 
   async fn handle_command(&mut self, cmd: RCCommand) -> Result<bool, SshClientError> {
     match cmd {
-      RCCommand::Connect(options) => match self.connect(options).await {
+      RCCommand::Connect(options) => match self.connect(*options).await {
         Ok(_) => {
           self.set_state(RCState::Connected).map_err(SshClientError::other)?;
           let ops = self.pending_ops.drain(..).collect::<Vec<_>>();
@@ -489,10 +495,29 @@ impl RemoteClient {
                           debug!(username=&ssh_options.username[..], "Authenticated with password");
                       }
                   }
-                  SSHTargetAuth::PublicKey(_) => {
+                  SSHTargetAuth::PublicKey(auth) => {
+                      let certificates = match auth.use_certificate {
+                          #[allow(clippy::explicit_auto_deref)]
+                          true => issue_client_certificates(&*self.services.config.lock().await, &ssh_options.username)?,
+                          false => vec![],
+                      };
+                      for (key, certificate) in certificates.into_iter() {
+                          let key_id = certificate.key_id().to_owned();
+                          auth_result = session
+                              .authenticate_openssh_cert(ssh_options.username.clone(), key, certificate)
+                              .await?.success();
+                          if auth_result {
+                              debug!(username=&ssh_options.username[..], %key_id, "Authenticated with certificate");
+                              break;
+                          }
+                      }
+
                       #[allow(clippy::explicit_auto_deref)]
                       let keys = load_all_usable_private_keys(&*self.services.config.lock().await, ssh_options.allow_insecure_algos.unwrap_or(false))?;
                       for key in keys.into_iter() {
+                          if auth_result {
+                              break;
+                          }
                           let key_str = key.public_key().to_openssh().map_err(russh::Error::from)?;
                           auth_result = session
                               .authenticate_publickey(
@@ -57,6 +57,7 @@ pub enum ChannelOperation {
   RequestEnv(String, String),
   RequestExec(String),
   RequestX11(X11Request),
+  RequestAgentForwarding,
   RequestSubsystem(String),
   Data(Bytes),
   ExtendedData { data: Bytes, ext: u32 },
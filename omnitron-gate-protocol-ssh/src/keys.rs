@@ -75,6 +75,15 @@ pub fn generate_client_keys(config: &OmnitronConfig) -> Result<()> {
   }
   secure_file(&key_path)?;
 
+  let key_path = path.join("ca-ed25519");
+  if !key_path.exists() {
+    info!("Generating Ed25519 client CA key");
+    let key = PrivateKey::random(&mut get_crypto_rng(), russh::keys::Algorithm::Ed25519)?;
+    let f = File::create(&key_path)?;
+    encode_pkcs8_pem(&key, f)?;
+  }
+  secure_file(&key_path)?;
+
   let key_path = path.join("client-rsa");
   if !key_path.exists() {
     info!("Generating RSA client key (this can take a bit)");
@@ -106,6 +115,11 @@ pub fn load_client_keys(config: &OmnitronConfig) -> Result<Vec<PrivateKey>, russ
   Ok(keys)
 }
 
+/// Loads the key used to sign certificates for the client keys
+pub fn load_client_ca_key(config: &OmnitronConfig) -> Result<PrivateKey, russh::keys::Error> {
+  load_secret_key(get_keys_path(config).join("ca-ed25519"), None)
+}
+
 pub fn load_all_usable_private_keys(
   config: &OmnitronConfig,
   allow_insecure_algos: bool,
@@ -1,14 +1,17 @@
 #![feature(type_alias_impl_trait, try_blocks)]
+mod certificates;
 mod client;
 mod common;
 mod compat;
 mod keys;
 mod known_hosts;
+mod policy;
 mod server;
 use std::fmt::Debug;
 
 use anyhow::Result;
 use async_trait::async_trait;
+pub use certificates::*;
 pub use client::*;
 pub use common::*;
 pub use keys::*;
@@ -48,7 +51,7 @@ impl ProtocolServer for SSHProtocolServer {
 
     let mut handles = RemoteClient::create(Uuid::new_v4(), self.services.clone())?;
 
-    let _ = handles.command_tx.send((RCCommand::Connect(ssh_options), None));
+    let _ = handles.command_tx.send((RCCommand::Connect(Box::new(ssh_options)), None));
 
     while let Some(event) = handles.event_rx.recv().await {
       match event {
@@ -0,0 +1,131 @@
+use omnitron_gate_common::{SshChannelPolicy, SshChannelType};
+use regex::Regex;
+
+#[derive(thiserror::Error, Debug)]
+pub enum PolicyViolation {
+  #[error("{0} requests are not allowed on this target")]
+  ChannelNotAllowed(SshChannelType),
+  #[error("command is not allowed on this target: {0}")]
+  CommandNotAllowed(String),
+  #[error("forwarding to {0}:{1} is not allowed on this target")]
+  DestinationNotAllowed(String, u32),
+  #[error("invalid command pattern {0:?} in the target policy")]
+  InvalidPattern(String),
+}
+
+pub fn check_channel_type(policy: &SshChannelPolicy, channel_type: SshChannelType) -> Result<(), PolicyViolation> {
+  match &policy.allowed_channels {
+    Some(allowed) if !allowed.contains(&channel_type) => Err(PolicyViolation::ChannelNotAllowed(channel_type)),
+    _ => Ok(()),
+  }
+}
+
+/// Checks the command against the exec denylist and allowlist. Patterns have to
+/// match the whole command, and an invalid pattern rejects every command.
+pub fn check_exec_command(policy: &SshChannelPolicy, command: &str) -> Result<(), PolicyViolation> {
+  check_channel_type(policy, SshChannelType::Exec)?;
+
+  for pattern in &policy.exec_denylist {
+    if command_matches(pattern, command)? {
+      return Err(PolicyViolation::CommandNotAllowed(command.to_owned()));
+    }
+  }
+
+  if let Some(allowlist) = &policy.exec_allowlist {
+    for pattern in allowlist {
+      if command_matches(pattern, command)? {
+        return Ok(());
+      }
+    }
+    return Err(PolicyViolation::CommandNotAllowed(command.to_owned()));
+  }
+
+  Ok(())
+}
+
+pub fn check_forward_destination(policy: &SshChannelPolicy, host: &str, port: u32) -> Result<(), PolicyViolation> {
+  check_channel_type(policy, SshChannelType::DirectTcpip)?;
+
+  match &policy.allowed_forward_destinations {
+    Some(allowed) if !allowed.iter().any(|x| destination_matches(x, host, port)) => {
+      Err(PolicyViolation::DestinationNotAllowed(host.to_owned(), port))
+    }
+    _ => Ok(()),
+  }
+}
+
+fn command_matches(pattern: &str, command: &str) -> Result<bool, PolicyViolation> {
+  let regex = Regex::new(&format!("^(?:{pattern})$")).map_err(|_| PolicyViolation::InvalidPattern(pattern.to_owned()))?;
+  Ok(regex.is_match(command))
+}
+
+fn destination_matches(pattern: &str, host: &str, port: u32) -> bool {
+  let Some((host_pattern, port_pattern)) = pattern.rsplit_once(':') else {
+    return false;
+  };
+  let host_pattern = host_pattern.trim_start_matches('[').trim_end_matches(']');
+  let host = host.trim_start_matches('[').trim_end_matches(']');
+
+  let port_matches = port_pattern == "*" || port_pattern.parse::<u32>().ok() == Some(port);
+  let host_matches = match host_pattern.strip_prefix('*') {
+    Some("") => true,
+    Some(suffix) if suffix.starts_with('.') => host.to_lowercase().ends_with(&suffix.to_lowercase()),
+    _ => host.eq_ignore_ascii_case(host_pattern),
+  };
+  port_matches && host_matches
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_exec_lists() {
+    let policy = SshChannelPolicy {
+      exec_allowlist: Some(vec!["psql( .*)?".into(), "uptime".into()]),
+      exec_denylist: vec![".*--command.*".into()],
+      ..Default::default()
+    };
+
+    check_exec_command(&policy, "uptime").unwrap();
+    check_exec_command(&policy, "psql -h db").unwrap();
+    assert!(check_exec_command(&policy, "uptime; rm -rf /").is_err());
+    assert!(check_exec_command(&policy, "psql --command 'drop table x'").is_err());
+
+    let policy = SshChannelPolicy {
+      exec_denylist: vec!["(".into()],
+      ..Default::default()
+    };
+    assert!(matches!(
+      check_exec_command(&policy, "ls"),
+      Err(PolicyViolation::InvalidPattern(_))
+    ));
+  }
+
+  #[test]
+  fn test_forward_destinations() {
+    let policy = SshChannelPolicy {
+      allowed_forward_destinations: Some(vec![
+        "db.internal:5432".into(),
+        "*.cache.internal:*".into(),
+        "[::1]:22".into(),
+      ]),
+      ..Default::default()
+    };
+
+    check_forward_destination(&policy, "DB.internal", 5432).unwrap();
+    check_forward_destination(&policy, "a.cache.internal", 6379).unwrap();
+    check_forward_destination(&policy, "::1", 22).unwrap();
+    assert!(check_forward_destination(&policy, "db.internal", 22).is_err());
+    assert!(check_forward_destination(&policy, "cache.internal", 6379).is_err());
+
+    let policy = SshChannelPolicy {
+      allowed_channels: Some(vec![SshChannelType::Shell]),
+      ..Default::default()
+    };
+    assert!(matches!(
+      check_forward_destination(&policy, "db.internal", 5432),
+      Err(PolicyViolation::ChannelNotAllowed(SshChannelType::DirectTcpip))
+    ));
+  }
+}
@@ -0,0 +1,125 @@
+use russh::keys::{HashAlg, PublicKey};
+
+/// SSH_AGENTC_SIGN_REQUEST from draft-miller-ssh-agent
+const SSH_AGENTC_SIGN_REQUEST: u8 = 13;
+
+/// Agent messages are tiny - anything larger means the stream isn't agent protocol
+const MAX_MESSAGE_SIZE: usize = 256 * 1024;
+
+/// A signing request made through a forwarded agent
+#[derive(Debug, PartialEq, Eq)]
+pub struct AgentSignRequest {
+  /// SHA-256 fingerprint of the key asked to sign, if it could be parsed
+  pub key_fingerprint: Option<String>,
+  pub data_length: usize,
+}
+
+/// Picks signing requests out of the agent protocol stream flowing from the target to the user's agent
+#[derive(Default)]
+pub struct AgentRequestInspector {
+  buffer: Vec<u8>,
+}
+
+impl AgentRequestInspector {
+  pub fn feed(&mut self, data: &[u8]) -> Vec<AgentSignRequest> {
+    self.buffer.extend_from_slice(data);
+
+    let mut requests = vec![];
+    loop {
+      let Some(length) = read_u32(&self.buffer, 0) else {
+        break;
+      };
+      if length > MAX_MESSAGE_SIZE {
+        // Not something we can make sense of, stop inspecting
+        self.buffer.clear();
+        break;
+      }
+      if self.buffer.len() < 4 + length {
+        break;
+      }
+
+      let message = self.buffer.drain(..4 + length).skip(4).collect::<Vec<_>>();
+      if message.first() == Some(&SSH_AGENTC_SIGN_REQUEST) {
+        requests.push(parse_sign_request(&message[1..]));
+      }
+    }
+    requests
+  }
+}
+
+fn parse_sign_request(body: &[u8]) -> AgentSignRequest {
+  let key_blob = read_string(body, 0);
+  let data_length = key_blob.and_then(|blob| read_u32(body, 4 + blob.len())).unwrap_or_default();
+  let key_fingerprint = key_blob
+    .and_then(|blob| PublicKey::from_bytes(blob).ok())
+    .map(|key| key.fingerprint(HashAlg::Sha256).to_string());
+  AgentSignRequest {
+    key_fingerprint,
+    data_length,
+  }
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Option<usize> {
+  let bytes = data.get(offset..offset + 4)?;
+  Some(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize)
+}
+
+fn read_string(data: &[u8], offset: usize) -> Option<&[u8]> {
+  let length = read_u32(data, offset)?;
+  data.get(offset + 4..offset + 4 + length)
+}
+
+#[cfg(test)]
+mod tests {
+  use omnitron_gate_common::helpers::rng::get_crypto_rng;
+  use russh::keys::{Algorithm, PrivateKey};
+
+  use super::*;
+
+  fn frame(body: &[u8]) -> Vec<u8> {
+    let mut result = (body.len() as u32).to_be_bytes().to_vec();
+    result.extend_from_slice(body);
+    result
+  }
+
+  fn sign_request(key_blob: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut body = vec![SSH_AGENTC_SIGN_REQUEST];
+    body.extend_from_slice(&frame(key_blob));
+    body.extend_from_slice(&frame(data));
+    body.extend_from_slice(&0u32.to_be_bytes());
+    frame(&body)
+  }
+
+  #[test]
+  fn test_sign_request_split_across_packets() {
+    let key = PrivateKey::random(&mut get_crypto_rng(), Algorithm::Ed25519).unwrap();
+    let blob = key.public_key().to_bytes().unwrap();
+
+    // SSH_AGENTC_REQUEST_IDENTITIES followed by a sign request
+    let mut stream = frame(&[11]);
+    stream.extend_from_slice(&sign_request(&blob, b"session data"));
+
+    let mut inspector = AgentRequestInspector::default();
+    let (first, second) = stream.split_at(10);
+    assert!(inspector.feed(first).is_empty());
+    assert_eq!(
+      inspector.feed(second),
+      vec![AgentSignRequest {
+        key_fingerprint: Some(key.public_key().fingerprint(HashAlg::Sha256).to_string()),
+        data_length: 12,
+      }]
+    );
+  }
+
+  #[test]
+  fn test_unparseable_key() {
+    let mut inspector = AgentRequestInspector::default();
+    assert_eq!(
+      inspector.feed(&sign_request(b"garbage", b"x")),
+      vec![AgentSignRequest {
+        key_fingerprint: None,
+        data_length: 1,
+      }]
+    );
+  }
+}
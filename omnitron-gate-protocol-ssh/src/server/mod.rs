@@ -1,8 +1,10 @@
+mod agent;
 mod channel_writer;
 mod russh_handler;
 mod service_output;
 mod session;
 mod session_handle;
+mod sftp;
 use std::borrow::Cow;
 use std::fmt::Debug;
 use std::sync::Arc;
@@ -3,7 +3,7 @@ use std::fmt::Debug;
 use async_trait::async_trait;
 use bytes::Bytes;
 use omnitron_gate_common::Secret;
-use russh::keys::PublicKey;
+use russh::keys::{Certificate, PublicKey};
 use russh::server::{Auth, Handle, Msg, Session};
 use russh::{Channel, ChannelId, Pty, Sig};
 use tokio::sync::mpsc::UnboundedSender;
@@ -30,6 +30,7 @@ pub enum ServerHandlerEvent {
   ShellRequest(ServerChannelId, oneshot::Sender<bool>),
   AuthPublicKey(Secret<String>, PublicKey, oneshot::Sender<Auth>),
   AuthPublicKeyOffer(Secret<String>, PublicKey, oneshot::Sender<Auth>),
+  AuthCertificate(Secret<String>, Certificate, oneshot::Sender<Auth>),
   AuthPassword(Secret<String>, Secret<String>, oneshot::Sender<Auth>),
   AuthKeyboardInteractive(Secret<String>, Option<Secret<String>>, oneshot::Sender<Auth>),
   Data(ServerChannelId, Bytes, oneshot::Sender<()>),
@@ -41,7 +42,8 @@ pub enum ServerHandlerEvent {
   ExecRequest(ServerChannelId, Bytes, oneshot::Sender<bool>),
   ChannelOpenDirectTcpIp(ServerChannelId, DirectTCPIPParams, oneshot::Sender<bool>),
   EnvRequest(ServerChannelId, String, String, oneshot::Sender<()>),
-  X11Request(ServerChannelId, X11Request, oneshot::Sender<()>),
+  X11Request(ServerChannelId, X11Request, oneshot::Sender<bool>),
+  AgentForwardRequest(ServerChannelId, oneshot::Sender<bool>),
   TcpIpForward(String, u32, oneshot::Sender<bool>),
   CancelTcpIpForward(String, u32, oneshot::Sender<bool>),
   Disconnect,
@@ -171,6 +173,16 @@ impl russh::server::Handler for ServerHandler {
     Ok(result)
   }
 
+  async fn auth_openssh_certificate(&mut self, user: &str, certificate: &Certificate) -> Result<Auth, Self::Error> {
+    let user = Secret::new(user.to_string());
+    let (tx, rx) = oneshot::channel();
+
+    self.send_event(ServerHandlerEvent::AuthCertificate(user, certificate.clone(), tx))?;
+
+    let result = rx.await.unwrap_or(Auth::UnsupportedMethod);
+    Ok(result)
+  }
+
   async fn auth_password(&mut self, user: &str, password: &str) -> Result<Auth, Self::Error> {
     let user = Secret::new(user.to_string());
     let password = Secret::new(password.to_string());
@@ -352,7 +364,7 @@ impl russh::server::Handler for ServerHandler {
     x11_auth_protocol: &str,
     x11_auth_cookie: &str,
     x11_screen_number: u32,
-    _session: &mut Session,
+    session: &mut Session,
   ) -> Result<(), Self::Error> {
     let x11_auth_protocol = x11_auth_protocol.to_string();
     let x11_auth_cookie = x11_auth_cookie.to_string();
@@ -367,10 +379,20 @@ impl russh::server::Handler for ServerHandler {
       },
       tx,
     ))?;
-    let _ = rx.await;
+    // The target's reply is relayed once it arrives
+    if !rx.await.unwrap_or(false) {
+      session.channel_failure(channel)?
+    }
     Ok(())
   }
 
+  async fn agent_request(&mut self, channel: ChannelId, _session: &mut Session) -> Result<bool, Self::Error> {
+    let (tx, rx) = oneshot::channel();
+    self.send_event(ServerHandlerEvent::AgentForwardRequest(ServerChannelId(channel), tx))?;
+    let allowed = rx.await.unwrap_or(false);
+    Ok(allowed)
+  }
+
   async fn tcpip_forward(&mut self, address: &str, port: &mut u32, session: &mut Session) -> Result<bool, Self::Error> {
     let address = address.to_string();
     let port = *port;
@@ -13,25 +13,30 @@ use futures::{Future, FutureExt};
 use omnitron_gate_common::auth::{AuthCredential, AuthResult, AuthSelector, AuthState, CredentialKind};
 use omnitron_gate_common::eventhub::{EventHub, EventSender, EventSubscription};
 use omnitron_gate_common::{
-  OmnitronError, Secret, SessionId, SshHostKeyVerificationMode, Target, TargetOptions, TargetSSHOptions,
+  OmnitronError, Secret, SessionId, SshChannelPolicy, SshChannelType, SshHostKeyVerificationMode, Target, TargetOptions,
+  TargetSSHOptions,
 };
+use omnitron_gate_core::recordings::{self, TerminalRecorder, TerminalRecorderParams, TerminalRecordingStreamId};
 use omnitron_gate_core::{authorize_ticket, consume_ticket, OmnitronServerHandle, Services};
-use russh::keys::{PublicKey, PublicKeyBase64};
+use russh::keys::{Certificate, PublicKey, PublicKeyBase64};
 use russh::{CryptoVec, MethodKind, MethodSet, Sig};
 use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
 use tokio::sync::{broadcast, oneshot, Mutex};
 use tracing::*;
 use uuid::Uuid;
 
+use super::agent::AgentRequestInspector;
 use super::channel_writer::ChannelWriter;
 use super::russh_handler::ServerHandlerEvent;
 use super::service_output::ServiceOutput;
 use super::session_handle::SessionHandleCommand;
+use super::sftp::{ExecTransfer, SftpEvent, SftpInspector};
 use crate::compat::ContextExt;
+use crate::policy::{check_channel_type, check_exec_command, check_forward_destination, PolicyViolation};
 use crate::server::service_output::ERASE_PROGRESS_SPINNER;
 use crate::{
-  ChannelOperation, ConnectionError, DirectTCPIPParams, PtyRequest, RCCommand, RCCommandReply, RCEvent, RCState, RemoteClient,
-  ServerChannelId, SshClientError, X11Request,
+  validate_user_certificate, ChannelOperation, ConnectionError, DirectTCPIPParams, PtyRequest, RCCommand, RCCommandReply,
+  RCEvent, RCState, RemoteClient, ServerChannelId, SshClientError, X11Request,
 };
 
 #[derive(Clone)]
@@ -39,6 +44,8 @@ use crate::{
 enum TargetSelection {
   None,
   NotFound(String),
+  /// Not allowed right now, e.g. because of session limits
+  Refused(String),
   Found(Target, TargetSSHOptions),
 }
 
@@ -70,6 +77,10 @@ pub struct ServerSession {
   all_channels: Vec<Uuid>,
   channel_map: BiMap<ServerChannelId, Uuid>,
   channel_pty_size_map: HashMap<Uuid, PtyRequest>,
+  channel_recorders: HashMap<Uuid, TerminalRecorder>,
+  agent_forwarding_requested: bool,
+  agent_channels: HashMap<Uuid, AgentRequestInspector>,
+  sftp_channels: HashMap<Uuid, SftpInspector>,
   rc_tx: UnboundedSender<(RCCommand, Option<RCCommandReply>)>,
   rc_abort_tx: UnboundedSender<()>,
   rc_state: RCState,
@@ -87,6 +98,22 @@ pub struct ServerSession {
   cached_successful_ticket_auth: Option<CachedSuccessfulTicketAuth>,
 }
 
+fn log_sftp_event(channel: Uuid, event: SftpEvent) {
+  match event {
+    SftpEvent::Open { path, write } => info!(%channel, %path, write, "SFTP open"),
+    SftpEvent::Close {
+      path,
+      bytes_read,
+      bytes_written,
+    } => info!(%channel, %path, bytes_read, bytes_written, "SFTP close"),
+    SftpEvent::Rename { from, to } => info!(%channel, %from, %to, "SFTP rename"),
+    SftpEvent::Remove { path } => info!(%channel, %path, "SFTP remove"),
+    SftpEvent::MakeDirectory { path } => info!(%channel, %path, "SFTP mkdir"),
+    SftpEvent::RemoveDirectory { path } => info!(%channel, %path, "SFTP rmdir"),
+    SftpEvent::Denied { operation, path } => warn!(%channel, %path, operation, "SFTP request denied by policy"),
+  }
+}
+
 fn session_debug_tag(id: &SessionId, remote_address: &SocketAddr) -> String {
   format!("[{id} - {remote_address}]")
 }
@@ -123,6 +150,10 @@ impl ServerSession {
       all_channels: vec![],
       channel_map: BiMap::new(),
       channel_pty_size_map: HashMap::new(),
+      channel_recorders: HashMap::new(),
+      agent_forwarding_requested: false,
+      agent_channels: HashMap::new(),
+      sftp_channels: HashMap::new(),
       rc_tx: rc_handles.command_tx.clone(),
       rc_abort_tx: rc_handles.abort_tx,
       rc_state: RCState::NotInitialized,
@@ -273,15 +304,60 @@ impl ServerSession {
 
   pub async fn emit_pty_output(&mut self, data: &[u8]) -> Result<()> {
     let channels = self.pty_channels.clone();
-    for channel in channels {
-      let channel = self.map_channel_reverse(&channel)?;
+    for channel_id in channels {
+      let channel = self.map_channel_reverse(&channel_id)?;
       if let Some(session) = self.session_handle.clone() {
         self.channel_writer.write(session, channel.0, CryptoVec::from_slice(data));
       }
+      self
+        .record_terminal_data(channel_id, TerminalRecordingStreamId::Output, data)
+        .await;
     }
+    self.server_handle.lock().await.emit_output(data).await;
     Ok(())
   }
 
+  async fn emit_live_output(&mut self, channel_id: Uuid, data: &[u8]) {
+    if self.pty_channels.contains(&channel_id) {
+      self.server_handle.lock().await.emit_output(data).await;
+    }
+  }
+
+  async fn start_terminal_recording(&mut self, channel_id: Uuid, name: String) {
+    let params = match self.channel_pty_size_map.get(&channel_id) {
+      Some(request) => TerminalRecorderParams {
+        cols: request.col_width,
+        rows: request.row_height,
+        term: Some(request.term.clone()),
+      },
+      None => TerminalRecorderParams::default(),
+    };
+
+    let recorder = {
+      let mut recordings = self.services.recordings.lock().await;
+      recordings.start::<TerminalRecorder>(&self.id, name, params).await
+    };
+
+    match recorder {
+      Ok(recorder) => {
+        self.channel_recorders.insert(channel_id, recorder);
+      }
+      Err(recordings::Error::Disabled) => (),
+      Err(error) => {
+        error!(channel=%channel_id, ?error, "Failed to start recording");
+      }
+    }
+  }
+
+  async fn record_terminal_data(&mut self, channel_id: Uuid, stream: TerminalRecordingStreamId, data: &[u8]) {
+    if let Some(recorder) = self.channel_recorders.get_mut(&channel_id) {
+      if let Err(error) = recorder.write(stream, data).await {
+        error!(channel=%channel_id, ?error, "Failed to record terminal data");
+        self.channel_recorders.remove(&channel_id);
+      }
+    }
+  }
+
   pub async fn maybe_connect_remote(&mut self) -> Result<()> {
     match self.target.clone() {
       TargetSelection::None => {
@@ -294,6 +370,11 @@ impl ServerSession {
         self.disconnect_server().await;
         anyhow::bail!("Target not found: {}", name);
       }
+      TargetSelection::Refused(reason) => {
+        self.emit_service_message(&format!("Access refused: {reason}")).await?;
+        self.disconnect_server().await;
+        anyhow::bail!("Access refused: {}", reason);
+      }
       TargetSelection::Found(target, ssh_options) => {
         if self.rc_state == RCState::NotInitialized {
           self.connect_remote(target, ssh_options).await?;
@@ -306,7 +387,7 @@ impl ServerSession {
   async fn connect_remote(&mut self, target: Target, ssh_options: TargetSSHOptions) -> Result<()> {
     self.rc_state = RCState::Connecting;
     self
-      .send_command(RCCommand::Connect(ssh_options))
+      .send_command(RCCommand::Connect(Box::new(ssh_options)))
       .map_err(|_| anyhow::anyhow!("cannot send command"))?;
     self.service_output.show_progress();
     self
@@ -383,8 +464,8 @@ impl ServerSession {
 
       ServerHandlerEvent::SubsystemRequest(server_channel_id, name, reply) => {
         return match self._channel_subsystem_request(server_channel_id, name).await {
-          Ok(()) => {
-            let _ = reply.send(true);
+          Ok(allowed) => {
+            let _ = reply.send(allowed);
             Ok(())
           }
           Err(SshClientError::Russh(russh::Error::ChannelOpenFailure(_))) => {
@@ -413,10 +494,21 @@ impl ServerSession {
 
       ServerHandlerEvent::ShellRequest(server_channel_id, reply) => {
         let channel_id = self.map_channel(&server_channel_id)?;
+        if !self
+          .enforce_policy(|policy| check_channel_type(policy, SshChannelType::Shell))
+          .await
+        {
+          let _ = reply.send(false);
+          return Ok(());
+        }
         let _ = self.maybe_connect_remote().await;
 
         let _ = self.send_command(RCCommand::Channel(channel_id, ChannelOperation::RequestShell));
 
+        self
+          .start_terminal_recording(channel_id, format!("shell-channel-{}", server_channel_id.0))
+          .await;
+
         info!(%channel_id, "Opening shell");
 
         let _ = self
@@ -437,6 +529,10 @@ impl ServerSession {
         let _ = reply.send(self._auth_publickey_offer(username, key).await);
       }
 
+      ServerHandlerEvent::AuthCertificate(username, certificate, reply) => {
+        let _ = reply.send(self._auth_certificate(username, certificate).await);
+      }
+
       ServerHandlerEvent::AuthPassword(username, password, reply) => {
         let _ = reply.send(self._auth_password(username, password).await);
       }
@@ -476,8 +572,7 @@ impl ServerSession {
       }
 
       ServerHandlerEvent::ExecRequest(channel, data, reply) => {
-        self._channel_exec_request(channel, data).await?;
-        let _ = reply.send(true);
+        let _ = reply.send(self._channel_exec_request(channel, data).await?);
       }
 
       ServerHandlerEvent::ChannelOpenDirectTcpIp(channel, params, reply) => {
@@ -490,13 +585,15 @@ impl ServerSession {
       }
 
       ServerHandlerEvent::X11Request(channel, request, reply) => {
-        self._channel_x11_request(channel, request).await?;
-        let _ = reply.send(());
+        let _ = reply.send(self._channel_x11_request(channel, request).await?);
+      }
+
+      ServerHandlerEvent::AgentForwardRequest(channel, reply) => {
+        let _ = reply.send(self._channel_agent_forward_request(channel).await?);
       }
 
       ServerHandlerEvent::TcpIpForward(address, port, reply) => {
-        self._tcpip_forward(address, port).await?;
-        let _ = reply.send(true);
+        let _ = reply.send(self._tcpip_forward(address, port).await?);
       }
 
       ServerHandlerEvent::CancelTcpIpForward(address, port, reply) => {
@@ -584,10 +681,28 @@ impl ServerSession {
         self.disconnect_server().await;
       }
       RCEvent::Output(channel, data) => {
+        if let Some(inspector) = self.agent_channels.get_mut(&channel) {
+          for request in inspector.feed(&data) {
+            info!(
+              key = request.key_fingerprint.as_deref().unwrap_or("unknown"),
+              data_length = request.data_length,
+              "Forwarded agent signing request"
+            );
+          }
+        }
+        if let Some(inspector) = self.sftp_channels.get_mut(&channel) {
+          for event in inspector.feed_server(&data) {
+            log_sftp_event(channel, event);
+          }
+        }
         let server_channel_id = self.map_channel_reverse(&channel)?;
         if let Some(session) = self.session_handle.as_mut() {
           let _ = session.data(server_channel_id.0, CryptoVec::from_slice(&data)).await;
         }
+        self
+          .record_terminal_data(channel, TerminalRecordingStreamId::Output, &data)
+          .await;
+        self.emit_live_output(channel, &data).await;
       }
       RCEvent::Success(channel) => {
         let server_channel_id = self.map_channel_reverse(&channel)?;
@@ -612,6 +727,13 @@ impl ServerSession {
           .await?;
       }
       RCEvent::Close(channel) => {
+        self.channel_recorders.remove(&channel);
+        self.agent_channels.remove(&channel);
+        if let Some(mut inspector) = self.sftp_channels.remove(&channel) {
+          for event in inspector.finish() {
+            log_sftp_event(channel, event);
+          }
+        }
         let server_channel_id = self.map_channel_reverse(&channel)?;
         let _ = self
           .maybe_with_session(|handle| async move { handle.close(server_channel_id.0).await.context("failed to close ch") })
@@ -654,6 +776,10 @@ impl ServerSession {
       }
       RCEvent::Done => {}
       RCEvent::ExtendedData { channel, data, ext } => {
+        self
+          .record_terminal_data(channel, TerminalRecordingStreamId::Error, &data)
+          .await;
+        self.emit_live_output(channel, &data).await;
         let server_channel_id = self.map_channel_reverse(&channel)?;
         self
           .maybe_with_session(|handle| async move {
@@ -697,6 +823,27 @@ impl ServerSession {
           self.all_channels.push(id);
         }
       }
+      RCEvent::AgentForward(id) => {
+        if !self.agent_forwarding_requested {
+          warn!(channel=%id, "Target opened an agent channel without agent forwarding being requested");
+          let _ = self.send_command(RCCommand::Channel(id, ChannelOperation::Close));
+        } else if let Some(session) = &mut self.session_handle {
+          match session.channel_open_agent().await {
+            Ok(server_channel) => {
+              self.channel_map.insert(ServerChannelId(server_channel.id()), id);
+              self.all_channels.push(id);
+              self.agent_channels.insert(id, AgentRequestInspector::default());
+            }
+            Err(error) => {
+              warn!(channel=%id, ?error, "Client refused the agent channel");
+              let _ = self.send_command(RCCommand::Channel(id, ChannelOperation::Close));
+            }
+          }
+        } else {
+          warn!(channel=%id, "Target opened an agent channel without a client session");
+          let _ = self.send_command(RCCommand::Channel(id, ChannelOperation::Close));
+        }
+      }
     }
     Ok(())
   }
@@ -755,6 +902,26 @@ impl ServerSession {
     Ok(())
   }
 
+  /// Runs a check against the target's channel policy, telling the user and
+  /// logging it if the request is rejected
+  async fn enforce_policy<F>(&mut self, check: F) -> bool
+  where
+    F: FnOnce(&SshChannelPolicy) -> Result<(), PolicyViolation>,
+  {
+    let result = match &self.target {
+      TargetSelection::Found(_, options) => check(&options.channel_policy),
+      _ => Ok(()),
+    };
+    match result {
+      Ok(()) => true,
+      Err(violation) => {
+        warn!(%violation, "Request denied by target policy");
+        let _ = self.emit_service_message(&format!("Denied: {violation}")).await;
+        false
+      }
+    }
+  }
+
   async fn maybe_with_session<'a, FN, FT, R>(&'a mut self, f: FN) -> Result<Option<R>>
   where
     FN: FnOnce(&'a mut russh::server::Handle) -> FT + 'a,
@@ -767,6 +934,13 @@ impl ServerSession {
   }
 
   async fn _channel_open_direct_tcpip(&mut self, channel: ServerChannelId, params: DirectTCPIPParams) -> Result<bool> {
+    if !self
+      .enforce_policy(|policy| check_forward_destination(policy, &params.host_to_connect, params.port_to_connect))
+      .await
+    {
+      return Ok(false);
+    }
+
     let uuid = Uuid::new_v4();
     self.channel_map.insert(channel, uuid);
 
@@ -790,13 +964,18 @@ impl ServerSession {
   async fn _window_change_request(&mut self, server_channel_id: ServerChannelId, request: PtyRequest) -> Result<()> {
     let channel_id = self.map_channel(&server_channel_id)?;
     self.channel_pty_size_map.insert(channel_id, request.clone());
+    if let Some(recorder) = self.channel_recorders.get_mut(&channel_id) {
+      if let Err(error) = recorder.write_pty_resize(request.col_width, request.row_height).await {
+        error!(channel=%channel_id, ?error, "Failed to record terminal resize");
+      }
+    }
     self
       .send_command_and_wait(RCCommand::Channel(channel_id, ChannelOperation::ResizePty(request)))
       .await?;
     Ok(())
   }
 
-  async fn _channel_exec_request(&mut self, server_channel_id: ServerChannelId, data: Bytes) -> Result<()> {
+  async fn _channel_exec_request(&mut self, server_channel_id: ServerChannelId, data: Bytes) -> Result<bool> {
     let channel_id = self.map_channel(&server_channel_id)?;
     match std::str::from_utf8(&data) {
       Err(e) => {
@@ -805,24 +984,93 @@ impl ServerSession {
       }
       Ok::<&str, _>(command) => {
         debug!(channel=%channel_id, %command, "Requested exec");
+        if !self.enforce_policy(|policy| check_exec_command(policy, command)).await
+          || !self.check_exec_transfer(channel_id, command)
+        {
+          return Ok(false);
+        }
         let _ = self.maybe_connect_remote().await;
+        // Binary SFTP/SCP streams are audited by the transfer checks instead
+        if ExecTransfer::from_command(command) == ExecTransfer::None {
+          self
+            .start_terminal_recording(channel_id, format!("exec-channel-{}", server_channel_id.0))
+            .await;
+        }
         let _ = self.send_command(RCCommand::Channel(
           channel_id,
           ChannelOperation::RequestExec(command.to_string()),
         ));
       }
     }
-    Ok(())
+    Ok(true)
+  }
+
+  /// Audits legacy SCP transfers and applies the target's file transfer policy to them.
+  /// SFTP servers started over exec are inspected like the SFTP subsystem.
+  /// Returns `false` if the command has to be rejected.
+  fn check_exec_transfer(&mut self, channel_id: Uuid, command: &str) -> bool {
+    let TargetSelection::Found(_, options) = &self.target else {
+      return true;
+    };
+    let policy = &options.file_transfer;
+    match ExecTransfer::from_command(command) {
+      ExecTransfer::None => true,
+      ExecTransfer::Sftp => {
+        info!(channel=%channel_id, "SFTP server started over exec");
+        self.sftp_channels.insert(channel_id, SftpInspector::new(policy.clone()));
+        true
+      }
+      ExecTransfer::Scp { upload, download, path } => {
+        let allowed = !((upload && !policy.allows_uploads()) || (download && policy.max_download_size.is_some()));
+        match (upload, download, allowed) {
+          (true, _, true) => info!(channel=%channel_id, %path, "SCP upload"),
+          (_, true, true) => info!(channel=%channel_id, %path, "SCP download"),
+          (_, _, false) => warn!(channel=%channel_id, %path, upload, "SCP transfer denied by policy"),
+          _ => (),
+        }
+        allowed
+      }
+    }
   }
 
-  async fn _channel_x11_request(&mut self, server_channel_id: ServerChannelId, request: X11Request) -> Result<()> {
+  async fn _channel_x11_request(&mut self, server_channel_id: ServerChannelId, request: X11Request) -> Result<bool> {
     let channel_id = self.map_channel(&server_channel_id)?;
     debug!(channel=%channel_id, "Requested X11");
+    if !self
+      .enforce_policy(|policy| check_channel_type(policy, SshChannelType::X11))
+      .await
+    {
+      return Ok(false);
+    }
     let _ = self.maybe_connect_remote().await;
     self
       .send_command_and_wait(RCCommand::Channel(channel_id, ChannelOperation::RequestX11(request)))
       .await?;
-    Ok(())
+    Ok(true)
+  }
+
+  async fn _channel_agent_forward_request(&mut self, server_channel_id: ServerChannelId) -> Result<bool> {
+    let channel_id = self.map_channel(&server_channel_id)?;
+    let allowed = match &self.target {
+      TargetSelection::Found(_, options) => options.allow_agent_forwarding.unwrap_or(false),
+      _ => false,
+    };
+    if !allowed {
+      info!(channel=%channel_id, "Agent forwarding is not enabled for this target");
+      return Ok(false);
+    }
+
+    debug!(channel=%channel_id, "Requested agent forwarding");
+    let _ = self.maybe_connect_remote().await;
+    if let Err(error) = self
+      .send_command_and_wait(RCCommand::Channel(channel_id, ChannelOperation::RequestAgentForwarding))
+      .await
+    {
+      warn!(channel=%channel_id, ?error, "Failed to request agent forwarding from the target");
+      return Ok(false);
+    }
+    self.agent_forwarding_requested = true;
+    Ok(true)
   }
 
   async fn _channel_env_request(&mut self, server_channel_id: ServerChannelId, name: String, value: String) -> Result<()> {
@@ -838,14 +1086,27 @@ impl ServerSession {
     &mut self,
     server_channel_id: ServerChannelId,
     name: String,
-  ) -> Result<(), SshClientError> {
+  ) -> Result<bool, SshClientError> {
     let channel_id = self.map_channel(&server_channel_id)?;
     info!(channel=%channel_id, "Requesting subsystem {}", &name);
+    if !self
+      .enforce_policy(|policy| check_channel_type(policy, SshChannelType::Subsystem))
+      .await
+    {
+      return Ok(false);
+    }
+    if name == "sftp" {
+      if let TargetSelection::Found(_, options) = &self.target {
+        self
+          .sftp_channels
+          .insert(channel_id, SftpInspector::new(options.file_transfer.clone()));
+      }
+    }
     let _ = self.maybe_connect_remote().await;
     self
       .send_command_and_wait(RCCommand::Channel(channel_id, ChannelOperation::RequestSubsystem(name)))
       .await?;
-    Ok(())
+    Ok(true)
   }
 
   async fn _data(&mut self, server_channel_id: ServerChannelId, data: Bytes) -> Result<()> {
@@ -861,6 +1122,34 @@ impl ServerSession {
       let _ = self.event_sender.send_once(Event::ConsoleInput(data.clone())).await;
     }
 
+    let data = match self.sftp_channels.get_mut(&channel_id).map(|x| x.feed_client(&data)) {
+      None => data,
+      Some(Ok(output)) => {
+        for event in output.events {
+          log_sftp_event(channel_id, event);
+        }
+        if !output.reply.is_empty() {
+          if let Some(session) = self.session_handle.as_mut() {
+            let _ = session.data(server_channel_id.0, CryptoVec::from(output.reply)).await;
+          }
+        }
+        if output.forward.is_empty() {
+          return Ok(());
+        }
+        Bytes::from(output.forward)
+      }
+      Some(Err(error)) => {
+        warn!(channel=%channel_id, %error, "Closing SFTP channel");
+        self.sftp_channels.remove(&channel_id);
+        let _ = self.send_command(RCCommand::Channel(channel_id, ChannelOperation::Close));
+        return Ok(());
+      }
+    };
+
+    self
+      .record_terminal_data(channel_id, TerminalRecordingStreamId::Input, &data)
+      .await;
+
     let _ = self.send_command(RCCommand::Channel(channel_id, ChannelOperation::Data(data)));
     Ok(())
   }
@@ -875,13 +1164,17 @@ impl ServerSession {
     Ok(())
   }
 
-  async fn _tcpip_forward(&mut self, address: String, port: u32) -> Result<()> {
+  async fn _tcpip_forward(&mut self, address: String, port: u32) -> Result<bool> {
     info!(%address, %port, "Remote port forwarding requested");
-    let _ = self.maybe_connect_remote().await;
-    self
-      .send_command_and_wait(RCCommand::ForwardTCPIP(address, port))
+    if !self
+      .enforce_policy(|policy| check_channel_type(policy, SshChannelType::TcpipForward))
       .await
-      .map_err(anyhow::Error::from)
+    {
+      return Ok(false);
+    }
+    let _ = self.maybe_connect_remote().await;
+    self.send_command_and_wait(RCCommand::ForwardTCPIP(address, port)).await?;
+    Ok(true)
   }
 
   pub async fn _cancel_tcpip_forward(&mut self, address: String, port: u32) -> Result<()> {
@@ -908,6 +1201,12 @@ impl ServerSession {
       return russh::server::Auth::Accept;
     }
 
+    // russh only passes the certificate's inner key at this stage, so the
+    // offer has to be accepted for the certificate to be checked once signed
+    if !self.services.config.lock().await.store.ssh.user_ca_keys.is_empty() {
+      return russh::server::Auth::Accept;
+    }
+
     let selector: AuthSelector = ssh_username.expose_secret().into();
     match self.try_auth_lazy(&selector, None).await {
       Ok(AuthResult::Need(kinds)) => russh::server::Auth::Reject {
@@ -961,6 +1260,57 @@ impl ServerSession {
     }
   }
 
+  async fn _auth_certificate(&mut self, ssh_username: Secret<String>, certificate: Certificate) -> russh::server::Auth {
+    let selector: AuthSelector = ssh_username.expose_secret().into();
+
+    info!(
+      "Certificate auth as {:?} with certificate {:?} (serial {})",
+      selector,
+      certificate.key_id(),
+      certificate.serial()
+    );
+
+    let AuthSelector::User { ref username, .. } = selector else {
+      return russh::server::Auth::Reject {
+        proceed_with_methods: Some(MethodSet::all()),
+      };
+    };
+
+    let validation = validate_user_certificate(
+      &self.services.config.lock().await.store.ssh,
+      &certificate,
+      username,
+      Some(self.remote_address),
+    );
+    if let Err(error) = validation {
+      warn!(%error, key_id = certificate.key_id(), "Certificate rejected");
+      return russh::server::Auth::Reject {
+        proceed_with_methods: Some(MethodSet::all()),
+      };
+    }
+
+    let credential = AuthCredential::Certificate {
+      key_id: certificate.key_id().to_owned(),
+      principals: certificate.valid_principals().to_vec(),
+    };
+
+    match self.try_auth_lazy(&selector, Some(credential)).await {
+      Ok(AuthResult::Accepted { .. }) => russh::server::Auth::Accept,
+      Ok(AuthResult::Rejected) => russh::server::Auth::Reject {
+        proceed_with_methods: Some(MethodSet::all()),
+      },
+      Ok(AuthResult::Need(kinds)) => russh::server::Auth::Reject {
+        proceed_with_methods: Some(self.get_remaining_auth_methods(kinds)),
+      },
+      Err(error) => {
+        error!(?error, "Failed to verify credentials");
+        russh::server::Auth::Reject {
+          proceed_with_methods: None,
+        }
+      }
+    }
+  }
+
   async fn _auth_password(&mut self, ssh_username: Secret<String>, password: Secret<String>) -> russh::server::Auth {
     let selector: AuthSelector = ssh_username.expose_secret().into();
     info!("Password auth as {:?}", selector);
@@ -1086,6 +1436,7 @@ impl ServerSession {
         CredentialKind::Totp => m.push(MethodKind::KeyboardInteractive),
         CredentialKind::WebUserApproval => m.push(MethodKind::KeyboardInteractive),
         CredentialKind::PublicKey => m.push(MethodKind::PublicKey),
+        CredentialKind::Sso => m.push(MethodKind::KeyboardInteractive),
       }
     }
     m
@@ -1137,13 +1488,22 @@ impl ServerSession {
     match selector {
       AuthSelector::User { username, target_name } => {
         let cp = self.services.config_provider.clone();
+        let address = Some(self.remote_address.ip());
+
+        if let Some(until) = self.services.login_protection.locked_until(username, address).await? {
+          warn!(%until, "Login attempt while locked out");
+          return Ok(AuthResult::Rejected);
+        }
 
         let state_arc = self.get_auth_state(username).await?;
         let mut state = state_arc.lock().await;
 
         if let Some(credential) = credential {
+          let guessable = credential.is_guessable();
           if cp.lock().await.validate_credential(username, &credential).await? {
             state.add_valid_credential(credential);
+          } else if guessable {
+            self.services.login_protection.record_failure(username, address).await?;
           }
         }
 
@@ -1152,6 +1512,7 @@ impl ServerSession {
         match user_auth_result {
           AuthResult::Accepted { username } => {
             self.services.auth_state_store.lock().await.complete(state.id()).await;
+            self.services.login_protection.record_success(&username).await?;
             let target_auth_result = {
               self
                 .services
@@ -1217,7 +1578,38 @@ impl ServerSession {
       ssh_options.username = username.to_string();
     }
 
-    let _ = self.server_handle.lock().await.set_target(&target).await;
+    let denial = self
+      .services
+      .config_provider
+      .lock()
+      .await
+      .check_address(username, Some(target_name), self.remote_address.ip())
+      .await?;
+    if let Some(denial) = denial {
+      warn!(%denial, "Connection refused by IP policy");
+      self.target = TargetSelection::Refused("connections from your address are not allowed".to_owned());
+      return Ok(());
+    }
+
+    let limits = self
+      .services
+      .config_provider
+      .lock()
+      .await
+      .get_session_limits(username, target_name)
+      .await?;
+    let result = self
+      .server_handle
+      .lock()
+      .await
+      .set_target_within_limits(username, &target, &limits)
+      .await;
+    if let Err(error @ OmnitronError::SessionLimitReached { .. }) = result {
+      warn!(%error, "Session limit reached");
+      self.target = TargetSelection::Refused(error.to_string());
+      return Ok(());
+    }
+
     self.target = TargetSelection::Found(target, ssh_options);
     Ok(())
   }
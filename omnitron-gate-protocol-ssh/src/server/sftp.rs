@@ -0,0 +1,666 @@
+use std::collections::HashMap;
+
+use omnitron_gate_common::SshFileTransferPolicy;
+
+// Packet types from draft-ietf-secsh-filexfer-02 (SFTP version 3)
+const SSH_FXP_INIT: u8 = 1;
+const SSH_FXP_OPEN: u8 = 3;
+const SSH_FXP_CLOSE: u8 = 4;
+const SSH_FXP_READ: u8 = 5;
+const SSH_FXP_WRITE: u8 = 6;
+const SSH_FXP_SETSTAT: u8 = 9;
+const SSH_FXP_FSETSTAT: u8 = 10;
+const SSH_FXP_REMOVE: u8 = 13;
+const SSH_FXP_MKDIR: u8 = 14;
+const SSH_FXP_RMDIR: u8 = 15;
+const SSH_FXP_RENAME: u8 = 18;
+const SSH_FXP_SYMLINK: u8 = 20;
+const SSH_FXP_STATUS: u8 = 101;
+const SSH_FXP_HANDLE: u8 = 102;
+const SSH_FXP_DATA: u8 = 103;
+const SSH_FXP_EXTENDED: u8 = 200;
+
+const SSH_FXF_WRITE: u32 = 0x02;
+const SSH_FXF_APPEND: u32 = 0x04;
+const SSH_FXF_CREAT: u32 = 0x08;
+const SSH_FXF_TRUNC: u32 = 0x10;
+
+const SSH_FX_PERMISSION_DENIED: u32 = 3;
+
+/// Extensions that never modify the remote filesystem
+const READ_ONLY_EXTENSIONS: &[&str] = &[
+  "statvfs@openssh.com",
+  "fstatvfs@openssh.com",
+  "limits@openssh.com",
+  "expand-path@openssh.com",
+  "home-directory",
+  "users-groups-by-id@openssh.com",
+];
+
+/// Programs that speak SFTP on their stdio, as run by `Subsystem sftp` lines or directly over exec
+const SFTP_SERVER_NAMES: &[&str] = &["sftp-server", "internal-sftp"];
+
+/// Client requests are held back until complete so that they can be rejected
+/// as a whole. OpenSSH caps them at 256 KiB, leave some room for other clients.
+const MAX_CLIENT_PACKET_LENGTH: usize = 1024 * 1024;
+
+/// Only the start of server packets is needed - enough for the longest handle
+/// allowed by the spec, the rest is passed through without buffering
+const SERVER_PACKET_PEEK_LENGTH: usize = 1 + 4 + 4 + 256;
+
+#[derive(thiserror::Error, Debug)]
+pub enum SftpError {
+  #[error("SFTP packet of {0} bytes exceeds the inspection limit")]
+  PacketTooLarge(usize),
+}
+
+/// File operation seen on an SFTP channel
+#[derive(Debug, PartialEq, Eq)]
+pub enum SftpEvent {
+  Open {
+    path: String,
+    write: bool,
+  },
+  Close {
+    path: String,
+    bytes_read: u64,
+    bytes_written: u64,
+  },
+  Rename {
+    from: String,
+    to: String,
+  },
+  Remove {
+    path: String,
+  },
+  MakeDirectory {
+    path: String,
+  },
+  RemoveDirectory {
+    path: String,
+  },
+  Denied {
+    operation: &'static str,
+    path: String,
+  },
+}
+
+/// File transfer recognized in the command line of an exec request
+#[derive(Debug, PartialEq, Eq)]
+pub enum ExecTransfer {
+  None,
+  /// An SFTP server run directly instead of through the subsystem request
+  Sftp,
+  /// Legacy SCP in sink (`-t`) or source (`-f`) mode
+  Scp {
+    upload: bool,
+    download: bool,
+    path: String,
+  },
+}
+
+impl ExecTransfer {
+  /// Looks for `scp` and SFTP server binaries anywhere in the command, by basename,
+  /// so that full paths and wrappers like `sh -c '…'` are recognized as well
+  pub fn from_command(command: &str) -> Self {
+    let words = command
+      .split(|x: char| x.is_whitespace() || ";|&()<>`'\"".contains(x))
+      .filter(|x| !x.is_empty())
+      .collect::<Vec<_>>();
+    let basename = |word: &str| word.rsplit('/').next().unwrap_or_default().to_owned();
+
+    if words.iter().any(|x| SFTP_SERVER_NAMES.contains(&basename(x).as_str())) {
+      return Self::Sftp;
+    }
+    let Some(position) = words.iter().position(|x| basename(x) == "scp") else {
+      return Self::None;
+    };
+    let args = &words[position + 1..];
+    // Flags can be combined, as in `-pt`
+    let has_flag = |flag: char| {
+      args
+        .iter()
+        .any(|x| x.starts_with('-') && !x.starts_with("--") && x.contains(flag))
+    };
+    Self::Scp {
+      upload: has_flag('t'),
+      download: has_flag('f'),
+      path: args
+        .iter()
+        .rev()
+        .find(|x| !x.starts_with('-'))
+        .copied()
+        .unwrap_or_default()
+        .to_owned(),
+    }
+  }
+}
+
+#[derive(Debug, Default)]
+pub struct SftpClientOutput {
+  /// Requests to pass on to the target
+  pub forward: Vec<u8>,
+  /// Responses to requests rejected by the policy, to be sent back to the user
+  pub reply: Vec<u8>,
+  pub events: Vec<SftpEvent>,
+}
+
+enum PendingRequest {
+  Open { path: String, write: bool },
+  Read { handle: Vec<u8> },
+}
+
+struct OpenFile {
+  path: String,
+  bytes_read: u64,
+  bytes_written: u64,
+}
+
+/// Follows both directions of an SFTP subsystem channel to audit file
+/// operations and enforce the target's file transfer policy
+pub struct SftpInspector {
+  policy: SshFileTransferPolicy,
+  client_buffer: Vec<u8>,
+  server_buffer: Vec<u8>,
+  server_skip: usize,
+  pending: HashMap<u32, PendingRequest>,
+  handles: HashMap<Vec<u8>, OpenFile>,
+}
+
+impl SftpInspector {
+  pub fn new(policy: SshFileTransferPolicy) -> Self {
+    Self {
+      policy,
+      client_buffer: vec![],
+      server_buffer: vec![],
+      server_skip: 0,
+      pending: HashMap::new(),
+      handles: HashMap::new(),
+    }
+  }
+
+  /// Processes data sent by the user. Incomplete requests are held back until the rest arrives.
+  pub fn feed_client(&mut self, data: &[u8]) -> Result<SftpClientOutput, SftpError> {
+    self.client_buffer.extend_from_slice(data);
+
+    let mut output = SftpClientOutput::default();
+    while let Some(length) = read_u32(&self.client_buffer, 0) {
+      if length > MAX_CLIENT_PACKET_LENGTH {
+        return Err(SftpError::PacketTooLarge(length));
+      }
+      if self.client_buffer.len() < 4 + length {
+        break;
+      }
+      let packet = self.client_buffer.drain(..4 + length).collect::<Vec<_>>();
+      self.handle_client_packet(packet, &mut output);
+    }
+    Ok(output)
+  }
+
+  /// Observes data sent by the target, which is always passed through unchanged
+  pub fn feed_server(&mut self, mut data: &[u8]) -> Vec<SftpEvent> {
+    let mut events = vec![];
+    while !data.is_empty() {
+      if self.server_skip > 0 {
+        let skipped = self.server_skip.min(data.len());
+        self.server_skip -= skipped;
+        data = &data[skipped..];
+        continue;
+      }
+
+      let needed = match read_u32(&self.server_buffer, 0) {
+        Some(length) => 4 + length.min(SERVER_PACKET_PEEK_LENGTH),
+        None => 4,
+      };
+      let taken = (needed - self.server_buffer.len()).min(data.len());
+      self.server_buffer.extend_from_slice(&data[..taken]);
+      data = &data[taken..];
+
+      if let Some(length) = read_u32(&self.server_buffer, 0) {
+        let peek_length = length.min(SERVER_PACKET_PEEK_LENGTH);
+        if self.server_buffer.len() == 4 + peek_length {
+          let header = std::mem::take(&mut self.server_buffer);
+          self.handle_server_packet(&header[4..], &mut events);
+          self.server_skip = length - peek_length;
+        }
+      }
+    }
+    events
+  }
+
+  /// Reports files that were still open when the channel went away
+  pub fn finish(&mut self) -> Vec<SftpEvent> {
+    self.handles.drain().map(|(_, file)| file.into_close_event()).collect()
+  }
+
+  fn handle_client_packet(&mut self, packet: Vec<u8>, output: &mut SftpClientOutput) {
+    let mut reader = Reader::new(&packet[4..]);
+    let (Some(kind), Some(id)) = (reader.u8(), reader.u32()) else {
+      output.forward.extend_from_slice(&packet);
+      return;
+    };
+    if kind == SSH_FXP_INIT {
+      output.forward.extend_from_slice(&packet);
+      return;
+    }
+
+    let denied = match kind {
+      SSH_FXP_OPEN => {
+        let path = reader.path();
+        let flags = reader.u32().unwrap_or_default();
+        let write = flags & (SSH_FXF_WRITE | SSH_FXF_APPEND | SSH_FXF_CREAT | SSH_FXF_TRUNC) != 0;
+        if write && !self.policy.allows_uploads() {
+          Some(("upload", path))
+        } else {
+          self.pending.insert(id, PendingRequest::Open { path, write });
+          None
+        }
+      }
+      SSH_FXP_READ => {
+        let handle = reader.string().unwrap_or_default().to_vec();
+        let offset = reader.u64().unwrap_or_default();
+        let length_position = packet.len() - reader.remaining();
+        let length = reader.u32().unwrap_or_default();
+        match self.policy.max_download_size {
+          Some(max) if offset >= max => Some(("download beyond the size limit", self.handle_path(&handle))),
+          Some(max) if offset.checked_add(length as u64).map_or(true, |end| end > max) => {
+            // Shorten the read so that the file is cut off exactly at the limit
+            let mut packet = packet;
+            let allowed = (max - offset) as u32;
+            packet[length_position..length_position + 4].copy_from_slice(&allowed.to_be_bytes());
+            self.pending.insert(id, PendingRequest::Read { handle });
+            output.forward.extend_from_slice(&packet);
+            return;
+          }
+          _ => {
+            self.pending.insert(id, PendingRequest::Read { handle });
+            None
+          }
+        }
+      }
+      SSH_FXP_WRITE => {
+        let handle = reader.string().unwrap_or_default().to_vec();
+        let _offset = reader.u64();
+        if !self.policy.allows_uploads() {
+          Some(("upload", self.handle_path(&handle)))
+        } else {
+          if let (Some(file), Some(data)) = (self.handles.get_mut(&handle), reader.string()) {
+            file.bytes_written += data.len() as u64;
+          }
+          None
+        }
+      }
+      SSH_FXP_CLOSE => {
+        if let Some(file) = reader.string().and_then(|handle| self.handles.remove(handle)) {
+          output.events.push(file.into_close_event());
+        }
+        None
+      }
+      SSH_FXP_REMOVE => self.check_modification("remove", reader.path(), |path| SftpEvent::Remove { path }, output),
+      SSH_FXP_MKDIR => self.check_modification("mkdir", reader.path(), |path| SftpEvent::MakeDirectory { path }, output),
+      SSH_FXP_RMDIR => self.check_modification("rmdir", reader.path(), |path| SftpEvent::RemoveDirectory { path }, output),
+      SSH_FXP_RENAME => {
+        let from = reader.path();
+        let to = reader.path();
+        self.check_modification("rename", from, |from| SftpEvent::Rename { from, to }, output)
+      }
+      SSH_FXP_SETSTAT | SSH_FXP_SYMLINK => {
+        let path = reader.path();
+        self.policy.read_only.then_some(("modify", path))
+      }
+      SSH_FXP_FSETSTAT => {
+        let handle = reader.string().unwrap_or_default().to_vec();
+        self.policy.read_only.then(|| ("modify", self.handle_path(&handle)))
+      }
+      SSH_FXP_EXTENDED => {
+        let name = reader.path();
+        if name == "posix-rename@openssh.com" {
+          let from = reader.path();
+          let to = reader.path();
+          self.check_modification("rename", from, |from| SftpEvent::Rename { from, to }, output)
+        } else if self.policy.read_only && !READ_ONLY_EXTENSIONS.contains(&name.as_str()) {
+          Some(("modify", name))
+        } else {
+          None
+        }
+      }
+      _ => None,
+    };
+
+    match denied {
+      Some((operation, path)) => {
+        output.reply.extend_from_slice(&permission_denied(id, operation));
+        output.events.push(SftpEvent::Denied { operation, path });
+      }
+      None => output.forward.extend_from_slice(&packet),
+    }
+  }
+
+  fn handle_server_packet(&mut self, header: &[u8], events: &mut Vec<SftpEvent>) {
+    let mut reader = Reader::new(header);
+    let (Some(kind), Some(id)) = (reader.u8(), reader.u32()) else {
+      return;
+    };
+    // Every response completes the request with the same ID
+    match (kind, self.pending.remove(&id)) {
+      (SSH_FXP_HANDLE, Some(PendingRequest::Open { path, write })) => {
+        if let Some(handle) = reader.string() {
+          events.push(SftpEvent::Open {
+            path: path.clone(),
+            write,
+          });
+          self.handles.insert(
+            handle.to_vec(),
+            OpenFile {
+              path,
+              bytes_read: 0,
+              bytes_written: 0,
+            },
+          );
+        }
+      }
+      (SSH_FXP_DATA, Some(PendingRequest::Read { handle })) => {
+        // The data itself is past the peeked header, only its length is available
+        if let (Some(file), Some(length)) = (self.handles.get_mut(&handle), reader.u32()) {
+          file.bytes_read += length as u64;
+        }
+      }
+      _ => (),
+    }
+  }
+
+  fn check_modification(
+    &self,
+    operation: &'static str,
+    path: String,
+    event: impl FnOnce(String) -> SftpEvent,
+    output: &mut SftpClientOutput,
+  ) -> Option<(&'static str, String)> {
+    if self.policy.read_only {
+      return Some((operation, path));
+    }
+    output.events.push(event(path));
+    None
+  }
+
+  fn handle_path(&self, handle: &[u8]) -> String {
+    self.handles.get(handle).map(|x| x.path.clone()).unwrap_or_default()
+  }
+}
+
+impl OpenFile {
+  fn into_close_event(self) -> SftpEvent {
+    SftpEvent::Close {
+      path: self.path,
+      bytes_read: self.bytes_read,
+      bytes_written: self.bytes_written,
+    }
+  }
+}
+
+fn permission_denied(id: u32, operation: &str) -> Vec<u8> {
+  let message = format!("Omnitron: {operation} is not allowed by policy");
+  let mut body = vec![SSH_FXP_STATUS];
+  body.extend_from_slice(&id.to_be_bytes());
+  body.extend_from_slice(&SSH_FX_PERMISSION_DENIED.to_be_bytes());
+  body.extend_from_slice(&(message.len() as u32).to_be_bytes());
+  body.extend_from_slice(message.as_bytes());
+  // Empty language tag
+  body.extend_from_slice(&0u32.to_be_bytes());
+
+  let mut packet = (body.len() as u32).to_be_bytes().to_vec();
+  packet.extend_from_slice(&body);
+  packet
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Option<usize> {
+  let bytes = data.get(offset..offset + 4)?;
+  Some(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize)
+}
+
+struct Reader<'a> {
+  data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+  fn new(data: &'a [u8]) -> Self {
+    Self { data }
+  }
+
+  fn remaining(&self) -> usize {
+    self.data.len()
+  }
+
+  fn take(&mut self, length: usize) -> Option<&'a [u8]> {
+    if self.data.len() < length {
+      return None;
+    }
+    let (result, rest) = self.data.split_at(length);
+    self.data = rest;
+    Some(result)
+  }
+
+  fn u8(&mut self) -> Option<u8> {
+    self.take(1).map(|x| x[0])
+  }
+
+  fn u32(&mut self) -> Option<u32> {
+    self.take(4).map(|x| u32::from_be_bytes([x[0], x[1], x[2], x[3]]))
+  }
+
+  fn u64(&mut self) -> Option<u64> {
+    let high = self.u32()? as u64;
+    let low = self.u32()? as u64;
+    Some(high << 32 | low)
+  }
+
+  fn string(&mut self) -> Option<&'a [u8]> {
+    let length = self.u32()? as usize;
+    self.take(length)
+  }
+
+  fn path(&mut self) -> String {
+    self
+      .string()
+      .map(|x| String::from_utf8_lossy(x).into_owned())
+      .unwrap_or_default()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_exec_transfer() {
+    assert_eq!(ExecTransfer::from_command("ls -la /tmp"), ExecTransfer::None);
+    assert_eq!(ExecTransfer::from_command("cat scp.log"), ExecTransfer::None);
+
+    for command in [
+      "/usr/lib/openssh/sftp-server",
+      "/usr/libexec/sftp-server -e",
+      "internal-sftp",
+      "sh -c '/usr/lib/ssh/sftp-server'",
+    ] {
+      assert_eq!(ExecTransfer::from_command(command), ExecTransfer::Sftp, "{command}");
+    }
+
+    let upload = ExecTransfer::Scp {
+      upload: true,
+      download: false,
+      path: "/tmp/x".into(),
+    };
+    assert_eq!(ExecTransfer::from_command("scp -t /tmp/x"), upload);
+    assert_eq!(ExecTransfer::from_command("/usr/bin/scp -v -pt -- /tmp/x"), upload);
+    assert_eq!(ExecTransfer::from_command("sh -c 'scp -t /tmp/x'"), upload);
+    assert_eq!(ExecTransfer::from_command("true; exec scp -t /tmp/x"), upload);
+    assert_eq!(
+      ExecTransfer::from_command("/usr/bin/scp -f /etc/passwd"),
+      ExecTransfer::Scp {
+        upload: false,
+        download: true,
+        path: "/etc/passwd".into(),
+      }
+    );
+  }
+
+  fn string(value: &[u8]) -> Vec<u8> {
+    let mut result = (value.len() as u32).to_be_bytes().to_vec();
+    result.extend_from_slice(value);
+    result
+  }
+
+  fn packet(kind: u8, id: u32, fields: &[&[u8]]) -> Vec<u8> {
+    let mut body = vec![kind];
+    body.extend_from_slice(&id.to_be_bytes());
+    for field in fields {
+      body.extend_from_slice(field);
+    }
+    string(&body)
+  }
+
+  fn open(id: u32, path: &str, flags: u32) -> Vec<u8> {
+    packet(
+      SSH_FXP_OPEN,
+      id,
+      &[&string(path.as_bytes()), &flags.to_be_bytes(), &0u32.to_be_bytes()],
+    )
+  }
+
+  fn read(id: u32, handle: &[u8], offset: u64, length: u32) -> Vec<u8> {
+    packet(
+      SSH_FXP_READ,
+      id,
+      &[&string(handle), &offset.to_be_bytes(), &length.to_be_bytes()],
+    )
+  }
+
+  #[test]
+  fn test_download_is_audited() {
+    let mut inspector = SftpInspector::new(SshFileTransferPolicy::default());
+
+    let output = inspector.feed_client(&open(1, "/etc/hosts", 1)).unwrap();
+    assert_eq!(output.forward, open(1, "/etc/hosts", 1));
+    assert!(output.reply.is_empty());
+
+    assert_eq!(
+      inspector.feed_server(&packet(SSH_FXP_HANDLE, 1, &[&string(b"h1")])),
+      vec![SftpEvent::Open {
+        path: "/etc/hosts".into(),
+        write: false
+      }]
+    );
+
+    inspector.feed_client(&read(2, b"h1", 0, 32768)).unwrap();
+    // A large data packet split across several channel messages
+    let data = packet(SSH_FXP_DATA, 2, &[&string(&[0; 2000])]);
+    for chunk in data.chunks(300) {
+      assert!(inspector.feed_server(chunk).is_empty());
+    }
+
+    let output = inspector.feed_client(&packet(SSH_FXP_CLOSE, 3, &[&string(b"h1")])).unwrap();
+    assert_eq!(
+      output.events,
+      vec![SftpEvent::Close {
+        path: "/etc/hosts".into(),
+        bytes_read: 2000,
+        bytes_written: 0
+      }]
+    );
+  }
+
+  #[test]
+  fn test_incomplete_request_is_held_back() {
+    let mut inspector = SftpInspector::new(SshFileTransferPolicy::default());
+    let request = packet(SSH_FXP_REMOVE, 1, &[&string(b"/tmp/x")]);
+    let (first, second) = request.split_at(7);
+
+    assert!(inspector.feed_client(first).unwrap().forward.is_empty());
+    let output = inspector.feed_client(second).unwrap();
+    assert_eq!(output.forward, request);
+    assert_eq!(output.events, vec![SftpEvent::Remove { path: "/tmp/x".into() }]);
+  }
+
+  #[test]
+  fn test_read_only_policy() {
+    let mut inspector = SftpInspector::new(SshFileTransferPolicy {
+      read_only: true,
+      ..Default::default()
+    });
+
+    let output = inspector.feed_client(&open(1, "/etc/passwd", SSH_FXF_WRITE)).unwrap();
+    assert!(output.forward.is_empty());
+    assert_eq!(read_u32(&output.reply, 9), Some(SSH_FX_PERMISSION_DENIED as usize));
+
+    let rename = packet(SSH_FXP_RENAME, 2, &[&string(b"/a"), &string(b"/b")]);
+    let output = inspector.feed_client(&rename).unwrap();
+    assert!(output.forward.is_empty());
+    assert_eq!(
+      output.events,
+      vec![SftpEvent::Denied {
+        operation: "rename",
+        path: "/a".into()
+      }]
+    );
+
+    let statvfs = packet(SSH_FXP_EXTENDED, 3, &[&string(b"statvfs@openssh.com"), &string(b"/")]);
+    assert_eq!(inspector.feed_client(&statvfs).unwrap().forward, statvfs);
+  }
+
+  #[test]
+  fn test_disabled_uploads_allow_removal() {
+    let mut inspector = SftpInspector::new(SshFileTransferPolicy {
+      disable_uploads: true,
+      ..Default::default()
+    });
+
+    assert!(inspector
+      .feed_client(&open(1, "/tmp/upload", SSH_FXF_CREAT | SSH_FXF_WRITE))
+      .unwrap()
+      .forward
+      .is_empty());
+    let remove = packet(SSH_FXP_REMOVE, 2, &[&string(b"/tmp/old")]);
+    assert_eq!(inspector.feed_client(&remove).unwrap().forward, remove);
+  }
+
+  #[test]
+  fn test_download_size_limit() {
+    let mut inspector = SftpInspector::new(SshFileTransferPolicy {
+      max_download_size: Some(1000),
+      ..Default::default()
+    });
+
+    assert_eq!(
+      inspector.feed_client(&read(1, b"h", 0, 512)).unwrap().forward,
+      read(1, b"h", 0, 512)
+    );
+    assert_eq!(
+      inspector.feed_client(&read(2, b"h", 512, 512)).unwrap().forward,
+      read(2, b"h", 512, 488)
+    );
+    let output = inspector.feed_client(&read(3, b"h", 1000, 512)).unwrap();
+    assert!(output.forward.is_empty());
+    assert!(!output.reply.is_empty());
+  }
+
+  #[test]
+  fn test_download_size_limit_overflow() {
+    let mut inspector = SftpInspector::new(SshFileTransferPolicy {
+      max_download_size: Some(u64::MAX),
+      ..Default::default()
+    });
+    assert_eq!(
+      inspector.feed_client(&read(1, b"h", u64::MAX - 10, 512)).unwrap().forward,
+      read(1, b"h", u64::MAX - 10, 10)
+    );
+  }
+
+  #[test]
+  fn test_oversized_packet() {
+    let mut inspector = SftpInspector::new(SshFileTransferPolicy::default());
+    assert!(matches!(
+      inspector.feed_client(&(MAX_CLIENT_PACKET_LENGTH as u32 + 1).to_be_bytes()),
+      Err(SftpError::PacketTooLarge(_))
+    ));
+  }
+}
@@ -0,0 +1,17 @@
+#[derive(thiserror::Error, Debug)]
+pub enum SsoError {
+  #[error("invalid provider configuration: {0}")]
+  Config(String),
+  #[error("provider discovery failed: {0}")]
+  Discovery(String),
+  #[error("code exchange failed: {0}")]
+  CodeExchange(String),
+  #[error("the provider did not return an ID token")]
+  NoIdToken,
+  #[error("ID token verification failed: {0}")]
+  Verification(String),
+  #[error("state parameter mismatch")]
+  StateMismatch,
+  #[error("the email address has not been verified by the provider")]
+  EmailNotVerified,
+}
@@ -0,0 +1,7 @@
+mod error;
+mod request;
+mod sso;
+
+pub use error::*;
+pub use request::*;
+pub use sso::*;
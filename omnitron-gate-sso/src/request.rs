@@ -0,0 +1,30 @@
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use subtle::ConstantTimeEq;
+
+/// An in-progress login, kept in the user's HTTP session until the provider redirects back
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SsoLoginRequest {
+  pub provider: String,
+  pub auth_url: String,
+  pub(crate) redirect_url: String,
+  pub(crate) csrf_token: String,
+  pub(crate) nonce: String,
+  pub(crate) pkce_verifier: String,
+}
+
+impl SsoLoginRequest {
+  pub fn verify_state(&self, state: &str) -> bool {
+    self.csrf_token.as_bytes().ct_eq(state.as_bytes()).into()
+  }
+}
+
+#[derive(Debug, Clone)]
+pub struct SsoLoginResponse {
+  /// Value of the provider's configured email claim
+  pub email: Option<String>,
+  pub email_verified: Option<bool>,
+  /// Roles assigned through the provider's role mappings, if role mapping is configured
+  pub roles: Option<Vec<String>>,
+  pub claims: Map<String, Value>,
+}
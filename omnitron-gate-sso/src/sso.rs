@@ -0,0 +1,157 @@
+use data_encoding::BASE64URL_NOPAD;
+use omnitron_gate_common::SsoProviderConfig;
+use openidconnect::core::{CoreAuthenticationFlow, CoreClient, CoreProviderMetadata};
+use openidconnect::{
+  reqwest, AuthorizationCode, ClientId, ClientSecret, CsrfToken, EndpointMaybeSet, EndpointNotSet, EndpointSet, IssuerUrl, Nonce,
+  PkceCodeChallenge, PkceCodeVerifier, RedirectUrl, Scope, TokenResponse,
+};
+use serde_json::{Map, Value};
+use tracing::*;
+
+use crate::{SsoError, SsoLoginRequest, SsoLoginResponse};
+
+type DiscoveredClient =
+  CoreClient<EndpointSet, EndpointNotSet, EndpointNotSet, EndpointNotSet, EndpointMaybeSet, EndpointMaybeSet>;
+
+pub struct SsoClient {
+  config: SsoProviderConfig,
+}
+
+fn make_http_client() -> Result<reqwest::Client, SsoError> {
+  reqwest::ClientBuilder::new()
+    // Following redirects opens the client up to SSRF vulnerabilities
+    .redirect(reqwest::redirect::Policy::none())
+    .build()
+    .map_err(|e| SsoError::Config(e.to_string()))
+}
+
+impl SsoClient {
+  pub fn new(config: SsoProviderConfig) -> Self {
+    Self { config }
+  }
+
+  pub fn config(&self) -> &SsoProviderConfig {
+    &self.config
+  }
+
+  async fn discover(&self, http_client: &reqwest::Client, redirect_url: &str) -> Result<DiscoveredClient, SsoError> {
+    let issuer_url = IssuerUrl::new(self.config.issuer_url.clone()).map_err(|e| SsoError::Config(e.to_string()))?;
+    let metadata = CoreProviderMetadata::discover_async(issuer_url, http_client)
+      .await
+      .map_err(|e| SsoError::Discovery(e.to_string()))?;
+
+    let redirect_url = RedirectUrl::new(redirect_url.to_owned()).map_err(|e| SsoError::Config(e.to_string()))?;
+
+    Ok(
+      CoreClient::from_provider_metadata(
+        metadata,
+        ClientId::new(self.config.client_id.clone()),
+        Some(ClientSecret::new(self.config.client_secret.expose_secret().clone())),
+      )
+      .set_redirect_uri(redirect_url),
+    )
+  }
+
+  /// Builds the provider's authorization URL that the user has to be redirected to
+  pub async fn start_login(&self, redirect_url: String) -> Result<SsoLoginRequest, SsoError> {
+    let http_client = make_http_client()?;
+    let client = self.discover(&http_client, &redirect_url).await?;
+
+    let (pkce_challenge, pkce_verifier) = PkceCodeChallenge::new_random_sha256();
+
+    let mut request = client
+      .authorize_url(
+        CoreAuthenticationFlow::AuthorizationCode,
+        CsrfToken::new_random,
+        Nonce::new_random,
+      )
+      .set_pkce_challenge(pkce_challenge);
+
+    for scope in &self.config.scopes {
+      request = request.add_scope(Scope::new(scope.clone()));
+    }
+
+    let (auth_url, csrf_token, nonce) = request.url();
+
+    Ok(SsoLoginRequest {
+      provider: self.config.name.clone(),
+      auth_url: auth_url.to_string(),
+      redirect_url,
+      csrf_token: csrf_token.secret().clone(),
+      nonce: nonce.secret().clone(),
+      pkce_verifier: pkce_verifier.secret().clone(),
+    })
+  }
+
+  /// Exchanges the authorization code and verifies the returned ID token
+  pub async fn finish_login(&self, request: &SsoLoginRequest, code: String) -> Result<SsoLoginResponse, SsoError> {
+    let http_client = make_http_client()?;
+    let client = self.discover(&http_client, &request.redirect_url).await?;
+
+    let token_response = client
+      .exchange_code(AuthorizationCode::new(code))
+      .map_err(|e| SsoError::Config(e.to_string()))?
+      .set_pkce_verifier(PkceCodeVerifier::new(request.pkce_verifier.clone()))
+      .request_async(&http_client)
+      .await
+      .map_err(|e| SsoError::CodeExchange(e.to_string()))?;
+
+    let id_token = token_response.id_token().ok_or(SsoError::NoIdToken)?;
+    let verified_claims = id_token
+      .claims(&client.id_token_verifier(), &Nonce::new(request.nonce.clone()))
+      .map_err(|e| SsoError::Verification(e.to_string()))?;
+    let email_verified = verified_claims.email_verified();
+
+    // The signature has been verified above, so the raw payload can be trusted
+    let claims = decode_token_payload(&id_token.to_string())?;
+
+    let email = claims
+      .get(&self.config.email_claim)
+      .and_then(|x| x.as_str())
+      .map(str::to_owned);
+
+    // Whichever claim is used for matching, a provider that says the email is unverified isn't trusted with it
+    if email_verified == Some(false) {
+      return Err(SsoError::EmailNotVerified);
+    }
+
+    let roles = self.config.role_claim.as_ref().map(|role_claim| {
+      let values: Vec<&str> = match claims.get(role_claim) {
+        Some(Value::String(value)) => vec![value.as_str()],
+        Some(Value::Array(values)) => values.iter().filter_map(|x| x.as_str()).collect(),
+        _ => vec![],
+      };
+      debug!(provider = %self.config.name, ?values, "Role claim values");
+      values
+        .into_iter()
+        .filter_map(|value| self.config.role_mappings.get(value).cloned())
+        .collect()
+    });
+
+    Ok(SsoLoginResponse {
+      email,
+      email_verified,
+      roles,
+      claims,
+    })
+  }
+
+  /// Role names whose assignment is controlled by this provider
+  pub fn managed_roles(&self) -> Vec<String> {
+    let mut roles: Vec<String> = self.config.role_mappings.values().cloned().collect();
+    roles.sort();
+    roles.dedup();
+    roles
+  }
+}
+
+fn decode_token_payload(token: &str) -> Result<Map<String, Value>, SsoError> {
+  let payload = token
+    .split('.')
+    .nth(1)
+    .ok_or_else(|| SsoError::Verification("malformed ID token".into()))?;
+  let payload = BASE64URL_NOPAD
+    .decode(payload.trim_end_matches('=').as_bytes())
+    .map_err(|e| SsoError::Verification(e.to_string()))?;
+  serde_json::from_slice(&payload).map_err(|e| SsoError::Verification(e.to_string()))
+}
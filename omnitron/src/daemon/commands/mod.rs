@@ -1,7 +1,9 @@
 mod info;
+mod sessions;
 mod start;
 mod stop;
 
 pub(crate) use info::info;
+pub(crate) use sessions::{close_session, list_sessions, logs, reload_config};
 pub(crate) use start::start;
 pub(crate) use stop::stop;
@@ -0,0 +1,101 @@
+use colored::Colorize;
+use macros_rs::fmt::crashln;
+use omnitron_pm::helpers;
+use omnitron_rpc::context;
+use tabled::settings::style::{BorderColor, Style};
+use tabled::settings::Color;
+use tabled::{Table, Tabled};
+use uuid::Uuid;
+
+use crate::daemon::rpc::{create_client, DaemonServiceClient};
+
+async fn connect() -> DaemonServiceClient {
+  match create_client().await {
+    Ok(client) => client,
+    Err(error) => crashln!("{} The daemon is not running: {}", *helpers::FAIL, error),
+  }
+}
+
+pub(crate) async fn list_sessions(format: &str) {
+  #[derive(Tabled)]
+  struct Row {
+    id: String,
+    protocol: String,
+    user: String,
+    target: String,
+    #[tabled(rename = "remote address")]
+    remote_address: String,
+    started: String,
+  }
+
+  let sessions = match connect().await.list_sessions(context::current()).await {
+    Ok(Ok(sessions)) => sessions,
+    Ok(Err(error)) => crashln!("{} Failed to list sessions: {}", *helpers::FAIL, error),
+    Err(error) => crashln!("{} Daemon RPC failed: {}", *helpers::FAIL, error),
+  };
+
+  match format {
+    "raw" => println!("{:?}", sessions),
+    "json" => match serde_json::to_string(&sessions) {
+      Ok(json) => println!("{json}"),
+      Err(error) => crashln!("{} Failed to serialize sessions: {}", *helpers::FAIL, error),
+    },
+    _ => {
+      if sessions.is_empty() {
+        println!("{} No active sessions", *helpers::SUCCESS);
+        return;
+      }
+
+      let none = || "-".to_string();
+      let rows = sessions.into_iter().map(|x| Row {
+        id: x.id.to_string(),
+        protocol: x.protocol.unwrap_or_else(none),
+        user: x.username.unwrap_or_else(none),
+        target: x.target.unwrap_or_else(none),
+        remote_address: x.remote_address.unwrap_or_else(none),
+        started: x.started.map(|x| x.to_rfc3339()).unwrap_or_else(none),
+      });
+
+      let table = Table::new(rows)
+        .with(Style::rounded().remove_horizontals())
+        .with(BorderColor::filled(Color::FG_BRIGHT_BLACK))
+        .to_string();
+      println!("{table}");
+    }
+  }
+}
+
+pub(crate) async fn close_session(id: &Uuid) {
+  match connect().await.close_session(context::current(), *id).await {
+    Ok(Ok(())) => println!("{} Session {} closed", *helpers::SUCCESS, id.to_string().bold()),
+    Ok(Err(error)) => crashln!("{} Failed to close session: {}", *helpers::FAIL, error),
+    Err(error) => crashln!("{} Daemon RPC failed: {}", *helpers::FAIL, error),
+  }
+}
+
+pub(crate) async fn reload_config() {
+  match connect().await.reload_config(context::current()).await {
+    Ok(Ok(())) => println!("{} Gate config reloaded", *helpers::SUCCESS),
+    Ok(Err(error)) => crashln!("{} Failed to reload config: {}", *helpers::FAIL, error),
+    Err(error) => crashln!("{} Daemon RPC failed: {}", *helpers::FAIL, error),
+  }
+}
+
+pub(crate) async fn logs(lines: u64, session_id: Option<Uuid>) {
+  let mut logs = match connect().await.recent_logs(context::current(), lines, session_id).await {
+    Ok(Ok(logs)) => logs,
+    Ok(Err(error)) => crashln!("{} Failed to fetch logs: {}", *helpers::FAIL, error),
+    Err(error) => crashln!("{} Daemon RPC failed: {}", *helpers::FAIL, error),
+  };
+
+  logs.reverse();
+  for entry in logs {
+    println!(
+      "{} {} {} {}",
+      entry.timestamp.to_rfc3339().bright_black(),
+      entry.session_id.to_string().cyan(),
+      entry.username.unwrap_or_else(|| "-".to_string()).white(),
+      entry.text
+    );
+  }
+}
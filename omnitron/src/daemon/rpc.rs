@@ -1,10 +1,41 @@
+use chrono::{DateTime, Utc};
 use global_placeholders::global;
 use omnitron_rpc::tokio_serde::formats::Bincode;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub(crate) struct SessionInfo {
+  pub id: Uuid,
+  pub protocol: Option<String>,
+  pub username: Option<String>,
+  pub target: Option<String>,
+  pub remote_address: Option<String>,
+  pub started: Option<DateTime<Utc>>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub(crate) struct LogEntryInfo {
+  pub timestamp: DateTime<Utc>,
+  pub session_id: Uuid,
+  pub username: Option<String>,
+  pub text: String,
+}
 
 #[omnitron_rpc::service]
 pub(crate) trait DaemonService {
   /// Returns omnitron version.
   async fn version(short: bool) -> String;
+  /// Lists the sessions currently active in the gate.
+  async fn list_sessions() -> Result<Vec<SessionInfo>, String>;
+  /// Closes an active session.
+  async fn close_session(id: Uuid) -> Result<(), String>;
+  /// Reloads the gate config from disk.
+  async fn reload_config() -> Result<(), String>;
+  /// Tests the connection to a target from the daemon.
+  async fn test_target(target_name: String) -> Result<(), String>;
+  /// Returns the most recent gate log entries, newest first.
+  async fn recent_logs(limit: u64, session_id: Option<Uuid>) -> Result<Vec<LogEntryInfo>, String>;
 }
 
 pub(crate) async fn create_client() -> Result<DaemonServiceClient, anyhow::Error> {
@@ -1,12 +1,105 @@
+use std::sync::Arc;
+
+use omnitron_db_entities::{LogEntry, Session};
+use omnitron_gate_core::Services;
 use omnitron_rpc::context;
+use sea_orm::{ColumnTrait, EntityTrait, QueryFilter, QueryOrder, QuerySelect};
+use tokio::sync::Mutex;
+use tracing::*;
+use uuid::Uuid;
 
-use super::rpc::DaemonService;
+use super::rpc::{DaemonService, LogEntryInfo, SessionInfo};
+use crate::gate::config::load_config;
+use crate::gate::listeners::{apply_config_reload, ProtocolListeners};
 
 #[derive(Clone)]
-pub(crate) struct DaemonServiceServer;
+pub(crate) struct DaemonServiceServer {
+  pub services: Services,
+  pub listeners: Arc<Mutex<ProtocolListeners>>,
+}
 
 impl DaemonService for DaemonServiceServer {
   async fn version(self, _: context::Context, short: bool) -> String {
     crate::helpers::get_version(short)
   }
+
+  async fn list_sessions(self, _: context::Context) -> Result<Vec<SessionInfo>, String> {
+    let mut sessions = vec![];
+    {
+      let state = self.services.state.lock().await;
+      for (id, session) in state.sessions.iter() {
+        let session = session.lock().await;
+        sessions.push(SessionInfo {
+          id: *id,
+          protocol: None,
+          username: session.username.clone(),
+          target: session.target.as_ref().map(|x| x.name.clone()),
+          remote_address: session.remote_address.map(|x| x.to_string()),
+          started: None,
+        });
+      }
+    }
+
+    let db = self.services.db.lock().await;
+    let models = Session::Entity::find()
+      .filter(Session::Column::Id.is_in(sessions.iter().map(|x| x.id)))
+      .all(&*db)
+      .await
+      .map_err(|error| error.to_string())?;
+    for session in sessions.iter_mut() {
+      if let Some(model) = models.iter().find(|x| x.id == session.id) {
+        session.protocol = Some(model.protocol.clone());
+        session.started = Some(model.started);
+      }
+    }
+
+    sessions.sort_by_key(|x| x.started);
+    Ok(sessions)
+  }
+
+  async fn close_session(self, _: context::Context, id: Uuid) -> Result<(), String> {
+    let state = self.services.state.lock().await;
+    let Some(session) = state.sessions.get(&id) else {
+      return Err(format!("Session not found: {id}"));
+    };
+    session.lock().await.handle.close();
+    info!(session_id=%id, "Session closed over RPC");
+    Ok(())
+  }
+
+  async fn reload_config(self, _: context::Context) -> Result<(), String> {
+    let config = load_config(false).map_err(|error| format!("{error:#}"))?;
+    *self.services.config.lock().await = config;
+    info!("Reloaded config");
+    apply_config_reload(&self.services, &self.listeners)
+      .await
+      .map_err(|error| format!("{error:#}"))
+  }
+
+  async fn test_target(self, _: context::Context, target_name: String) -> Result<(), String> {
+    crate::gate::commands::test_target::test_target(&self.services, &target_name).await
+  }
+
+  async fn recent_logs(self, _: context::Context, limit: u64, session_id: Option<Uuid>) -> Result<Vec<LogEntryInfo>, String> {
+    let db = self.services.db.lock().await;
+    let mut q = LogEntry::Entity::find()
+      .order_by_desc(LogEntry::Column::Timestamp)
+      .limit(limit);
+    if let Some(session_id) = session_id {
+      q = q.filter(LogEntry::Column::SessionId.eq(session_id));
+    }
+
+    let logs = q.all(&*db).await.map_err(|error| error.to_string())?;
+    Ok(
+      logs
+        .into_iter()
+        .map(|x| LogEntryInfo {
+          timestamp: x.timestamp,
+          session_id: x.session_id,
+          username: x.username,
+          text: x.text,
+        })
+        .collect(),
+    )
+  }
 }
@@ -11,5 +11,15 @@ pub(crate) async fn command() -> Result<()> {
   for key in keys {
     println!("{}", key.public_key().to_openssh()?);
   }
+
+  if config.store.ssh.client_certificates.enable {
+    let ca_key = omnitron_gate_protocol_ssh::load_client_ca_key(&config)?;
+    println!();
+    println!("Omnitron SSH client CA key:");
+    println!("(or trust this CA via `TrustedUserCAKeys` in the target's sshd_config");
+    println!(" and set `use_certificate` in the target's public key auth options)");
+    println!();
+    println!("{}", ca_key.public_key().to_openssh()?);
+  }
   Ok(())
 }
@@ -5,56 +5,75 @@ use tracing::*;
 
 use crate::gate::config::load_config;
 
-pub(crate) async fn command(target_name: &String) -> Result<()> {
-  let config = load_config(true)?;
-  let services = Services::new(config.clone(), None).await?;
+pub(crate) async fn command(target_name: &str, daemon: bool) -> Result<()> {
+  let result = if daemon {
+    let client = crate::daemon::rpc::create_client().await?;
+    client
+      .test_target(omnitron_rpc::context::current(), target_name.to_owned())
+      .await?
+  } else {
+    let config = load_config(true)?;
+    let services = Services::new(config.clone(), None).await?;
+    test_target(&services, target_name).await
+  };
+
+  match result {
+    Ok(()) => {
+      info!("Connection successful!");
+      Ok(())
+    }
+    Err(error) => {
+      error!("{}", error);
+      anyhow::bail!("Connection test failed")
+    }
+  }
+}
 
-  let Some(target) = services
+/// Connects to the target with the matching protocol server, describing the failure if there is one
+pub(crate) async fn test_target(services: &Services, target_name: &str) -> Result<(), String> {
+  let targets = services
     .config_provider
     .lock()
     .await
     .list_targets()
-    .await?
-    .iter()
-    .find(|x| &x.name == target_name)
-    .cloned()
-  else {
-    error!("Target not found: {}", target_name);
-    return Ok(());
+    .await
+    .map_err(|error| format!("Failed to list targets: {error}"))?;
+
+  let Some(target) = targets.into_iter().find(|x| x.name == target_name) else {
+    return Err(format!("Target not found: {}", target_name));
   };
 
-  let s: Box<dyn ProtocolServer> = match target.options {
-    TargetOptions::Ssh(_) => Box::new(omnitron_gate_protocol_ssh::SSHProtocolServer::new(&services).await?),
-    TargetOptions::Http(_) => Box::new(omnitron_gate_protocol_http::HTTPProtocolServer::new(&services).await?),
-    TargetOptions::MySql(_) => Box::new(omnitron_gate_protocol_mysql::MySQLProtocolServer::new(&services).await?),
-    TargetOptions::Postgres(_) => Box::new(omnitron_gate_protocol_postgres::PostgresProtocolServer::new(&services).await?),
-    TargetOptions::WebAdmin(_) => {
-      error!("Unsupported target type");
-      return Ok(());
-    }
+  let server_error = |error: anyhow::Error| format!("Could not start the protocol server: {error:#}");
+  let s: Box<dyn ProtocolServer + Send + Sync> = match target.options {
+    TargetOptions::Ssh(_) => Box::new(
+      omnitron_gate_protocol_ssh::SSHProtocolServer::new(services)
+        .await
+        .map_err(server_error)?,
+    ),
+    TargetOptions::Http(_) => Box::new(
+      omnitron_gate_protocol_http::HTTPProtocolServer::new(services)
+        .await
+        .map_err(server_error)?,
+    ),
+    TargetOptions::MySql(_) => Box::new(
+      omnitron_gate_protocol_mysql::MySQLProtocolServer::new(services)
+        .await
+        .map_err(server_error)?,
+    ),
+    TargetOptions::Postgres(_) => Box::new(
+      omnitron_gate_protocol_postgres::PostgresProtocolServer::new(services)
+        .await
+        .map_err(server_error)?,
+    ),
+    TargetOptions::WebAdmin(_) => return Err("Unsupported target type".into()),
   };
 
   match s.test_target(target).await {
-    Err(TargetTestError::AuthenticationError) => {
-      error!("Authentication failed");
-    }
-    Err(TargetTestError::ConnectionError(error)) => {
-      error!(?error, "Connection error");
-    }
-    Err(TargetTestError::Io(error)) => {
-      error!(?error, "I/O error");
-    }
-    Err(TargetTestError::Misconfigured(error)) => {
-      error!(?error, "Misconfigured");
-    }
-    Err(TargetTestError::Unreachable) => {
-      error!("Target is unreachable");
-    }
-    Ok(()) => {
-      info!("Connection successful!");
-      return Ok(());
-    }
+    Err(TargetTestError::AuthenticationError) => Err("Authentication failed".into()),
+    Err(TargetTestError::ConnectionError(error)) => Err(format!("Connection error: {error}")),
+    Err(TargetTestError::Io(error)) => Err(format!("I/O error: {error}")),
+    Err(TargetTestError::Misconfigured(error)) => Err(format!("Misconfigured: {error}")),
+    Err(TargetTestError::Unreachable) => Err("Target is unreachable".into()),
+    Ok(()) => Ok(()),
   }
-
-  anyhow::bail!("Connection test failed")
 }
@@ -51,6 +51,9 @@ pub(crate) async fn create_config() -> Result<()> {
     format!("sqlite:{db_path}")
   });
 
+  store.recordings.enable = true;
+  store.recordings.path = data_path.join("recordings").to_string_lossy().to_string();
+
   store.http.enable = true;
   store.http.listen = HttpConfig::default().listen;
 
@@ -68,6 +71,8 @@ pub(crate) async fn create_config() -> Result<()> {
 
   store.http.key = data_path.join("tls.key.pem").to_string_lossy().to_string();
 
+  store.http.acme.path = data_path.join("acme").to_string_lossy().to_string();
+
   store.mysql.certificate = store.http.certificate.clone();
   store.mysql.key = store.http.key.clone();
 
@@ -116,6 +121,7 @@ pub(crate) async fn create_config() -> Result<()> {
           id: Set(Uuid::new_v4()),
           username: Set(BUILTIN_ADMIN_USERNAME.to_owned()),
           credential_policy: Set(serde_json::to_value(None::<UserRequireCredentialsPolicy>)?),
+          ip_policy: Set(None),
         };
         values.insert(&*db).await.map_err(OmnitronError::from)?
       }
@@ -0,0 +1,279 @@
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Result;
+use omnitron_gate_common::{ListenEndpoint, OmnitronConfig};
+use omnitron_gate_core::db::cleanup_db;
+use omnitron_gate_core::logging::install_database_logger;
+use omnitron_gate_core::{ProtocolServer, Services};
+use omnitron_gate_protocol_http::HTTPProtocolServer;
+use omnitron_gate_protocol_mysql::MySQLProtocolServer;
+use omnitron_gate_protocol_postgres::PostgresProtocolServer;
+use omnitron_gate_protocol_ssh::SSHProtocolServer;
+#[cfg(target_os = "linux")]
+use sd_notify::NotifyState;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::{mpsc, Mutex};
+use tokio::task::JoinHandle;
+use tokio::time::MissedTickBehavior;
+use tracing::*;
+
+use crate::gate::config::watch_config;
+
+const GRANT_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub(crate) enum GateProtocol {
+  Ssh,
+  Http,
+  MySql,
+  Postgres,
+}
+
+impl GateProtocol {
+  pub(crate) const ALL: [GateProtocol; 4] = [Self::Ssh, Self::Http, Self::MySql, Self::Postgres];
+
+  /// Address the protocol should listen on, or `None` if it's disabled
+  pub(crate) fn listen_endpoint(&self, config: &OmnitronConfig) -> Option<ListenEndpoint> {
+    let store = &config.store;
+    let (enable, listen) = match self {
+      Self::Ssh => (store.ssh.enable, &store.ssh.listen),
+      Self::Http => (store.http.enable, &store.http.listen),
+      Self::MySql => (store.mysql.enable, &store.mysql.listen),
+      Self::Postgres => (store.postgres.enable, &store.postgres.listen),
+    };
+    enable.then(|| listen.clone())
+  }
+
+  async fn run(self, services: Services, address: ListenEndpoint) -> Result<()> {
+    match self {
+      Self::Ssh => SSHProtocolServer::new(&services).await?.run(address).await,
+      Self::Http => HTTPProtocolServer::new(&services).await?.run(address).await,
+      Self::MySql => MySQLProtocolServer::new(&services).await?.run(address).await,
+      Self::Postgres => PostgresProtocolServer::new(&services).await?.run(address).await,
+    }
+  }
+}
+
+impl fmt::Display for GateProtocol {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Self::Ssh => write!(f, "SSH"),
+      Self::Http => write!(f, "HTTP"),
+      Self::MySql => write!(f, "MySQL"),
+      Self::Postgres => write!(f, "PostgreSQL"),
+    }
+  }
+}
+
+pub(crate) struct ProtocolError {
+  pub protocol: GateProtocol,
+  pub error: anyhow::Error,
+}
+
+struct RunningListener {
+  address: ListenEndpoint,
+  task: JoinHandle<()>,
+}
+
+/// Keeps one listener running for every protocol enabled in the config.
+///
+/// Stopping a listener only closes its socket - sessions that were already
+/// accepted keep running until they end on their own.
+pub(crate) struct ProtocolListeners {
+  services: Services,
+  running: HashMap<GateProtocol, RunningListener>,
+  error_tx: mpsc::UnboundedSender<ProtocolError>,
+}
+
+impl ProtocolListeners {
+  pub(crate) fn new(services: &Services) -> (Self, mpsc::UnboundedReceiver<ProtocolError>) {
+    let (error_tx, error_rx) = mpsc::unbounded_channel();
+    (
+      Self {
+        services: services.clone(),
+        running: HashMap::new(),
+        error_tx,
+      },
+      error_rx,
+    )
+  }
+
+  pub(crate) fn is_empty(&self) -> bool {
+    self.running.is_empty()
+  }
+
+  /// Starts newly enabled protocols, stops disabled ones and restarts
+  /// the ones whose listen address has changed or whose listener has died.
+  pub(crate) async fn sync(&mut self) {
+    let config = self.services.config.lock().await.clone();
+    for protocol in GateProtocol::ALL {
+      let desired = protocol.listen_endpoint(&config);
+      if let Some(running) = self.running.get(&protocol) {
+        if Some(&running.address) == desired.as_ref() && !running.task.is_finished() {
+          continue;
+        }
+        self.stop(protocol);
+      }
+      if let Some(address) = desired {
+        self.start(protocol, address);
+      }
+    }
+  }
+
+  pub(crate) fn stop_all(&mut self) {
+    for protocol in GateProtocol::ALL {
+      self.stop(protocol);
+    }
+  }
+
+  fn start(&mut self, protocol: GateProtocol, address: ListenEndpoint) {
+    info!(%protocol, ?address, "Starting listener");
+    let task = tokio::spawn({
+      let services = self.services.clone();
+      let error_tx = self.error_tx.clone();
+      let address = address.clone();
+      async move {
+        if let Err(error) = protocol.run(services, address).await {
+          let _ = error_tx.send(ProtocolError { protocol, error });
+        }
+      }
+    });
+    self.running.insert(protocol, RunningListener { address, task });
+  }
+
+  fn stop(&mut self, protocol: GateProtocol) {
+    if let Some(listener) = self.running.remove(&protocol) {
+      info!(%protocol, address=?listener.address, "Stopping listener");
+      listener.task.abort();
+    }
+  }
+}
+
+/// Protocol bootstrap shared by `gate run` and the daemon: starts the enabled
+/// listeners along with the database housekeeping, systemd notifications
+/// and the config watcher.
+pub(crate) async fn start_gate(
+  services: &Services,
+) -> Result<(Arc<Mutex<ProtocolListeners>>, mpsc::UnboundedReceiver<ProtocolError>)> {
+  install_database_logger(services.db.clone());
+
+  let (listeners, errors) = ProtocolListeners::new(services);
+  let listeners = Arc::new(Mutex::new(listeners));
+  listeners.lock().await.sync().await;
+
+  tokio::spawn(cleanup_db_periodically(services.clone()));
+
+  #[cfg(target_os = "linux")]
+  if let Ok(true) = sd_notify::booted() {
+    tokio::spawn(notify_systemd());
+  }
+
+  tokio::spawn(watch_config_and_reload(services.clone(), listeners.clone()));
+
+  Ok((listeners, errors))
+}
+
+async fn cleanup_db_periodically(services: Services) {
+  loop {
+    let retention = { services.config.lock().await.store.log.retention };
+    let interval = retention / 10;
+    // Same lock order as `SessionRecordings::start`: recordings, then db
+    let mut recordings = services.recordings.lock().await;
+    let mut db = services.db.lock().await;
+    match cleanup_db(&mut db, &mut recordings, &retention).await {
+      Err(error) => error!(?error, "Failed to cleanup the database"),
+      Ok(_) => debug!("Database cleaned up, next in {:?}", interval),
+    }
+    drop(db);
+    drop(recordings);
+    tokio::time::sleep(interval).await;
+  }
+}
+
+#[cfg(target_os = "linux")]
+async fn notify_systemd() {
+  use std::time::Duration;
+  if let Err(error) = async {
+    sd_notify::notify(false, &[NotifyState::Ready])?;
+    loop {
+      sd_notify::notify(false, &[NotifyState::Watchdog])?;
+      tokio::time::sleep(Duration::from_secs(15)).await;
+    }
+    #[allow(unreachable_code)]
+    Ok::<(), anyhow::Error>(())
+  }
+  .await
+  {
+    error!(?error, "Failed to communicate with systemd");
+  }
+}
+
+pub async fn watch_config_and_reload(services: Services, listeners: Arc<Mutex<ProtocolListeners>>) -> Result<()> {
+  let mut reload_event = watch_config(services.config.clone())?;
+  // Role grants can expire or leave their time window without any config change
+  let mut grant_check = tokio::time::interval(GRANT_CHECK_INTERVAL);
+  grant_check.set_missed_tick_behavior(MissedTickBehavior::Skip);
+
+  loop {
+    tokio::select! {
+      event = reload_event.recv() => {
+        // Missed events just mean that the config has been reloaded more than once
+        if let Err(RecvError::Closed) = event {
+          break;
+        }
+        if let Err(error) = apply_config_reload(&services, &listeners).await {
+          error!(?error, "Failed to apply the reloaded config");
+        }
+      }
+      _ = grant_check.tick() => {
+        if let Err(error) = close_unauthorized_sessions(&services, "Session no longer authorized by an active role grant").await {
+          error!(?error, "Failed to re-check session authorization");
+        }
+      }
+    }
+  }
+
+  Ok(())
+}
+
+/// Rebuilds the config provider, re-checks active sessions against the freshly
+/// loaded config and brings the protocol listeners in line with it
+pub(crate) async fn apply_config_reload(services: &Services, listeners: &Mutex<ProtocolListeners>) -> Result<()> {
+  services.reload_config_provider().await;
+  let result = close_unauthorized_sessions(services, "Session no longer authorized after config reload").await;
+  listeners.lock().await.sync().await;
+  result
+}
+
+async fn close_unauthorized_sessions(services: &Services, reason: &str) -> Result<()> {
+  // Collected first, so that the config provider's queries don't hold up the session state
+  let mut sessions = vec![];
+  for (id, session) in services.state.lock().await.sessions.iter() {
+    let state = session.lock().await;
+    if let (Some(username), Some(target)) = (state.username.as_ref(), state.target.as_ref()) {
+      sessions.push((*id, username.clone(), target.name.clone(), session.clone()));
+    }
+  }
+
+  let mut authorized = HashMap::new();
+  {
+    let mut cp = services.config_provider.lock().await;
+    for (_, username, target, _) in &sessions {
+      if let Entry::Vacant(entry) = authorized.entry((username, target)) {
+        entry.insert(cp.authorize_target(username, target).await?);
+      }
+    }
+  }
+
+  for (id, username, target, session) in &sessions {
+    if authorized.get(&(username, target)) == Some(&false) {
+      warn!(sesson_id=%id, %username, %target, "{reason}");
+      session.lock().await.handle.close();
+    }
+  }
+  Ok(())
+}
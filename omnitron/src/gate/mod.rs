@@ -1,2 +1,3 @@
 pub(crate) mod commands;
 pub(crate) mod config;
+pub(crate) mod listeners;
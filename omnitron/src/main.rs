@@ -12,6 +12,7 @@ use gate::config::load_config;
 use logging::init_logging;
 use macros_rs::fmt::{str, string};
 use tracing::*;
+use uuid::Uuid;
 
 #[derive(clap::Parser)]
 #[clap(author, version =str!(helpers::get_version(false)), about, long_about = None, arg_required_else_help = true, propagate_version = true)]
@@ -56,6 +57,22 @@ enum Commands {
   /// Stop daemon
   #[command(visible_alias = "kill", visible_alias = "stop")]
   Down,
+  /// Manage gate sessions on the running daemon
+  Sessions {
+    #[command(subcommand)]
+    command: SessionCommands,
+  },
+  /// Reload the gate config of the running daemon
+  Reload,
+  /// Show recent gate log entries from the running daemon
+  Logs {
+    /// Number of entries to show
+    #[arg(long, default_value_t = 50)]
+    lines: u64,
+    /// Only show entries of this session
+    #[arg(long)]
+    session: Option<Uuid>,
+  },
   // /// Restart daemon
   // #[command(visible_alias = "restart", visible_alias = "start")]
   // Restore {
@@ -91,6 +108,9 @@ pub(crate) enum GateCommands {
   TestTarget {
     #[clap(action=ArgAction::Set)]
     target_name: String,
+    /// Run the test from the running daemon
+    #[clap(long, action=ArgAction::SetTrue)]
+    daemon: bool,
   },
   /// Reset password and auth policy for a user
   RecoverAccess {
@@ -99,6 +119,23 @@ pub(crate) enum GateCommands {
   },
 }
 
+#[derive(clap::Subcommand)]
+enum SessionCommands {
+  /// List active sessions
+  #[command(visible_alias = "ls")]
+  List {
+    /// Format output
+    #[arg(long, default_value_t = string!("default"))]
+    format: String,
+  },
+  /// Close an active session
+  #[command(visible_alias = "kill")]
+  Close {
+    /// Session ID
+    id: Uuid,
+  },
+}
+
 #[derive(clap::Subcommand)]
 enum PmDaemon {
   /// Reset process index
@@ -286,10 +323,25 @@ async fn run(cli: &Cli) -> Result<()> {
       daemon::commands::info(format).await;
       Ok(())
     }
+    Commands::Sessions { command } => {
+      match command {
+        SessionCommands::List { format } => daemon::commands::list_sessions(format).await,
+        SessionCommands::Close { id } => daemon::commands::close_session(id).await,
+      }
+      Ok(())
+    }
+    Commands::Reload => {
+      daemon::commands::reload_config().await;
+      Ok(())
+    }
+    Commands::Logs { lines, session } => {
+      daemon::commands::logs(*lines, *session).await;
+      Ok(())
+    }
     Commands::Gate { command } => match command {
       GateCommands::Run { enable_admin_token } => gate::commands::run::command(*enable_admin_token).await,
       GateCommands::Check => gate::commands::check::command().await,
-      GateCommands::TestTarget { target_name } => gate::commands::test_target::command(target_name).await,
+      GateCommands::TestTarget { target_name, daemon } => gate::commands::test_target::command(target_name, *daemon).await,
       GateCommands::ClientKeys => gate::commands::client_keys::command().await,
       GateCommands::RecoverAccess { username } => gate::commands::recover_access::command(username).await,
     },
@@ -178,6 +178,77 @@ class ProcessManager:
         logging.debug(f"Postgres {container_name} is up")
         return port
 
+    def start_pebble(self):
+        """Starts a pebble ACME test server that skips challenge validation.
+        Returns the directory URL and the path of the CA certificate for its HTTPS API."""
+        port = alloc_port()
+        container_name = f"omnitron-e2e-pebble-{uuid.uuid4()}"
+        self.start(
+            [
+                "docker",
+                "run",
+                "--rm",
+                "--name",
+                container_name,
+                "-p",
+                f"{port}:14000",
+                "-e",
+                "PEBBLE_VA_ALWAYS_VALID=1",
+                "-e",
+                "PEBBLE_WFE_NONCEREJECT=0",
+                "ghcr.io/letsencrypt/pebble",
+            ]
+        )
+        wait_port(port, recv=False)
+
+        ca_path = self.ctx.tmpdir / f"pebble-{uuid.uuid4()}.pem"
+        subprocess.check_call(
+            ["docker", "cp", f"{container_name}:/test/certs/pebble.minica.pem", str(ca_path)]
+        )
+        return f"https://localhost:{port}/dir", ca_path
+
+    def start_oidc_mock(self, port, redirect_uris):
+        """Starts the mock OIDC provider from oidc-mock/ on the given port,
+        allowing its client to redirect to redirect_uris. Returns the issuer URL."""
+        import json
+        import yaml
+
+        service = yaml.safe_load(Path("oidc-mock/docker-compose.yml").read_text())[
+            "services"
+        ]["oidc-server-mock"]
+        env = dict(service["environment"])
+        clients = json.loads(Path("oidc-mock/clients-config.json").read_text())
+        for client in clients:
+            client["RedirectUris"] = redirect_uris
+        del env["CLIENTS_CONFIGURATION_PATH"]
+        env["CLIENTS_CONFIGURATION_INLINE"] = json.dumps(clients)
+
+        self.start(
+            [
+                "docker",
+                "run",
+                "--rm",
+                "-p",
+                f"{port}:80",
+                *[arg for k, v in env.items() for arg in ("-e", f"{k}={v}")],
+                service["image"],
+            ]
+        )
+        issuer_url = f"http://localhost:{port}"
+
+        def wait_discovery():
+            while True:
+                try:
+                    requests.get(
+                        f"{issuer_url}/.well-known/openid-configuration"
+                    ).raise_for_status()
+                    break
+                except requests.RequestException:
+                    time.sleep(1)
+
+        _wait_timeout(wait_discovery, "OIDC mock is not ready", timeout=self.timeout * 3)
+        return issuer_url
+
     def start_wg(
         self,
         config="",
@@ -185,6 +256,8 @@ class ProcessManager:
         share_with: Optional[OmnitronProcess] = None,
         stderr=None,
         stdout=None,
+        config_patch=None,
+        env=None,
     ) -> OmnitronProcess:
         args = args or ["run", "--enable-admin-token"]
 
@@ -266,10 +339,12 @@ class ProcessManager:
 
             config = yaml.safe_load(config_path.open())
             config["ssh"]["host_key_verification"] = "auto_accept"
+            if config_patch:
+                config_patch(config, data_dir)
             with config_path.open("w") as f:
                 yaml.safe_dump(config, f)
 
-        p = run(args)
+        p = run(args, env=env or {})
         return OmnitronProcess(
             process=p,
             config_path=config_path,
@@ -0,0 +1,68 @@
+import socket
+import ssl
+import time
+
+from .conftest import ProcessManager
+from .util import alloc_port, wait_port
+
+DOMAIN = "acme.omnitron.test"
+
+
+def get_certificate(port, server_hostname):
+    context = ssl.create_default_context()
+    context.check_hostname = False
+    context.verify_mode = ssl.CERT_NONE
+    with socket.create_connection(("localhost", port), timeout=5) as s:
+        with context.wrap_socket(s, server_hostname=server_hostname) as tls:
+            return tls.getpeercert(binary_form=True)
+
+
+class Test:
+    def test_issue_certificate(self, processes: ProcessManager, timeout):
+        directory_url, ca_path = processes.start_pebble()
+        acme_path = None
+
+        def patch(config, data_dir):
+            nonlocal acme_path
+            acme_path = data_dir / "acme"
+            config["http"]["acme"] = {
+                "enable": True,
+                "directory_url": directory_url,
+                "challenge": "http-01",
+                "http_challenge_listen": f"127.0.0.1:{alloc_port()}",
+                "domains": [DOMAIN],
+                "path": str(acme_path),
+            }
+
+        wg = processes.start_wg(
+            config_patch=patch,
+            env={"SSL_CERT_FILE": str(ca_path)},
+        )
+        wait_port(wg.http_port, for_process=wg.process, recv=False)
+
+        deadline = time.time() + timeout * 3
+        while b"Pebble" not in get_certificate(wg.http_port, DOMAIN):
+            assert wg.process.poll() is None, "Omnitron exited"
+            assert time.time() < deadline, "No certificate was issued"
+            time.sleep(1)
+
+        assert (acme_path / "domains").read_text() == DOMAIN
+        assert (acme_path / "key.pem").stat().st_mode & 0o777 == 0o600
+        assert acme_path.stat().st_mode & 0o777 == 0o700
+
+    def test_acme_failure_keeps_serving(self, processes: ProcessManager, timeout):
+        def patch(config, data_dir):
+            config["http"]["acme"] = {
+                "enable": True,
+                "directory_url": f"https://localhost:{alloc_port()}/dir",
+                # Unbindable, so the challenge listener fails too
+                "http_challenge_listen": "192.0.2.1:80",
+                "domains": [DOMAIN],
+                "path": str(data_dir / "acme"),
+            }
+
+        wg = processes.start_wg(config_patch=patch)
+        wait_port(wg.http_port, for_process=wg.process, recv=False)
+        time.sleep(2)
+        assert wg.process.poll() is None
+        assert get_certificate(wg.http_port, "localhost")
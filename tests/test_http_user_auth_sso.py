@@ -0,0 +1,85 @@
+import html
+import re
+import requests
+from uuid import uuid4
+
+from .api_client import admin_client, sdk
+from .conftest import ProcessManager
+from .util import alloc_port, wait_port
+
+# Client and user defined in oidc-mock/
+CLIENT_ID = "client-credentials-mock-client"
+CLIENT_SECRET = "client-credentials-mock-client-secret"
+USER_EMAIL = "sam.tailor@gmail.com"
+
+
+def form_field(page, name):
+    match = re.search(rf'name="{name}"[^>]*value="([^"]*)"', page)
+    assert match, f"No {name} field on the login page"
+    return html.unescape(match.group(1))
+
+
+class TestHTTPUserAuthSSO:
+    def test_auth_sso_login(self, processes: ProcessManager, timeout):
+        mock_port = alloc_port()
+
+        def patch(config, data_dir):
+            config["sso_providers"] = [
+                {
+                    "name": "mock",
+                    "issuer_url": f"http://localhost:{mock_port}",
+                    "client_id": CLIENT_ID,
+                    "client_secret": CLIENT_SECRET,
+                    "scopes": ["openid", "email", "profile"],
+                }
+            ]
+
+        wg = processes.start_wg(config_patch=patch)
+        wait_port(wg.http_port, for_process=wg.process, recv=False)
+        url = f"https://localhost:{wg.http_port}"
+        processes.start_oidc_mock(
+            mock_port, [f"{url}/@omnitron/api/sso/return"]
+        )
+
+        with admin_client(url) as api:
+            user = api.create_user(sdk.CreateUserRequest(username=f"user-{uuid4()}"))
+            api.create_sso_credential(
+                user.id, sdk.NewSsoCredential(provider="mock", email=USER_EMAIL)
+            )
+
+        session = requests.Session()
+        session.verify = False
+
+        # Ends up on the provider's login page
+        response = session.get(
+            f"{url}/@omnitron/api/sso/providers/mock/start?next=/@omnitron/api/info"
+        )
+        assert response.url.startswith(f"http://localhost:{mock_port}/")
+        page = response.text
+
+        response = session.post(
+            response.url,
+            data={
+                "ReturnUrl": form_field(page, "ReturnUrl"),
+                "__RequestVerificationToken": form_field(
+                    page, "__RequestVerificationToken"
+                ),
+                "Username": "User1",
+                "Password": "pwd",
+                "button": "login",
+            },
+        )
+        assert response.status_code == 200
+        assert response.url == f"{url}/@omnitron/api/info"
+        assert response.json()["username"] == user.username
+
+        # A callback that doesn't carry the login's state is refused
+        response = session.get(
+            f"{url}/@omnitron/api/sso/providers/mock/start", allow_redirects=False
+        )
+        assert response.status_code == 307
+        response = session.get(
+            f"{url}/@omnitron/api/sso/return?code=x&state=forged",
+            allow_redirects=False,
+        )
+        assert response.status_code == 400